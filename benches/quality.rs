@@ -0,0 +1,29 @@
+// Benchmarks of the per-base quality averaging, the hottest loop of FASTQ
+// processing: `cargo bench --bench quality`
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nanoget_rs::utils::{average_quality_with_offset, SplitMix64, FASTQ_PHRED_OFFSET};
+use std::hint::black_box;
+
+/// A FASTQ quality string of `length` bases, Q5 to Q40
+fn quality_string(length: usize) -> Vec<u8> {
+    let mut rng = SplitMix64::new(length as u64);
+    (0..length)
+        .map(|_| FASTQ_PHRED_OFFSET + 5 + rng.below(36) as u8)
+        .collect()
+}
+
+fn average_quality(c: &mut Criterion) {
+    let mut group = c.benchmark_group("average_quality");
+    for length in [150, 10_000, 100_000] {
+        let qualities = quality_string(length);
+        group.throughput(Throughput::Bytes(length as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(length), &qualities, |b, q| {
+            b.iter(|| average_quality_with_offset(black_box(q), FASTQ_PHRED_OFFSET))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, average_quality);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+// Benchmarks of the summary statistics over extracted reads, computed once
+// per run over every read: `cargo bench --bench summary`
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nanoget_rs::utils::SplitMix64;
+use nanoget_rs::{MetricsSummary, ReadMetrics};
+use std::hint::black_box;
+
+/// `count` aligned reads with a length, quality, mapping quality, identity
+/// and aligned length, as extracted from a BAM file
+fn aligned_reads(count: usize) -> Vec<ReadMetrics> {
+    let mut rng = SplitMix64::new(count as u64);
+    (0..count)
+        .map(|_| {
+            let length = 200 + rng.below(50_000);
+            let aligned_length = length - rng.below(length / 10 + 1);
+            ReadMetrics::new(None, length)
+                .with_quality(5.0 + rng.below(3500) as f64 / 100.0)
+                .with_alignment(
+                    aligned_length,
+                    None,
+                    Some(rng.below(61) as u8),
+                    Some(85.0 + rng.below(1500) as f64 / 100.0),
+                )
+        })
+        .collect()
+}
+
+fn summary(c: &mut Criterion) {
+    let mut group = c.benchmark_group("summary");
+    group.sample_size(10);
+    for count in [100_000, 1_000_000] {
+        let reads = aligned_reads(count);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &reads, |b, reads| {
+            b.iter(|| MetricsSummary::from_reads(black_box(reads)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, summary);
+criterion_main!(benches);
@@ -1,22 +1,15 @@
 // Example of using nanoget-rs as a library
 
-use nanoget_rs::{extract_metrics, ExtractArgs, FileType, ReadMetrics, MetricsCollection};
-use std::path::PathBuf;
+use nanoget_rs::{
+    extract_metrics, CombineMethod, ExtractArgs, FileType, MetricsCollection, ReadMetrics,
+};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Example 1: Extract metrics from a single FASTQ file
-    let args = ExtractArgs {
-        files: vec![PathBuf::from("reads.fastq")],
-        file_type: FileType::Fastq,
-        threads: 4,
-        output_format: "json".to_string(),
-        output: None,
-        read_type: "1D".to_string(),
-        barcoded: false,
-        keep_supplementary: true,
-        combine: "simple".to_string(),
-        names: None,
-    };
+    let args = ExtractArgs::builder()
+        .files(["reads.fastq"])
+        .file_type(FileType::Fastq)
+        .build();
     
     let metrics: MetricsCollection = extract_metrics(&args)?;
     
@@ -37,28 +30,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                  .unwrap_or(0.0));
     
     // Example 2: Process multiple files with tracking
-    let multi_args = ExtractArgs {
-        files: vec![
-            PathBuf::from("sample1.fastq"),
-            PathBuf::from("sample2.fastq"),
-        ],
-        file_type: FileType::Fastq,
-        threads: 8,
-        output_format: "json".to_string(),
-        output: None,
-        read_type: "1D".to_string(),
-        barcoded: false,
-        keep_supplementary: true,
-        combine: "track".to_string(),
-        names: Some(vec!["Sample1".to_string(), "Sample2".to_string()]),
-    };
+    let multi_args = ExtractArgs::builder()
+        .files(["sample1.fastq", "sample2.fastq"])
+        .file_type(FileType::Fastq)
+        .threads(8)
+        .combine(CombineMethod::Track)
+        .names(["Sample1", "Sample2"])
+        .build();
     
     let multi_metrics = extract_metrics(&multi_args)?;
     
     // Analyze per-dataset
     for dataset_name in ["Sample1", "Sample2"] {
         let dataset_reads: Vec<_> = multi_metrics.reads.iter()
-            .filter(|r| r.dataset.as_deref() == Some(dataset_name))
+            .filter(|r| r.dataset().map(AsRef::as_ref) == Some(dataset_name))
             .collect();
         
         println!("{}: {} reads", dataset_name, dataset_reads.len());
@@ -1,5 +1,5 @@
-use clap::{Args, Parser, Subcommand};
-use std::path::PathBuf;
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -8,53 +8,1239 @@ use std::path::PathBuf;
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Only log errors
+    #[arg(short = 'q', long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log more: -v for progress messages, -vv for debugging output, -vvv for
+    /// everything (RUST_LOG takes precedence when set)
+    #[arg(short = 'v', long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+}
+
+impl Cli {
+    /// Log level selected by --quiet and --verbose; warnings by default
+    pub fn log_level(&self) -> log::LevelFilter {
+        if self.quiet {
+            return log::LevelFilter::Error;
+        }
+        match self.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
 }
 
+// Parsed once at startup, so the size of the largest variant does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Commands {
     /// Extract metrics from sequencing files
     Extract(ExtractArgs),
+
+    /// Print a summary of sequencing files: extract without the per-read
+    /// metrics, in a single pass, as a human-readable report unless -f is given
+    Stats(StatsArgs),
+
+    /// Compare two metrics files written by `extract -f json`
+    Compare(CompareArgs),
+
+    /// Merge metrics files written by `extract -f json` (e.g. one per flow
+    /// cell) into one, tagging the reads of each file with its dataset name
+    /// and recomputing the summary, without reading the inputs again
+    Merge(MergeArgs),
+
+    /// Check input files before a long extraction: open each one, read its
+    /// header and first records, and report its type, compression, estimated
+    /// number of records and any problems (e.g. a truncated download); exits
+    /// with an error when any file fails
+    Validate(ValidateArgs),
+
+    /// Print the JSON Schema of the output of `extract -f json` (fields, types
+    /// and which ones are optional), for tools that read it
+    Schema(SchemaArgs),
+
+    /// Print a shell completion script, e.g.
+    /// `nanoget completions bash > /etc/bash_completion.d/nanoget`
+    Completions(CompletionsArgs),
+
+    /// Print the man page (roff) of nanoget or one of its subcommands, e.g.
+    /// `nanoget man > nanoget.1` and `nanoget man extract > nanoget-extract.1`
+    Man(ManArgs),
 }
 
-#[derive(Args)]
-pub struct ExtractArgs {
-    /// Input files to process
+#[derive(Args, Clone)]
+pub struct SchemaArgs {
+    /// Print the schema of the summary alone, as written by `-f json-summary`
+    #[arg(long)]
+    pub summary: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct ManArgs {
+    /// Subcommand to print the man page of
+    pub command: Option<String>,
+}
+
+#[derive(Args, Clone)]
+pub struct CompletionsArgs {
+    /// Shell to complete for
+    #[arg(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+#[derive(Args, Clone)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub extract: ExtractArgs,
+
+    /// Keep every read in memory while summarizing, for exact quantiles and
+    /// the read length N50 (memory grows with the number of reads); implied by
+    /// the options that need every read, such as --write-reads, --per-file,
+    /// --split-by, --max-memory or --cumulative-yield
+    #[arg(long)]
+    pub exact: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct CompareArgs {
+    /// Metrics of the first (reference) dataset, from `extract -f json`
+    pub first: PathBuf,
+
+    /// Metrics of the second dataset, compared against the first
+    pub second: PathBuf,
+
+    /// Output format (table, json)
+    #[arg(short = 'f', long, default_value = "table")]
+    pub output_format: String,
+
+    /// Output file (optional, defaults to stdout)
+    #[arg(short = 'o', long)]
+    pub output: Option<PathBuf>,
+
+    /// Overwrite an existing output file
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct MergeArgs {
+    /// Metrics files to merge, from `extract -f json` (possibly compressed)
     #[arg(required = true)]
     pub files: Vec<PathBuf>,
 
-    /// Type of input files
+    /// Dataset names, comma-separated, one per file; by default the file
+    /// names without their extensions (fc1.json -> fc1)
+    #[arg(long, value_delimiter = ',')]
+    pub names: Option<Vec<String>>,
+
+    /// Output format (json, json-summary, tsv); by default inferred from the
+    /// extension of --output, and json otherwise
+    #[arg(short = 'f', long)]
+    pub output_format: Option<String>,
+
+    /// Output file (optional, defaults to stdout)
+    #[arg(short = 'o', long)]
+    pub output: Option<PathBuf>,
+
+    /// Overwrite an existing output file
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Clone)]
+pub struct ValidateArgs {
+    /// Input files to check
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Type of input files; detected per file when omitted
     #[arg(short = 't', long, value_enum)]
-    pub file_type: crate::formats::FileType,
+    pub file_type: Option<crate::formats::FileType>,
 
-    /// Number of threads to use for processing
-    #[arg(short = 'j', long, default_value = "4")]
-    pub threads: usize,
+    /// Number of records to read from each file
+    #[arg(short = 'n', long, default_value = "1000", value_name = "N")]
+    pub records: usize,
 
-    /// Output format (json, tsv)
-    #[arg(short = 'f', long, default_value = "json")]
+    /// Also check that this region can be queried in the BAM/CRAM inputs
+    /// with their index, as for `extract --region`
+    #[arg(long, value_name = "REGION")]
+    pub region: Option<String>,
+
+    /// Index of the inputs for --region, when it does not sit next to the
+    /// data file
+    #[arg(long, value_name = "PATH|URL", requires = "region")]
+    pub index: Option<String>,
+
+    /// Output format (table, json)
+    #[arg(short = 'f', long, default_value = "table")]
     pub output_format: String,
+}
 
-    /// Output file (optional, defaults to stdout)
+/// Options of `nanoget extract`, which the library functions (`extract_metrics`
+/// and friends) take as well. Outside this crate, start from
+/// `ExtractArgs::builder()` or `ExtractArgs::default()` (the defaults of the
+/// command line): new options may be added in any release.
+#[derive(Args, Clone, Debug)]
+#[non_exhaustive]
+pub struct ExtractArgs {
+    /// Input files to process; directories need --recursive
+    #[arg(required_unless_present = "glob")]
+    pub files: Vec<PathBuf>,
+
+    /// Also process the paths matching this pattern, independent of the shell
+    /// (e.g. 'run*/**/*.fastq.gz'; quote it); can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    pub glob: Vec<String>,
+
+    /// Search the directories among the inputs, and their subdirectories, for
+    /// files with the extensions of --file-type (any known type when omitted)
+    #[arg(short = 'r', long)]
+    pub recursive: bool,
+
+    /// Type of input files; detected from the extension (e.g. .fq.gz) or, failing
+    /// that, the content of each file when omitted
+    #[arg(short = 't', long, value_enum)]
+    pub file_type: Option<crate::formats::FileType>,
+
+    /// Type of each input file, in order (e.g. fastq,bam), for inputs of
+    /// different types; detected per file when omitted
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with = "file_type")]
+    pub file_types: Option<Vec<crate::formats::FileType>>,
+
+    /// Number of threads to use for processing; 0 or auto for all available
+    /// cores. Files are processed in parallel, and threads left over when
+    /// there are fewer files than threads decompress BAM/CRAM input
+    #[arg(short = 'j', long, default_value = "4", value_parser = parse_threads)]
+    pub threads: usize,
+
+    /// Decompression threads per BAM/CRAM file, besides the thread reading it
+    /// (0 for none); by default the share of --threads not needed for files
+    #[arg(long, value_name = "N")]
+    pub decode_threads: Option<usize>,
+
+    /// Threads computing the metrics of BAM/CRAM alignments (CIGAR, identity,
+    /// quality), besides the thread decoding the records (0 for none). Reads
+    /// are then reported in no particular order. These are per file, taken
+    /// from its share of --threads for decompression when there is one, and
+    /// otherwise started on top of --threads
+    #[arg(long, value_name = "N", default_value = "0")]
+    pub bam_workers: usize,
+
+    /// Output format (json, json-summary, tsv, human or its alias text, sqlite);
+    /// json-summary omits the per-read records, human is a compact, colored
+    /// summary and sqlite a database with reads and summary tables (needs
+    /// --output). By default, inferred from the extension of --output (.json,
+    /// .tsv, .txt, .sqlite, .db; see `format_from_extension`), and otherwise
+    /// (auto) human when writing to a terminal and json otherwise
+    #[arg(short = 'f', long)]
+    pub output_format: Option<String>,
+
+    /// Output file (optional, defaults to stdout); written to a temporary file
+    /// first and moved into place when complete
     #[arg(short = 'o', long)]
     pub output: Option<PathBuf>,
 
-    /// For summary files: read type (1D, 2D, 1D2)
-    #[arg(long, default_value = "1D")]
-    pub read_type: String,
+    /// Write one output per barcode instead, into the directory given with
+    /// --output (created if needed): <barcode>.tsv for each barcode (TSV
+    /// unless --output-format is given), with its own summary, plus
+    /// demux_summary.tsv for all reads. Reads without a barcode are left out
+    #[arg(
+        long,
+        value_name = "FIELD",
+        value_parser = ["barcode"],
+        requires = "output",
+        conflicts_with_all = ["streaming", "count_only"]
+    )]
+    pub split_by: Option<String>,
+
+    /// Overwrite existing output files (--output, --write-reads)
+    #[arg(long)]
+    pub force: bool,
+
+    /// For summary files: read type (1D, 2D, 1D2), selecting the template or
+    /// the 2d length/quality columns. Other inputs accept only 1D: duplex reads
+    /// in BAM files are recognized from their dx tag instead (see read_class)
+    #[arg(long, value_enum, default_value = "1D")]
+    pub read_type: crate::metrics::ReadType,
+
+    /// How reads without a quality (e.g. FASTA) enter the quality statistics:
+    /// skip leaves them out, zero counts them as quality 0, and error fails
+    /// the run when any read has none
+    #[arg(long, value_enum, default_value = "skip")]
+    pub missing_quality: crate::metrics::MissingQuality,
+
+    /// How the mean quality of a read is computed: eprob averages the error
+    /// probabilities of the bases (the quality of the expected number of
+    /// errors), arithmetic averages the Phred scores as some other tools do,
+    /// which gives higher values for reads with some low-quality bases
+    #[arg(long, value_enum, default_value = "eprob")]
+    pub quality_method: crate::metrics::QualityMethod,
 
     /// Include barcoded reads analysis
     #[arg(long)]
     pub barcoded: bool,
 
-    /// Keep supplementary alignments (for BAM files)
-    #[arg(long, default_value = "true")]
+    /// Keep supplementary alignments (BAM/CRAM): true (the default) or false,
+    /// e.g. `--keep-supplementary false` to count each read only once
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = true,
+        value_name = "true|false"
+    )]
     pub keep_supplementary: bool,
 
     /// Combine multiple files: simple or track
-    #[arg(long, default_value = "simple")]
-    pub combine: String,
+    #[arg(long, value_enum, default_value = "simple")]
+    pub combine: crate::metrics::CombineMethod,
 
-    /// Names for datasets when using track mode
-    #[arg(long)]
+    /// Names for datasets when using track mode, one per input file,
+    /// comma-separated (by default, the file names without their extensions)
+    #[arg(long, value_delimiter = ',')]
     pub names: Option<Vec<String>>,
+
+    /// Subtract a fixed offset from Phred quality scores before averaging
+    /// (uBAM base qualities, summary mean qscores, and FASTQ qualities after
+    /// the standard Phred+33 decoding). Aligned BAM/CRAM reads have no mean
+    /// quality, so the offset does not apply to them
+    #[arg(long, default_value = "0")]
+    pub quality_offset: u8,
+
+    /// Width of the time bins for throughput over time, in minutes
+    #[arg(long, default_value = "60")]
+    pub throughput_bin_minutes: u64,
+
+    /// Start of the throughput time bins, as an RFC 3339 timestamp (e.g.
+    /// 2024-05-01T09:30:00Z), instead of the earliest read: give the start of
+    /// the run to align the bins of separate nanoget runs on parts of it.
+    /// Reads that started before it are not binned
+    #[arg(long, value_name = "TIMESTAMP", value_parser = parse_run_start)]
+    pub run_start: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Include the cumulative yield curve (bases in the N longest reads) in the summary
+    #[arg(long)]
+    pub cumulative_yield: bool,
+
+    /// Additional percentiles to report in the summary statistics, comma-separated (e.g. 1,5,95,99)
+    #[arg(long, value_delimiter = ',')]
+    pub percentiles: Vec<f64>,
+
+    /// BAM aux tags to carry through to the output, comma-separated (e.g. sp,RG)
+    #[arg(long, value_delimiter = ',')]
+    pub bam_tags: Vec<String>,
+
+    /// Treat warnings about the input files as errors: truncated, repeated or
+    /// timed-out input files and --glob patterns without matches still only
+    /// log a warning as they are found, but the run then fails with all of
+    /// them listed. Other warnings (alignments without a sequence, --mmap
+    /// falling back to streaming, reads left out of --split-by barcode) do not
+    /// fail the run
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Keep only every Nth record of each file (indices 0, N, 2N, ...)
+    #[arg(long, value_name = "N")]
+    pub subsample_every: Option<usize>,
+
+    /// Include read counts on a length-vs-quality grid in the summary
+    #[arg(long)]
+    pub length_quality_density: bool,
+
+    /// Width of the length bins of the length-vs-quality grid, in bases
+    #[arg(long, default_value = "1000")]
+    pub density_length_bin: u64,
+
+    /// Width of the quality bins of the length-vs-quality grid
+    #[arg(long, default_value = "1.0")]
+    pub density_quality_bin: f64,
+
+    /// Width of the read length histogram bins used for the modal read length,
+    /// in bases
+    #[arg(long, value_name = "BASES", default_value = "100")]
+    pub length_bin: u64,
+
+    /// Also report the top 3 read length histogram peaks, for multi-modal
+    /// runs such as amplicon or cDNA sequencing
+    #[arg(long)]
+    pub length_modes: bool,
+
+    /// Also report statistics of log10(read length) (`log_length_stats`),
+    /// whose mean is the log of the geometric mean length
+    #[arg(long)]
+    pub log_length: bool,
+
+    /// Report the reads of at most this many bases (`short_read_spike`), with
+    /// a warning when they are more than --short-read-limit of all reads: a
+    /// spike of very short reads can point to adapter dimers
+    #[arg(long, value_name = "N")]
+    pub short_read_length: Option<u64>,
+
+    /// Fraction of reads of at most --short-read-length bases above which the
+    /// summary warns of possible adapter dimers
+    #[arg(long, value_name = "FRACTION", default_value = "0.05")]
+    pub short_read_limit: f64,
+
+    /// Decimal places for fractional values in TSV output (defaults to 3 for
+    /// per-read values and 2 for summary statistics; JSON keeps full precision)
+    #[arg(long, value_name = "N")]
+    pub precision: Option<usize>,
+
+    /// Compute only the summary, in a single pass without keeping per-read
+    /// metrics in memory (quantiles are approximate; for very large inputs).
+    /// Parts of the summary that need all reads at once (cumulative yield,
+    /// length-vs-quality density, throughput over time, per-file and
+    /// per-barcode summaries) are not available
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "cumulative_yield",
+            "length_quality_density",
+            "run_start",
+            "per_file"
+        ]
+    )]
+    pub streaming: bool,
+
+    /// Keep at most about this much memory of per-read metrics (e.g. 8G,
+    /// 512M; K, M, G and T are powers of 1024): beyond it, reads are spilled
+    /// to temporary files and read back for the output. Files are then read
+    /// one after the other, and the summary is computed in a single pass as
+    /// with --streaming
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_memory,
+        conflicts_with_all = ["split_by", "sort", "per_file", "downsample", "write_reads"]
+    )]
+    pub max_memory: Option<u64>,
+
+    /// Read uncompressed FASTQ/FASTA files through a memory map, parsing the
+    /// records in place instead of copying them through a buffered reader.
+    /// Compressed files and files that cannot be mapped are read as usual
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// Only count reads and bases, printed as a single `reads<TAB>bases` line
+    #[arg(long)]
+    pub count_only: bool,
+
+    /// Keep a random sample of N reads (chosen after extraction; summary is recomputed)
+    #[arg(long, value_name = "N")]
+    pub downsample: Option<usize>,
+
+    /// Seed for --downsample; the same seed always selects the same reads
+    #[arg(long, default_value = "42")]
+    pub seed: u64,
+
+    /// Scan FASTQ/FASTA sequences for base-composition metrics (GC content and
+    /// the number of ambiguous, non-ACGT bases per read and their overall fraction)
+    #[arg(long)]
+    pub sequence_stats: bool,
+
+    /// Order of the read rows in TSV output: length or quality, optionally
+    /// followed by :asc or :desc (e.g. length:desc); input order by default
+    #[arg(long, value_name = "KEY[:DIR]")]
+    pub sort: Option<String>,
+
+    /// Drop reads shorter than this many bases. Length filters apply to the
+    /// read length; for BAM/CRAM that is the full query sequence including
+    /// soft clips, not the aligned length
+    #[arg(long, value_name = "BASES")]
+    pub min_length: Option<u64>,
+
+    /// Drop reads longer than this many bases
+    #[arg(long, value_name = "BASES")]
+    pub max_length: Option<u64>,
+
+    /// Drop reads with a mean quality below this (and reads without qualities)
+    #[arg(long, value_name = "Q")]
+    pub min_quality: Option<f64>,
+
+    /// Drop BAM/CRAM alignments with a mapping quality below this while reading
+    /// them; alignments with MAPQ 255 (unavailable) are dropped as well
+    #[arg(long, value_name = "MAPQ")]
+    pub min_mapq: Option<u8>,
+
+    /// Drop reads with a GC content below this percentage (FASTQ/FASTA input
+    /// only, other inputs are rejected; reads without a GC content are dropped)
+    #[arg(long, value_name = "PERCENT")]
+    pub min_gc: Option<f64>,
+
+    /// Drop reads with a GC content above this percentage (FASTQ/FASTA input
+    /// only, other inputs are rejected; reads without a GC content are dropped)
+    #[arg(long, value_name = "PERCENT")]
+    pub max_gc: Option<f64>,
+
+    /// Only extract the alignments overlapping this region of an indexed BAM
+    /// or CRAM file: a reference name, optionally with a range (chr1:1000-2000)
+    #[arg(long, value_name = "REGION")]
+    pub region: Option<String>,
+
+    /// Index of the input for --region (.bai, .csi or .crai; a local path or a
+    /// URL), when it does not sit next to the data file
+    #[arg(long, value_name = "PATH|URL")]
+    pub index: Option<String>,
+
+    /// Also output a summary of each input file (a `per_file` object in JSON,
+    /// a per-file section in TSV)
+    #[arg(long)]
+    pub per_file: bool,
+
+    /// Show a progress bar for each input file being read, and one for all of
+    /// them (only when stderr is a terminal)
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Log the number of reads processed and the reads per second every this
+    /// many seconds, and the totals at the end; plain log lines on stderr,
+    /// also when it is a pipe or a log file
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub log_interval: Option<u64>,
+
+    /// At the end of the run, print the wall-clock time (overall, per phase
+    /// and per file), CPU time, peak memory and reads per second to stderr,
+    /// and record them in the provenance of the output
+    #[arg(long, conflicts_with_all = ["streaming", "count_only", "max_memory"])]
+    pub resource_report: bool,
+
+    /// Give up on an input file that has not been processed after this many
+    /// seconds (e.g. a read hanging on a network mount): the file is skipped
+    /// with a warning, or the run fails with --strict
+    #[arg(long, value_name = "N")]
+    pub timeout_secs: Option<u64>,
+
+    /// Also write the FASTQ/FASTA records of the reads that pass the filters to
+    /// this file (FASTQ and FASTA input only; the inputs are read a second time)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["streaming", "count_only"])]
+    pub write_reads: Option<PathBuf>,
+}
+
+impl Default for ExtractArgs {
+    /// The defaults of the command line, without input files
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            glob: Vec::new(),
+            recursive: false,
+            file_type: None,
+            file_types: None,
+            threads: 4,
+            decode_threads: None,
+            bam_workers: 0,
+            output_format: None,
+            output: None,
+            split_by: None,
+            force: false,
+            read_type: crate::metrics::ReadType::default(),
+            missing_quality: crate::metrics::MissingQuality::default(),
+            quality_method: crate::metrics::QualityMethod::default(),
+            barcoded: false,
+            keep_supplementary: true,
+            combine: crate::metrics::CombineMethod::default(),
+            names: None,
+            quality_offset: 0,
+            throughput_bin_minutes: 60,
+            run_start: None,
+            cumulative_yield: false,
+            percentiles: Vec::new(),
+            bam_tags: Vec::new(),
+            strict: false,
+            subsample_every: None,
+            length_quality_density: false,
+            density_length_bin: 1000,
+            density_quality_bin: 1.0,
+            length_bin: crate::metrics::DEFAULT_LENGTH_BIN,
+            length_modes: false,
+            log_length: false,
+            short_read_length: None,
+            short_read_limit: 0.05,
+            precision: None,
+            streaming: false,
+            max_memory: None,
+            mmap: false,
+            count_only: false,
+            downsample: None,
+            seed: 42,
+            sequence_stats: false,
+            sort: None,
+            min_length: None,
+            max_length: None,
+            min_quality: None,
+            min_mapq: None,
+            min_gc: None,
+            max_gc: None,
+            region: None,
+            index: None,
+            per_file: false,
+            progress: false,
+            log_interval: None,
+            resource_report: false,
+            timeout_secs: None,
+            write_reads: None,
+        }
+    }
+}
+
+impl ExtractArgs {
+    /// A builder of the options, starting from the defaults of the command line
+    ///
+    /// ```rust,no_run
+    /// use nanoget_rs::{extract_metrics, ExtractArgs, FileType};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let args = ExtractArgs::builder()
+    ///     .files(["alignments.bam"])
+    ///     .file_type(FileType::Bam)
+    ///     .threads(8)
+    ///     .keep_supplementary(false)
+    ///     .build();
+    /// let metrics = extract_metrics(&args)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[allow(dead_code)]
+    pub fn builder() -> ExtractArgsBuilder {
+        ExtractArgsBuilder::default()
+    }
+}
+
+/// Builder of `ExtractArgs` for library users, with a setter per option (see
+/// the matching command line option for each). Options not set keep the
+/// defaults of the command line.
+#[derive(Clone, Debug, Default)]
+pub struct ExtractArgsBuilder {
+    args: ExtractArgs,
+}
+
+#[allow(dead_code)]
+impl ExtractArgsBuilder {
+    /// The options set so far
+    pub fn build(self) -> ExtractArgs {
+        self.args
+    }
+
+    /// Input files (`files`)
+    pub fn files<I, P>(mut self, files: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.args.files = files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Add a pattern of input paths (`--glob`)
+    pub fn glob(mut self, pattern: impl Into<String>) -> Self {
+        self.args.glob.push(pattern.into());
+        self
+    }
+
+    /// Search input directories recursively (`--recursive`)
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.args.recursive = recursive;
+        self
+    }
+
+    /// Type of all input files (`--file-type`)
+    pub fn file_type(mut self, file_type: crate::formats::FileType) -> Self {
+        self.args.file_type = Some(file_type);
+        self
+    }
+
+    /// Type of each input file, in order (`--file-types`)
+    pub fn file_types(mut self, file_types: Vec<crate::formats::FileType>) -> Self {
+        self.args.file_types = Some(file_types);
+        self
+    }
+
+    /// Number of threads; 0 for all available cores (`--threads`)
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.args.threads = threads;
+        self
+    }
+
+    /// Decompression threads per BAM/CRAM file (`--decode-threads`)
+    pub fn decode_threads(mut self, threads: usize) -> Self {
+        self.args.decode_threads = Some(threads);
+        self
+    }
+
+    /// Threads computing the metrics of BAM/CRAM alignments (`--bam-workers`)
+    pub fn bam_workers(mut self, workers: usize) -> Self {
+        self.args.bam_workers = workers;
+        self
+    }
+
+    /// Output format (`--output-format`)
+    pub fn output_format(mut self, format: impl Into<String>) -> Self {
+        self.args.output_format = Some(format.into());
+        self
+    }
+
+    /// Output file (`--output`)
+    pub fn output(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.output = Some(path.into());
+        self
+    }
+
+    /// Write one output per value of this field into `output` (`--split-by`)
+    pub fn split_by(mut self, field: impl Into<String>) -> Self {
+        self.args.split_by = Some(field.into());
+        self
+    }
+
+    /// Overwrite existing output files (`--force`)
+    pub fn force(mut self, force: bool) -> Self {
+        self.args.force = force;
+        self
+    }
+
+    /// Read type of sequencing summaries (`--read-type`)
+    pub fn read_type(mut self, read_type: crate::metrics::ReadType) -> Self {
+        self.args.read_type = read_type;
+        self
+    }
+
+    /// How reads without a quality enter the statistics (`--missing-quality`)
+    pub fn missing_quality(mut self, missing_quality: crate::metrics::MissingQuality) -> Self {
+        self.args.missing_quality = missing_quality;
+        self
+    }
+
+    /// How the mean quality of a read is computed (`--quality-method`)
+    pub fn quality_method(mut self, quality_method: crate::metrics::QualityMethod) -> Self {
+        self.args.quality_method = quality_method;
+        self
+    }
+
+    /// Include barcoded reads analysis (`--barcoded`)
+    pub fn barcoded(mut self, barcoded: bool) -> Self {
+        self.args.barcoded = barcoded;
+        self
+    }
+
+    /// Keep supplementary alignments (`--keep-supplementary`)
+    pub fn keep_supplementary(mut self, keep: bool) -> Self {
+        self.args.keep_supplementary = keep;
+        self
+    }
+
+    /// How multiple files are combined (`--combine`)
+    pub fn combine(mut self, combine: crate::metrics::CombineMethod) -> Self {
+        self.args.combine = combine;
+        self
+    }
+
+    /// Dataset names in track mode, one per input file (`--names`)
+    pub fn names<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.names = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Offset subtracted from Phred quality scores (`--quality-offset`)
+    pub fn quality_offset(mut self, offset: u8) -> Self {
+        self.args.quality_offset = offset;
+        self
+    }
+
+    /// Width of the throughput time bins, in minutes (`--throughput-bin-minutes`)
+    pub fn throughput_bin_minutes(mut self, minutes: u64) -> Self {
+        self.args.throughput_bin_minutes = minutes;
+        self
+    }
+
+    /// Start of the throughput time bins (`--run-start`)
+    pub fn run_start(mut self, start: chrono::DateTime<chrono::Utc>) -> Self {
+        self.args.run_start = Some(start);
+        self
+    }
+
+    /// Include the cumulative yield curve (`--cumulative-yield`)
+    pub fn cumulative_yield(mut self, cumulative_yield: bool) -> Self {
+        self.args.cumulative_yield = cumulative_yield;
+        self
+    }
+
+    /// Additional percentiles of the summary statistics (`--percentiles`)
+    pub fn percentiles(mut self, percentiles: Vec<f64>) -> Self {
+        self.args.percentiles = percentiles;
+        self
+    }
+
+    /// BAM aux tags to carry through (`--bam-tags`)
+    pub fn bam_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.bam_tags = tags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Fail on warnings about the input files, all reported at the end (`--strict`)
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.args.strict = strict;
+        self
+    }
+
+    /// Keep only every Nth record of each file (`--subsample-every`)
+    pub fn subsample_every(mut self, every: usize) -> Self {
+        self.args.subsample_every = Some(every);
+        self
+    }
+
+    /// Include the length-vs-quality grid (`--length-quality-density`)
+    pub fn length_quality_density(mut self, density: bool) -> Self {
+        self.args.length_quality_density = density;
+        self
+    }
+
+    /// Width of the length bins of the length-vs-quality grid (`--density-length-bin`)
+    pub fn density_length_bin(mut self, bin: u64) -> Self {
+        self.args.density_length_bin = bin;
+        self
+    }
+
+    /// Width of the quality bins of the length-vs-quality grid (`--density-quality-bin`)
+    pub fn density_quality_bin(mut self, bin: f64) -> Self {
+        self.args.density_quality_bin = bin;
+        self
+    }
+
+    /// Width of the read length histogram bins (`--length-bin`)
+    pub fn length_bin(mut self, bin: u64) -> Self {
+        self.args.length_bin = bin;
+        self
+    }
+
+    /// Also report the read length histogram peaks (`--length-modes`)
+    pub fn length_modes(mut self, length_modes: bool) -> Self {
+        self.args.length_modes = length_modes;
+        self
+    }
+
+    /// Also report statistics of log10(read length) (`--log-length`)
+    pub fn log_length(mut self, log_length: bool) -> Self {
+        self.args.log_length = log_length;
+        self
+    }
+
+    /// Report the reads of at most this many bases (`--short-read-length`)
+    pub fn short_read_length(mut self, length: u64) -> Self {
+        self.args.short_read_length = Some(length);
+        self
+    }
+
+    /// Fraction of short reads above which the summary warns (`--short-read-limit`)
+    pub fn short_read_limit(mut self, limit: f64) -> Self {
+        self.args.short_read_limit = limit;
+        self
+    }
+
+    /// Decimal places for fractional values in TSV output (`--precision`)
+    pub fn precision(mut self, places: usize) -> Self {
+        self.args.precision = Some(places);
+        self
+    }
+
+    /// Compute only the summary, in a single pass (`--streaming`)
+    pub fn streaming(mut self, streaming: bool) -> Self {
+        self.args.streaming = streaming;
+        self
+    }
+
+    /// Keep at most about this many bytes of per-read metrics in memory (`--max-memory`)
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.args.max_memory = Some(bytes);
+        self
+    }
+
+    /// Read uncompressed FASTQ/FASTA files through a memory map (`--mmap`)
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.args.mmap = mmap;
+        self
+    }
+
+    /// Only count the reads and bases (`--count-only`)
+    pub fn count_only(mut self, count_only: bool) -> Self {
+        self.args.count_only = count_only;
+        self
+    }
+
+    /// Keep a random sample of this many reads (`--downsample`)
+    pub fn downsample(mut self, reads: usize) -> Self {
+        self.args.downsample = Some(reads);
+        self
+    }
+
+    /// Seed of `downsample` (`--seed`)
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.args.seed = seed;
+        self
+    }
+
+    /// Compute base-composition metrics of FASTQ/FASTA reads (`--sequence-stats`)
+    pub fn sequence_stats(mut self, sequence_stats: bool) -> Self {
+        self.args.sequence_stats = sequence_stats;
+        self
+    }
+
+    /// Order of the read rows in TSV output, e.g. "length:desc" (`--sort`)
+    pub fn sort(mut self, order: impl Into<String>) -> Self {
+        self.args.sort = Some(order.into());
+        self
+    }
+
+    /// Drop reads shorter than this (`--min-length`)
+    pub fn min_length(mut self, bases: u64) -> Self {
+        self.args.min_length = Some(bases);
+        self
+    }
+
+    /// Drop reads longer than this (`--max-length`)
+    pub fn max_length(mut self, bases: u64) -> Self {
+        self.args.max_length = Some(bases);
+        self
+    }
+
+    /// Drop reads with a lower mean quality (`--min-quality`)
+    pub fn min_quality(mut self, quality: f64) -> Self {
+        self.args.min_quality = Some(quality);
+        self
+    }
+
+    /// Drop alignments with a lower mapping quality (`--min-mapq`)
+    pub fn min_mapq(mut self, mapq: u8) -> Self {
+        self.args.min_mapq = Some(mapq);
+        self
+    }
+
+    /// Drop reads with a lower GC percentage (`--min-gc`)
+    pub fn min_gc(mut self, percent: f64) -> Self {
+        self.args.min_gc = Some(percent);
+        self
+    }
+
+    /// Drop reads with a higher GC percentage (`--max-gc`)
+    pub fn max_gc(mut self, percent: f64) -> Self {
+        self.args.max_gc = Some(percent);
+        self
+    }
+
+    /// Only extract the alignments overlapping this region (`--region`)
+    pub fn region(mut self, region: impl Into<String>) -> Self {
+        self.args.region = Some(region.into());
+        self
+    }
+
+    /// Index of the input for `region` (`--index`)
+    pub fn index(mut self, index: impl Into<String>) -> Self {
+        self.args.index = Some(index.into());
+        self
+    }
+
+    /// Also summarize each input file on its own (`--per-file`)
+    pub fn per_file(mut self, per_file: bool) -> Self {
+        self.args.per_file = per_file;
+        self
+    }
+
+    /// Show progress bars on a terminal (`--progress`)
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.args.progress = progress;
+        self
+    }
+
+    /// Log the reads processed every this many seconds (`--log-interval`)
+    pub fn log_interval(mut self, secs: u64) -> Self {
+        self.args.log_interval = Some(secs);
+        self
+    }
+
+    /// Record the time and memory used in the provenance (`--resource-report`)
+    pub fn resource_report(mut self, report: bool) -> Self {
+        self.args.resource_report = report;
+        self
+    }
+
+    /// Give up on an input file after this many seconds (`--timeout-secs`)
+    pub fn timeout_secs(mut self, secs: u64) -> Self {
+        self.args.timeout_secs = Some(secs);
+        self
+    }
+
+    /// Also write the records of the reads that pass the filters (`--write-reads`)
+    pub fn write_reads(mut self, path: impl Into<PathBuf>) -> Self {
+        self.args.write_reads = Some(path.into());
+        self
+    }
+}
+
+/// Write the completion script of the command line for `shell` to `w`
+pub fn write_completions<W: std::io::Write>(shell: clap_complete::Shell, w: &mut W) {
+    clap_complete::generate(shell, &mut Cli::command(), "nanoget", w);
+}
+
+/// Write the man page of nanoget, or of its subcommand `command`
+/// (nanoget-<command>), as roff to `w`
+pub fn write_man_page<W: std::io::Write>(
+    command: Option<&str>,
+    w: &mut W,
+) -> Result<(), crate::error::NanogetError> {
+    let mut cli = Cli::command();
+    cli.build();
+    let page = match command {
+        None => cli,
+        Some(name) => {
+            let subcommand = cli.find_subcommand(name).ok_or_else(|| {
+                crate::error::NanogetError::InvalidInput(format!(
+                    "Unknown subcommand '{}' for the man page",
+                    name
+                ))
+            })?;
+            let title = format!("nanoget-{}", subcommand.get_name());
+            subcommand
+                .clone()
+                .name(title)
+                .version(env!("CARGO_PKG_VERSION"))
+        }
+    };
+    clap_mangen::Man::new(page).render(w)?;
+    Ok(())
+}
+
+/// Output format matching the extension of `path`, if any: .json (json),
+/// .tsv or .tab (tsv), .txt (human) and .sqlite, .sqlite3 or .db (sqlite)
+pub fn format_from_extension(path: &Path) -> Option<&'static str> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    match name.rsplit_once('.')?.1 {
+        "json" => Some("json"),
+        "tsv" | "tab" => Some("tsv"),
+        "txt" => Some("human"),
+        "sqlite" | "sqlite3" | "db" => Some("sqlite"),
+        _ => None,
+    }
+}
+
+/// `--run-start` value: an RFC 3339 timestamp, in any time zone
+fn parse_run_start(value: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&chrono::Utc))
+        .map_err(|e| {
+            format!(
+                "'{}' is not an RFC 3339 timestamp such as 2024-05-01T09:30:00Z ({})",
+                value, e
+            )
+        })
+}
+
+/// `--max-memory` value: a number of bytes, optionally fractional and followed
+/// by K, M, G or T (powers of 1024) and an optional B
+fn parse_memory(value: &str) -> Result<u64, String> {
+    let error = || format!("'{}' is not a memory size such as 8G or 512M", value);
+    let upper = value.trim().to_ascii_uppercase();
+    let number = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, unit) = match number.char_indices().last() {
+        Some((i, 'K')) => (&number[..i], 1u64 << 10),
+        Some((i, 'M')) => (&number[..i], 1 << 20),
+        Some((i, 'G')) => (&number[..i], 1 << 30),
+        Some((i, 'T')) => (&number[..i], 1 << 40),
+        _ => (number, 1),
+    };
+    let amount: f64 = number.trim().parse().map_err(|_| error())?;
+    if !(amount.is_finite() && amount > 0.0) {
+        return Err(error());
+    }
+    Ok((amount * unit as f64).round() as u64)
+}
+
+/// `--threads` value: a count, or `auto` for all available cores (as 0)
+fn parse_threads(value: &str) -> Result<usize, String> {
+    if value.eq_ignore_ascii_case("auto") {
+        return Ok(0);
+    }
+    value
+        .parse()
+        .map_err(|_| format!("'{}' is not a thread count or auto", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keep_supplementary(extra: &[&str]) -> Result<bool, clap::Error> {
+        let args = ["nanoget", "extract", "-t", "bam", "calls.bam"];
+        let cli = Cli::try_parse_from(args.iter().chain(extra))?;
+        match cli.command {
+            Commands::Extract(args) => Ok(args.keep_supplementary),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_keep_supplementary_values() {
+        assert!(keep_supplementary(&[]).unwrap());
+        assert!(keep_supplementary(&["--keep-supplementary", "true"]).unwrap());
+        assert!(!keep_supplementary(&["--keep-supplementary", "false"]).unwrap());
+        assert!(!keep_supplementary(&["--keep-supplementary=false"]).unwrap());
+        // The value is required, so it is never mistaken for an input file
+        assert!(keep_supplementary(&["--keep-supplementary"]).is_err());
+        assert!(keep_supplementary(&["--keep-supplementary", "no"]).is_err());
+    }
+
+    #[test]
+    fn test_log_level() {
+        let level = |extra: &[&str]| {
+            let args = ["nanoget", "extract", "calls.bam"];
+            Cli::try_parse_from(args.iter().chain(extra)).map(|cli| cli.log_level())
+        };
+        assert_eq!(level(&[]).unwrap(), log::LevelFilter::Warn);
+        assert_eq!(level(&["-q"]).unwrap(), log::LevelFilter::Error);
+        assert_eq!(level(&["-v"]).unwrap(), log::LevelFilter::Info);
+        assert_eq!(level(&["-vv"]).unwrap(), log::LevelFilter::Debug);
+        assert_eq!(level(&["-v", "-v", "-v"]).unwrap(), log::LevelFilter::Trace);
+        assert!(level(&["-q", "-v"]).is_err());
+        // Global flags are also accepted before the subcommand
+        let cli = Cli::try_parse_from(["nanoget", "--quiet", "extract", "calls.bam"]).unwrap();
+        assert_eq!(cli.log_level(), log::LevelFilter::Error);
+    }
+
+    #[test]
+    fn test_completions() {
+        Cli::command().debug_assert();
+
+        let mut bash = Vec::new();
+        write_completions(clap_complete::Shell::Bash, &mut bash);
+        let bash = String::from_utf8(bash).unwrap();
+        assert!(bash.contains("extract"));
+        assert!(bash.contains("--file-type"));
+        assert!(bash.contains("fastq fastq-rich fastq-minimal fasta bam cram sam ubam summary"));
+
+        for shell in [
+            clap_complete::Shell::Zsh,
+            clap_complete::Shell::Fish,
+            clap_complete::Shell::PowerShell,
+        ] {
+            let mut script = Vec::new();
+            write_completions(shell, &mut script);
+            assert!(String::from_utf8(script).unwrap().contains("extract"));
+        }
+
+        let mut man = Vec::new();
+        write_man_page(None, &mut man).unwrap();
+        let man = String::from_utf8(man).unwrap();
+        assert!(man.contains(".TH nanoget 1"));
+        assert!(man.contains("nanoget\\-extract(1)"));
+
+        let mut man = Vec::new();
+        write_man_page(Some("extract"), &mut man).unwrap();
+        let man = String::from_utf8(man).unwrap();
+        assert!(man.contains(".TH nanoget-extract 1"));
+        assert!(man.contains("\\-\\-file\\-type"));
+        assert!(write_man_page(Some("frobnicate"), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_threads_values() {
+        let threads = |extra: &[&str]| {
+            let args = ["nanoget", "extract", "calls.bam"];
+            Cli::try_parse_from(args.iter().chain(extra)).map(|cli| match cli.command {
+                Commands::Extract(args) => args.threads,
+                _ => unreachable!(),
+            })
+        };
+        assert_eq!(threads(&[]).unwrap(), 4);
+        assert_eq!(threads(&["-j", "16"]).unwrap(), 16);
+        assert_eq!(threads(&["-j", "0"]).unwrap(), 0);
+        assert_eq!(threads(&["--threads", "auto"]).unwrap(), 0);
+        assert!(threads(&["-j", "many"]).is_err());
+    }
+
+    #[test]
+    fn test_run_start_values() {
+        let run_start = |extra: &[&str]| {
+            let args = ["nanoget", "extract", "calls.bam"];
+            Cli::try_parse_from(args.iter().chain(extra)).map(|cli| match cli.command {
+                Commands::Extract(args) => args.run_start,
+                _ => unreachable!(),
+            })
+        };
+        assert_eq!(run_start(&[]).unwrap(), None);
+        let expected = "2024-05-01T09:30:00Z".parse().unwrap();
+        assert_eq!(
+            run_start(&["--run-start", "2024-05-01T09:30:00Z"]).unwrap(),
+            Some(expected)
+        );
+        assert_eq!(
+            run_start(&["--run-start", "2024-05-01T11:30:00+02:00"]).unwrap(),
+            Some(expected)
+        );
+        assert!(run_start(&["--run-start", "2024-05-01"]).is_err());
+        assert!(run_start(&["--run-start", "yesterday"]).is_err());
+    }
+
+    #[test]
+    fn test_builder_defaults_match_command_line() {
+        let parsed = match Cli::try_parse_from(["nanoget", "extract", "calls.bam"])
+            .unwrap()
+            .command
+        {
+            Commands::Extract(args) => args,
+            _ => unreachable!(),
+        };
+        let built = ExtractArgs::builder().files(["calls.bam"]).build();
+        assert_eq!(format!("{:?}", built), format!("{:?}", parsed));
+
+        let built = ExtractArgs::builder()
+            .files(["calls.bam"])
+            .file_type(crate::formats::FileType::Bam)
+            .threads(8)
+            .keep_supplementary(false)
+            .build();
+        assert_eq!(built.file_type, Some(crate::formats::FileType::Bam));
+        assert_eq!(built.threads, 8);
+        assert!(!built.keep_supplementary);
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        let format = |path: &str| format_from_extension(Path::new(path));
+        assert_eq!(format("metrics.json"), Some("json"));
+        assert_eq!(format("out/metrics.JSON"), Some("json"));
+        assert_eq!(format("reads.tsv"), Some("tsv"));
+        assert_eq!(format("reads.tab"), Some("tsv"));
+        assert_eq!(format("summary.txt"), Some("human"));
+        assert_eq!(format("metrics.sqlite"), Some("sqlite"));
+        assert_eq!(format("metrics.sqlite3"), Some("sqlite"));
+        assert_eq!(format("metrics.db"), Some("sqlite"));
+        // Formats nanoget does not write, including compressed ones, and
+        // names without an extension
+        assert_eq!(format("reads.tsv.gz"), None);
+        assert_eq!(format("reads.csv"), None);
+        assert_eq!(format("metrics.parquet"), None);
+        assert_eq!(format("metrics"), None);
+        assert_eq!(format("metrics.gz"), None);
+
+        let output_format = |extra: &[&str]| {
+            let args = ["nanoget", "extract", "calls.bam"];
+            match Cli::try_parse_from(args.iter().chain(extra))
+                .unwrap()
+                .command
+            {
+                Commands::Extract(args) => args.output_format,
+                _ => unreachable!(),
+            }
+        };
+        // Unset unless given, so the extension can fill it in
+        assert_eq!(output_format(&[]), None);
+        assert_eq!(output_format(&["-f", "tsv"]).as_deref(), Some("tsv"));
+    }
 }
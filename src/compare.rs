@@ -0,0 +1,358 @@
+use crate::error::NanogetError;
+use crate::metrics::{n50, MetricsCollection};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Structured differences between two collections (`a` → `b`), e.g. two
+/// basecaller versions run on the same data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComparisonReport {
+    /// Per-metric values and differences
+    pub metrics: Vec<MetricDelta>,
+
+    /// Two-sample tests on the read length distributions
+    pub length_distribution: DistributionComparison,
+
+    /// Two-sample tests on the read quality distributions (if both have qualities)
+    pub quality_distribution: Option<DistributionComparison>,
+}
+
+/// One metric in both collections; values are `None` when a collection lacks
+/// the underlying field (e.g. identity for unaligned reads)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricDelta {
+    pub metric: String,
+    pub a: Option<f64>,
+    pub b: Option<f64>,
+    /// `b - a`
+    pub difference: Option<f64>,
+    /// `(b - a) / a` (`None` when `a` is zero)
+    pub relative_difference: Option<f64>,
+}
+
+impl MetricDelta {
+    fn new(metric: &str, a: Option<f64>, b: Option<f64>) -> Self {
+        let difference = a.zip(b).map(|(a, b)| b - a);
+        let relative_difference = a
+            .zip(difference)
+            .filter(|(a, _)| *a != 0.0)
+            .map(|(a, d)| d / a);
+        Self {
+            metric: metric.to_string(),
+            a,
+            b,
+            difference,
+            relative_difference,
+        }
+    }
+}
+
+/// Kolmogorov–Smirnov and Mann–Whitney U tests of two samples
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DistributionComparison {
+    /// Largest distance between the two empirical CDFs
+    pub ks_statistic: f64,
+
+    /// Asymptotic two-sided p-value of `ks_statistic`
+    pub ks_p_value: f64,
+
+    /// U statistic of the `a` sample
+    pub mann_whitney_u: f64,
+
+    /// Two-sided p-value of `mann_whitney_u` (normal approximation with tie correction)
+    pub mann_whitney_p_value: f64,
+}
+
+impl DistributionComparison {
+    /// Compare two non-empty samples; `None` if either is empty
+    pub fn from_samples(a: &[f64], b: &[f64]) -> Option<Self> {
+        if a.is_empty() || b.is_empty() {
+            return None;
+        }
+        let mut a = a.to_vec();
+        let mut b = b.to_vec();
+        a.sort_by(f64::total_cmp);
+        b.sort_by(f64::total_cmp);
+
+        let (ks_statistic, ks_p_value) = kolmogorov_smirnov(&a, &b);
+        let (mann_whitney_u, mann_whitney_p_value) = mann_whitney(&a, &b);
+        Some(Self {
+            ks_statistic,
+            ks_p_value,
+            mann_whitney_u,
+            mann_whitney_p_value,
+        })
+    }
+}
+
+impl MetricsCollection {
+    /// Compare this collection (`a`) with `other` (`b`): reads, bases, N50 and
+    /// medians, and two-sample tests on the length and quality distributions
+    pub fn compare(&self, other: &MetricsCollection) -> ComparisonReport {
+        let metrics = vec![
+            MetricDelta::new(
+                "reads",
+                Some(self.reads.len() as f64),
+                Some(other.reads.len() as f64),
+            ),
+            MetricDelta::new("bases", Some(total_bases(self)), Some(total_bases(other))),
+            MetricDelta::new("n50", length_n50(self), length_n50(other)),
+            MetricDelta::new(
+                "median_length",
+                Some(self.summary.length_stats.median),
+                Some(other.summary.length_stats.median),
+            ),
+            MetricDelta::new(
+                "median_quality",
+                self.summary.quality_stats.as_ref().map(|s| s.median),
+                other.summary.quality_stats.as_ref().map(|s| s.median),
+            ),
+            MetricDelta::new(
+                "median_identity",
+                self.summary
+                    .percent_identity_stats
+                    .as_ref()
+                    .map(|s| s.median),
+                other
+                    .summary
+                    .percent_identity_stats
+                    .as_ref()
+                    .map(|s| s.median),
+            ),
+        ];
+
+        let lengths = |c: &MetricsCollection| -> Vec<f64> {
+            c.reads.iter().map(|r| r.length as f64).collect()
+        };
+        let qualities = |c: &MetricsCollection| -> Vec<f64> {
+            c.reads.iter().filter_map(|r| r.quality).collect()
+        };
+
+        ComparisonReport {
+            metrics,
+            length_distribution: DistributionComparison::from_samples(
+                &lengths(self),
+                &lengths(other),
+            )
+            .unwrap_or(DistributionComparison {
+                ks_statistic: 0.0,
+                ks_p_value: 1.0,
+                mann_whitney_u: 0.0,
+                mann_whitney_p_value: 1.0,
+            }),
+            quality_distribution: DistributionComparison::from_samples(
+                &qualities(self),
+                &qualities(other),
+            ),
+        }
+    }
+}
+
+impl ComparisonReport {
+    /// Write the report as a tab-separated table
+    pub fn write_table<W: Write>(&self, w: &mut W) -> Result<(), NanogetError> {
+        let value = |v: Option<f64>| v.map(|v| format!("{:.2}", v)).unwrap_or_default();
+        writeln!(w, "metric\ta\tb\tdifference\trelative_difference")?;
+        for delta in &self.metrics {
+            writeln!(
+                w,
+                "{}\t{}\t{}\t{}\t{}",
+                delta.metric,
+                value(delta.a),
+                value(delta.b),
+                value(delta.difference),
+                delta
+                    .relative_difference
+                    .map(|r| format!("{:.4}", r))
+                    .unwrap_or_default()
+            )?;
+        }
+
+        writeln!(
+            w,
+            "\n# distribution\tks_statistic\tks_p_value\tmann_whitney_u\tmann_whitney_p_value"
+        )?;
+        let distributions = [
+            ("length", Some(&self.length_distribution)),
+            ("quality", self.quality_distribution.as_ref()),
+        ];
+        for (name, comparison) in distributions {
+            if let Some(c) = comparison {
+                writeln!(
+                    w,
+                    "# {}\t{:.4}\t{:.4e}\t{:.1}\t{:.4e}",
+                    name, c.ks_statistic, c.ks_p_value, c.mann_whitney_u, c.mann_whitney_p_value
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn total_bases(collection: &MetricsCollection) -> f64 {
+    collection.reads.iter().map(|r| r.length).sum::<u64>() as f64
+}
+
+fn length_n50(collection: &MetricsCollection) -> Option<f64> {
+    if collection.reads.is_empty() {
+        return None;
+    }
+    let mut lengths: Vec<u64> = collection.reads.iter().map(|r| r.length).collect();
+    lengths.sort_unstable();
+    Some(n50(&lengths) as f64)
+}
+
+/// Two-sample KS statistic of sorted samples and its asymptotic p-value
+fn kolmogorov_smirnov(a: &[f64], b: &[f64]) -> (f64, f64) {
+    let (n, m) = (a.len() as f64, b.len() as f64);
+    let (mut i, mut j) = (0, 0);
+    let mut d: f64 = 0.0;
+    while i < a.len() && j < b.len() {
+        // Step past every copy of the smaller value so ties move both CDFs at once
+        let x = a[i].min(b[j]);
+        while i < a.len() && a[i] <= x {
+            i += 1;
+        }
+        while j < b.len() && b[j] <= x {
+            j += 1;
+        }
+        d = d.max((i as f64 / n - j as f64 / m).abs());
+    }
+
+    let effective = (n * m / (n + m)).sqrt();
+    let lambda = (effective + 0.12 + 0.11 / effective) * d;
+    (d, kolmogorov_q(lambda))
+}
+
+/// Survival function of the Kolmogorov distribution
+fn kolmogorov_q(lambda: f64) -> f64 {
+    if lambda < 1e-3 {
+        return 1.0;
+    }
+    let mut sum = 0.0;
+    for k in 1..=100 {
+        let k = f64::from(k);
+        let term = 2.0 * (-1f64).powf(k - 1.0) * (-2.0 * k * k * lambda * lambda).exp();
+        sum += term;
+        if term.abs() < 1e-12 {
+            break;
+        }
+    }
+    sum.clamp(0.0, 1.0)
+}
+
+/// Mann–Whitney U of sorted sample `a` against sorted sample `b`, with the
+/// two-sided p-value from the tie-corrected normal approximation
+fn mann_whitney(a: &[f64], b: &[f64]) -> (f64, f64) {
+    let (n, m) = (a.len() as f64, b.len() as f64);
+    let total = n + m;
+
+    // Walk the merged order, giving each run of tied values its average rank
+    let (mut i, mut j) = (0, 0);
+    let mut rank_sum_a = 0.0;
+    let mut tie_term = 0.0;
+    let mut next_rank = 1.0;
+    while i < a.len() || j < b.len() {
+        let x = match (a.get(i), b.get(j)) {
+            (Some(&va), Some(&vb)) => va.min(vb),
+            (Some(&va), None) => va,
+            (None, Some(&vb)) => vb,
+            (None, None) => unreachable!(),
+        };
+        let start_i = i;
+        while i < a.len() && a[i] == x {
+            i += 1;
+        }
+        let start_j = j;
+        while j < b.len() && b[j] == x {
+            j += 1;
+        }
+        let ties = ((i - start_i) + (j - start_j)) as f64;
+        let average_rank = next_rank + (ties - 1.0) / 2.0;
+        rank_sum_a += average_rank * (i - start_i) as f64;
+        tie_term += ties * ties * ties - ties;
+        next_rank += ties;
+    }
+
+    let u = rank_sum_a - n * (n + 1.0) / 2.0;
+    let mean = n * m / 2.0;
+    let variance = n * m / 12.0 * ((total + 1.0) - tie_term / (total * (total - 1.0)));
+    let p_value = if variance > 0.0 {
+        let z = (u - mean).abs() / variance.sqrt();
+        statrs::function::erf::erfc(z / std::f64::consts::SQRT_2)
+    } else {
+        1.0
+    };
+    (u, p_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ReadMetrics;
+
+    fn collection(lengths: &[u64], quality: f64) -> MetricsCollection {
+        MetricsCollection::new(
+            lengths
+                .iter()
+                .map(|&l| ReadMetrics::new(None, l).with_quality(quality))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_compare() {
+        let a = collection(&[100, 200, 300, 400], 10.0);
+        let b = collection(&[200, 400, 600, 800, 1000], 12.0);
+        let report = a.compare(&b);
+
+        let reads = &report.metrics[0];
+        assert_eq!(reads.metric, "reads");
+        assert_eq!(reads.difference, Some(1.0));
+        assert_eq!(reads.relative_difference, Some(0.25));
+        let bases = &report.metrics[1];
+        assert_eq!((bases.a, bases.b), (Some(1000.0), Some(3000.0)));
+        assert_eq!(bases.relative_difference, Some(2.0));
+        assert_eq!(report.metrics[2].b, Some(800.0));
+        assert_eq!(report.metrics[4].difference, Some(2.0));
+        assert_eq!(report.metrics[5].a, None);
+        assert_eq!(report.metrics[5].difference, None);
+
+        // All of b's qualities are above a's
+        let quality = report.quality_distribution.as_ref().unwrap();
+        assert_eq!(quality.ks_statistic, 1.0);
+        assert_eq!(quality.mann_whitney_u, 0.0);
+
+        let mut table = Vec::new();
+        report.write_table(&mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.starts_with("metric\ta\tb\tdifference\trelative_difference\n"));
+        assert!(table.contains("bases\t1000.00\t3000.00\t2000.00\t2.0000\n"));
+        assert!(table.contains("median_identity\t\t\t\t\n"));
+    }
+
+    #[test]
+    fn test_distribution_tests() {
+        // Identical samples
+        let same =
+            DistributionComparison::from_samples(&[1.0, 2.0, 3.0], &[1.0, 2.0, 3.0]).unwrap();
+        assert_eq!(same.ks_statistic, 0.0);
+        assert_eq!(same.mann_whitney_u, 4.5);
+        assert!((same.mann_whitney_p_value - 1.0).abs() < 1e-12);
+
+        // Interleaved samples: U counts the pairs with a above b (ties as half)
+        let mixed = DistributionComparison::from_samples(&[1.0, 3.0, 5.0], &[2.0, 4.0]).unwrap();
+        assert_eq!(mixed.mann_whitney_u, 3.0);
+        assert!((mixed.ks_statistic - 1.0 / 3.0).abs() < 1e-12);
+
+        // Clearly shifted samples are significant
+        let a: Vec<f64> = (0..200).map(f64::from).collect();
+        let b: Vec<f64> = (100..300).map(f64::from).collect();
+        let shifted = DistributionComparison::from_samples(&a, &b).unwrap();
+        assert_eq!(shifted.ks_statistic, 0.5);
+        assert!(shifted.ks_p_value < 1e-10);
+        assert!(shifted.mann_whitney_p_value < 1e-10);
+
+        assert!(DistributionComparison::from_samples(&[], &[1.0]).is_none());
+    }
+}
@@ -0,0 +1,318 @@
+use crate::cli::Cli;
+use crate::error::NanogetError;
+use clap::parser::ValueSource;
+use clap::CommandFactory;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the environment variables holding option defaults, followed by
+/// the option name in upper case (NANOGET_THREADS, NANOGET_MIN_QUALITY)
+pub const ENV_PREFIX: &str = "NANOGET_";
+
+/// Environment variable pointing at a config file to use instead of the
+/// default locations
+pub const CONFIG_ENV: &str = "NANOGET_CONFIG";
+
+/// Name of the config file, looked up in the current directory and then in
+/// the nanoget directory of the XDG config directory
+pub const CONFIG_FILE: &str = "nanoget.toml";
+
+/// Options that describe a single run rather than a site-wide default: they
+/// are never taken from the environment or a config file
+const PER_RUN_OPTIONS: [&str; 10] = [
+    "files",
+    "glob",
+    "file_types",
+    "names",
+    "output",
+    "split_by",
+    "write_reads",
+    "force",
+    "region",
+    "index",
+];
+
+/// Command line `args` with the defaults from the `NANOGET_*` environment
+/// variables and the config file (see `config_path`) filled in, for the
+/// options of `extract` and `stats` not given on the command line. Precedence
+/// is command line, then environment, then config file, then the built-in
+/// default. `args` that do not parse are returned as they are, for clap to
+/// report.
+pub fn with_defaults(args: Vec<OsString>) -> Result<Vec<OsString>, NanogetError> {
+    let env = |name: &str| std::env::var(name).ok();
+    let config = match config_path(&env) {
+        Some(path) => Some(read_config(&path)?),
+        None => None,
+    };
+    apply_defaults(args, &env, config.as_ref())
+}
+
+/// The config file: `$NANOGET_CONFIG`, or else nanoget.toml in the current
+/// directory or in `$XDG_CONFIG_HOME/nanoget` (~/.config/nanoget by default),
+/// whichever exists first
+pub fn config_path(env: &dyn Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    if let Some(path) = env(CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = env("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| Path::new(&home).join(".config")));
+    std::iter::once(PathBuf::from(CONFIG_FILE))
+        .chain(config_home.map(|dir| dir.join("nanoget").join(CONFIG_FILE)))
+        .find(|path| path.is_file())
+}
+
+/// Parse a config file: a TOML table of option names (as on the command line,
+/// or with underscores) and their values
+pub fn read_config(path: &Path) -> Result<toml::Table, NanogetError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|_| NanogetError::FileNotFound(path.display().to_string()))?;
+    text.parse().map_err(|e| {
+        NanogetError::InvalidInput(format!("Invalid config file {}: {}", path.display(), e))
+    })
+}
+
+/// `args` with the options of the subcommand that are missing from them
+/// added right after the subcommand, from `env` (see `ENV_PREFIX`) or else
+/// from `config`
+pub fn apply_defaults(
+    mut args: Vec<OsString>,
+    env: &dyn Fn(&str) -> Option<String>,
+    config: Option<&toml::Table>,
+) -> Result<Vec<OsString>, NanogetError> {
+    let mut command = Cli::command();
+    command.build();
+    let Ok(matches) = command.clone().try_get_matches_from(&args) else {
+        return Ok(args);
+    };
+    let Some((name, sub_matches)) = matches.subcommand() else {
+        return Ok(args);
+    };
+    if !matches!(name, "extract" | "stats") {
+        return Ok(args);
+    }
+    let subcommand = command
+        .find_subcommand(name)
+        .expect("the subcommand was just parsed");
+
+    let options: Vec<&clap::Arg> = subcommand
+        .get_arguments()
+        .filter(|arg| arg.get_long().is_some_and(|long| long != "help"))
+        .filter(|arg| !PER_RUN_OPTIONS.contains(&arg.get_id().as_str()))
+        .collect();
+    if let Some(config) = config {
+        check_config_keys(config, &options)?;
+    }
+
+    let mut defaults = Vec::new();
+    for option in options {
+        let id = option.get_id().as_str();
+        let long = option.get_long().expect("options have a long name");
+        if sub_matches.value_source(id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let env_name = format!("{}{}", ENV_PREFIX, id.to_uppercase());
+        let (value, source) = match env(&env_name) {
+            Some(value) => (value, env_name),
+            None => match config.and_then(|c| c.get(id).or_else(|| c.get(long))) {
+                Some(value) => (
+                    config_value(value, long)?,
+                    format!("{} in the config file", long),
+                ),
+                None => continue,
+            },
+        };
+        if option.get_action().takes_values() {
+            defaults.push(OsString::from(format!("--{}={}", long, value)));
+        } else {
+            match value.as_str() {
+                "true" | "1" => defaults.push(OsString::from(format!("--{}", long))),
+                "false" | "0" => {}
+                _ => {
+                    return Err(NanogetError::InvalidInput(format!(
+                        "{} is a flag; set it to true or false, not '{}'",
+                        source, value
+                    )))
+                }
+            }
+        }
+    }
+
+    // Global options take no values, so the subcommand is the first argument
+    // that is not an option
+    let position = args
+        .iter()
+        .skip(1)
+        .position(|arg| arg == name)
+        .map_or(args.len(), |i| i + 2);
+    args.splice(position..position, defaults);
+    Ok(args)
+}
+
+/// Reject config keys that are not options (e.g. typos), or that only apply
+/// to a single run
+fn check_config_keys(config: &toml::Table, options: &[&clap::Arg]) -> Result<(), NanogetError> {
+    for key in config.keys() {
+        let known = options.iter().any(|option| {
+            option.get_id().as_str() == key || option.get_long() == Some(key.as_str())
+        });
+        if known {
+            continue;
+        }
+        let reason = if PER_RUN_OPTIONS.contains(&key.replace('-', "_").as_str()) {
+            "it only applies to a single run"
+        } else {
+            "it is not an option of extract or stats"
+        };
+        return Err(NanogetError::InvalidInput(format!(
+            "Config file option '{}' is not supported: {}",
+            key, reason
+        )));
+    }
+    Ok(())
+}
+
+/// The command line value of a config file value; arrays are joined with
+/// commas, as for the options that take lists (--percentiles 10,90)
+fn config_value(value: &toml::Value, long: &str) -> Result<String, NanogetError> {
+    Ok(match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => f.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        toml::Value::Datetime(t) => t.to_string(),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(|item| config_value(item, long))
+            .collect::<Result<Vec<_>, _>>()?
+            .join(","),
+        toml::Value::Table(_) => {
+            return Err(NanogetError::InvalidInput(format!(
+                "Config file option '{}' must be a value, not a table",
+                long
+            )))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Commands;
+    use clap::Parser;
+    use std::collections::HashMap;
+
+    fn parse(
+        args: &[&str],
+        env: &[(&str, &str)],
+        config: Option<&str>,
+    ) -> Result<crate::cli::ExtractArgs, NanogetError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let config: Option<toml::Table> = config.map(|c| c.parse().unwrap());
+        let args = apply_defaults(
+            args.iter().map(OsString::from).collect(),
+            &|name| env.get(name).cloned(),
+            config.as_ref(),
+        )?;
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Extract(args) => Ok(args),
+            Commands::Stats(stats) => Ok(stats.extract),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_defaults_precedence() {
+        let cli = ["nanoget", "extract", "reads.fastq"];
+        let config = "threads = 2\noutput-format = \"tsv\"\nmin_quality = 7.5";
+
+        let args = parse(&cli, &[], None).unwrap();
+        assert_eq!((args.threads, args.min_quality), (4, None));
+
+        // The config file fills in what was not given
+        let args = parse(&cli, &[], Some(config)).unwrap();
+        assert_eq!(args.threads, 2);
+        assert_eq!(args.output_format.as_deref(), Some("tsv"));
+        assert_eq!(args.min_quality, Some(7.5));
+
+        // The environment wins over the config file
+        let args = parse(&cli, &[("NANOGET_THREADS", "8")], Some(config)).unwrap();
+        assert_eq!(args.threads, 8);
+        assert_eq!(args.output_format.as_deref(), Some("tsv"));
+
+        // The command line wins over both, also after global options
+        let cli = ["nanoget", "-v", "extract", "-j", "16", "reads.fastq"];
+        let args = parse(&cli, &[("NANOGET_THREADS", "8")], Some(config)).unwrap();
+        assert_eq!(args.threads, 16);
+        assert_eq!(args.files, [PathBuf::from("reads.fastq")]);
+        assert_eq!(args.min_quality, Some(7.5));
+    }
+
+    #[test]
+    fn test_defaults_values() {
+        let cli = ["nanoget", "stats", "reads.fastq"];
+        let config = "strict = true\npercentiles = [10, 90]\nkeep_supplementary = false";
+        let args = parse(&cli, &[], Some(config)).unwrap();
+        assert!(args.strict);
+        assert_eq!(args.percentiles, [10.0, 90.0]);
+        assert!(!args.keep_supplementary);
+
+        let args = parse(&cli, &[("NANOGET_STRICT", "false")], Some(config)).unwrap();
+        assert!(!args.strict);
+        assert!(parse(&cli, &[("NANOGET_STRICT", "yes")], None).is_err());
+
+        // Typos and per-run options are rejected rather than ignored
+        let error = parse(&cli, &[], Some("treads = 2")).err().unwrap();
+        assert!(error.to_string().contains("'treads' is not supported"));
+        let error = parse(&cli, &[], Some("output = \"out.json\""))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("single run"));
+        // ... and so are not taken from the environment
+        let args = parse(&cli, &[("NANOGET_OUTPUT", "out.json")], None).unwrap();
+        assert_eq!(args.output, None);
+    }
+
+    #[test]
+    fn test_config_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_home = dir.path().to_str().unwrap().to_string();
+        let env = |vars: Vec<(&'static str, String)>| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.clone())
+            }
+        };
+
+        assert_eq!(
+            config_path(&env(vec![("XDG_CONFIG_HOME", config_home.clone())])),
+            None
+        );
+        std::fs::create_dir(dir.path().join("nanoget")).unwrap();
+        let path = dir.path().join("nanoget").join(CONFIG_FILE);
+        std::fs::write(&path, "threads = 2\n").unwrap();
+        assert_eq!(
+            config_path(&env(vec![("XDG_CONFIG_HOME", config_home)])),
+            Some(path.clone())
+        );
+        assert_eq!(
+            config_path(&env(vec![
+                ("HOME", "/nonexistent".to_string()),
+                (CONFIG_ENV, "site.toml".to_string())
+            ])),
+            Some(PathBuf::from("site.toml"))
+        );
+        assert_eq!(read_config(&path).unwrap()["threads"].as_integer(), Some(2));
+
+        std::fs::write(&path, "threads = \n").unwrap();
+        assert!(read_config(&path)
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid config file"));
+    }
+}
@@ -14,11 +14,13 @@ pub enum NanogetError {
     #[error("JSON serialization error: {0}")]
     Json(#[from] serde_json::Error),
 
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
     #[error("File not found: {0}")]
     FileNotFound(String),
 
     #[error("Unsupported file format: {0}")]
-    #[allow(dead_code)]
     UnsupportedFormat(String),
 
     #[error("Invalid input: {0}")]
@@ -29,4 +31,99 @@ pub enum NanogetError {
 
     #[error("Processing error: {0}")]
     ProcessingError(String),
+
+    #[error("No reads found in {0}")]
+    NoReads(String),
+
+    /// The warnings of a `--strict` run
+    #[error("{} warning{} with --strict:\n  {}", .0.len(), if .0.len() == 1 { "" } else { "s" }, .0.join("\n  "))]
+    Warnings(Vec<String>),
+}
+
+/// Process exit codes of the `nanoget` binary, per error category
+pub mod exit_code {
+    /// Any other error, e.g. an I/O or processing error
+    pub const ERROR: i32 = 1;
+    /// An input file does not exist
+    pub const FILE_NOT_FOUND: i32 = 2;
+    /// An input file could not be parsed, e.g. because it is corrupt
+    pub const PARSE_ERROR: i32 = 3;
+    /// The inputs hold no reads (after filtering)
+    pub const NO_READS: i32 = 4;
+    /// The format of an input is unknown or not supported
+    pub const UNSUPPORTED_FORMAT: i32 = 5;
+    /// Invalid command-line arguments or options
+    pub const INVALID_INPUT: i32 = 6;
+}
+
+impl NanogetError {
+    /// A `ParseError` from a reader error, with the messages of its sources
+    /// appended, such as the decompression error behind "can't read input"
+    pub fn parse(error: &dyn std::error::Error) -> Self {
+        let mut message = error.to_string();
+        let mut source = error.source();
+        while let Some(cause) = source {
+            message.push_str(": ");
+            message.push_str(&cause.to_string());
+            source = cause.source();
+        }
+        Self::ParseError(message)
+    }
+
+    /// The process exit code for this error; see `exit_code`
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::FileNotFound(_) => exit_code::FILE_NOT_FOUND,
+            Self::Io(e) if e.kind() == std::io::ErrorKind::NotFound => exit_code::FILE_NOT_FOUND,
+            Self::Htslib(_) | Self::Csv(_) | Self::ParseError(_) => exit_code::PARSE_ERROR,
+            Self::NoReads(_) => exit_code::NO_READS,
+            Self::UnsupportedFormat(_) => exit_code::UNSUPPORTED_FORMAT,
+            Self::InvalidInput(_) => exit_code::INVALID_INPUT,
+            Self::Io(_)
+            | Self::Json(_)
+            | Self::Sqlite(_)
+            | Self::ProcessingError(_)
+            | Self::Warnings(_) => exit_code::ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let cases = [
+            (NanogetError::FileNotFound("reads.fastq".to_string()), 2),
+            (
+                NanogetError::Io(std::io::Error::from(std::io::ErrorKind::NotFound)),
+                2,
+            ),
+            (NanogetError::ParseError("truncated record".to_string()), 3),
+            (
+                NanogetError::Htslib(rust_htslib::errors::Error::BamInvalidRecord),
+                3,
+            ),
+            (NanogetError::NoReads("input files".to_string()), 4),
+            (NanogetError::UnsupportedFormat("notes.dat".to_string()), 5),
+            (NanogetError::InvalidInput("--threads".to_string()), 6),
+            (
+                NanogetError::Io(std::io::Error::from(std::io::ErrorKind::PermissionDenied)),
+                1,
+            ),
+            (NanogetError::ProcessingError("timed out".to_string()), 1),
+            (
+                NanogetError::Warnings(vec!["reads.bam is truncated".to_string()]),
+                1,
+            ),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.exit_code(), code, "{}", error);
+        }
+        assert_eq!(
+            NanogetError::NoReads("input files".to_string()).to_string(),
+            "No reads found in input files"
+        );
+    }
 }
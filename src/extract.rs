@@ -1,20 +1,36 @@
 use crate::cli::ExtractArgs;
 use crate::error::NanogetError;
 use crate::formats::FileType;
-use crate::metrics::{MetricsCollection, ReadMetrics};
-use crate::utils;
+use crate::metrics::{
+    CombineMethod, ExcludedReads, MetricsCollection, MetricsSummary, MissingQuality, OnlineSummary,
+    Provenance, ReadClass, ReadMetrics, ReadType, ReservoirSampler, SummaryOptions,
+};
+use crate::mmap;
+use crate::progress::{self, ThroughputLog};
+use crate::resources::ResourceRecorder;
+use crate::spill::{SpilledReads, SpillingCollector};
+use crate::utils::{self, StringInterner};
+use crate::warnings::Warnings;
 
 use chrono::{DateTime, TimeZone, Utc};
-use log::info;
+use crossbeam_channel::{Receiver, Select, Sender};
+use csv::{ReaderBuilder, StringRecord};
+use log::{debug, info, warn};
 use rayon::prelude::*;
-use rust_htslib::bam::record::{Aux, Cigar};
+use rust_htslib::bam::record::{Aux, Cigar, Record};
 use rust_htslib::bam::Read as BamRead;
 use rust_htslib::htslib::{
-    hts_fmt_option_CRAM_OPT_REQUIRED_FIELDS, sam_fields_SAM_AUX, sam_fields_SAM_CIGAR,
-    sam_fields_SAM_FLAG, sam_fields_SAM_MAPQ, sam_fields_SAM_QNAME, sam_fields_SAM_SEQ,
+    htsCompression_bgzf, hts_fmt_option_CRAM_OPT_REQUIRED_FIELDS, sam_fields_SAM_AUX,
+    sam_fields_SAM_CIGAR, sam_fields_SAM_FLAG, sam_fields_SAM_MAPQ, sam_fields_SAM_QNAME,
+    sam_fields_SAM_SEQ,
 };
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::Read;
 use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Safely parse a timestamp (seconds since epoch) to DateTime<Utc>
 /// Handles nanosecond overflow by clamping to valid range
@@ -31,12 +47,19 @@ fn parse_timestamp(timestamp: f64) -> Option<DateTime<Utc>> {
     Utc.timestamp_opt(seconds, nanos).single()
 }
 
-/// Main entry point for extracting metrics from files
+/// Main entry point for extracting metrics from files.
+///
+/// The input files are processed in parallel on the current rayon pool: the
+/// global one, unless called within another pool (see
+/// `extract_metrics_in_pool`). With `threads == 1` they are processed one
+/// after the other on the calling thread, without rayon.
 pub fn extract_metrics(args: &ExtractArgs) -> Result<MetricsCollection, NanogetError> {
     // Stdin shortcut: single "-" path handled entirely here.
     if args.files.len() == 1 && args.files[0].as_os_str() == "-" {
         return extract_metrics_stdin(args);
     }
+    let extraction = Arc::new(Extraction::new(args));
+    let args = &*resolve_file_type(args, &extraction.warnings)?;
 
     info!(
         "Starting nanoget extraction with {} files",
@@ -44,18 +67,77 @@ pub fn extract_metrics(args: &ExtractArgs) -> Result<MetricsCollection, NanogetE
     );
 
     // Validate input files
-    for file in &args.files {
-        utils::check_file_exists(file)?;
-    }
+    let (files, names) = input_files(args, &extraction.warnings)?;
+    validate_args(args)?;
+    let options = summary_options(args)?;
+    progress::start(&files);
+
+    // Summaries split into chunks: later chunks are checked against, or take,
+    // the header of the first file
+    let summary_header = if args.file_type == Some(FileType::Summary) && files.len() > 1 {
+        read_summary_header(files[0])?
+    } else {
+        None
+    };
 
-    let collections = args
-        .files
-        .par_iter()
-        .map(|file| process_single_file(file, &args.file_type, args))
-        .collect::<Result<Vec<_>, _>>()?;
+    let outcomes = map_files(&files, args, |file| match args.timeout_secs {
+        Some(secs) => process_single_file_with_timeout(
+            file,
+            args,
+            summary_header.as_ref(),
+            &options,
+            &extraction,
+            Duration::from_secs(secs),
+        ),
+        None => process_single_file(
+            file,
+            file_type_of(args, file)?,
+            args,
+            summary_header.as_ref(),
+            &options,
+            &extraction,
+        )
+        .map(Some),
+    })?;
+    extraction.warnings.check(args.strict)?;
+    let resources = extraction.resources.clone();
+    drop(extraction);
+    let extracted = resources.as_ref().map(|r| r.elapsed());
+
+    // Files that timed out are left out; the others keep their dataset names
+    let names = if outcomes.iter().any(Option::is_none) {
+        Some(
+            (0..files.len())
+                .map(|i| {
+                    names
+                        .as_ref()
+                        .and_then(|n| n.get(i))
+                        .cloned()
+                        .unwrap_or_else(|| format!("dataset_{}", i))
+                })
+                .zip(&outcomes)
+                .filter(|(_, outcome)| outcome.is_some())
+                .map(|(name, _)| name)
+                .collect(),
+        )
+    } else {
+        names
+    };
+    let (files, collections): (Vec<&Path>, Vec<MetricsCollection>) = files
+        .into_iter()
+        .zip(outcomes)
+        .filter_map(|(file, outcome)| outcome.map(|collection| (file, collection)))
+        .unzip();
+    let per_file = files
+        .iter()
+        .zip(&collections)
+        .map(|(file, collection)| (file.display().to_string(), collection.summary.clone()))
+        .collect();
 
     // Combine results
-    let combined = MetricsCollection::combine(collections, &args.combine, args.names.clone());
+    let mut combined =
+        MetricsCollection::combine_with_options(collections, args.combine, names, &options);
+    combined.set_per_file_summaries(per_file);
 
     info!(
         "Extraction complete: {} reads processed",
@@ -63,12 +145,883 @@ pub fn extract_metrics(args: &ExtractArgs) -> Result<MetricsCollection, NanogetE
     );
 
     if combined.summary.read_count == 0 {
-        return Err(NanogetError::ProcessingError(
-            "No reads found in input files".to_string(),
+        return Err(NanogetError::NoReads("input files".to_string()));
+    }
+    check_missing_quality(args, &combined.summary)?;
+
+    let reads = combined.summary.read_count as u64;
+    let mut collection = finish_collection(combined, args);
+    if let (Some(resources), Some(extracted)) = (resources, extracted) {
+        add_resource_report(&mut collection, &resources, reads, extracted);
+    }
+    Ok(collection)
+}
+
+/// Record in the provenance of `collection` what its extraction cost: the
+/// files were read in the first `extracted` of `resources`, and the rest of
+/// the time went into combining them
+fn add_resource_report(
+    collection: &mut MetricsCollection,
+    resources: &ResourceRecorder,
+    reads: u64,
+    extracted: Duration,
+) {
+    let combined = resources.elapsed().saturating_sub(extracted);
+    let report = resources.report(reads, extracted, combined);
+    if let Some(provenance) = collection.provenance.as_mut() {
+        provenance.resources = Some(report);
+    }
+}
+
+/// `extract_metrics` on `pool` instead of the current rayon pool, for
+/// applications that manage their own pools; the pool is reused across calls
+/// rather than built for each one
+#[allow(dead_code)]
+pub fn extract_metrics_in_pool(
+    args: &ExtractArgs,
+    pool: &rayon::ThreadPool,
+) -> Result<MetricsCollection, NanogetError> {
+    pool.install(|| extract_metrics(args))
+}
+
+/// `process` applied to each of `files`, in order: in parallel on the current
+/// rayon pool, or sequentially on the calling thread when `args.threads` is 1
+fn map_files<T, F>(files: &[&Path], args: &ExtractArgs, process: F) -> Result<Vec<T>, NanogetError>
+where
+    T: Send,
+    F: Fn(&Path) -> Result<T, NanogetError> + Sync + Send,
+{
+    if utils::resolve_threads(args.threads) == 1 {
+        files.iter().map(|file| process(file)).collect()
+    } else {
+        files.par_iter().map(|file| process(file)).collect()
+    }
+}
+
+/// Fill in `--file-type` when it was omitted, detecting the type of each input
+/// file from its extension or content (see `FileType::detect`) unless
+/// `--file-types` gives them. Inputs of a single type set `file_type`; inputs
+/// of different types set `file_types`, one per file (see `file_type_of`).
+/// Stdin is detected by its own reader.
+fn resolve_file_type<'a>(
+    args: &'a ExtractArgs,
+    warnings: &Warnings,
+) -> Result<Cow<'a, ExtractArgs>, NanogetError> {
+    let args = expand_inputs(args, warnings)?;
+    if args.file_type.is_some() || args.files.iter().any(|f| f.as_os_str() == "-") {
+        return Ok(args);
+    }
+    let types = match &args.file_types {
+        Some(types) if types.len() != args.files.len() => {
+            return Err(NanogetError::InvalidInput(format!(
+                "--file-types has {} types for {} input files; give one type per file",
+                types.len(),
+                args.files.len()
+            )));
+        }
+        Some(types) => types.clone(),
+        None => args
+            .files
+            .iter()
+            .map(|file| {
+                utils::check_file_exists(file)?;
+                let (detected, from_extension) = FileType::detect(file)?;
+                info!(
+                    "{}: detected file type {:?} from its {}",
+                    file.display(),
+                    detected,
+                    if from_extension {
+                        "extension"
+                    } else {
+                        "content"
+                    }
+                );
+                Ok(detected)
+            })
+            .collect::<Result<Vec<_>, NanogetError>>()?,
+    };
+    let mut args = args.into_owned();
+    if types.iter().all(|t| Some(t) == types.first()) {
+        args.file_type = types.into_iter().next();
+        args.file_types = None;
+    } else {
+        info!("Input files have different types; processing each by its own type");
+        args.file_types = Some(types);
+    }
+    Ok(Cow::Owned(args))
+}
+
+/// `args` with the matches of the `--glob` patterns added to the inputs, and
+/// every directory among them replaced by the files below it whose extension
+/// matches `--file-type` (any known extension when omitted), in path order.
+/// Directories are only searched with `--recursive`.
+fn expand_inputs<'a>(
+    args: &'a ExtractArgs,
+    warnings: &Warnings,
+) -> Result<Cow<'a, ExtractArgs>, NanogetError> {
+    if args.glob.is_empty() && !args.files.iter().any(|f| f.is_dir()) {
+        return Ok(Cow::Borrowed(args));
+    }
+    let mut inputs = args.files.clone();
+    for pattern in &args.glob {
+        inputs.extend(glob_matches(pattern, warnings)?);
+    }
+    let mut files = Vec::new();
+    for file in &inputs {
+        if !file.is_dir() {
+            files.push(file.clone());
+            continue;
+        }
+        if !args.recursive {
+            return Err(NanogetError::InvalidInput(format!(
+                "{} is a directory; pass --recursive to extract from the files in it",
+                file.display()
+            )));
+        }
+        let found = files.len();
+        collect_directory(file, args.file_type.as_ref(), &mut files)?;
+        if files.len() == found {
+            return Err(NanogetError::InvalidInput(format!(
+                "No input files found in directory {}",
+                file.display()
+            )));
+        }
+        info!(
+            "{}: found {} input files",
+            file.display(),
+            files.len() - found
+        );
+    }
+    if files.is_empty() {
+        return Err(NanogetError::InvalidInput(
+            "No input files to process".to_string(),
+        ));
+    }
+    let mut args = args.clone();
+    args.files = files;
+    Ok(Cow::Owned(args))
+}
+
+/// Paths matching a `--glob` pattern, in path order; a pattern without matches
+/// is a warning
+fn glob_matches(
+    pattern: &str,
+    warnings: &Warnings,
+) -> Result<Vec<std::path::PathBuf>, NanogetError> {
+    let paths = glob::glob(pattern).map_err(|e| {
+        NanogetError::InvalidInput(format!("Invalid --glob pattern '{}': {}", pattern, e))
+    })?;
+    let matches = paths
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.into_error())?;
+    if matches.is_empty() {
+        warnings.add(format!("--glob pattern '{}' matches no files", pattern));
+    } else {
+        info!(
+            "--glob pattern '{}' matches {} paths",
+            pattern,
+            matches.len()
+        );
+    }
+    Ok(matches)
+}
+
+/// Add the files below `dir` matching `file_type` (or any known type) to `files`
+fn collect_directory(
+    dir: &Path,
+    file_type: Option<&FileType>,
+    files: &mut Vec<std::path::PathBuf>,
+) -> Result<(), NanogetError> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.path());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_directory(&path, file_type, files)?;
+        } else if match file_type {
+            Some(file_type) => file_type.matches_extension(&path),
+            None => FileType::from_extension(&path).is_some(),
+        } {
+            files.push(path);
+        } else {
+            debug!("{}: skipped, not a matching input file", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// The type of the input `file`, one of `args.files`; always known for file
+/// inputs after `resolve_file_type`
+fn file_type_of<'a>(args: &'a ExtractArgs, file: &Path) -> Result<&'a FileType, NanogetError> {
+    let per_file = args.file_types.as_ref().and_then(|types| {
+        let index = args.files.iter().position(|f| f == file)?;
+        types.get(index)
+    });
+    per_file.or(args.file_type.as_ref()).ok_or_else(|| {
+        NanogetError::InvalidInput(format!(
+            "The type of input file {} is unknown; pass --file-type",
+            file.display()
+        ))
+    })
+}
+
+/// The types of the input files: `--file-type`, or the per-file types
+fn input_types(args: &ExtractArgs) -> impl Iterator<Item = &FileType> {
+    args.file_type
+        .iter()
+        .chain(args.file_types.iter().flatten())
+}
+
+/// Check that the input files exist and drop repeated ones, so that a file
+/// given twice (possibly under different spellings of its path) is not counted
+/// twice. Repeats are a warning. Returns the unique files in order, with
+/// their dataset names in track mode: the matching `--names` entries, or else
+/// names derived from the files (see `dataset_names`).
+fn input_files<'a>(
+    args: &'a ExtractArgs,
+    warnings: &Warnings,
+) -> Result<(Vec<&'a Path>, Option<Vec<String>>), NanogetError> {
+    let mut seen = HashSet::new();
+    let mut kept = Vec::with_capacity(args.files.len());
+    for (i, file) in args.files.iter().enumerate() {
+        utils::check_file_exists(file)?;
+        let canonical = std::fs::canonicalize(file)?;
+        if seen.insert(canonical) {
+            kept.push(i);
+            continue;
+        }
+        warnings.add(format!(
+            "Input file {} was given more than once; it is processed only once",
+            file.display()
+        ));
+    }
+
+    let files: Vec<&Path> = kept.iter().map(|&i| args.files[i].as_path()).collect();
+    let names = match &args.names {
+        Some(names) => Some(kept.iter().filter_map(|&i| names.get(i).cloned()).collect()),
+        None if args.combine == CombineMethod::Track => Some(dataset_names(&files)),
+        None => None,
+    };
+    Ok((files, names))
+}
+
+/// Dataset names of input files without `--names`: their file names without
+/// the compression and format extensions (reads.fastq.gz -> reads), or their
+/// paths as given where those names collide
+fn dataset_names(files: &[&Path]) -> Vec<String> {
+    let stems: Vec<String> = files
+        .iter()
+        .map(|file| {
+            let name = file.file_name().unwrap_or(file.as_os_str());
+            let name = name.to_string_lossy();
+            let lower = name.to_lowercase();
+            let name = if lower.ends_with(".gz") || lower.ends_with(".bz2") {
+                let end = name.rfind('.').unwrap_or(name.len());
+                &name[..end]
+            } else {
+                &name[..]
+            };
+            Path::new(name)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| name.to_string())
+        })
+        .collect();
+    stems
+        .iter()
+        .zip(files)
+        .map(|(stem, file)| {
+            if stems.iter().filter(|other| *other == stem).count() > 1 {
+                file.display().to_string()
+            } else {
+                stem.clone()
+            }
+        })
+        .collect()
+}
+
+/// Merge collections exported with `extract -f json` (e.g. one per flow cell)
+/// without going back to the inputs: the reads of each file are tagged with
+/// its dataset name, as in track mode (`names`, or names derived from the
+/// files), and the summary is recomputed with the default options. Files
+/// written by different nanoget releases (major.minor) are not merged, as
+/// their metrics may not be comparable.
+pub fn merge_exports(
+    files: &[std::path::PathBuf],
+    names: Option<Vec<String>>,
+) -> Result<MetricsCollection, NanogetError> {
+    if let Some(names) = &names {
+        check_dataset_names(names, files.len())?;
+    }
+    let mut collections = Vec::with_capacity(files.len());
+    let mut first_version: Option<(&Path, String)> = None;
+    for file in files {
+        let collection = MetricsCollection::from_json_file(file)?;
+        if let Some(version) = collection.provenance.as_ref().map(|p| &p.nanoget_version) {
+            let release = |v: &str| v.split('.').take(2).collect::<Vec<_>>().join(".");
+            match &first_version {
+                Some((first, first_version)) if release(first_version) != release(version) => {
+                    return Err(NanogetError::InvalidInput(format!(
+                        "{} was written by nanoget {} and {} by nanoget {}; extract them with the same release to merge them",
+                        first.display(),
+                        first_version,
+                        file.display(),
+                        version
+                    )));
+                }
+                Some(_) => {}
+                None => first_version = Some((file, version.clone())),
+            }
+        }
+        info!(
+            "{}: {} reads",
+            file.display(),
+            collection.summary.read_count
+        );
+        collections.push(collection);
+    }
+    let names = names
+        .unwrap_or_else(|| dataset_names(&files.iter().map(|f| f.as_path()).collect::<Vec<_>>()));
+    let mut merged = MetricsCollection::combine(collections, CombineMethod::Track, Some(names));
+    merged.provenance = Some(Provenance::default());
+    Ok(merged)
+}
+
+/// The reads of `metrics` per barcode (for `--split-by barcode`), in barcode
+/// order, each with its own summary computed with the options of `args`.
+/// Reads without a barcode are left out, with a warning; it is an error when
+/// none of the reads has one.
+pub fn split_by_barcode(
+    args: &ExtractArgs,
+    metrics: &MetricsCollection,
+) -> Result<Vec<(String, MetricsCollection)>, NanogetError> {
+    let options = summary_options(args)?;
+    let mut groups: BTreeMap<&str, Vec<ReadMetrics>> = BTreeMap::new();
+    let mut missing = 0;
+    for read in &metrics.reads {
+        match read.barcode() {
+            Some(barcode) => groups.entry(barcode).or_default().push(read.clone()),
+            None => missing += 1,
+        }
+    }
+    if groups.is_empty() && !metrics.reads.is_empty() {
+        return Err(NanogetError::InvalidInput(
+            "None of the reads has a barcode to split by (for sequencing summaries, add --barcoded)"
+                .to_string(),
+        ));
+    }
+    if missing > 0 {
+        warn!(
+            "{} reads without a barcode are left out of the split",
+            missing
+        );
+    }
+    Ok(groups
+        .into_iter()
+        .map(|(barcode, reads)| {
+            (
+                barcode.to_string(),
+                MetricsCollection::with_options(reads, &options),
+            )
+        })
+        .collect())
+}
+
+/// Destination of the reads produced by the processors, one call per read
+pub type ReadSink<'a> = dyn FnMut(ReadMetrics) + 'a;
+
+/// Stream every read to `visitor` instead of collecting them, so memory use does
+/// not grow with the input. Files are processed one after the other, in order;
+/// in track mode each read's `dataset` is set as in `extract_metrics`.
+/// Returns the number of reads visited.
+#[allow(dead_code)]
+pub fn extract_metrics_streaming(
+    args: &ExtractArgs,
+    visitor: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    stream_reads(args, &mut ExcludedReads::default(), visitor)
+}
+
+/// Default number of reads per chunk of `extract_metrics_chunked`
+pub const DEFAULT_CHUNK_SIZE: usize = 10_000;
+
+/// `extract_metrics_streaming`, passing the reads to `visitor` in chunks of
+/// `chunk_size` reads (the last one may be smaller) instead of one at a time.
+/// Smaller chunks hand reads over sooner, larger ones save per-call overhead;
+/// see `DEFAULT_CHUNK_SIZE`. Returns the number of reads visited.
+#[allow(dead_code)]
+pub fn extract_metrics_chunked(
+    args: &ExtractArgs,
+    chunk_size: usize,
+    visitor: &mut dyn FnMut(Vec<ReadMetrics>),
+) -> Result<usize, NanogetError> {
+    if chunk_size == 0 {
+        return Err(NanogetError::InvalidInput(
+            "The chunk size must be at least 1".to_string(),
+        ));
+    }
+    let mut chunk = Vec::with_capacity(chunk_size.min(DEFAULT_CHUNK_SIZE));
+    let count = stream_reads(args, &mut ExcludedReads::default(), &mut |read| {
+        chunk.push(read);
+        if chunk.len() == chunk_size {
+            visitor(std::mem::replace(
+                &mut chunk,
+                Vec::with_capacity(chunk_size.min(DEFAULT_CHUNK_SIZE)),
+            ));
+        }
+    })?;
+    if !chunk.is_empty() {
+        visitor(chunk);
+    }
+    Ok(count)
+}
+
+/// `extract_metrics_streaming`, adding the reads dropped by the read filters
+/// to `excluded`
+fn stream_reads(
+    args: &ExtractArgs,
+    excluded: &mut ExcludedReads,
+    visitor: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    let extraction = Extraction::new(args);
+    let args = &*resolve_file_type(args, &extraction.warnings)?;
+    validate_args(args)?;
+    if args.files.len() == 1 && args.files[0].as_os_str() == "-" {
+        return process_stdin(args, excluded, visitor);
+    }
+    let (files, names) = input_files(args, &extraction.warnings)?;
+    progress::start(&files);
+    let summary_header = if args.file_type == Some(FileType::Summary) && files.len() > 1 {
+        read_summary_header(files[0])?
+    } else {
+        None
+    };
+
+    let mut total = 0;
+    for (i, file) in files.into_iter().enumerate() {
+        total += if args.combine == CombineMethod::Track {
+            let dataset_name: Arc<str> = match names.as_ref().and_then(|n| n.get(i)) {
+                Some(name) => Arc::from(name.as_str()),
+                None => Arc::from(format!("dataset_{}", i)),
+            };
+            process_file(
+                file,
+                file_type_of(args, file)?,
+                args,
+                summary_header.as_ref(),
+                &extraction,
+                excluded,
+                &mut |mut read: ReadMetrics| {
+                    read.extended_mut()
+                        .dataset
+                        .get_or_insert_with(|| Arc::clone(&dataset_name));
+                    visitor(read)
+                },
+            )?
+        } else {
+            process_file(
+                file,
+                file_type_of(args, file)?,
+                args,
+                summary_header.as_ref(),
+                &extraction,
+                excluded,
+                visitor,
+            )?
+        };
+    }
+    extraction.warnings.check(args.strict)?;
+    Ok(total)
+}
+
+/// Compute the summary in a single pass without keeping per-read metrics in
+/// memory; see `OnlineSummary` for which values are approximate or omitted.
+///
+/// With `--downsample`, a reservoir of the sampled reads is kept instead and
+/// summarized at the end, so memory is bounded by the sample size.
+pub fn extract_summary_streaming(args: &ExtractArgs) -> Result<MetricsSummary, NanogetError> {
+    let mut online = OnlineSummary::new(&summary_options(args)?);
+    let mut excluded = ExcludedReads::default();
+    let read_count = match args.downsample {
+        Some(n) => {
+            let mut sampler = ReservoirSampler::new(n, args.seed);
+            let read_count = stream_reads(args, &mut excluded, &mut |read| sampler.add(read))?;
+            for read in sampler.finish() {
+                online.add(&read);
+            }
+            read_count
+        }
+        None => stream_reads(args, &mut excluded, &mut |read| online.add(&read))?,
+    };
+    info!(
+        "Streaming extraction complete: {} reads processed",
+        read_count
+    );
+
+    if read_count == 0 {
+        return Err(NanogetError::NoReads("input files".to_string()));
+    }
+    let mut summary = online.finish();
+    check_missing_quality(args, &summary)?;
+    summary.excluded_reads = ReadFilter::new(args).report(excluded);
+    Ok(summary)
+}
+
+/// `extract_metrics` keeping about `max_memory` bytes of reads in memory
+/// (`--max-memory`): the reads are streamed into a `SpillingCollector`, which
+/// spills them to temporary files beyond the cap, so files are processed one
+/// after the other, and the summary is computed online as in
+/// `extract_summary_streaming`.
+pub fn extract_metrics_spilling(
+    args: &ExtractArgs,
+    max_memory: u64,
+) -> Result<SpilledReads, NanogetError> {
+    let mut collector = SpillingCollector::new(max_memory, &summary_options(args)?)?;
+    let mut excluded = ExcludedReads::default();
+    let read_count = stream_reads(args, &mut excluded, &mut |read| collector.add(read))?;
+    info!("Extraction complete: {} reads processed", read_count);
+
+    if read_count == 0 {
+        return Err(NanogetError::NoReads("input files".to_string()));
+    }
+    let mut spilled = collector.finish()?;
+    check_missing_quality(args, &spilled.summary)?;
+    spilled.summary.excluded_reads = ReadFilter::new(args).report(excluded);
+    spilled.provenance = Some(Provenance::default());
+    Ok(spilled)
+}
+
+/// Count reads and total bases with as little per-record work as possible:
+/// no quality, alignment or metadata parsing and no summary statistics.
+///
+/// BAM/CRAM files are scanned rather than answered from the index: idxstats
+/// counts every alignment record (including secondary and supplementary ones)
+/// and has no base counts. The same primary-alignment filters as
+/// `extract_metrics` apply, so the numbers agree with its read count and yield.
+pub fn count_reads(args: &ExtractArgs) -> Result<(u64, u64), NanogetError> {
+    let extraction = Extraction::default();
+    let args = &*resolve_file_type(args, &extraction.warnings)?;
+    validate_args(args)?;
+    if args.files.len() == 1 && args.files[0].as_os_str() == "-" {
+        let mut totals = (0u64, 0u64);
+        process_stdin(args, &mut ExcludedReads::default(), &mut |read| {
+            totals.0 += 1;
+            totals.1 += read.length;
+        })?;
+        return Ok(totals);
+    }
+    let (files, _) = input_files(args, &extraction.warnings)?;
+    progress::start(&files);
+
+    let counts = map_files(&files, args, |file| count_file(file, args, &extraction))?;
+    extraction.warnings.check(args.strict)?;
+    Ok(counts
+        .into_iter()
+        .fold((0, 0), |(reads, bases), (r, b)| (reads + r, bases + b)))
+}
+
+/// Count the reads and bases of a single file; see `count_reads`. With read
+/// filters the records are fully processed, as the filters need their metrics,
+/// and so are region queries.
+fn count_file(
+    file: &Path,
+    args: &ExtractArgs,
+    extraction: &Extraction,
+) -> Result<(u64, u64), NanogetError> {
+    let mut reads = 0u64;
+    let mut bases = 0u64;
+    let file_type = file_type_of(args, file)?;
+    if ReadFilter::new(args).is_active() || args.region.is_some() {
+        let mut excluded = ExcludedReads::default();
+        process_file(
+            file,
+            file_type,
+            args,
+            None,
+            extraction,
+            &mut excluded,
+            &mut |read| {
+                reads += 1;
+                bases += read.length;
+            },
+        )?;
+        return Ok((reads, bases));
+    }
+    let _progress = progress::track_file(file);
+    match file_type {
+        FileType::Fastq | FileType::FastqRich | FileType::FastqMinimal => {
+            for result in bio::io::fastq::Reader::new(utils::open_file(file)?).records() {
+                let record = result.map_err(|e| NanogetError::parse(&e))?;
+                reads += 1;
+                bases += record.seq().len() as u64;
+            }
+        }
+        FileType::Fasta => {
+            for result in bio::io::fasta::Reader::new(utils::open_file(file)?).records() {
+                let record = result.map_err(|e| NanogetError::parse(&e))?;
+                reads += 1;
+                bases += record.seq().len() as u64;
+            }
+        }
+        FileType::Bam | FileType::Cram | FileType::Sam | FileType::Ubam => {
+            let mut reader = rust_htslib::bam::Reader::from_path(file)?;
+            let bgzf_threads = decode_threads(args);
+            if bgzf_threads > 0 {
+                reader
+                    .set_threads(bgzf_threads)
+                    .map_err(|e| NanogetError::ProcessingError(e.to_string()))?;
+            }
+            let aligned = file_type != &FileType::Ubam;
+            let bgzf = is_bgzf(&reader);
+            let mut record = rust_htslib::bam::Record::new();
+            for index in 0u64.. {
+                match reader.read(&mut record) {
+                    Some(result) => result?,
+                    None => break,
+                }
+                if bgzf && index % PROGRESS_INTERVAL == 0 {
+                    progress::advance_to(reader.tell() as u64 >> 16);
+                }
+                if aligned
+                    && (record.is_unmapped()
+                        || record.is_secondary()
+                        || (!args.keep_supplementary && record.is_supplementary())
+                        || !meets_min_mapq(&record, args.min_mapq))
+                {
+                    continue;
+                }
+                reads += 1;
+                bases += query_length(&record);
+            }
+        }
+        FileType::Summary => {
+            process_summary(file, args, None, &StringInterner::new(), &mut |read| {
+                reads += 1;
+                bases += read.length;
+            })?;
+        }
+    }
+    Ok((reads, bases))
+}
+
+/// `--names`: one name per input file, each used once
+fn check_dataset_names(names: &[String], file_count: usize) -> Result<(), NanogetError> {
+    if names.len() != file_count {
+        return Err(NanogetError::InvalidInput(format!(
+            "--names has {} names for {} input files; give one name per file",
+            names.len(),
+            file_count
+        )));
+    }
+    let mut seen = HashSet::new();
+    if let Some(name) = names.iter().find(|name| !seen.insert(*name)) {
+        return Err(NanogetError::InvalidInput(format!(
+            "--names gives the name '{}' more than once; dataset names must be unique",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Validate per-record options that the processors rely on
+fn validate_args(args: &ExtractArgs) -> Result<(), NanogetError> {
+    if let Some(tag) = args.bam_tags.iter().find(|t| t.len() != 2) {
+        return Err(NanogetError::InvalidInput(format!(
+            "Invalid BAM tag '{}': tags are exactly two characters",
+            tag
+        )));
+    }
+    // Stdin is detected later, by its reader
+    if args.read_type != ReadType::OneD && input_types(args).any(|t| t != &FileType::Summary) {
+        return Err(NanogetError::InvalidInput(format!(
+            "--read-type {} only applies to sequencing summaries; duplex reads in BAM \
+             files are classified from their dx tag",
+            args.read_type.as_str()
+        )));
+    }
+    if let (CombineMethod::Track, Some(names)) = (args.combine, &args.names) {
+        check_dataset_names(names, args.files.len())?;
+    }
+    if args.downsample == Some(0) {
+        return Err(NanogetError::InvalidInput(
+            "--downsample must be at least 1".to_string(),
+        ));
+    }
+    if args.timeout_secs == Some(0) {
+        return Err(NanogetError::InvalidInput(
+            "--timeout-secs must be at least 1".to_string(),
+        ));
+    }
+    if args.subsample_every == Some(0) {
+        return Err(NanogetError::InvalidInput(
+            "--subsample-every must be at least 1".to_string(),
+        ));
+    }
+    for gc in [args.min_gc, args.max_gc].into_iter().flatten() {
+        if !(0.0..=100.0).contains(&gc) {
+            return Err(NanogetError::InvalidInput(format!(
+                "GC bound {} is outside the range [0, 100]",
+                gc
+            )));
+        }
+    }
+    // Only FASTQ and FASTA reads have a GC content (see `scans_sequences`);
+    // the filters would drop every other read
+    if args.min_gc.is_some() || args.max_gc.is_some() {
+        if let Some(other) = input_types(args)
+            .find(|t| !matches!(t, FileType::Fastq | FileType::FastqRich | FileType::Fasta))
+        {
+            return Err(NanogetError::InvalidInput(format!(
+                "--min-gc and --max-gc need FASTQ or FASTA input, not {:?}",
+                other
+            )));
+        }
+    }
+    if let (Some(min), Some(max)) = (args.min_length, args.max_length) {
+        if min > max {
+            return Err(NanogetError::InvalidInput(format!(
+                "--min-length {} is larger than --max-length {}",
+                min, max
+            )));
+        }
+    }
+    if let (Some(min), Some(max)) = (args.min_gc, args.max_gc) {
+        if min > max {
+            return Err(NanogetError::InvalidInput(format!(
+                "--min-gc {} is larger than --max-gc {}",
+                min, max
+            )));
+        }
+    }
+    if args.region.is_some() {
+        if args.file_type.is_none() && args.file_types.is_none()
+            || !input_types(args).all(|t| matches!(t, FileType::Bam | FileType::Cram))
+        {
+            return Err(NanogetError::InvalidInput(
+                "--region needs indexed BAM or CRAM input".to_string(),
+            ));
+        }
+        if args.index.is_some() && args.files.len() > 1 {
+            return Err(NanogetError::InvalidInput(
+                "--index names the index of a single input file".to_string(),
+            ));
+        }
+    } else if args.index.is_some() {
+        return Err(NanogetError::InvalidInput(
+            "--index only applies to region queries (--region)".to_string(),
+        ));
+    }
+    if args.write_reads.is_some()
+        && !matches!(
+            args.file_type,
+            Some(FileType::Fastq | FileType::FastqRich | FileType::Fasta)
+        )
+    {
+        return Err(NanogetError::InvalidInput(
+            "--write-reads needs FASTQ or FASTA input with read ids".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Deterministic thinning for `--subsample-every N`: keeps the records at
+/// indices 0, N, 2N, ... of each file in a single pass, without a seed.
+struct Subsampler {
+    every: usize,
+    index: usize,
+}
+
+impl Subsampler {
+    fn new(every: Option<usize>) -> Self {
+        Self {
+            every: every.unwrap_or(1).max(1),
+            index: 0,
+        }
+    }
+
+    /// Advance past one record, returning whether it should be kept
+    fn keep(&mut self) -> bool {
+        let keep = self.index.is_multiple_of(self.every);
+        self.index += 1;
+        keep
+    }
+}
+
+/// Build the summary options from the command line arguments
+fn summary_options(args: &ExtractArgs) -> Result<SummaryOptions, NanogetError> {
+    if args.throughput_bin_minutes == 0 {
+        return Err(NanogetError::InvalidInput(
+            "Throughput bin width must be at least one minute".to_string(),
+        ));
+    }
+    if let Some(p) = args
+        .percentiles
+        .iter()
+        .find(|p| !(0.0..=100.0).contains(*p))
+    {
+        return Err(NanogetError::InvalidInput(format!(
+            "Percentile {} is outside the range [0, 100]",
+            p
+        )));
+    }
+    if args.length_quality_density
+        && (args.density_length_bin == 0
+            || !(args.density_quality_bin > 0.0 && args.density_quality_bin.is_finite()))
+    {
+        return Err(NanogetError::InvalidInput(
+            "Length-vs-quality density bins must be positive".to_string(),
+        ));
+    }
+    if args.length_bin == 0 {
+        return Err(NanogetError::InvalidInput(
+            "Length histogram bins must be at least one base wide".to_string(),
         ));
     }
+    if !(0.0..=1.0).contains(&args.short_read_limit) {
+        return Err(NanogetError::InvalidInput(format!(
+            "Short read limit {} is outside the range [0, 1]",
+            args.short_read_limit
+        )));
+    }
+    Ok(SummaryOptions {
+        throughput_bin_seconds: args.throughput_bin_minutes * 60,
+        cumulative_yield: args.cumulative_yield,
+        percentiles: args.percentiles.clone(),
+        length_quality_density: args
+            .length_quality_density
+            .then_some((args.density_length_bin, args.density_quality_bin)),
+        length_bin: args.length_bin,
+        length_modes: args.length_modes,
+        log_length: args.log_length,
+        missing_quality: args.missing_quality,
+        run_start: args.run_start,
+        short_read_spike: args
+            .short_read_length
+            .map(|max_length| (max_length, args.short_read_limit)),
+    })
+}
 
-    Ok(combined)
+/// How `--threads` (0 for all cores) is shared out over `files` input files:
+/// the files are processed in parallel, one worker thread each, and when there
+/// are fewer files than threads the surplus is split evenly over them as BGZF
+/// decompression threads for BAM/CRAM, after the `bam_workers` metric threads
+/// of each file (`--bam-workers`). FASTQ, FASTA and summary parsing is
+/// single-threaded per file. Returns the number of file workers and the
+/// decompression threads per file.
+///
+/// The BAM workers are not taken from the file workers: with as many files as
+/// threads, each file still starts its `bam_workers` threads on top of them.
+fn thread_allocation(threads: usize, files: usize, bam_workers: usize) -> (usize, usize) {
+    let threads = utils::resolve_threads(threads);
+    let workers = files.clamp(1, threads);
+    (workers, (threads / workers).saturating_sub(1 + bam_workers))
+}
+
+/// BGZF decompression threads per BAM/CRAM file: `--decode-threads`, or else
+/// the share of `--threads` from `thread_allocation`
+fn decode_threads(args: &ExtractArgs) -> usize {
+    args.decode_threads
+        .unwrap_or_else(|| thread_allocation(args.threads, args.files.len(), args.bam_workers).1)
 }
 
 /// Process a single file and return metrics
@@ -76,110 +1029,709 @@ fn process_single_file(
     file: &Path,
     file_type: &FileType,
     args: &ExtractArgs,
+    summary_header: Option<&StringRecord>,
+    options: &SummaryOptions,
+    extraction: &Extraction,
 ) -> Result<MetricsCollection, NanogetError> {
+    let mut reads = Vec::new();
+    let mut excluded = ExcludedReads::default();
+    process_file(
+        file,
+        file_type,
+        args,
+        summary_header,
+        extraction,
+        &mut excluded,
+        &mut |read| reads.push(read),
+    )?;
+    let mut collection = MetricsCollection::with_options(reads, options);
+    collection.set_excluded_reads(ReadFilter::new(args).report(excluded));
+    Ok(collection)
+}
+
+/// `process_single_file` on a worker thread that is given at most `timeout`.
+/// A file that times out is skipped with a warning (`Ok(None)`) so that the
+/// other files still complete; with `--strict` the extraction then fails.
+fn process_single_file_with_timeout(
+    file: &Path,
+    args: &ExtractArgs,
+    summary_header: Option<&StringRecord>,
+    options: &SummaryOptions,
+    extraction: &Arc<Extraction>,
+    timeout: Duration,
+) -> Result<Option<MetricsCollection>, NanogetError> {
+    let file_buf = file.to_path_buf();
+    let worker_args = args.clone();
+    let summary_header = summary_header.cloned();
+    let options = options.clone();
+    let worker_extraction = Arc::clone(extraction);
+    let outcome = run_with_timeout(timeout, move || {
+        process_single_file(
+            &file_buf,
+            file_type_of(&worker_args, &file_buf)?,
+            &worker_args,
+            summary_header.as_ref(),
+            &options,
+            &worker_extraction,
+        )
+    });
+    match outcome {
+        Some(result) => result.map(Some),
+        None => {
+            extraction.warnings.add(format!(
+                "{}: not processed within {} seconds (--timeout-secs); skipping it",
+                file.display(),
+                timeout.as_secs()
+            ));
+            Ok(None)
+        }
+    }
+}
+
+/// Run `job` on its own thread and wait at most `timeout` for its result;
+/// `None` when it times out. A timed-out job cannot be cancelled (it may be
+/// blocked inside a read): its thread keeps running detached and its result
+/// is discarded.
+fn run_with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    job: impl FnOnce() -> Result<T, NanogetError> + Send + 'static,
+) -> Option<Result<T, NanogetError>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        // The receiver is gone when the job finishes after the deadline
+        let _ = sender.send(job());
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => Some(result),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => Some(Err(NanogetError::ProcessingError(
+            "File processing thread panicked".to_string(),
+        ))),
+    }
+}
+
+/// State shared by the files of one extraction
+#[derive(Debug, Default)]
+struct Extraction {
+    /// Barcodes and run ids, so that each is stored once
+    interner: StringInterner,
+
+    /// Reads passed on, logged with `--log-interval`
+    throughput: ThroughputLog,
+
+    /// Processing time of the files, with `--resource-report`
+    resources: Option<Arc<ResourceRecorder>>,
+
+    /// Issues met along the way, which fail the extraction with `--strict`
+    warnings: Warnings,
+}
+
+impl Extraction {
+    fn new(args: &ExtractArgs) -> Self {
+        Self {
+            interner: StringInterner::new(),
+            throughput: ThroughputLog::start(args.log_interval.map(Duration::from_secs)),
+            resources: args
+                .resource_report
+                .then(|| Arc::new(ResourceRecorder::start())),
+            warnings: Warnings::new(),
+        }
+    }
+}
+
+/// Reads a worker passes on before adding them to the shared throughput count
+const THROUGHPUT_BATCH: u64 = 1024;
+
+/// `process` with the reads it passes to `sink` counted in `throughput`
+fn with_throughput(
+    throughput: &ThroughputLog,
+    sink: &mut ReadSink<'_>,
+    process: impl FnOnce(&mut ReadSink<'_>) -> Result<usize, NanogetError>,
+) -> Result<usize, NanogetError> {
+    let mut pending = 0;
+    let result = process(&mut |read| {
+        pending += 1;
+        if pending == THROUGHPUT_BATCH {
+            throughput.add(pending);
+            pending = 0;
+        }
+        sink(read);
+    });
+    throughput.add(pending);
+    result
+}
+
+/// Process a single file, passing each read that passes the read filters to
+/// `sink`; returns the number of reads passed on
+fn process_file(
+    file: &Path,
+    file_type: &FileType,
+    args: &ExtractArgs,
+    summary_header: Option<&StringRecord>,
+    extraction: &Extraction,
+    excluded: &mut ExcludedReads,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
     info!("Processing file: {}", file.display());
+    let _progress = progress::track_file(file);
+
+    let interner = &extraction.interner;
+    let warnings = &extraction.warnings;
+    let started = Instant::now();
+    let count = with_throughput(&extraction.throughput, sink, |sink| {
+        with_read_filter(args, sink, excluded, |sink| match file_type {
+            FileType::Fastq => process_fastq(file, false, args, interner, sink),
+            FileType::FastqRich => process_fastq(file, true, args, interner, sink),
+            FileType::FastqMinimal => process_fastq_minimal(file, args, sink),
+            FileType::Fasta => process_fasta(file, args, sink),
+            FileType::Bam => process_bam(file, args, warnings, sink),
+            FileType::Cram => process_bam(file, args, warnings, sink),
+            FileType::Sam => process_bam(file, args, warnings, sink),
+            FileType::Ubam => process_ubam(file, args, warnings, sink),
+            FileType::Summary => process_summary(file, args, summary_header, interner, sink),
+        })
+    })?;
+    info!("Finished {}: {} reads", file.display(), count);
+    if let Some(resources) = &extraction.resources {
+        resources.record_file(file, count, started.elapsed());
+    }
+    Ok(count)
+}
+
+/// Read filters applied during extraction (`--min-length`, `--max-length`,
+/// `--min-quality`, `--min-gc`, `--max-gc`), so that dropped reads are never
+/// collected. Lengths are read lengths; for BAM/CRAM that is the query
+/// sequence including soft clips, not the aligned length.
+struct ReadFilter {
+    min_length: Option<u64>,
+    max_length: Option<u64>,
+    min_quality: Option<f64>,
+    min_gc: Option<f64>,
+    max_gc: Option<f64>,
+}
+
+impl ReadFilter {
+    fn new(args: &ExtractArgs) -> Self {
+        Self {
+            min_length: args.min_length,
+            max_length: args.max_length,
+            min_quality: args.min_quality,
+            min_gc: args.min_gc,
+            max_gc: args.max_gc,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.min_length.is_some()
+            || self.max_length.is_some()
+            || self.min_quality.is_some()
+            || self.min_gc.is_some()
+            || self.max_gc.is_some()
+    }
 
-    let reads = match file_type {
-        FileType::Fastq => process_fastq(file, false)?,
-        FileType::FastqRich => process_fastq(file, true)?,
-        FileType::FastqMinimal => process_fastq_minimal(file)?,
-        FileType::Fasta => process_fasta(file)?,
-        FileType::Bam => process_bam(file, args.keep_supplementary, args.threads)?,
-        FileType::Cram => process_bam(file, args.keep_supplementary, args.threads)?,
-        FileType::Ubam => process_ubam(file)?,
-        FileType::Summary => process_summary(file, &args.read_type, args.barcoded)?,
+    /// Whether `read` passes; a read that fails is counted in `excluded` under
+    /// the first criterion it fails (length, then quality, then GC content).
+    /// Reads without a quality fail an active `--min-quality`, and reads
+    /// without a GC content fail active GC bounds.
+    fn passes(&self, read: &ReadMetrics, excluded: &mut ExcludedReads) -> bool {
+        let counter = if self.min_length.is_some_and(|min| read.length < min) {
+            &mut excluded.min_length
+        } else if self.max_length.is_some_and(|max| read.length > max) {
+            &mut excluded.max_length
+        } else if self
+            .min_quality
+            .is_some_and(|min| !read.quality.is_some_and(|q| q >= min))
+        {
+            &mut excluded.min_quality
+        } else if self
+            .min_gc
+            .is_some_and(|min| !read.gc_content().is_some_and(|gc| gc >= min))
+            || self
+                .max_gc
+                .is_some_and(|max| !read.gc_content().is_some_and(|gc| gc <= max))
+        {
+            &mut excluded.gc_content
+        } else {
+            return true;
+        };
+        *counter += 1;
+        false
+    }
+
+    /// `excluded` when any filter is active, for the summary
+    fn report(&self, excluded: ExcludedReads) -> Option<ExcludedReads> {
+        self.is_active().then_some(excluded)
+    }
+}
+
+/// Run `process` with a sink that drops the reads failing the read filters
+/// before passing them on to `sink`, adding them to `excluded`; returns the
+/// number of reads passed on
+fn with_read_filter(
+    args: &ExtractArgs,
+    sink: &mut ReadSink<'_>,
+    excluded: &mut ExcludedReads,
+    process: impl FnOnce(&mut ReadSink<'_>) -> Result<usize, NanogetError>,
+) -> Result<usize, NanogetError> {
+    let filter = ReadFilter::new(args);
+    if !filter.is_active() {
+        return process(sink);
+    }
+    let mut kept = 0;
+    process(&mut |read| {
+        if filter.passes(&read, excluded) {
+            kept += 1;
+            sink(read);
+        }
+    })?;
+    Ok(kept)
+}
+
+/// Write the FASTQ/FASTA records of the reads in `collection` to `output`, in
+/// input order. `ReadMetrics` does not keep sequences, so the input files are
+/// read a second time: each record goes through the same subsampling and read
+/// filters as during the extraction, and is written when `collection` still
+/// holds a read of its dataset with its id. Downsampling therefore carries
+/// over, and a read id repeated across files is written as often as it was
+/// kept. `output` is written atomically (see `utils::AtomicFile`) and must not
+/// exist yet unless `args.force`. Returns the number of records written.
+pub fn write_reads(
+    args: &ExtractArgs,
+    collection: &MetricsCollection,
+    output: &Path,
+) -> Result<usize, NanogetError> {
+    if args.files.iter().any(|f| f.as_os_str() == "-") {
+        return Err(NanogetError::InvalidInput(
+            "--write-reads cannot re-read stdin input".to_string(),
+        ));
+    }
+    // The inputs were checked by the extraction, which reported any warnings
+    let warnings = Warnings::new();
+    let args = &*resolve_file_type(args, &warnings)?;
+    let (files, names) = input_files(args, &warnings)?;
+    let file_type = args.file_type.as_ref().ok_or_else(|| {
+        NanogetError::InvalidInput("--write-reads needs input files of a single type".to_string())
+    })?;
+    let rich = match file_type {
+        FileType::Fastq | FileType::Fasta => false,
+        FileType::FastqRich => true,
+        other => {
+            return Err(NanogetError::InvalidInput(format!(
+                "--write-reads is not supported for {:?} input",
+                other
+            )))
+        }
+    };
+
+    // The reads left to write, per read id: how many of each dataset
+    let mut remaining: HashMap<&str, Vec<(Option<&str>, usize)>> = HashMap::new();
+    for read in &collection.reads {
+        if let Some(id) = read.read_id.as_deref() {
+            let dataset = read.dataset().map(AsRef::as_ref);
+            let kept = remaining.entry(id).or_default();
+            match kept.iter_mut().find(|(d, _)| *d == dataset) {
+                Some((_, count)) => *count += 1,
+                None => kept.push((dataset, 1)),
+            }
+        }
+    }
+    let filter = ReadFilter::new(args);
+    let mut excluded = ExcludedReads::default();
+    let mut select = |read: &ReadMetrics, dataset: Option<&str>| -> bool {
+        if !filter.passes(read, &mut excluded) {
+            return false;
+        }
+        let id = read.read_id.as_deref().unwrap_or_default();
+        match remaining
+            .get_mut(id)
+            .and_then(|kept| kept.iter_mut().find(|(d, n)| *d == dataset && *n > 0))
+        {
+            Some((_, count)) => {
+                *count -= 1;
+                true
+            }
+            None => false,
+        }
     };
+    let interner = StringInterner::new();
+    let mut output = utils::AtomicFile::create(output, args.force)?;
+    let mut written = 0;
+
+    for (i, file) in files.into_iter().enumerate() {
+        // As the datasets of track mode in `stream_reads`
+        let dataset = (args.combine == CombineMethod::Track).then(|| {
+            match names.as_ref().and_then(|n| n.get(i)) {
+                Some(name) => name.clone(),
+                None => format!("dataset_{}", i),
+            }
+        });
+        let dataset = dataset.as_deref();
+        let mut subsampler = Subsampler::new(args.subsample_every);
+        if *file_type == FileType::Fasta {
+            let mut writer = bio::io::fasta::Writer::new(&mut output);
+            for result in bio::io::fasta::Reader::new(utils::open_file(file)?).records() {
+                let record = result.map_err(|e| NanogetError::parse(&e))?;
+                if !subsampler.keep() {
+                    continue;
+                }
+                let mut read =
+                    ReadMetrics::new(Some(record.id().to_string()), record.seq().len() as u64);
+                if scans_sequences(args) {
+                    add_sequence_stats(&mut read, record.seq());
+                }
+                if select(&read, dataset) {
+                    writer.write_record(&record)?;
+                    written += 1;
+                }
+            }
+            writer.flush()?;
+        } else {
+            let mut writer = bio::io::fastq::Writer::new(&mut output);
+            for result in bio::io::fastq::Reader::new(utils::open_file(file)?).records() {
+                let record = result.map_err(|e| NanogetError::parse(&e))?;
+                if !subsampler.keep() {
+                    continue;
+                }
+                let read = fastq_read_metrics(
+                    record.id(),
+                    record.desc(),
+                    record.seq(),
+                    record.qual(),
+                    rich,
+                    args,
+                    &interner,
+                )?;
+                if select(&read, dataset) {
+                    writer.write_record(&record)?;
+                    written += 1;
+                }
+            }
+            writer.flush()?;
+        }
+    }
+    output.commit()?;
+    Ok(written)
+}
+
+/// Whether FASTQ/FASTA sequences are scanned for base composition: with
+/// `--sequence-stats`, or when the GC filters need the GC content
+fn scans_sequences(args: &ExtractArgs) -> bool {
+    args.sequence_stats || args.min_gc.is_some() || args.max_gc.is_some()
+}
+
+/// Set the ambiguous base count and GC content of a read from its sequence.
+/// GC is the percentage of G/C among the unambiguous bases.
+fn add_sequence_stats(read: &mut ReadMetrics, seq: &[u8]) {
+    set_sequence_stats(read, seq.len() as u64, utils::base_composition(seq));
+}
 
-    Ok(MetricsCollection::new(reads))
+/// As `add_sequence_stats`, from the (GC, ambiguous) base counts of a
+/// sequence of `length` bases
+fn set_sequence_stats(read: &mut ReadMetrics, length: u64, (gc, ambiguous): (u64, u64)) {
+    let extended = read.extended_mut();
+    extended.n_count = Some(ambiguous);
+    let unambiguous = length - ambiguous;
+    extended.gc_content = (unambiguous > 0).then(|| gc as f64 / unambiguous as f64 * 100.0);
 }
 
 /// Process FASTQ files
-fn process_fastq(file: &Path, rich: bool) -> Result<Vec<ReadMetrics>, NanogetError> {
+fn process_fastq(
+    file: &Path,
+    rich: bool,
+    args: &ExtractArgs,
+    interner: &StringInterner,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    if args.mmap {
+        if let Some(map) = mmap::map_file(file)? {
+            return process_fastq_mapped(&map, rich, args, interner, sink);
+        }
+    }
     let reader = utils::open_file(file)?;
-    process_fastq_from_reader(reader, rich)
+    process_fastq_from_reader(reader, rich, args, interner, sink)
 }
 
+/// Process the FASTQ records of `reader` (see `fastq_read_metrics`)
 fn process_fastq_from_reader<R: Read>(
     reader: R,
     rich: bool,
-) -> Result<Vec<ReadMetrics>, NanogetError> {
+    args: &ExtractArgs,
+    interner: &StringInterner,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
     use bio::io::fastq;
 
     let fastq_reader = fastq::Reader::new(reader);
-    let mut metrics = Vec::new();
+    let mut count = 0;
+    let mut subsampler = Subsampler::new(args.subsample_every);
 
     for (i, result) in fastq_reader.records().enumerate() {
-        let record = result.map_err(|e| NanogetError::ParseError(e.to_string()))?;
-
-        let read_id = record.id().to_string();
-        let length = record.seq().len() as u32;
-        let quality = utils::average_quality(record.qual());
+        let record = result.map_err(|e| NanogetError::parse(&e))?;
+        check_quality_length(&record)?;
+        if !subsampler.keep() {
+            continue;
+        }
 
-        let mut read_metrics = ReadMetrics::new(Some(read_id), length);
+        sink(fastq_read_metrics(
+            record.id(),
+            record.desc(),
+            record.seq(),
+            record.qual(),
+            rich,
+            args,
+            interner,
+        )?);
+        count += 1;
 
-        if let Some(q) = quality {
-            read_metrics = read_metrics.with_quality(q);
+        if i % 10000 == 0 && i > 0 {
+            debug!("Processed {} reads", i);
         }
+    }
 
-        if rich {
-            let desc = record.desc().unwrap_or("");
-            if let Some(metadata) = parse_rich_fastq_metadata(desc) {
-                read_metrics = read_metrics.with_sequencing_metadata(
-                    metadata.channel_id,
-                    metadata.start_time,
-                    metadata.duration,
-                );
-                read_metrics.run_id = metadata.run_id;
-            }
+    Ok(count)
+}
+
+/// `--mmap`: the records are parsed in place in the mapped file, and only the
+/// read ids are copied
+fn process_fastq_mapped(
+    data: &[u8],
+    rich: bool,
+    args: &ExtractArgs,
+    interner: &StringInterner,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    let mut records = mmap::FastqRecords::new(data);
+    let mut count = 0;
+    let mut subsampler = Subsampler::new(args.subsample_every);
+
+    for i in 0u64.. {
+        let Some(record) = records.next().transpose()? else {
+            break;
+        };
+        if i % PROGRESS_INTERVAL == 0 {
+            progress::advance_to(records.position() as u64);
+        }
+        check_quality_lengths(record.id, record.seq.len(), record.qual.len())?;
+        if !subsampler.keep() {
+            continue;
         }
 
-        metrics.push(read_metrics);
+        sink(fastq_read_metrics(
+            record.id,
+            record.desc,
+            &record.seq,
+            &record.qual,
+            rich,
+            args,
+            interner,
+        )?);
+        count += 1;
 
         if i % 10000 == 0 && i > 0 {
-            info!("Processed {} reads", i);
+            debug!("Processed {} reads", i);
+        }
+    }
+    progress::advance_to(data.len() as u64);
+
+    Ok(count)
+}
+
+/// The metrics of a FASTQ record. Quality strings are decoded as Phred+33;
+/// `--quality-offset` is subtracted on top of that.
+fn fastq_read_metrics(
+    id: &str,
+    desc: Option<&str>,
+    seq: &[u8],
+    qual: &[u8],
+    rich: bool,
+    args: &ExtractArgs,
+    interner: &StringInterner,
+) -> Result<ReadMetrics, NanogetError> {
+    utils::check_quality_offset(qual, utils::FASTQ_PHRED_OFFSET, args.quality_offset)?;
+    let offset = utils::FASTQ_PHRED_OFFSET.saturating_add(args.quality_offset);
+    let quality = args.quality_method.average(qual, offset)?;
+
+    let mut read_metrics = ReadMetrics::new(Some(id.to_string()), seq.len() as u64);
+
+    if let Some(q) = quality {
+        read_metrics = read_metrics.with_quality(q);
+        read_metrics.quality_std = utils::quality_std(qual, offset);
+    }
+    if scans_sequences(args) {
+        add_sequence_stats(&mut read_metrics, seq);
+    }
+
+    if rich {
+        if let Some(metadata) = parse_rich_fastq_metadata(desc.unwrap_or("")) {
+            read_metrics = read_metrics.with_sequencing_metadata(
+                metadata.channel_id,
+                metadata.start_time,
+                metadata.duration,
+            );
+            read_metrics.extended_mut().run_id = metadata.run_id.map(|id| interner.intern(id));
         }
     }
+    Ok(read_metrics)
+}
+
+/// A FASTQ record whose quality string is not as long as its sequence is
+/// malformed (e.g. a truncated line); its average quality would be wrong
+pub(crate) fn check_quality_length(record: &bio::io::fastq::Record) -> Result<(), NanogetError> {
+    check_quality_lengths(record.id(), record.seq().len(), record.qual().len())
+}
 
-    Ok(metrics)
+/// As `check_quality_length`, from the id and lengths of a record
+fn check_quality_lengths(id: &str, seq_len: usize, qual_len: usize) -> Result<(), NanogetError> {
+    if qual_len == seq_len {
+        return Ok(());
+    }
+    Err(NanogetError::ParseError(format!(
+        "Read {}: quality string of {} characters for a sequence of {} bases",
+        id, qual_len, seq_len
+    )))
 }
 
 /// Process FASTQ files with minimal information (length only)
-fn process_fastq_minimal(file: &Path) -> Result<Vec<ReadMetrics>, NanogetError> {
+fn process_fastq_minimal(
+    file: &Path,
+    args: &ExtractArgs,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
     use bio::io::fastq;
 
+    if args.mmap {
+        if let Some(map) = mmap::map_file(file)? {
+            return process_fastq_minimal_mapped(&map, args, sink);
+        }
+    }
     let reader = utils::open_file(file)?;
     let fastq_reader = fastq::Reader::new(reader);
-    let mut metrics = Vec::new();
+    let mut count = 0;
+    let mut subsampler = Subsampler::new(args.subsample_every);
 
     for result in fastq_reader.records() {
-        let record = result.map_err(|e| NanogetError::ParseError(e.to_string()))?;
-        metrics.push(ReadMetrics::new(None, record.seq().len() as u32));
+        let record = result.map_err(|e| NanogetError::parse(&e))?;
+        check_quality_length(&record)?;
+        if !subsampler.keep() {
+            continue;
+        }
+        sink(ReadMetrics::new(None, record.seq().len() as u64));
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// `--mmap` for `process_fastq_minimal`
+fn process_fastq_minimal_mapped(
+    data: &[u8],
+    args: &ExtractArgs,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    let mut records = mmap::FastqRecords::new(data);
+    let mut count = 0;
+    let mut subsampler = Subsampler::new(args.subsample_every);
+
+    for i in 0u64.. {
+        let Some(record) = records.next().transpose()? else {
+            break;
+        };
+        if i % PROGRESS_INTERVAL == 0 {
+            progress::advance_to(records.position() as u64);
+        }
+        check_quality_lengths(record.id, record.seq.len(), record.qual.len())?;
+        if !subsampler.keep() {
+            continue;
+        }
+        sink(ReadMetrics::new(None, record.seq.len() as u64));
+        count += 1;
     }
+    progress::advance_to(data.len() as u64);
 
-    Ok(metrics)
+    Ok(count)
 }
 
 /// Process FASTA files
-fn process_fasta(file: &Path) -> Result<Vec<ReadMetrics>, NanogetError> {
+fn process_fasta(
+    file: &Path,
+    args: &ExtractArgs,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    if args.mmap {
+        if let Some(map) = mmap::map_file(file)? {
+            return process_fasta_mapped(&map, args, sink);
+        }
+    }
     let reader = utils::open_file(file)?;
-    process_fasta_from_reader(reader)
+    process_fasta_from_reader(reader, args, sink)
+}
+
+/// `--mmap` for FASTA: sequences are measured line by line in the mapped
+/// file, without joining the lines of multi-line records
+fn process_fasta_mapped(
+    data: &[u8],
+    args: &ExtractArgs,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    let mut records = mmap::FastaRecords::new(data);
+    let mut count = 0;
+    let mut subsampler = Subsampler::new(args.subsample_every);
+
+    for i in 0u64.. {
+        let Some(record) = records.next().transpose()? else {
+            break;
+        };
+        if i % PROGRESS_INTERVAL == 0 {
+            progress::advance_to(records.position() as u64);
+        }
+        if !subsampler.keep() {
+            continue;
+        }
+        let length = record.seq_len() as u64;
+        let mut read_metrics = ReadMetrics::new(Some(record.id.to_string()), length);
+        if scans_sequences(args) {
+            let composition = record
+                .seq_lines()
+                .map(utils::base_composition)
+                .fold((0, 0), |(gc, n), (line_gc, line_n)| {
+                    (gc + line_gc, n + line_n)
+                });
+            set_sequence_stats(&mut read_metrics, length, composition);
+        }
+        sink(read_metrics);
+        count += 1;
+    }
+    progress::advance_to(data.len() as u64);
+
+    Ok(count)
 }
 
-fn process_fasta_from_reader<R: Read>(reader: R) -> Result<Vec<ReadMetrics>, NanogetError> {
+fn process_fasta_from_reader<R: Read>(
+    reader: R,
+    args: &ExtractArgs,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
     use bio::io::fasta;
 
     let fasta_reader = fasta::Reader::new(reader);
-    let mut metrics = Vec::new();
+    let mut count = 0;
+    let mut subsampler = Subsampler::new(args.subsample_every);
 
     for result in fasta_reader.records() {
-        let record = result.map_err(|e| NanogetError::ParseError(e.to_string()))?;
-        metrics.push(ReadMetrics::new(
-            Some(record.id().to_string()),
-            record.seq().len() as u32,
-        ));
+        let record = result.map_err(|e| NanogetError::parse(&e))?;
+        if !subsampler.keep() {
+            continue;
+        }
+        let mut read_metrics =
+            ReadMetrics::new(Some(record.id().to_string()), record.seq().len() as u64);
+        if scans_sequences(args) {
+            add_sequence_stats(&mut read_metrics, record.seq());
+        }
+        sink(read_metrics);
+        count += 1;
     }
 
-    Ok(metrics)
+    Ok(count)
 }
 
 /// Get the NM (edit distance) tag from a BAM record
@@ -210,19 +1762,79 @@ fn get_de_tag(record: &rust_htslib::bam::Record) -> Option<f64> {
     }
 }
 
+/// Get the pi (parent read id) tag that dorado sets on reads it split
+fn get_parent_id(record: &rust_htslib::bam::Record) -> Option<String> {
+    match record.aux(b"pi") {
+        Ok(Aux::String(v)) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+/// Get the read class from the dx (duplex) tag that dorado sets
+fn get_read_class(record: &rust_htslib::bam::Record) -> Option<ReadClass> {
+    let value = match record.aux(b"dx").ok()? {
+        Aux::I8(v) => i64::from(v),
+        Aux::U8(v) => i64::from(v),
+        Aux::I16(v) => i64::from(v),
+        Aux::U16(v) => i64::from(v),
+        Aux::I32(v) => i64::from(v),
+        Aux::U32(v) => i64::from(v),
+        _ => return None,
+    };
+    ReadClass::from_dx(value)
+}
+
+/// Get the poly(A) tail length that dorado estimates with `--estimate-poly-a`,
+/// from the pt tag or, failing that, an integer pa tag. The array form of pa
+/// (anchor positions) is not a length and is ignored.
+fn get_polya_length(record: &rust_htslib::bam::Record) -> Option<u32> {
+    [b"pt", b"pa"]
+        .iter()
+        .find_map(|tag| match record.aux(*tag).ok()? {
+            Aux::I8(v) => u32::try_from(v).ok(),
+            Aux::U8(v) => Some(u32::from(v)),
+            Aux::I16(v) => u32::try_from(v).ok(),
+            Aux::U16(v) => Some(u32::from(v)),
+            Aux::I32(v) => u32::try_from(v).ok(),
+            Aux::U32(v) => Some(v),
+            _ => None,
+        })
+}
+
+/// Length of the read of an alignment: its sequence, or for a record stored
+/// without one (SEQ `*`, as written by some pipelines to save space) the
+/// query bases of its CIGAR (M, I, S, = and X), which SEQ would have held
+fn query_length(record: &rust_htslib::bam::Record) -> u64 {
+    if record.seq_len() > 0 {
+        return record.seq_len() as u64;
+    }
+    record
+        .cigar()
+        .iter()
+        .map(|entry| match entry {
+            Cigar::Match(len)
+            | Cigar::Ins(len)
+            | Cigar::SoftClip(len)
+            | Cigar::Equal(len)
+            | Cigar::Diff(len) => u64::from(*len),
+            _ => 0,
+        })
+        .sum()
+}
+
 /// Extract aligned length and gap-compressed identity with at most one CIGAR pass.
 ///
 /// When the minimap2 `de` tag is present: one minimal CIGAR pass for aligned length only.
 /// When absent: one combined CIGAR pass computing both values simultaneously.
-fn alignment_stats(record: &rust_htslib::bam::Record) -> (u32, Option<f64>) {
-    let mut aligned_len: u32 = 0;
+fn alignment_stats(record: &rust_htslib::bam::Record) -> (u64, Option<f64>) {
+    let mut aligned_len: u64 = 0;
 
     if let Some(identity) = get_de_tag(record) {
         // Minimal pass: aligned length only, no identity bookkeeping needed
         for entry in record.cigar().iter() {
             match entry {
                 Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) | Cigar::Ins(len) => {
-                    aligned_len += len;
+                    aligned_len += u64::from(*len);
                 }
                 _ => {}
             }
@@ -239,11 +1851,11 @@ fn alignment_stats(record: &rust_htslib::bam::Record) -> (u32, Option<f64>) {
     for entry in record.cigar().iter() {
         match entry {
             Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
-                aligned_len += len;
+                aligned_len += u64::from(*len);
                 matches += len;
             }
             Cigar::Ins(len) => {
-                aligned_len += len;
+                aligned_len += u64::from(*len);
                 gap_size += len;
                 gap_count += 1;
             }
@@ -274,16 +1886,76 @@ fn alignment_stats(record: &rust_htslib::bam::Record) -> (u32, Option<f64>) {
 /// which forces random seeks that break sequential BGZF streaming.
 fn process_bam(
     file: &Path,
-    keep_supplementary: bool,
-    threads: usize,
-) -> Result<Vec<ReadMetrics>, NanogetError> {
-    let mut reader = if file.as_os_str() == "-" {
+    args: &ExtractArgs,
+    warnings: &Warnings,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    if let Some(region) = &args.region {
+        let mut reader = open_indexed_bam(file, args.index.as_deref())?;
+        reader.fetch(region.as_str()).map_err(|_| {
+            NanogetError::InvalidInput(format!(
+                "Invalid region '{}' for {}",
+                region,
+                file.display()
+            ))
+        })?;
+        return extract_alignments(reader, file, args, warnings, sink);
+    }
+    let reader = if file.as_os_str() == "-" {
         rust_htslib::bam::Reader::from_stdin()?
     } else {
         rust_htslib::bam::Reader::from_path(file)?
     };
-    // Use all-but-one thread for BGZF decompression; htslib manages the pool.
-    let bgzf_threads = threads.saturating_sub(1);
+    extract_alignments(reader, file, args, warnings, sink)
+}
+
+/// Open a BAM/CRAM file for region queries, with the index next to it or at
+/// `index`: a local path or a URL (e.g. https:// or s3://), which htslib
+/// fetches. Both .bai and .csi indexes work (.crai for CRAM).
+pub(crate) fn open_indexed_bam(
+    file: &Path,
+    index: Option<&str>,
+) -> Result<rust_htslib::bam::IndexedReader, NanogetError> {
+    use rust_htslib::bam::IndexedReader;
+
+    let reader = match index {
+        None => IndexedReader::from_path(file),
+        Some(index) if utils::is_url(index) => {
+            // htslib's `<data>##idx##<index>` naming points it at a remote index
+            let data = url::Url::from_file_path(std::fs::canonicalize(file)?).map_err(|_| {
+                NanogetError::InvalidInput(format!("Invalid input path {}", file.display()))
+            })?;
+            let combined = url::Url::parse(&format!("{}##idx##{}", data, index))
+                .map_err(|e| NanogetError::InvalidInput(format!("Invalid index URL: {}", e)))?;
+            IndexedReader::from_url(&combined)
+        }
+        Some(index) => {
+            utils::check_file_exists(Path::new(index))?;
+            IndexedReader::from_path_and_index(file, Path::new(index))
+        }
+    };
+    reader.map_err(|e| {
+        NanogetError::InvalidInput(format!(
+            "Cannot open {} with its index{}: {}",
+            file.display(),
+            index.map(|i| format!(" {}", i)).unwrap_or_default(),
+            e
+        ))
+    })
+}
+
+/// Configure decompression threads (and, for CRAM, the decoded fields) and
+/// extract the alignments of an opened reader
+fn extract_alignments<R: BamRead>(
+    mut reader: R,
+    file: &Path,
+    args: &ExtractArgs,
+    warnings: &Warnings,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    let truncated = missing_eof_marker(&reader, file);
+    // BGZF decompression threads; htslib manages the pool
+    let bgzf_threads = decode_threads(args);
     if bgzf_threads > 0 {
         reader
             .set_threads(bgzf_threads)
@@ -312,52 +1984,329 @@ fn process_bam(
         file.display(),
         bgzf_threads
     );
-    extract_bam_records(&mut reader, keep_supplementary)
+    let bgzf = is_bgzf(&reader);
+    let count = if args.bam_workers > 0 {
+        extract_bam_records_pipelined(&mut reader, bgzf, args, sink, args.bam_workers)?
+    } else {
+        extract_bam_records(&mut reader, bgzf, args, sink)?
+    };
+    if truncated {
+        report_truncated(file, count, warnings);
+    }
+    Ok(count)
+}
+
+/// Check for the end-of-file marker that BAM (BGZF) and CRAM writers append on close.
+///
+/// A missing marker usually means an interrupted transfer: htslib then stops at the
+/// last complete block and the file silently yields a partial read set. Returns false
+/// when the check is not possible (e.g. stdin, which cannot be seeked).
+pub(crate) fn missing_eof_marker<R: BamRead>(reader: &R, file: &Path) -> bool {
+    // 1 = marker present, 0 = absent, 2/3 = cannot be checked, -1 = error
+    // SAFETY: `htsfile()` is the open htsFile owned by `reader`, which the
+    // borrow keeps alive for the call; hts_check_EOF restores the file offset.
+    let status = unsafe { rust_htslib::htslib::hts_check_EOF(reader.htsfile()) };
+    if status < 0 {
+        warn!("Could not check {} for an EOF marker", file.display());
+    }
+    status == 0
+}
+
+/// Whether the reader decompresses BGZF, whose compressed offsets (see
+/// `tell`) track the progress through the file. False for CRAM and for plain
+/// or gzipped SAM.
+fn is_bgzf<R: BamRead>(reader: &R) -> bool {
+    // SAFETY: `htsfile()` points to the htsFile owned by `reader`, valid and
+    // not written by htslib while `reader` is borrowed; only a field is read.
+    unsafe { (*reader.htsfile()).format.compression == htsCompression_bgzf }
+}
+
+/// Warn about an input file that appears truncated
+fn report_truncated(file: &Path, read_count: usize, warnings: &Warnings) {
+    warnings.add(format!(
+        "{} appears to be truncated (missing EOF marker); {} reads were processed before the end of the file",
+        file.display(),
+        read_count
+    ));
+}
+
+/// Record-level `--min-mapq` check, applied before a record becomes a read.
+/// MAPQ 255 means the mapping quality is unavailable, which never meets a minimum.
+fn meets_min_mapq(record: &rust_htslib::bam::Record, min_mapq: Option<u8>) -> bool {
+    min_mapq.is_none_or(|min| record.mapq() != 255 && record.mapq() >= min)
 }
 
+/// Records read between updates of the progress bar of a BAM file
+const PROGRESS_INTERVAL: u64 = 4096;
+
+/// Records handed to a `--bam-workers` worker at a time
+const BAM_BATCH_SIZE: usize = 256;
+
 /// Extract ReadMetrics from any type implementing bam::Read.
 fn extract_bam_records<R: BamRead>(
     reader: &mut R,
-    keep_supplementary: bool,
-) -> Result<Vec<ReadMetrics>, NanogetError> {
-    let mut metrics = Vec::new();
+    bgzf: bool,
+    args: &ExtractArgs,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    let mut count = 0;
+    let mut subsampler = Subsampler::new(args.subsample_every);
+    let mut record = rust_htslib::bam::Record::new();
+    let mut warned_missing_seq = false;
+
+    for index in 0u64.. {
+        match reader.read(&mut record) {
+            Some(result) => result?,
+            None => break,
+        }
+        if bgzf && index % PROGRESS_INTERVAL == 0 {
+            progress::advance_to(reader.tell() as u64 >> 16);
+        }
+        if !keep_alignment(&record, args, &mut subsampler) {
+            continue;
+        }
+        warn_missing_sequence(&record, &mut warned_missing_seq);
 
-    for result in reader.records() {
-        let record = result?;
+        sink(bam_read_metrics(&record, args));
+        count += 1;
+    }
 
-        // Secondary alignments are always excluded: they carry no full read
-        // sequence (SEQ is '*' or hard-clipped) and would double-count reads.
-        if record.is_unmapped() || record.is_secondary() {
-            continue;
+    Ok(count)
+}
+
+/// Whether an alignment becomes a read, after the record-level filters and
+/// `--subsample-every`
+fn keep_alignment(
+    record: &rust_htslib::bam::Record,
+    args: &ExtractArgs,
+    subsampler: &mut Subsampler,
+) -> bool {
+    // Secondary alignments are always excluded: they carry no full read
+    // sequence (SEQ is '*' or hard-clipped) and would double-count reads.
+    if record.is_unmapped() || record.is_secondary() {
+        return false;
+    }
+    // Supplementary alignments are hard-clipped fragments of a read; including
+    // them inflates read counts and yield, so they are excluded unless asked for.
+    if !args.keep_supplementary && record.is_supplementary() {
+        return false;
+    }
+    meets_min_mapq(record, args.min_mapq) && subsampler.keep()
+}
+
+/// Warn, once per file, of an alignment without a sequence
+fn warn_missing_sequence(record: &rust_htslib::bam::Record, warned: &mut bool) {
+    if record.seq_len() == 0 && !*warned {
+        warn!(
+            "Alignment of {} has no sequence (SEQ is *); read lengths of such records are taken from their CIGAR",
+            String::from_utf8_lossy(record.qname())
+        );
+        *warned = true;
+    }
+}
+
+/// The metrics of a kept alignment
+fn bam_read_metrics(record: &rust_htslib::bam::Record, args: &ExtractArgs) -> ReadMetrics {
+    let read_id = String::from_utf8_lossy(record.qname()).to_string();
+    let length = query_length(record);
+    let (aligned_length, percent_identity) = alignment_stats(record);
+    let mapping_quality = if record.mapq() == 255 {
+        None
+    } else {
+        Some(record.mapq())
+    };
+
+    // Base qualities of alignments are not read (nor decoded for CRAM, see
+    // `extract_alignments`): like the mean quality, their spread is unset
+    let mut read_metrics = ReadMetrics::new(Some(read_id), length).with_alignment(
+        aligned_length,
+        None,
+        mapping_quality,
+        percent_identity,
+    );
+    add_dorado_tags(record, &mut read_metrics);
+    extract_aux_tags(record, &args.bam_tags, &mut read_metrics);
+    read_metrics
+}
+
+/// `--bam-workers`: this thread decodes and filters the records, and hands
+/// them in batches to `workers` threads computing their metrics. Both
+/// channels are bounded, so a slow side holds the other back and at most a
+/// few batches are in flight. Reads reach `sink` in the order in which their
+/// batches finish, not in input order.
+fn extract_bam_records_pipelined<R: BamRead>(
+    reader: &mut R,
+    bgzf: bool,
+    args: &ExtractArgs,
+    sink: &mut ReadSink<'_>,
+    workers: usize,
+) -> Result<usize, NanogetError> {
+    let (batches, queued) = crossbeam_channel::bounded::<Vec<Record>>(2 * workers);
+    let (finished, results) = crossbeam_channel::bounded::<Vec<ReadMetrics>>(2 * workers);
+    let mut count = 0;
+    let mut emit = |reads: Vec<ReadMetrics>| {
+        count += reads.len();
+        reads.into_iter().for_each(&mut *sink);
+    };
+
+    let read = std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let (queued, finished) = (queued.clone(), finished.clone());
+            scope.spawn(move || {
+                for batch in queued {
+                    let reads = batch
+                        .iter()
+                        .map(|record| bam_read_metrics(record, args))
+                        .collect();
+                    if finished.send(reads).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop((queued, finished));
+
+        let read = read_bam_batches(reader, bgzf, args, |batch| {
+            hand_on(batch, &batches, &results, &mut emit)
+        });
+        // The workers finish the queued batches and stop, also after an error
+        drop(batches);
+        for reads in &results {
+            emit(reads);
         }
-        // Supplementary alignments are hard-clipped fragments of a read; including
-        // them inflates read counts and yield, so they are excluded unless asked for.
-        if !keep_supplementary && record.is_supplementary() {
+        read
+    });
+    read?;
+    Ok(count)
+}
+
+/// Read the alignments kept as reads in batches of `BAM_BATCH_SIZE`, passed
+/// to `hand_on`
+fn read_bam_batches<R: BamRead>(
+    reader: &mut R,
+    bgzf: bool,
+    args: &ExtractArgs,
+    mut hand_on: impl FnMut(Vec<Record>),
+) -> Result<(), NanogetError> {
+    let mut subsampler = Subsampler::new(args.subsample_every);
+    let mut warned_missing_seq = false;
+    let mut batch = Vec::with_capacity(BAM_BATCH_SIZE);
+    let mut record = Record::new();
+
+    for index in 0u64.. {
+        match reader.read(&mut record) {
+            Some(result) => result?,
+            None => break,
+        }
+        if bgzf && index % PROGRESS_INTERVAL == 0 {
+            progress::advance_to(reader.tell() as u64 >> 16);
+        }
+        if !keep_alignment(&record, args, &mut subsampler) {
             continue;
         }
+        warn_missing_sequence(&record, &mut warned_missing_seq);
+        batch.push(std::mem::replace(&mut record, Record::new()));
+        if batch.len() == BAM_BATCH_SIZE {
+            hand_on(std::mem::replace(
+                &mut batch,
+                Vec::with_capacity(BAM_BATCH_SIZE),
+            ));
+        }
+    }
+    if !batch.is_empty() {
+        hand_on(batch);
+    }
+    Ok(())
+}
 
-        let read_id = String::from_utf8_lossy(record.qname()).to_string();
-        let length = record.seq().len() as u32;
-        let (aligned_length, percent_identity) = alignment_stats(&record);
-        let mapping_quality = if record.mapq() == 255 {
-            None
-        } else {
-            Some(record.mapq())
-        };
+/// Queue `batch` for the workers, emitting the reads of finished batches
+/// while the queue is full
+fn hand_on(
+    batch: Vec<Record>,
+    batches: &Sender<Vec<Record>>,
+    results: &Receiver<Vec<ReadMetrics>>,
+    emit: &mut impl FnMut(Vec<ReadMetrics>),
+) {
+    let mut select = Select::new();
+    let send = select.send(batches);
+    select.recv(results);
+    loop {
+        let operation = select.select();
+        if operation.index() == send {
+            // Only fails if every worker has panicked, which the scope reports
+            let _ = operation.send(batches, batch);
+            return;
+        }
+        if let Ok(reads) = operation.recv(results) {
+            emit(reads);
+        }
+    }
+}
 
-        metrics.push(ReadMetrics::new(Some(read_id), length).with_alignment(
-            aligned_length,
-            None,
-            mapping_quality,
-            percent_identity,
-        ));
+/// Set the parent id, read class and poly(A) tail length of a read split,
+/// called as duplex or with its tail estimated by dorado; reads without these
+/// tags are left as they are
+fn add_dorado_tags(record: &rust_htslib::bam::Record, read_metrics: &mut ReadMetrics) {
+    let parent_id = get_parent_id(record);
+    let read_class = get_read_class(record);
+    let polya_length = get_polya_length(record);
+    if parent_id.is_some() || read_class.is_some() || polya_length.is_some() {
+        let extended = read_metrics.extended_mut();
+        extended.parent_id = parent_id;
+        extended.read_class = read_class;
+        extended.polya_length = polya_length;
     }
+}
 
-    Ok(metrics)
+/// Copy the requested aux tags into `extra` (numeric types `i`/`f`) or
+/// `extra_text` (types `Z`/`A`). Absent tags and array/hex types are skipped.
+fn extract_aux_tags(
+    record: &rust_htslib::bam::Record,
+    tags: &[String],
+    read_metrics: &mut ReadMetrics,
+) {
+    for tag in tags {
+        let Ok(value) = record.aux(tag.as_bytes()) else {
+            continue;
+        };
+        let numeric = match value {
+            Aux::I8(v) => Some(f64::from(v)),
+            Aux::U8(v) => Some(f64::from(v)),
+            Aux::I16(v) => Some(f64::from(v)),
+            Aux::U16(v) => Some(f64::from(v)),
+            Aux::I32(v) => Some(f64::from(v)),
+            Aux::U32(v) => Some(f64::from(v)),
+            Aux::Float(v) => Some(f64::from(v)),
+            Aux::Double(v) => Some(v),
+            Aux::String(v) => {
+                read_metrics
+                    .extended_mut()
+                    .extra_text
+                    .insert(tag.clone(), v.to_string());
+                None
+            }
+            Aux::Char(v) => {
+                read_metrics
+                    .extended_mut()
+                    .extra_text
+                    .insert(tag.clone(), char::from(v).to_string());
+                None
+            }
+            _ => None,
+        };
+        if let Some(v) = numeric {
+            read_metrics.extended_mut().extra.insert(tag.clone(), v);
+        }
+    }
 }
 
 /// Process unaligned BAM files
-fn process_ubam(file: &Path) -> Result<Vec<ReadMetrics>, NanogetError> {
+fn process_ubam(
+    file: &Path,
+    args: &ExtractArgs,
+    warnings: &Warnings,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
     use rust_htslib::{bam, bam::Read};
 
     let mut bam_reader = if file.as_os_str() == "-" {
@@ -365,86 +2314,190 @@ fn process_ubam(file: &Path) -> Result<Vec<ReadMetrics>, NanogetError> {
     } else {
         bam::Reader::from_path(file)?
     };
-    let mut metrics = Vec::new();
-
-    for result in bam_reader.records() {
-        let record = result?;
+    let truncated = missing_eof_marker(&bam_reader, file);
+    let mut count = 0;
+    let mut subsampler = Subsampler::new(args.subsample_every);
+    let mut record = bam::Record::new();
+
+    for index in 0u64.. {
+        match bam_reader.read(&mut record) {
+            Some(result) => result?,
+            None => break,
+        }
+        if index % PROGRESS_INTERVAL == 0 {
+            progress::advance_to(bam_reader.tell() as u64 >> 16);
+        }
+        if !subsampler.keep() {
+            continue;
+        }
 
         let read_id = String::from_utf8_lossy(record.qname()).to_string();
-        let length = record.seq().len() as u32;
+        let length = record.seq().len() as u64;
 
         // Calculate quality scores
-        let quality = record
-            .qual()
-            .iter()
-            .any(|&q| q != 255)
-            .then(|| utils::average_quality(record.qual()).unwrap_or(0.0));
+        let quality = if record.qual().iter().any(|&q| q != 255) {
+            let avg = args
+                .quality_method
+                .average(record.qual(), args.quality_offset)?;
+            Some(avg.unwrap_or(0.0))
+        } else {
+            None
+        };
 
         let mut read_metrics = ReadMetrics::new(Some(read_id), length);
 
         if let Some(q) = quality {
             read_metrics = read_metrics.with_quality(q);
+            read_metrics.quality_std = utils::quality_std(record.qual(), args.quality_offset);
         }
+        add_dorado_tags(&record, &mut read_metrics);
+        extract_aux_tags(&record, &args.bam_tags, &mut read_metrics);
 
-        metrics.push(read_metrics);
+        sink(read_metrics);
+        count += 1;
     }
 
-    Ok(metrics)
+    if truncated {
+        report_truncated(file, count, warnings);
+    }
+    Ok(count)
 }
 
 /// Process sequencing summary files
+///
+/// `reference_header` is the header of the first of several summary chunks: a
+/// chunk with its own header must have the same columns, and a chunk without
+/// one is parsed with the reference header.
 fn process_summary(
     file: &Path,
-    read_type: &str,
-    barcoded: bool,
-) -> Result<Vec<ReadMetrics>, NanogetError> {
+    args: &ExtractArgs,
+    reference_header: Option<&StringRecord>,
+    interner: &StringInterner,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    let reader = utils::open_file(file)?;
+    process_summary_from_reader(reader, args, reference_header, interner, sink).map_err(|e| match e
+    {
+        NanogetError::InvalidInput(msg) => {
+            NanogetError::InvalidInput(format!("{}: {}", file.display(), msg))
+        }
+        other => other,
+    })
+}
+
+/// Read the header row of a sequencing summary file (None if the file is empty)
+pub(crate) fn read_summary_header(file: &Path) -> Result<Option<StringRecord>, NanogetError> {
     let reader = utils::open_file(file)?;
-    process_summary_from_reader(reader, read_type, barcoded)
+    let mut csv_reader = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .from_reader(reader);
+    Ok(csv_reader.records().next().transpose()?)
 }
 
-fn process_summary_from_reader<R: Read>(
-    reader: R,
-    read_type: &str,
-    barcoded: bool,
-) -> Result<Vec<ReadMetrics>, NanogetError> {
-    use csv::ReaderBuilder;
-    use std::collections::HashMap;
+/// A row is taken to be a header when it repeats any of the reference column
+/// names; data rows hold numbers and identifiers, never column names.
+fn is_summary_header(row: &StringRecord, reference: &StringRecord) -> bool {
+    row.iter()
+        .any(|field| reference.iter().any(|column| column == field))
+}
 
-    let mut csv_reader = ReaderBuilder::new().delimiter(b'\t').from_reader(reader);
+/// Check that a chunk's header has the same columns as the reference header;
+/// column order may differ as fields are looked up by name.
+fn check_summary_columns(
+    header: &StringRecord,
+    reference: &StringRecord,
+) -> Result<(), NanogetError> {
+    let missing: Vec<&str> = reference
+        .iter()
+        .filter(|column| !header.iter().any(|c| c == *column))
+        .collect();
+    let unexpected: Vec<&str> = header
+        .iter()
+        .filter(|column| !reference.iter().any(|c| c == *column))
+        .collect();
+    if missing.is_empty() && unexpected.is_empty() {
+        return Ok(());
+    }
+    Err(NanogetError::InvalidInput(format!(
+        "Summary columns differ from the first summary file (missing: [{}], unexpected: [{}])",
+        missing.join(", "),
+        unexpected.join(", ")
+    )))
+}
 
-    // Get headers
-    let headers = csv_reader.headers()?.clone();
-    let mut metrics = Vec::new();
+fn process_summary_from_reader<R: Read>(
+    reader: R,
+    args: &ExtractArgs,
+    reference_header: Option<&StringRecord>,
+    interner: &StringInterner,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    let mut csv_reader = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .from_reader(reader);
+    let mut records = csv_reader.records();
+    let batch_datasets = summary_batch_datasets(args);
+
+    // Get headers, reusing the reference header for a chunk that has none
+    let Some(first_row) = records.next().transpose()? else {
+        return Ok(0);
+    };
+    let (headers, first_data_row) = match reference_header {
+        Some(reference) if !is_summary_header(&first_row, reference) => {
+            (reference.clone(), Some(first_row))
+        }
+        Some(reference) => {
+            check_summary_columns(&first_row, reference)?;
+            (first_row, None)
+        }
+        None => (first_row, None),
+    };
+    let mut count = 0;
+    let mut subsampler = Subsampler::new(args.subsample_every);
 
-    for result in csv_reader.records() {
+    for result in first_data_row.map(Ok).into_iter().chain(records) {
         let record = result?;
+        if !subsampler.keep() {
+            continue;
+        }
         let row: HashMap<&str, &str> = headers.iter().zip(record.iter()).collect();
 
         // Extract fields based on read type
-        let (length_field, quality_field) = match read_type {
-            "1D" => ("sequence_length_template", "mean_qscore_template"),
-            "2D" | "1D2" => ("sequence_length_2d", "mean_qscore_2d"),
-            _ => {
-                return Err(NanogetError::InvalidInput(format!(
-                    "Unsupported read type: {}",
-                    read_type
-                )))
-            }
+        let (length_field, quality_field) = match args.read_type {
+            ReadType::OneD => ("sequence_length_template", "mean_qscore_template"),
+            ReadType::TwoD | ReadType::OneDSquared => ("sequence_length_2d", "mean_qscore_2d"),
         };
 
-        let length: u32 = row
+        let length: u64 = row
             .get(length_field)
             .ok_or_else(|| NanogetError::ParseError(format!("Missing column: {}", length_field)))?
             .parse()
             .map_err(|e| NanogetError::ParseError(format!("Invalid length: {}", e)))?;
 
-        let quality: f64 = row
+        let raw_quality: f64 = row
             .get(quality_field)
             .ok_or_else(|| NanogetError::ParseError(format!("Missing column: {}", quality_field)))?
             .parse()
             .map_err(|e| NanogetError::ParseError(format!("Invalid quality: {}", e)))?;
-
-        let channel_id: Option<u16> = row.get("channel").and_then(|s| s.parse().ok());
+        if raw_quality < f64::from(args.quality_offset) {
+            return Err(NanogetError::InvalidInput(format!(
+                "Quality value {} is below the quality offset {}",
+                raw_quality, args.quality_offset
+            )));
+        }
+        let quality = raw_quality - f64::from(args.quality_offset);
+
+        let channel_id: Option<u32> = row.get("channel").and_then(|s| s.parse().ok());
+        let mux: Option<u8> = row
+            .get("mux")
+            .or_else(|| row.get("start_mux"))
+            .and_then(|s| s.parse().ok());
+        let end_reason = row
+            .get("end_reason")
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string());
 
         let start_time = row
             .get("start_time")
@@ -453,8 +2506,8 @@ fn process_summary_from_reader<R: Read>(
 
         let duration: Option<f64> = row.get("duration").and_then(|s| s.parse().ok());
 
-        let barcode = if barcoded {
-            row.get("barcode_arrangement").map(|s| s.to_string())
+        let barcode = if args.barcoded {
+            row.get("barcode_arrangement").map(|s| interner.intern(s))
         } else {
             None
         };
@@ -463,12 +2516,91 @@ fn process_summary_from_reader<R: Read>(
             .with_quality(quality)
             .with_sequencing_metadata(channel_id, start_time, duration);
 
-        read_metrics.barcode = barcode;
+        read_metrics.extended_mut().barcode = barcode;
+        read_metrics.extended_mut().mux = mux;
+        read_metrics.extended_mut().end_reason = end_reason;
+        if batch_datasets {
+            read_metrics.extended_mut().dataset =
+                summary_batch(&row).map(|name| interner.intern(name));
+        }
+
+        sink(read_metrics);
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Whether the reads of a sequencing summary take their FASTQ batch as their
+/// dataset (see `summary_batch`): in track mode with a single input and no
+/// `--names`. Otherwise each input file is a dataset, as for other inputs.
+fn summary_batch_datasets(args: &ExtractArgs) -> bool {
+    args.combine == CombineMethod::Track && args.files.len() == 1 && args.names.is_none()
+}
+
+/// The FASTQ batch a summary row was basecalled into: its `filename_fastq`
+/// column, or the `filename` column of older summaries. For a single summary
+/// in track mode this is the dataset of the read, so that a summary spanning
+/// several batches is broken down per batch.
+fn summary_batch<'a>(row: &HashMap<&str, &'a str>) -> Option<&'a str> {
+    row.get("filename_fastq")
+        .or_else(|| row.get("filename"))
+        .copied()
+        .filter(|name| !name.is_empty())
+}
+
+/// Extract metrics from a single "-" input
+fn extract_metrics_stdin(args: &ExtractArgs) -> Result<MetricsCollection, NanogetError> {
+    validate_args(args)?;
+    let options = summary_options(args)?;
+    let resources = args.resource_report.then(ResourceRecorder::start);
+    let mut reads = Vec::new();
+    let mut excluded = ExcludedReads::default();
+    let count = process_stdin(args, &mut excluded, &mut |read| reads.push(read))?;
+    let extracted = resources.as_ref().map(|resources| {
+        let extracted = resources.elapsed();
+        resources.record_file(Path::new("-"), count, extracted);
+        extracted
+    });
+    let mut collection = MetricsCollection::with_options(reads, &options);
+    collection.set_excluded_reads(ReadFilter::new(args).report(excluded));
+    collection.set_per_file_summaries([("-".to_string(), collection.summary.clone())].into());
+    check_missing_quality(args, &collection.summary)?;
+    let reads = collection.summary.read_count as u64;
+    let mut collection = finish_collection(collection, args);
+    if let (Some(resources), Some(extracted)) = (resources, extracted) {
+        add_resource_report(&mut collection, &resources, reads, extracted);
+    }
+    Ok(collection)
+}
 
-        metrics.push(read_metrics);
+/// With `--missing-quality error`, fail when some of the summarized reads
+/// have no quality
+fn check_missing_quality(args: &ExtractArgs, summary: &MetricsSummary) -> Result<(), NanogetError> {
+    if args.missing_quality != MissingQuality::Error {
+        return Ok(());
+    }
+    let with_quality = summary.quality_stats.as_ref().map_or(0, |s| s.count);
+    if with_quality < summary.read_count {
+        return Err(NanogetError::InvalidInput(format!(
+            "{} of {} reads have no quality (--missing-quality error)",
+            summary.read_count - with_quality,
+            summary.read_count
+        )));
     }
+    Ok(())
+}
 
-    Ok(metrics)
+/// Record provenance and apply `--downsample` to an extracted collection
+fn finish_collection(mut collection: MetricsCollection, args: &ExtractArgs) -> MetricsCollection {
+    collection.provenance = Some(Provenance::default());
+    match args.downsample {
+        Some(n) => {
+            info!("Downsampling to {} reads with seed {}", n, args.seed);
+            collection.sample(n, args.seed)
+        }
+        None => collection,
+    }
 }
 
 /// Read from stdin: peek with fill_buf() to detect format, then route to the appropriate parser.
@@ -478,7 +2610,27 @@ fn process_summary_from_reader<R: Read>(
 ///
 /// For binary formats (BAM/CRAM): htslib reads from OS fd 0 directly, bypassing the BufReader.
 /// We reconstruct stdin at the OS level by prepending the peeked bytes via a pipe + background thread.
-fn extract_metrics_stdin(args: &ExtractArgs) -> Result<MetricsCollection, NanogetError> {
+fn process_stdin(
+    args: &ExtractArgs,
+    excluded: &mut ExcludedReads,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
+    let extraction = Extraction::new(args);
+    let count = with_throughput(&extraction.throughput, sink, |sink| {
+        with_read_filter(args, sink, excluded, |sink| {
+            process_stdin_records(args, &extraction.interner, &extraction.warnings, sink)
+        })
+    })?;
+    extraction.warnings.check(args.strict)?;
+    Ok(count)
+}
+
+fn process_stdin_records(
+    args: &ExtractArgs,
+    interner: &StringInterner,
+    warnings: &Warnings,
+    sink: &mut ReadSink<'_>,
+) -> Result<usize, NanogetError> {
     use std::io::BufRead;
 
     let mut stdin_reader = std::io::BufReader::new(std::io::stdin());
@@ -493,7 +2645,7 @@ fn extract_metrics_stdin(args: &ExtractArgs) -> Result<MetricsCollection, Nanoge
 
     info!("Detected stdin format: {:?}", file_type);
 
-    let reads = match &file_type {
+    match &file_type {
         FileType::Bam | FileType::Cram | FileType::Ubam => {
             // htslib reads from OS fd 0 directly, bypassing the BufReader.
             // Extract the peeked bytes and reconstruct fd 0 via a pipe so htslib
@@ -502,14 +2654,14 @@ fn extract_metrics_stdin(args: &ExtractArgs) -> Result<MetricsCollection, Nanoge
             drop(stdin_reader);
             reconstruct_stdin_prefix(sniffed)?;
             match file_type {
-                FileType::Ubam => process_ubam(Path::new("-"))?,
-                _ => process_bam(Path::new("-"), args.keep_supplementary, args.threads)?,
+                FileType::Ubam => process_ubam(Path::new("-"), args, warnings, sink),
+                _ => process_bam(Path::new("-"), args, warnings, sink),
             }
         }
         _ => {
             // Text formats (FASTQ, FASTA, summary TSV) — may be gzip-compressed.
             // The BufReader still has all peeked bytes at position 0, so we can wrap
-            // it in a GzDecoder if the stream is gzip-encoded.
+            // it in a MultiGzDecoder if the stream is gzip-encoded.
             let is_plain_gzip = {
                 let buf = stdin_reader.buffer();
                 buf.len() >= 2
@@ -520,28 +2672,26 @@ fn extract_metrics_stdin(args: &ExtractArgs) -> Result<MetricsCollection, Nanoge
                         && buf[12..16] == [0x42, 0x43, 0x02, 0x00])
             };
             let reader: Box<dyn Read> = if is_plain_gzip {
-                Box::new(flate2::bufread::GzDecoder::new(stdin_reader))
+                Box::new(flate2::bufread::MultiGzDecoder::new(stdin_reader))
             } else {
                 Box::new(stdin_reader)
             };
             match file_type {
-                FileType::Fastq => process_fastq_from_reader(reader, false)?,
-                FileType::FastqRich => process_fastq_from_reader(reader, true)?,
-                FileType::Fasta => process_fasta_from_reader(reader)?,
-                FileType::Summary => {
-                    process_summary_from_reader(reader, &args.read_type, args.barcoded)?
+                FileType::Fastq => process_fastq_from_reader(reader, false, args, interner, sink),
+                FileType::FastqRich => {
+                    process_fastq_from_reader(reader, true, args, interner, sink)
                 }
-                other => {
-                    return Err(NanogetError::ParseError(format!(
-                        "Format {:?} is not supported for stdin input",
-                        other
-                    )))
+                FileType::Fasta => process_fasta_from_reader(reader, args, sink),
+                FileType::Summary => {
+                    process_summary_from_reader(reader, args, None, interner, sink)
                 }
+                other => Err(NanogetError::UnsupportedFormat(format!(
+                    "Format {:?} is not supported for stdin input",
+                    other
+                ))),
             }
         }
-    };
-
-    Ok(MetricsCollection::new(reads))
+    }
 }
 
 /// Prepend `prefix` bytes to stdin by replacing fd 0 with a pipe whose write end is fed by a
@@ -606,11 +2756,11 @@ fn reconstruct_stdin_prefix(_prefix: Vec<u8>) -> Result<(), NanogetError> {
 
 /// Metadata extracted from rich FASTQ descriptions
 #[derive(Debug)]
-struct RichFastqMetadata {
-    channel_id: Option<u16>,
+struct RichFastqMetadata<'a> {
+    channel_id: Option<u32>,
     start_time: Option<chrono::DateTime<chrono::Utc>>,
     duration: Option<f64>,
-    run_id: Option<String>,
+    run_id: Option<&'a str>,
 }
 
 /// Parse a read start time, accepting either an RFC3339 timestamp string
@@ -629,7 +2779,7 @@ fn parse_start_time(value: &str) -> Option<DateTime<Utc>> {
 /// "tag:type:value" format introduced in MinKNOW 26.01 (e.g. "ch:i:123"), which also
 /// renames fields: start_time -> "st", runid -> "RG" (with the runid as the first
 /// underscore-separated component of the read group).
-fn parse_rich_fastq_metadata(desc: &str) -> Option<RichFastqMetadata> {
+fn parse_rich_fastq_metadata(desc: &str) -> Option<RichFastqMetadata<'_>> {
     let mut metadata = RichFastqMetadata {
         channel_id: None,
         start_time: None,
@@ -651,7 +2801,7 @@ fn parse_rich_fastq_metadata(desc: &str) -> Option<RichFastqMetadata> {
                     metadata.duration = value.parse().ok();
                 }
                 "runid" => {
-                    metadata.run_id = Some(value.to_string());
+                    metadata.run_id = Some(value);
                 }
                 _ => {} // Ignore unknown keys
             }
@@ -673,7 +2823,7 @@ fn parse_rich_fastq_metadata(desc: &str) -> Option<RichFastqMetadata> {
                     "RG" => {
                         // RG holds "<runid>_<model>@<version>_<barcode>"
                         let runid = value.split('_').next().unwrap_or(value);
-                        metadata.run_id = Some(runid.to_string());
+                        metadata.run_id = Some(runid);
                     }
                     _ => {} // Ignore unknown tags
                 }
@@ -697,6 +2847,199 @@ fn parse_rich_fastq_metadata(desc: &str) -> Option<RichFastqMetadata> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_dataset_names() {
+        let files = [
+            Path::new("run1/reads.fastq.gz"),
+            Path::new("run2/reads.fq"),
+            Path::new("sequencing_summary.txt"),
+            Path::new("calls.BAM"),
+        ];
+        assert_eq!(
+            dataset_names(&files),
+            [
+                "run1/reads.fastq.gz",
+                "run2/reads.fq",
+                "sequencing_summary",
+                "calls"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thread_allocation() {
+        // A single file gets every thread, the surplus for decompression
+        assert_eq!(thread_allocation(8, 1, 0), (1, 7));
+        assert_eq!(thread_allocation(8, 3, 0), (3, 1));
+        assert_eq!(thread_allocation(8, 4, 0), (4, 1));
+        // More files than threads: one worker per thread, no decompression
+        assert_eq!(thread_allocation(4, 10, 0), (4, 0));
+        assert_eq!(thread_allocation(1, 1, 0), (1, 0));
+        // No files still counts one worker
+        assert_eq!(thread_allocation(2, 0, 0), (1, 1));
+        // 0 uses every available core
+        let cores = utils::resolve_threads(0);
+        assert!(cores >= 1);
+        assert_eq!(thread_allocation(0, 1, 0), (1, cores - 1));
+        assert_eq!(thread_allocation(0, cores + 1, 0), (cores, 0));
+        // The BAM workers of a file come out of its decompression threads
+        assert_eq!(thread_allocation(8, 1, 4), (1, 3));
+        assert_eq!(thread_allocation(8, 3, 4), (3, 0));
+        assert_eq!(thread_allocation(8, 8, 4), (8, 0));
+    }
+
+    #[test]
+    fn test_map_files_threads() {
+        use crate::cli::{Cli, Commands};
+        use clap::Parser;
+        let args = |threads: &str| {
+            let cli = Cli::try_parse_from(["nanoget", "extract", "-j", threads, "reads.fastq"]);
+            match cli.unwrap().command {
+                Commands::Extract(args) => args,
+                _ => unreachable!(),
+            }
+        };
+        let files = [Path::new("a"), Path::new("b"), Path::new("c")];
+        let visit = |file: &Path| {
+            Ok((
+                file.to_path_buf(),
+                std::thread::current().id(),
+                rayon::current_thread_index(),
+            ))
+        };
+
+        // One thread: in order on the calling thread, outside any rayon pool
+        let caller = std::thread::current().id();
+        let visits = map_files(&files, &args("1"), visit).unwrap();
+        assert!(visits
+            .iter()
+            .all(|(_, thread, index)| *thread == caller && index.is_none()));
+
+        // More: on the workers of the pool it runs in, results in input order
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let visits = pool
+            .install(|| map_files(&files, &args("2"), visit))
+            .unwrap();
+        let order: Vec<_> = visits.iter().map(|(file, _, _)| file.clone()).collect();
+        assert_eq!(order, files);
+        assert!(visits.iter().all(|(_, _, index)| index.is_some()));
+    }
+
+    #[test]
+    fn test_throughput_count() {
+        use crate::cli::{Cli, Commands};
+        use clap::Parser;
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<std::path::PathBuf> = (0..3)
+            .map(|i| {
+                let path = dir.path().join(format!("reads{}.fastq", i));
+                let records: String = (0..1500)
+                    .map(|j| format!("@read{}_{}\nACGT\n+\nIIII\n", i, j))
+                    .collect();
+                std::fs::write(&path, records).unwrap();
+                path
+            })
+            .collect();
+        let cli = Cli::try_parse_from(
+            [
+                "nanoget",
+                "extract",
+                "-t",
+                "fastq",
+                "-j",
+                "3",
+                "--log-interval",
+                "1",
+            ]
+            .into_iter()
+            .map(std::ffi::OsString::from)
+            .chain(files.iter().map(|f| f.clone().into_os_string())),
+        );
+        let Commands::Extract(args) = cli.unwrap().command else {
+            unreachable!()
+        };
+        let extraction = Extraction::new(&args);
+        let paths: Vec<&Path> = files.iter().map(|f| f.as_path()).collect();
+        let options = SummaryOptions::default();
+        let collections = map_files(&paths, &args, |file| {
+            process_single_file(file, &FileType::Fastq, &args, None, &options, &extraction)
+        })
+        .unwrap();
+        // Every worker's reads are counted, including its last partial batch
+        assert_eq!(
+            collections.iter().map(|c| c.reads.len()).sum::<usize>(),
+            4500
+        );
+        assert_eq!(extraction.throughput.reads(), 4500);
+    }
+
+    #[test]
+    fn test_interned_barcodes() {
+        use crate::cli::{Cli, Commands};
+        use clap::Parser;
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<String> = (0..2)
+            .map(|i| {
+                let path = dir.path().join(format!("summary{}.txt", i));
+                std::fs::write(
+                    &path,
+                    "read_id\tsequence_length_template\tmean_qscore_template\tbarcode_arrangement\n\
+                     r1\t1000\t10.0\tbarcode01\n\
+                     r2\t2000\t10.0\tbarcode02\n\
+                     r3\t3000\t10.0\tbarcode01\n",
+                )
+                .unwrap();
+                path.display().to_string()
+            })
+            .collect();
+        let cli = Cli::try_parse_from(
+            [
+                "nanoget",
+                "extract",
+                "-t",
+                "summary",
+                "--barcoded",
+                "-j",
+                "2",
+            ]
+            .into_iter()
+            .chain(files.iter().map(String::as_str)),
+        );
+        let Commands::Extract(args) = cli.unwrap().command else {
+            unreachable!()
+        };
+        let collection = extract_metrics(&args).unwrap();
+
+        // One copy of each barcode across the reads of both files
+        let barcode01: Vec<&Arc<str>> = collection
+            .reads
+            .iter()
+            .filter_map(|read| read.barcode())
+            .filter(|barcode| &***barcode == "barcode01")
+            .collect();
+        assert_eq!(barcode01.len(), 4);
+        assert!(barcode01.iter().all(|b| Arc::ptr_eq(b, barcode01[0])));
+        let json = serde_json::to_value(&collection.reads[0]).unwrap();
+        assert_eq!(json["barcode"], "barcode01");
+    }
+
+    #[test]
+    fn test_run_with_timeout() {
+        let (_unblock, blocked) = mpsc::channel::<()>();
+        // A job blocked forever (like a read on a hung mount) times out
+        let outcome = run_with_timeout(Duration::from_millis(50), move || {
+            blocked.recv().ok();
+            Ok(())
+        });
+        assert!(outcome.is_none());
+
+        let outcome = run_with_timeout(Duration::from_secs(5), || Ok(42));
+        assert_eq!(outcome.unwrap().unwrap(), 42);
+    }
+
     #[test]
     fn test_rich_fastq_metadata_parsing() {
         let desc = "ch=100 start_time=1234567890.5 duration=2.5 runid=test_run";
@@ -704,7 +3047,16 @@ mod tests {
 
         assert_eq!(metadata.channel_id, Some(100));
         assert_eq!(metadata.duration, Some(2.5));
-        assert_eq!(metadata.run_id, Some("test_run".to_string()));
+        assert_eq!(metadata.run_id, Some("test_run"));
+    }
+
+    #[test]
+    fn test_rich_fastq_metadata_large_channel() {
+        // Channel numbers beyond u16 used to be dropped by the parse
+        let metadata = parse_rich_fastq_metadata("ch=70000 runid=r").unwrap();
+        assert_eq!(metadata.channel_id, Some(70000));
+        let metadata = parse_rich_fastq_metadata("ch:i:70000").unwrap();
+        assert_eq!(metadata.channel_id, Some(70000));
     }
 
     #[test]
@@ -713,7 +3065,7 @@ mod tests {
         let metadata = parse_rich_fastq_metadata(desc).unwrap();
 
         assert_eq!(metadata.channel_id, Some(53));
-        assert_eq!(metadata.run_id, Some("ff83cfa".to_string()));
+        assert_eq!(metadata.run_id, Some("ff83cfa"));
         assert!(metadata.start_time.is_some());
     }
 
@@ -728,7 +3080,7 @@ mod tests {
         assert_eq!(metadata.duration, Some(1.23));
         assert_eq!(
             metadata.run_id,
-            Some("e4994c62-93f9-439a-bc8f-d20c95a137a5".to_string())
+            Some("e4994c62-93f9-439a-bc8f-d20c95a137a5")
         );
         assert!(metadata.start_time.is_some());
     }
@@ -17,6 +17,8 @@ pub enum FileType {
     Bam,
     /// CRAM alignment file
     Cram,
+    /// SAM alignment file (plain text)
+    Sam,
     /// Unaligned BAM file
     Ubam,
     /// Sequencing summary file
@@ -24,6 +26,18 @@ pub enum FileType {
 }
 
 impl FileType {
+    /// Detect the type of an input file: from its extension (ignoring a .gz or
+    /// .bz2 suffix) when it has a known one, otherwise from its content (see
+    /// `sniff`). A .bam file without reference sequences is an unaligned BAM.
+    /// Returns the type and whether it came from the extension.
+    pub fn detect(path: &Path) -> Result<(Self, bool), NanogetError> {
+        match Self::from_extension(path) {
+            Some(Self::Bam) => Ok((sniff_bam_or_ubam(path)?, true)),
+            Some(file_type) => Ok((file_type, true)),
+            None => Ok((Self::sniff(path)?, false)),
+        }
+    }
+
     /// Detect file type by inspecting magic bytes and, when ambiguous, the header content.
     ///
     /// Detection order:
@@ -33,8 +47,6 @@ impl FileType {
     /// 4. `@` first byte → FASTQ
     /// 5. `>` first byte → FASTA
     /// 6. Tab-separated first line with known summary columns → Summary
-    // Public library API (re-exported via `nanoget_rs::FileType`); not yet wired into the binary.
-    #[allow(dead_code)]
     pub fn sniff(path: &Path) -> Result<Self, NanogetError> {
         use std::fs::File;
         use std::io::{Read, Seek, SeekFrom};
@@ -69,7 +81,7 @@ impl FileType {
             }
             // Plain gzip — distinguish FASTQ vs FASTA via extension
             return Self::from_extension(path).ok_or_else(|| {
-                NanogetError::ParseError(format!(
+                NanogetError::UnsupportedFormat(format!(
                     "Cannot determine format for gzipped file: {} \
                      (use a standard extension like .fastq.gz or .fasta.gz)",
                     path.display()
@@ -114,7 +126,7 @@ impl FileType {
             }
         }
 
-        Err(NanogetError::ParseError(format!(
+        Err(NanogetError::UnsupportedFormat(format!(
             "Cannot determine file format for: {}\n\
              Hint: ensure files have a standard extension (.fastq, .bam, .cram, .fasta) \
              or recognisable content",
@@ -132,17 +144,20 @@ impl FileType {
             let inner_ext = Path::new(stem).extension()?.to_str()?.to_lowercase();
             return match inner_ext.as_str() {
                 "fastq" | "fq" => Some(Self::Fastq),
-                "fasta" | "fa" | "fas" => Some(Self::Fasta),
+                "fasta" | "fa" | "fas" | "fna" => Some(Self::Fasta),
                 "bam" => Some(Self::Bam),
+                "sam" => Some(Self::Sam),
+                "txt" | "tsv" if stem.to_str()?.contains("summary") => Some(Self::Summary),
                 _ => None,
             };
         }
 
         match extension.as_str() {
             "fastq" | "fq" => Some(Self::Fastq),
-            "fasta" | "fa" | "fas" => Some(Self::Fasta),
+            "fasta" | "fa" | "fas" | "fna" => Some(Self::Fasta),
             "bam" => Some(Self::Bam),
             "cram" => Some(Self::Cram),
+            "sam" => Some(Self::Sam),
             "txt" | "tsv" => {
                 if path.file_name()?.to_str()?.contains("summary") {
                     Some(Self::Summary)
@@ -154,11 +169,22 @@ impl FileType {
         }
     }
 
-    /// Returns true for aligned formats (BAM/CRAM).
+    /// Whether the extension of `path` (ignoring a .gz or .bz2 suffix) is one
+    /// of this type's, e.g. .fq.gz for the FASTQ variants and .bam for uBAM.
+    pub fn matches_extension(&self, path: &Path) -> bool {
+        let family = |file_type: &Self| match file_type {
+            Self::FastqRich | Self::FastqMinimal => Self::Fastq,
+            Self::Ubam => Self::Bam,
+            other => other.clone(),
+        };
+        Self::from_extension(path).is_some_and(|found| family(&found) == family(self))
+    }
+
+    /// Returns true for aligned formats (BAM/CRAM/SAM).
     // Public library API (re-exported via `nanoget_rs::FileType`); not used by the binary.
     #[allow(dead_code)]
     pub fn is_aligned(&self) -> bool {
-        matches!(self, Self::Bam | Self::Cram)
+        matches!(self, Self::Bam | Self::Cram | Self::Sam)
     }
 
     /// Detect format from the first bytes of a stream (no I/O).
@@ -238,7 +264,7 @@ impl FileType {
                 }
             }
         }
-        Err(NanogetError::ParseError(
+        Err(NanogetError::UnsupportedFormat(
             "Cannot determine stdin format from magic bytes — \
              ensure the stream starts with a recognisable header"
                 .into(),
@@ -340,10 +366,47 @@ mod tests {
             FileType::from_extension(Path::new("test.cram")),
             Some(FileType::Cram)
         );
+        assert_eq!(
+            FileType::from_extension(Path::new("test.sam")),
+            Some(FileType::Sam)
+        );
         assert_eq!(
             FileType::from_extension(Path::new("sequencing_summary.txt")),
             Some(FileType::Summary)
         );
         assert_eq!(FileType::from_extension(Path::new("test.unknown")), None);
     }
+
+    #[test]
+    fn test_from_extension_compressed_and_case() {
+        let cases = [
+            ("reads.fq", Some(FileType::Fastq)),
+            ("reads.fq.gz", Some(FileType::Fastq)),
+            ("reads.FASTQ.GZ", Some(FileType::Fastq)),
+            ("reads.fastq.bz2", Some(FileType::Fastq)),
+            ("contigs.fa.gz", Some(FileType::Fasta)),
+            ("contigs.fna", Some(FileType::Fasta)),
+            ("contigs.fas.bz2", Some(FileType::Fasta)),
+            ("sequencing_summary.txt.gz", Some(FileType::Summary)),
+            ("sequencing_summary_FAX123.tsv", Some(FileType::Summary)),
+            ("notes.txt", None),
+            ("notes.txt.gz", None),
+            ("archive.tar.gz", None),
+            ("reads.gz", None),
+            ("reads", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                FileType::from_extension(Path::new(name)),
+                expected,
+                "{}",
+                name
+            );
+        }
+
+        assert!(FileType::FastqRich.matches_extension(Path::new("reads.fq.gz")));
+        assert!(FileType::Ubam.matches_extension(Path::new("calls.bam")));
+        assert!(!FileType::Fastq.matches_extension(Path::new("contigs.fa")));
+        assert!(!FileType::Cram.matches_extension(Path::new("calls.bam")));
+    }
 }
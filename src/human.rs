@@ -0,0 +1,211 @@
+use crate::error::NanogetError;
+use crate::metrics::{n50, MetricsCollection, MetricsSummary};
+use std::io::Write;
+
+const BOLD_CYAN: &str = "\x1b[1;36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+impl MetricsCollection {
+    /// Write a compact, human-readable summary (e.g. for a terminal), with
+    /// ANSI colors when `color` is set
+    pub fn write_human<W: Write>(&self, w: &mut W, color: bool) -> Result<(), NanogetError> {
+        let mut lengths: Vec<u64> = self.reads.iter().map(|r| r.length).collect();
+        lengths.sort_unstable();
+        let bases: u64 = lengths.iter().sum();
+        let read_n50 = (!lengths.is_empty()).then(|| n50(&lengths));
+        write_summary(w, &self.summary, bases, read_n50, color)
+    }
+}
+
+impl MetricsSummary {
+    /// Write a compact, human-readable summary; without the reads, the total
+    /// is derived from the mean length and the read length N50 is omitted
+    pub fn write_human<W: Write>(&self, w: &mut W, color: bool) -> Result<(), NanogetError> {
+        let bases = (self.length_stats.mean * self.read_count as f64).round() as u64;
+        write_summary(w, self, bases, None, color)
+    }
+}
+
+fn write_summary<W: Write>(
+    w: &mut W,
+    summary: &MetricsSummary,
+    bases: u64,
+    read_n50: Option<u64>,
+    color: bool,
+) -> Result<(), NanogetError> {
+    let mut line = |label: &str, value: String, highlight: bool| -> std::io::Result<()> {
+        if color {
+            let value_style = if highlight { BOLD } else { "" };
+            let value_reset = if highlight { RESET } else { "" };
+            writeln!(
+                w,
+                "{}{:<20}{} {}{}{}",
+                BOLD_CYAN, label, RESET, value_style, value, value_reset
+            )
+        } else {
+            writeln!(w, "{:<20} {}", label, value)
+        }
+    };
+
+    line("Reads", thousands(summary.read_count as u64), false)?;
+    if let Some(excluded) = &summary.excluded_reads {
+        line("Excluded reads", thousands(excluded.total() as u64), false)?;
+    }
+    line("Bases", format_bases(bases), false)?;
+    if let Some(read_n50) = read_n50 {
+        line("Read length N50", thousands(read_n50), true)?;
+    }
+    if summary.read_count > 0 {
+        let lengths = &summary.length_stats;
+        line("Mean read length", format!("{:.1}", lengths.mean), false)?;
+        line(
+            "Median read length",
+            format!("{:.1}", lengths.median),
+            false,
+        )?;
+        let longest = match &summary.longest_read {
+            Some((read_id, length)) => format!("{} ({})", thousands(*length), read_id),
+            None => thousands(lengths.max as u64),
+        };
+        line("Longest read", longest, false)?;
+    }
+    // The bin rather than its center, which can lie far from every read
+    // when the reads are much shorter than the bin width
+    if let Some(mode) = &summary.length_mode_bin {
+        line(
+            "Modal length bin",
+            format!(
+                "{}\u{2013}{} ({} reads)",
+                thousands(mode.start),
+                thousands(mode.end),
+                thousands(mode.read_count as u64)
+            ),
+            false,
+        )?;
+    }
+    if let Some(spike) = &summary.short_read_spike {
+        line(
+            &format!("Reads <= {} bases", thousands(spike.max_length)),
+            format!("{:.1}%", spike.fraction * 100.0),
+            spike.warning.is_some(),
+        )?;
+        if let Some(warning) = &spike.warning {
+            line("Warning", warning.clone(), true)?;
+        }
+    }
+    if let Some(quality) = &summary.quality_stats {
+        line("Mean quality", format!("{:.1}", quality.mean), true)?;
+        line("Median quality", format!("{:.1}", quality.median), false)?;
+    }
+    if let Some(identity) = &summary.percent_identity_stats {
+        line("Median identity", format!("{:.1}%", identity.median), false)?;
+    }
+    if let Some(polya_length) = &summary.polya_length_stats {
+        line(
+            "Median poly(A) length",
+            format!("{:.0}", polya_length.median),
+            false,
+        )?;
+    }
+    if let Some(aligned_n50) = summary.aligned_length_n50 {
+        line("Aligned length N50", thousands(aligned_n50), false)?;
+    }
+    if let Some(rate) = summary.demux_rate {
+        line("Demultiplexed", format!("{:.1}%", rate * 100.0), false)?;
+    }
+    Ok(())
+}
+
+/// `1234567` as `1,234,567`
+fn thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// Base count with a kb/Mb/Gb unit
+fn format_bases(bases: u64) -> String {
+    let units = [(1e9, "Gb"), (1e6, "Mb"), (1e3, "kb")];
+    for (scale, unit) in units {
+        if bases as f64 >= scale {
+            return format!("{:.2} {}", bases as f64 / scale, unit);
+        }
+    }
+    format!("{} b", bases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ReadMetrics;
+
+    #[test]
+    fn test_formatting_helpers() {
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1000), "1,000");
+        assert_eq!(thousands(1234567), "1,234,567");
+        assert_eq!(format_bases(512), "512 b");
+        assert_eq!(format_bases(1_500), "1.50 kb");
+        assert_eq!(format_bases(18_600_000), "18.60 Mb");
+        assert_eq!(format_bases(2_000_000_000), "2.00 Gb");
+    }
+
+    #[test]
+    fn test_write_human() {
+        let collection = MetricsCollection::new(vec![
+            ReadMetrics::new(None, 1000).with_quality(10.0),
+            ReadMetrics::new(None, 3000).with_quality(14.0),
+        ]);
+
+        let mut plain = Vec::new();
+        collection.write_human(&mut plain, false).unwrap();
+        let plain = String::from_utf8(plain).unwrap();
+        assert!(!plain.contains('\x1b'));
+        assert!(plain.contains("Reads                2\n"));
+        assert!(plain.contains("Bases                4.00 kb\n"));
+        assert!(plain.contains("Read length N50      3,000\n"));
+        assert!(plain.contains("Mean quality         12.0\n"));
+        assert!(plain.contains("Modal length bin     1,000\u{2013}1,100 (1 reads)\n"));
+
+        let mut colored = Vec::new();
+        collection.write_human(&mut colored, true).unwrap();
+        let colored = String::from_utf8(colored).unwrap();
+        assert!(colored.contains("\x1b[1;36mRead length N50     \x1b[0m \x1b[1m3,000\x1b[0m"));
+
+        // The summary alone has no N50 but still prints
+        let mut summary_only = Vec::new();
+        collection
+            .summary
+            .write_human(&mut summary_only, false)
+            .unwrap();
+        let summary_only = String::from_utf8(summary_only).unwrap();
+        assert!(summary_only.contains("Bases                4.00 kb\n"));
+        assert!(!summary_only.contains("N50"));
+
+        // Reads far shorter than the bins are reported in their bin, not at
+        // its center
+        let mut short = Vec::new();
+        MetricsCollection::new(vec![ReadMetrics::new(None, 7), ReadMetrics::new(None, 8)])
+            .write_human(&mut short, false)
+            .unwrap();
+        let short = String::from_utf8(short).unwrap();
+        assert!(short.contains("Modal length bin     0\u{2013}100 (2 reads)\n"));
+
+        let mut empty = Vec::new();
+        MetricsCollection::new(Vec::new())
+            .write_human(&mut empty, false)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(empty).unwrap(),
+            "Reads                0\nBases                0 b\n"
+        );
+    }
+}
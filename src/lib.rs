@@ -11,21 +11,14 @@
 //! ## Example
 //!
 //! ```rust,no_run
-//! use nanoget_rs::{extract_metrics, FileType, ExtractArgs};
+//! use nanoget_rs::{extract_metrics, ExtractArgs, FileType};
 //!
 //! # fn main() -> Result<(), Box<dyn std::error::Error>> {
-//! let args = ExtractArgs {
-//!     files: vec!["reads.fastq".into()],
-//!     file_type: FileType::Fastq,
-//!     threads: 4,
-//!     output_format: "json".to_string(),
-//!     output: None,
-//!     read_type: "1D".to_string(),
-//!     barcoded: false,
-//!     keep_supplementary: true,
-//!     combine: "simple".to_string(),
-//!     names: None,
-//! };
+//! let args = ExtractArgs::builder()
+//!     .files(["reads.fastq"])
+//!     .file_type(FileType::Fastq)
+//!     .threads(4)
+//!     .build();
 //!
 //! let metrics = extract_metrics(&args)?;
 //! # Ok(())
@@ -33,43 +26,55 @@
 //! ```
 
 pub mod cli;
+pub mod compare;
+pub mod config;
 pub mod error;
 pub mod extract;
 pub mod formats;
+pub mod human;
 pub mod metrics;
+pub mod mmap;
+pub mod progress;
+pub mod resources;
+pub mod spill;
+pub mod sqlite;
 pub mod utils;
+pub mod validate;
+pub mod warnings;
 
-pub use cli::{Cli, Commands, ExtractArgs};
+pub use cli::{
+    Cli, Commands, CompareArgs, CompletionsArgs, ExtractArgs, ExtractArgsBuilder, ManArgs,
+    MergeArgs, SchemaArgs, StatsArgs, ValidateArgs,
+};
+pub use compare::{ComparisonReport, DistributionComparison, MetricDelta};
 pub use error::NanogetError;
-pub use extract::extract_metrics;
+pub use extract::{
+    extract_metrics, extract_metrics_chunked, extract_metrics_in_pool, extract_metrics_spilling,
+    extract_metrics_streaming, extract_summary_streaming, merge_exports, split_by_barcode,
+    write_reads, ReadSink, DEFAULT_CHUNK_SIZE,
+};
 pub use formats::FileType;
-pub use metrics::{MetricsCollection, MetricsSummary, ReadMetrics, StatsSummary};
+pub use metrics::{
+    CombineMethod, ExcludedReads, ExtendedMetrics, GroupSummary, LengthBin, MetricsCollection,
+    MetricsSummary, MissingQuality, OnlineStats, OnlineSummary, Provenance, QualityMethod,
+    ReadClass, ReadMetrics, ReadType, ReservoirSampler, SamplingInfo, ShortReadSpike, SortKey,
+    SortOrder, StatsSummary, SummaryOptions, ThroughputBin,
+};
+pub use resources::{FileTime, ResourceRecorder, ResourceReport};
+pub use spill::{SpilledReads, SpillingCollector};
+pub use validate::{validate_inputs, FileReport};
 
 /// Convenience functions for common use cases
 pub mod convenience {
     use super::*;
     use std::path::Path;
 
-    // Default values as constants to avoid repeated allocations
-    const DEFAULT_OUTPUT_FORMAT: &str = "json";
-    const DEFAULT_READ_TYPE: &str = "1D";
-    const DEFAULT_COMBINE: &str = "simple";
-    const DEFAULT_THREADS: usize = 4;
-
     /// Create default ExtractArgs with the given files and file type
     fn default_args(files: Vec<std::path::PathBuf>, file_type: FileType) -> ExtractArgs {
-        ExtractArgs {
-            files,
-            file_type,
-            threads: DEFAULT_THREADS,
-            output_format: DEFAULT_OUTPUT_FORMAT.to_string(),
-            output: None,
-            read_type: DEFAULT_READ_TYPE.to_string(),
-            barcoded: false,
-            keep_supplementary: true,
-            combine: DEFAULT_COMBINE.to_string(),
-            names: None,
-        }
+        ExtractArgs::builder()
+            .files(files)
+            .file_type(file_type)
+            .build()
     }
 
     /// Extract metrics from a single FASTQ file with default settings
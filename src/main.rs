@@ -1,43 +1,574 @@
 use clap::Parser;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, IsTerminal, Write};
 
 mod cli;
+mod compare;
+mod config;
 mod error;
 mod extract;
 mod formats;
+mod human;
 mod metrics;
+mod mmap;
+mod progress;
+mod resources;
+mod spill;
+mod sqlite;
 mod utils;
+mod validate;
+mod warnings;
 
-use crate::cli::{Cli, Commands};
-use crate::error::NanogetError;
+use crate::cli::{Cli, Commands, ExtractArgs};
+use crate::error::{exit_code, NanogetError};
+use crate::metrics::MetricsSummary;
 
-fn main() -> Result<(), NanogetError> {
-    env_logger::init();
+/// JSON output with the per-file summaries added next to the fields of `inner`
+#[derive(Serialize)]
+struct WithPerFile<'a, T: Serialize> {
+    #[serde(flatten)]
+    inner: &'a T,
+    per_file: &'a BTreeMap<String, MetricsSummary>,
+}
 
-    let cli = Cli::parse();
+fn write_json<W: Write, T: Serialize>(
+    writer: &mut W,
+    value: &T,
+    per_file: Option<&BTreeMap<String, MetricsSummary>>,
+) -> Result<(), NanogetError> {
+    match per_file {
+        Some(per_file) => serde_json::to_writer_pretty(
+            &mut *writer,
+            &WithPerFile {
+                inner: value,
+                per_file,
+            },
+        )?,
+        None => serde_json::to_writer_pretty(&mut *writer, value)?,
+    }
+    writeln!(writer)?;
+    Ok(())
+}
 
-    match cli.command {
-        Commands::Extract(args) => {
-            let pool = rayon::ThreadPoolBuilder::new()
-                .num_threads(args.threads)
-                .build()
-                .map_err(|e| NanogetError::ProcessingError(e.to_string()))?;
-
-            let metrics = pool.install(|| extract::extract_metrics(&args))?;
-
-            // Generate output based on format
-            let output = match args.output_format.as_str() {
-                "json" => serde_json::to_string_pretty(&metrics)?,
-                "tsv" => metrics.to_tsv()?,
-                _ => format!("{:#?}", metrics),
+/// Where the output goes: stdout, or a file that is only moved into place
+/// when complete (see `utils::AtomicFile`)
+enum Output {
+    Stdout(BufWriter<std::io::StdoutLock<'static>>),
+    File(utils::AtomicFile),
+}
+
+impl Output {
+    /// `path`, or stdout when omitted; an existing file is an error unless `force`
+    fn open(path: Option<&std::path::Path>, force: bool) -> Result<Self, NanogetError> {
+        Ok(match path {
+            Some(path) => Output::File(utils::AtomicFile::create(path, force)?),
+            None => Output::Stdout(BufWriter::new(std::io::stdout().lock())),
+        })
+    }
+
+    /// Flush the output, moving a file into place
+    fn finish(self) -> Result<(), NanogetError> {
+        match self {
+            Output::Stdout(mut writer) => writer.flush()?,
+            Output::File(file) => file.commit()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Stdout(writer) => writer.write(buf),
+            Output::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Stdout(writer) => writer.flush(),
+            Output::File(file) => file.flush(),
+        }
+    }
+}
+
+/// The output format asked for: `--output-format`, or else the one matching
+/// the extension of `--output`. An explicit format wins over the extension,
+/// with a warning when they disagree
+fn requested_output_format(args: &ExtractArgs) -> Option<&str> {
+    // With --split-by, --output is a directory
+    if args.split_by.is_some() {
+        return args.output_format.as_deref();
+    }
+    let inferred = args
+        .output
+        .as_deref()
+        .and_then(|path| Some((path, cli::format_from_extension(path)?)));
+    let explicit = args.output_format.as_deref();
+    match (explicit, inferred) {
+        (Some(explicit), Some((path, inferred))) => {
+            let family = match explicit {
+                "text" => "human",
+                "json-summary" => "json",
+                other => other,
             };
+            if family != inferred && explicit != "auto" {
+                log::warn!(
+                    "--output-format {} does not match the extension of {} ({}); writing {}",
+                    explicit,
+                    path.display(),
+                    inferred,
+                    explicit
+                );
+            }
+            Some(explicit)
+        }
+        (None, Some((_, inferred))) => {
+            log::debug!(
+                "Output format {} inferred from the --output extension",
+                inferred
+            );
+            Some(inferred)
+        }
+        (None, None) => {
+            // e.g. .csv: better a warning than JSON silently written under that name
+            if let Some(path) = args.output.as_deref().filter(|p| p.extension().is_some()) {
+                log::warn!(
+                    "No output format matches the extension of {}; pass --output-format to choose one",
+                    path.display()
+                );
+            }
+            None
+        }
+        (explicit, None) => explicit,
+    }
+}
+
+/// `run` on `pool`, or on this thread without one
+fn in_pool<T: Send>(pool: Option<&rayon::ThreadPool>, run: impl FnOnce() -> T + Send) -> T {
+    match pool {
+        Some(pool) => pool.install(run),
+        None => run(),
+    }
+}
+
+/// The first option set in `args` that the single pass of `stats` cannot
+/// honour (those that conflict with `--streaming` on `extract`), which then
+/// summarizes exactly instead of dropping it
+fn exact_only_option(args: &ExtractArgs) -> Option<&'static str> {
+    [
+        (args.write_reads.is_some(), "--write-reads"),
+        (args.resource_report, "--resource-report"),
+        (args.split_by.is_some(), "--split-by"),
+        (args.per_file, "--per-file"),
+        (args.max_memory.is_some(), "--max-memory"),
+        (args.cumulative_yield, "--cumulative-yield"),
+        (args.length_quality_density, "--length-quality-density"),
+        (args.run_start.is_some(), "--run-start"),
+    ]
+    .into_iter()
+    .find_map(|(set, option)| set.then_some(option))
+}
+
+/// Run `extract` with `args`; with `summary_only`, the output holds only the
+/// summary, as for `stats`, which is human-readable by default
+fn run_extract(args: &ExtractArgs, summary_only: bool) -> Result<(), NanogetError> {
+    // One thread runs everything on this thread, without rayon
+    let threads = utils::resolve_threads(args.threads);
+    let pool = (threads > 1)
+        .then(|| rayon::ThreadPoolBuilder::new().num_threads(threads).build())
+        .transpose()
+        .map_err(|e| NanogetError::ProcessingError(e.to_string()))?;
+
+    // Refuse to overwrite results before spending time on the extraction
+    let output_file = args.output.as_ref().filter(|_| args.split_by.is_none());
+    for path in output_file.iter().copied().chain(&args.write_reads) {
+        utils::check_output_path(path, args.force)?;
+    }
+    // Write to file or stdout, streaming rather than building the output in memory
+    let open_writer = || Output::open(args.output.as_deref(), args.force);
+
+    // Human-readable output by default on a terminal, machine formats for pipes
+    let to_terminal = args.output.is_none() && std::io::stdout().is_terminal();
+    let color = to_terminal && std::env::var_os("NO_COLOR").is_none();
+    let requested = match (
+        requested_output_format(args).unwrap_or("auto"),
+        summary_only,
+    ) {
+        ("auto", _) if args.split_by.is_some() => "tsv",
+        ("auto", true) => "human",
+        ("json", true) => "json-summary",
+        (format, _) => format,
+    };
+    let output_format = match requested {
+        "auto" if to_terminal => "human",
+        "auto" => "json",
+        "text" => "human",
+        format @ ("json" | "json-summary" | "tsv" | "human") => format,
+        "sqlite" if args.output.is_none() => {
+            return Err(NanogetError::InvalidInput(
+                "--output-format sqlite needs --output".to_string(),
+            ))
+        }
+        "sqlite" => "sqlite",
+        other => {
+            return Err(NanogetError::InvalidInput(format!(
+                "Unknown output format '{}' (expected one of: auto, json, json-summary, tsv, human, text, sqlite)",
+                other
+            )))
+        }
+    };
+    let sqlite_path = || args.output.as_deref().expect("sqlite output has a path");
+
+    if args.count_only {
+        let (reads, bases) = in_pool(pool.as_ref(), || extract::count_reads(args))?;
+        let mut writer = open_writer()?;
+        writeln!(writer, "{}\t{}", reads, bases)?;
+        writer.finish()?;
+        return Ok(());
+    }
+
+    if args.streaming {
+        if args.barcoded {
+            log::warn!("--streaming reports the barcode distribution but no per-barcode summaries");
+        }
+        let summary = in_pool(pool.as_ref(), || extract::extract_summary_streaming(args))?;
+        if output_format == "sqlite" {
+            return write_sqlite(sqlite_path(), args.force, |path| summary.write_sqlite(path));
+        }
+        let mut writer = open_writer()?;
+        match output_format {
+            "tsv" => summary.write_tsv(&mut writer, args.precision)?,
+            "human" => summary.write_human(&mut writer, color)?,
+            // Without per-read metrics, JSON output is the summary alone
+            "json" | "json-summary" => {
+                serde_json::to_writer_pretty(&mut writer, &summary)?;
+                writeln!(writer)?;
+            }
+            other => unreachable!("unresolved output format '{}'", other),
+        }
+        writer.finish()?;
+        return Ok(());
+    }
+
+    if let Some(max_memory) = args.max_memory {
+        let spilled = in_pool(pool.as_ref(), || {
+            extract::extract_metrics_spilling(args, max_memory)
+        })?;
+        if output_format == "sqlite" {
+            return write_sqlite(sqlite_path(), args.force, |path| {
+                if summary_only {
+                    spilled.summary.write_sqlite(path)
+                } else {
+                    spilled.write_sqlite(path)
+                }
+            });
+        }
+        let mut writer = open_writer()?;
+        match output_format {
+            "human" => spilled.summary.write_human(&mut writer, color)?,
+            "json-summary" => write_json(&mut writer, &spilled.summary, None)?,
+            "tsv" if summary_only => spilled.summary.write_tsv(&mut writer, args.precision)?,
+            "tsv" => spilled.write_tsv(&mut writer, args.precision)?,
+            _ => spilled.write_json(&mut writer)?,
+        }
+        writer.finish()?;
+        return Ok(());
+    }
 
-            // Write to file or stdout
-            if let Some(output_path) = &args.output {
-                std::fs::write(output_path, output)?;
+    let sort = args
+        .sort
+        .as_deref()
+        .map(str::parse::<metrics::SortOrder>)
+        .transpose()?;
+    let metrics = in_pool(pool.as_ref(), || extract::extract_metrics(args))?;
+    let serialize_started = std::time::Instant::now();
+    if let Some(path) = &args.write_reads {
+        extract::write_reads(args, &metrics, path)?;
+    }
+    let output = OutputOptions {
+        format: output_format,
+        summary_only,
+        color,
+        sort,
+    };
+    match (&args.split_by, &args.output) {
+        (Some(_), Some(dir)) => write_split(args, &metrics, dir, &output)?,
+        _ => write_metrics(args, &metrics, args.output.as_deref(), &output)?,
+    }
+    // The report in the output cannot include the time spent writing it
+    if let Some(mut report) = metrics.provenance.and_then(|p| p.resources) {
+        report.add_serialize(serialize_started.elapsed());
+        report.write_block(&mut std::io::stderr().lock())?;
+    }
+    Ok(())
+}
+
+/// How `write_metrics` writes a collection
+struct OutputOptions<'a> {
+    format: &'a str,
+    summary_only: bool,
+    color: bool,
+    sort: Option<metrics::SortOrder>,
+}
+
+/// Write an SQLite database to `path` with `write`. SQLite writes it into a
+/// temporary file, which is then moved into place like any other output; an
+/// existing file is an error unless `force`
+fn write_sqlite(
+    path: &std::path::Path,
+    force: bool,
+    write: impl FnOnce(&std::path::Path) -> Result<(), NanogetError>,
+) -> Result<(), NanogetError> {
+    let file = utils::AtomicFile::create(path, force)?;
+    write(file.temp_path())?;
+    file.commit()
+}
+
+/// Write `metrics` to `path` (stdout when `None`) as asked by `output`
+fn write_metrics(
+    args: &ExtractArgs,
+    metrics: &metrics::MetricsCollection,
+    path: Option<&std::path::Path>,
+    output: &OutputOptions<'_>,
+) -> Result<(), NanogetError> {
+    if output.format == "sqlite" {
+        let path = path.expect("sqlite output has a path");
+        return write_sqlite(path, args.force, |path| {
+            if output.summary_only {
+                metrics.summary.write_sqlite(path)
             } else {
-                println!("{}", output);
+                metrics.write_sqlite(path)
+            }
+        });
+    }
+    let mut writer = Output::open(path, args.force)?;
+
+    // Generate output based on format
+    let per_file = args.per_file.then(|| metrics.per_file_summaries());
+    match output.format {
+        "human" => metrics.write_human(&mut writer, output.color)?,
+        "json-summary" => write_json(&mut writer, &metrics.summary, per_file)?,
+        "tsv" if output.summary_only => metrics.summary.write_tsv(&mut writer, args.precision)?,
+        "tsv" => {
+            metrics.write_tsv_sorted(&mut writer, args.precision, output.sort)?;
+            if args.per_file {
+                metrics.write_per_file_tsv(&mut writer, args.precision)?;
             }
         }
+        _ => write_json(&mut writer, &metrics, per_file)?,
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// `--split-by barcode`: one output per barcode in `dir`, named after the
+/// barcode (see `utils::sanitize_file_name`), and demux_summary.<ext> with the
+/// summary of all reads, which has the demultiplexing rate and the
+/// per-barcode numbers
+fn write_split(
+    args: &ExtractArgs,
+    metrics: &metrics::MetricsCollection,
+    dir: &std::path::Path,
+    output: &OutputOptions<'_>,
+) -> Result<(), NanogetError> {
+    let groups = extract::split_by_barcode(args, metrics)?;
+    let extension = match output.format {
+        "tsv" => "tsv",
+        "human" => "txt",
+        "sqlite" => "sqlite",
+        _ => "json",
+    };
+    let summary_path = dir.join(format!("demux_summary.{}", extension));
+    let mut paths: BTreeMap<std::path::PathBuf, &str> = BTreeMap::new();
+    let mut outputs = Vec::with_capacity(groups.len());
+    for (barcode, group) in &groups {
+        let path = dir.join(format!(
+            "{}.{}",
+            utils::sanitize_file_name(barcode),
+            extension
+        ));
+        if path == summary_path {
+            return Err(NanogetError::InvalidInput(format!(
+                "Barcode '{}' would overwrite the demultiplexing summary {}",
+                barcode,
+                path.display()
+            )));
+        }
+        if let Some(other) = paths.insert(path.clone(), barcode) {
+            return Err(NanogetError::InvalidInput(format!(
+                "Barcodes '{}' and '{}' would both be written to {}",
+                other,
+                barcode,
+                path.display()
+            )));
+        }
+        outputs.push((group, path));
+    }
+    // Check every file before writing any of them
+    std::fs::create_dir_all(dir)?;
+    for path in paths.keys().chain(std::iter::once(&summary_path)) {
+        utils::check_output_path(path, args.force)?;
+    }
+
+    let file_output = OutputOptions {
+        color: false,
+        ..*output
+    };
+    for (group, path) in &outputs {
+        write_metrics(args, group, Some(path), &file_output)?;
+    }
+    let summary_output = OutputOptions {
+        format: if output.format == "json" {
+            "json-summary"
+        } else {
+            output.format
+        },
+        summary_only: true,
+        ..file_output
+    };
+    write_metrics(args, metrics, Some(&summary_path), &summary_output)
+}
+
+/// Exit with the code of the error category (see `error::exit_code`); usage
+/// errors exit with `INVALID_INPUT` rather than clap's 2, which means a missing
+/// input file here. Options not given are filled in from the environment and
+/// the config file first (see `config::with_defaults`).
+fn main() {
+    let args = match config::with_defaults(std::env::args_os().collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+    let cli = match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        Err(e) if e.use_stderr() => {
+            let _ = e.print();
+            std::process::exit(exit_code::INVALID_INPUT);
+        }
+        Err(e) => e.exit(),
+    };
+    if let Err(e) = run(cli) {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn run(cli: Cli) -> Result<(), NanogetError> {
+    let extract_args = match &cli.command {
+        Commands::Extract(args) => Some(args),
+        Commands::Stats(stats) => Some(&stats.extract),
+        Commands::Compare(_)
+        | Commands::Merge(_)
+        | Commands::Validate(_)
+        | Commands::Schema(_)
+        | Commands::Completions(_)
+        | Commands::Man(_) => None,
+    };
+    progress::init(
+        cli.log_level(),
+        extract_args.is_some_and(|args| args.progress),
+        extract_args.is_some_and(|args| args.log_interval.is_some()),
+    );
+
+    match cli.command {
+        Commands::Extract(args) => run_extract(&args, false)?,
+        Commands::Stats(stats) => {
+            let mut args = stats.extract;
+            let exact_option = exact_only_option(&args);
+            if let (false, Some(option)) = (stats.exact, exact_option) {
+                log::info!("{} needs every read; summarizing them exactly", option);
+            }
+            args.streaming = !stats.exact && exact_option.is_none();
+            run_extract(&args, true)?;
+        }
+        Commands::Compare(args) => {
+            let load = metrics::MetricsCollection::from_json_file;
+            if let Some(path) = &args.output {
+                utils::check_output_path(path, args.force)?;
+            }
+            let report = load(&args.first)?.compare(&load(&args.second)?);
+
+            let mut writer = Output::open(args.output.as_deref(), args.force)?;
+            match args.output_format.as_str() {
+                "json" => write_json(&mut writer, &report, None)?,
+                "table" => report.write_table(&mut writer)?,
+                other => {
+                    return Err(NanogetError::InvalidInput(format!(
+                        "Unknown output format '{}' (expected table or json)",
+                        other
+                    )))
+                }
+            }
+            writer.finish()?;
+        }
+        Commands::Merge(args) => {
+            let output_format = args
+                .output_format
+                .as_deref()
+                .or_else(|| args.output.as_deref().and_then(cli::format_from_extension))
+                .unwrap_or("json");
+            if !matches!(output_format, "json" | "json-summary" | "tsv") {
+                return Err(NanogetError::InvalidInput(format!(
+                    "Unknown output format '{}' (expected json, json-summary or tsv)",
+                    output_format
+                )));
+            }
+            if let Some(path) = &args.output {
+                utils::check_output_path(path, args.force)?;
+            }
+            let merged = extract::merge_exports(&args.files, args.names)?;
+
+            let mut writer = Output::open(args.output.as_deref(), args.force)?;
+            match output_format {
+                "json-summary" => write_json(&mut writer, &merged.summary, None)?,
+                "tsv" => merged.write_tsv_sorted(&mut writer, None, None)?,
+                _ => write_json(&mut writer, &merged, None)?,
+            }
+            writer.finish()?;
+        }
+        Commands::Validate(args) => {
+            let reports = validate::validate_inputs(&args);
+            let mut writer = Output::open(None, false)?;
+            match args.output_format.as_str() {
+                "json" => write_json(&mut writer, &reports, None)?,
+                "table" => validate::write_table(&reports, &mut writer)?,
+                other => {
+                    return Err(NanogetError::InvalidInput(format!(
+                        "Unknown output format '{}' (expected table or json)",
+                        other
+                    )))
+                }
+            }
+            writer.finish()?;
+            let failed = reports.iter().filter(|r| !r.is_ok()).count();
+            if failed > 0 {
+                return Err(NanogetError::InvalidInput(format!(
+                    "{} of {} input files failed validation",
+                    failed,
+                    reports.len()
+                )));
+            }
+        }
+        Commands::Schema(args) => {
+            let schema = if args.summary {
+                metrics::MetricsSummary::json_schema()
+            } else {
+                metrics::MetricsCollection::json_schema()
+            };
+            let mut stdout = std::io::stdout().lock();
+            serde_json::to_writer_pretty(&mut stdout, &schema)?;
+            writeln!(stdout)?;
+        }
+        Commands::Completions(args) => {
+            cli::write_completions(args.shell, &mut std::io::stdout().lock());
+        }
+        Commands::Man(args) => {
+            cli::write_man_page(args.command.as_deref(), &mut std::io::stdout().lock())?
+        }
     }
 
     Ok(())
@@ -1,34 +1,71 @@
 use crate::error::NanogetError;
+use crate::resources::ResourceReport;
+use crate::utils;
 use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use log::warn;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 
 /// Represents the metrics extracted from a single read
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ReadMetrics {
     /// Read identifier
     pub read_id: Option<String>,
 
-    /// Read length (number of bases)
-    pub length: u32,
+    /// Read length (number of bases); 64 bits, as assembled contigs given as
+    /// FASTA can be longer than 4 Gb
+    pub length: u64,
 
     /// Average quality score of the read
     pub quality: Option<f64>,
 
+    /// Standard deviation of the per-base Phred scores, the spread of quality
+    /// along the read (from FASTQ and BAM qualities). Computed on Phred scores
+    /// rather than error probabilities, where the few worst bases would
+    /// dominate.
+    pub quality_std: Option<f64>,
+
+    /// The fields that most reads lack (plain FASTQ only has the ones above),
+    /// kept apart to keep reads small: allocated when the first of them is
+    /// set (see `extended_mut`), read with the accessors of the same name and
+    /// serialized as fields of the read
+    #[serde(flatten, with = "extended_fields")]
+    #[schemars(with = "ExtendedMetrics")]
+    extended: Option<Box<ExtendedMetrics>>,
+}
+
+/// The fields of a `ReadMetrics` that only some inputs provide: alignments,
+/// sequencing metadata, sequence statistics and BAM tags
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExtendedMetrics {
     /// Length of aligned portion (for aligned reads)
-    pub aligned_length: Option<u32>,
+    pub aligned_length: Option<u64>,
 
     /// Average quality of aligned portion
     pub aligned_quality: Option<f64>,
 
-    /// Mapping quality (for aligned reads)
+    /// Mapping quality (for aligned reads); `None` when the aligner reported
+    /// 255, i.e. unavailable
     pub mapping_quality: Option<u8>,
 
     /// Percent identity to reference (for aligned reads)
     pub percent_identity: Option<f64>,
 
     /// Channel ID (from sequencing summary or rich FASTQ)
-    pub channel_id: Option<u16>,
+    pub channel_id: Option<u32>,
+
+    /// Mux (pore of the channel) the read started on (from sequencing summary)
+    pub mux: Option<u8>,
+
+    /// Why the read ended, e.g. signal_positive or data_service_unblock_mux_change
+    /// for reads rejected by adaptive sampling (from sequencing summary)
+    pub end_reason: Option<String>,
 
     /// Start time of sequencing
     pub start_time: Option<DateTime<Utc>>,
@@ -36,33 +73,80 @@ pub struct ReadMetrics {
     /// Duration of sequencing
     pub duration: Option<f64>,
 
-    /// Barcode assignment (for barcoded samples)
-    pub barcode: Option<String>,
+    /// Barcode assignment (for barcoded samples); shared by the reads of the
+    /// same barcode (see `utils::StringInterner`)
+    pub barcode: Option<Arc<str>>,
 
-    /// Run ID
-    pub run_id: Option<String>,
+    /// Run ID, shared by the reads of the same run
+    pub run_id: Option<Arc<str>>,
 
-    /// Dataset name (when combining multiple files with tracking)
-    pub dataset: Option<String>,
+    /// Dataset name (when combining multiple files with tracking), shared by
+    /// the reads of the dataset. For sequencing summaries this is the FASTQ
+    /// batch of the read (`filename_fastq` or `filename` column), when known.
+    pub dataset: Option<Arc<str>>,
+
+    /// Parent read id of a read split by dorado (`pi` aux tag)
+    pub parent_id: Option<String>,
+
+    /// Simplex or duplex, from the dorado `dx` aux tag
+    pub read_class: Option<ReadClass>,
+
+    /// Estimated poly(A) tail length, from the dorado `pt` aux tag (or `pa`)
+    pub polya_length: Option<u32>,
+
+    /// Number of ambiguous (non-ACGT) bases (with `--sequence-stats`)
+    pub n_count: Option<u64>,
+
+    /// Percentage of G/C among the unambiguous bases (with `--sequence-stats`)
+    pub gc_content: Option<f64>,
+
+    /// Numeric BAM aux tags requested with `--bam-tags`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub extra: HashMap<String, f64>,
+
+    /// Text BAM aux tags requested with `--bam-tags`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub extra_text: HashMap<String, String>,
+}
+
+/// Serde of `ReadMetrics::extended`: absent extended fields are written as
+/// nulls, like the fields of a read without them, and a read whose extended
+/// fields are all null or empty is read back without them
+mod extended_fields {
+    use super::ExtendedMetrics;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::sync::OnceLock;
+
+    pub fn serialize<S: Serializer>(
+        extended: &Option<Box<ExtendedMetrics>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        static EMPTY: OnceLock<ExtendedMetrics> = OnceLock::new();
+        match extended {
+            Some(extended) => extended.serialize(serializer),
+            None => EMPTY
+                .get_or_init(ExtendedMetrics::default)
+                .serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Box<ExtendedMetrics>>, D::Error> {
+        let extended = ExtendedMetrics::deserialize(deserializer)?;
+        Ok((extended != ExtendedMetrics::default()).then(|| Box::new(extended)))
+    }
 }
 
 impl ReadMetrics {
     /// Create a new ReadMetrics with basic information
-    pub fn new(read_id: Option<String>, length: u32) -> Self {
+    pub fn new(read_id: Option<String>, length: u64) -> Self {
         Self {
             read_id,
             length,
             quality: None,
-            aligned_length: None,
-            aligned_quality: None,
-            mapping_quality: None,
-            percent_identity: None,
-            channel_id: None,
-            start_time: None,
-            duration: None,
-            barcode: None,
-            run_id: None,
-            dataset: None,
+            quality_std: None,
+            extended: None,
         }
     }
 
@@ -75,78 +159,666 @@ impl ReadMetrics {
     /// Set alignment information
     pub fn with_alignment(
         mut self,
-        aligned_length: u32,
+        aligned_length: u64,
         aligned_quality: Option<f64>,
         mapping_quality: Option<u8>,
         percent_identity: Option<f64>,
     ) -> Self {
-        self.aligned_length = Some(aligned_length);
-        self.aligned_quality = aligned_quality;
-        self.mapping_quality = mapping_quality;
-        self.percent_identity = percent_identity;
+        let extended = self.extended_mut();
+        extended.aligned_length = Some(aligned_length);
+        extended.aligned_quality = aligned_quality;
+        extended.mapping_quality = mapping_quality;
+        extended.percent_identity = percent_identity;
         self
     }
 
     /// Set sequencing metadata
     pub fn with_sequencing_metadata(
         mut self,
-        channel_id: Option<u16>,
+        channel_id: Option<u32>,
         start_time: Option<DateTime<Utc>>,
         duration: Option<f64>,
     ) -> Self {
-        self.channel_id = channel_id;
-        self.start_time = start_time;
-        self.duration = duration;
+        let extended = self.extended_mut();
+        extended.channel_id = channel_id;
+        extended.start_time = start_time;
+        extended.duration = duration;
         self
     }
+
+    /// The less common fields, if any was set
+    pub fn extended(&self) -> Option<&ExtendedMetrics> {
+        self.extended.as_deref()
+    }
+
+    /// The less common fields, to set them; allocated on the first call
+    pub fn extended_mut(&mut self) -> &mut ExtendedMetrics {
+        self.extended.get_or_insert_with(Box::default)
+    }
+
+    /// Length of aligned portion (for aligned reads)
+    pub fn aligned_length(&self) -> Option<u64> {
+        self.extended()?.aligned_length
+    }
+
+    /// Average quality of aligned portion
+    pub fn aligned_quality(&self) -> Option<f64> {
+        self.extended()?.aligned_quality
+    }
+
+    /// Mapping quality (for aligned reads)
+    pub fn mapping_quality(&self) -> Option<u8> {
+        self.extended()?.mapping_quality
+    }
+
+    /// Percent identity to reference (for aligned reads)
+    pub fn percent_identity(&self) -> Option<f64> {
+        self.extended()?.percent_identity
+    }
+
+    /// Channel ID (from sequencing summary or rich FASTQ)
+    pub fn channel_id(&self) -> Option<u32> {
+        self.extended()?.channel_id
+    }
+
+    /// Mux the read started on (from sequencing summary)
+    pub fn mux(&self) -> Option<u8> {
+        self.extended()?.mux
+    }
+
+    /// Why the read ended (from sequencing summary)
+    pub fn end_reason(&self) -> Option<&str> {
+        self.extended()?.end_reason.as_deref()
+    }
+
+    /// Start time of sequencing
+    pub fn start_time(&self) -> Option<DateTime<Utc>> {
+        self.extended()?.start_time
+    }
+
+    /// Duration of sequencing
+    pub fn duration(&self) -> Option<f64> {
+        self.extended()?.duration
+    }
+
+    /// Barcode assignment (for barcoded samples)
+    pub fn barcode(&self) -> Option<&Arc<str>> {
+        self.extended()?.barcode.as_ref()
+    }
+
+    /// Run ID
+    pub fn run_id(&self) -> Option<&Arc<str>> {
+        self.extended()?.run_id.as_ref()
+    }
+
+    /// Dataset name (when combining multiple files with tracking)
+    pub fn dataset(&self) -> Option<&Arc<str>> {
+        self.extended()?.dataset.as_ref()
+    }
+
+    /// Parent read id of a read split by dorado
+    pub fn parent_id(&self) -> Option<&str> {
+        self.extended()?.parent_id.as_deref()
+    }
+
+    /// Simplex or duplex, from the dorado `dx` aux tag
+    pub fn read_class(&self) -> Option<ReadClass> {
+        self.extended()?.read_class
+    }
+
+    /// Estimated poly(A) tail length, from the dorado `pt` aux tag (or `pa`)
+    pub fn polya_length(&self) -> Option<u32> {
+        self.extended()?.polya_length
+    }
+
+    /// Number of ambiguous (non-ACGT) bases (with `--sequence-stats`)
+    pub fn n_count(&self) -> Option<u64> {
+        self.extended()?.n_count
+    }
+
+    /// Percentage of G/C among the unambiguous bases (with `--sequence-stats`)
+    pub fn gc_content(&self) -> Option<f64> {
+        self.extended()?.gc_content
+    }
+
+    /// Numeric BAM aux tags requested with `--bam-tags`
+    pub fn extra(&self) -> &HashMap<String, f64> {
+        static EMPTY: OnceLock<HashMap<String, f64>> = OnceLock::new();
+        self.extended()
+            .map_or_else(|| EMPTY.get_or_init(HashMap::new), |e| &e.extra)
+    }
+
+    /// Text BAM aux tags requested with `--bam-tags`
+    pub fn extra_text(&self) -> &HashMap<String, String> {
+        static EMPTY: OnceLock<HashMap<String, String>> = OnceLock::new();
+        self.extended()
+            .map_or_else(|| EMPTY.get_or_init(HashMap::new), |e| &e.extra_text)
+    }
+}
+
+/// Duplex status of a read, as set by dorado in the `dx` aux tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadClass {
+    /// `dx:i:0`
+    Simplex,
+    /// `dx:i:1`
+    Duplex,
+    /// `dx:i:-1`: a simplex read that has duplex offspring
+    DuplexParent,
+}
+
+impl ReadClass {
+    /// Class for a `dx` tag value; `None` for values dorado does not use
+    pub fn from_dx(value: i64) -> Option<Self> {
+        match value {
+            0 => Some(ReadClass::Simplex),
+            1 => Some(ReadClass::Duplex),
+            -1 => Some(ReadClass::DuplexParent),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReadClass::Simplex => "simplex",
+            ReadClass::Duplex => "duplex",
+            ReadClass::DuplexParent => "duplex_parent",
+        }
+    }
+}
+
+/// Read field to sort by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Length,
+    Quality,
+}
+
+/// Order of reads, parsed from `<key>[:asc|:desc]` with key `length` or
+/// `quality` (e.g. `length:desc`); ascending when no direction is given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl SortOrder {
+    /// Compare two reads; a missing quality orders before any quality.
+    /// Qualities are never NaN (averages of finite Phred scores), and
+    /// `total_cmp` keeps the order total regardless.
+    pub fn compare(&self, a: &ReadMetrics, b: &ReadMetrics) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Length => a.length.cmp(&b.length),
+            SortKey::Quality => match (a.quality, b.quality) {
+                (Some(qa), Some(qb)) => qa.total_cmp(&qb),
+                (qa, qb) => qa.is_some().cmp(&qb.is_some()),
+            },
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = NanogetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, direction) = s.split_once(':').unwrap_or((s, "asc"));
+        let key = match key {
+            "length" => SortKey::Length,
+            "quality" => SortKey::Quality,
+            _ => {
+                return Err(NanogetError::InvalidInput(format!(
+                    "Unknown sort key '{}' (expected length or quality)",
+                    key
+                )))
+            }
+        };
+        let descending = match direction {
+            "asc" => false,
+            "desc" => true,
+            _ => {
+                return Err(NanogetError::InvalidInput(format!(
+                    "Unknown sort direction '{}' (expected asc or desc)",
+                    direction
+                )))
+            }
+        };
+        Ok(Self { key, descending })
+    }
+}
+
+/// Options controlling how a `MetricsSummary` is computed
+#[derive(Debug, Clone)]
+pub struct SummaryOptions {
+    /// Width of the time bins used for `throughput_over_time`, in seconds
+    pub throughput_bin_seconds: u64,
+
+    /// Compute the cumulative yield curve (`cumulative_yield`)
+    pub cumulative_yield: bool,
+
+    /// Additional percentiles (0-100) reported in every `StatsSummary`
+    pub percentiles: Vec<f64>,
+
+    /// (length bin, quality bin) widths of the length-vs-quality density, if requested
+    pub length_quality_density: Option<(u64, f64)>,
+
+    /// Width of the read length histogram bins behind `length_mode_bin`, in bases
+    pub length_bin: u64,
+
+    /// Also report the most populated length histogram peaks (`length_modes`)
+    pub length_modes: bool,
+
+    /// Also report statistics of log10(length) (`log_length_stats`)
+    pub log_length: bool,
+
+    /// How reads without a quality enter `quality_stats`
+    pub missing_quality: MissingQuality,
+
+    /// Start of the first throughput bin; the earliest read when None
+    pub run_start: Option<DateTime<Utc>>,
+
+    /// (maximum length, fraction limit) of the short read spike check
+    /// (`short_read_spike`), if requested
+    pub short_read_spike: Option<(u64, f64)>,
+}
+
+impl Default for SummaryOptions {
+    fn default() -> Self {
+        Self {
+            throughput_bin_seconds: 3600,
+            cumulative_yield: false,
+            percentiles: Vec::new(),
+            length_quality_density: None,
+            length_bin: DEFAULT_LENGTH_BIN,
+            length_modes: false,
+            log_length: false,
+            missing_quality: MissingQuality::Skip,
+            run_start: None,
+            short_read_spike: None,
+        }
+    }
+}
+
+/// Default width of the read length histogram bins, in bases
+pub const DEFAULT_LENGTH_BIN: u64 = 100;
+
+/// Number of peaks reported in `MetricsSummary::length_modes`
+pub const LENGTH_MODES: usize = 3;
+
+/// How `MetricsCollection::combine_with_options` merges collections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CombineMethod {
+    /// Concatenate the reads
+    #[default]
+    Simple,
+    /// Concatenate the reads, labelling each with the name of its dataset
+    Track,
+}
+
+/// How reads without a quality (e.g. from FASTA, or BAM records stored
+/// without qualities) enter `quality_stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingQuality {
+    /// Leave them out: `quality_stats.count` is the number of reads with a
+    /// quality
+    #[default]
+    Skip,
+    /// Count them with quality 0
+    Zero,
+    /// Fail the extraction when any read has no quality; summaries computed
+    /// directly from reads leave them out, as with `Skip`
+    Error,
+}
+
+/// How the mean quality of a read is computed from its base qualities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityMethod {
+    /// Average the error probabilities of the bases and convert the mean back
+    /// to a Phred score: the quality matching the expected number of errors
+    #[default]
+    Eprob,
+    /// Arithmetic mean of the Phred scores, as reported by some other tools;
+    /// higher for reads whose quality varies
+    Arithmetic,
+}
+
+impl QualityMethod {
+    /// Mean quality of `qualities` after subtracting `offset` from every
+    /// score (see `utils::average_quality_with_offset`)
+    pub fn average(self, qualities: &[u8], offset: u8) -> Result<Option<f64>, NanogetError> {
+        match self {
+            Self::Eprob => utils::average_quality_with_offset(qualities, offset),
+            Self::Arithmetic => utils::average_quality_arithmetic_with_offset(qualities, offset),
+        }
+    }
+}
+
+/// Reads of a sequencing summary to report: 1D selects the template columns,
+/// 2D and 1D2 the 2d length and quality columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+pub enum ReadType {
+    #[default]
+    #[value(name = "1D")]
+    #[serde(rename = "1D")]
+    OneD,
+    #[value(name = "2D")]
+    #[serde(rename = "2D")]
+    TwoD,
+    #[value(name = "1D2")]
+    #[serde(rename = "1D2")]
+    OneDSquared,
+}
+
+/// Parse one of the command line names of a `ValueEnum` (e.g. "track", "1D")
+fn parse_value_enum<T: ValueEnum>(value: &str, what: &str) -> Result<T, NanogetError> {
+    T::from_str(value, false).map_err(|_| {
+        let expected: Vec<String> = T::value_variants()
+            .iter()
+            .filter_map(|v| v.to_possible_value())
+            .map(|v| v.get_name().to_string())
+            .collect();
+        NanogetError::InvalidInput(format!(
+            "Unknown {} '{}' (expected one of: {})",
+            what,
+            value,
+            expected.join(", ")
+        ))
+    })
+}
+
+impl CombineMethod {
+    /// The command line name of the method
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Simple => "simple",
+            Self::Track => "track",
+        }
+    }
+}
+
+impl ReadType {
+    /// The command line name of the read type
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::OneD => "1D",
+            Self::TwoD => "2D",
+            Self::OneDSquared => "1D2",
+        }
+    }
+}
+
+impl FromStr for CombineMethod {
+    type Err = NanogetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_value_enum(s, "combine method")
+    }
+}
+
+impl FromStr for ReadType {
+    type Err = NanogetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_value_enum(s, "read type")
+    }
 }
 
+/// Maximum number of points sampled from the cumulative yield curve
+pub const CUMULATIVE_YIELD_POINTS: usize = 1000;
+
 /// Collection of read metrics with summary statistics
-#[derive(Debug, Serialize, Deserialize)]
+///
+/// The summary is maintained eagerly: `push`, `merge` and `extend` recompute it
+/// before returning, with the options the collection was built with, so
+/// `summary` always describes `reads`. Each call costs a full recomputation;
+/// when adding many reads, prefer a single `extend` over repeated `push`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct MetricsCollection {
     /// Individual read metrics
     pub reads: Vec<ReadMetrics>,
 
     /// Summary statistics
     pub summary: MetricsSummary,
+
+    /// How the collection was produced (set by `extract_metrics`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+
+    /// Options used to compute `summary`, reused when it is recomputed
+    #[serde(skip)]
+    options: SummaryOptions,
+
+    /// Summary of each input file, keyed by its path (set by `extract_metrics`)
+    #[serde(skip)]
+    per_file: BTreeMap<String, MetricsSummary>,
+
+    /// Reads dropped by the extraction filters, kept in `summary` when it is
+    /// recomputed
+    #[serde(skip)]
+    excluded: Option<ExcludedReads>,
 }
 
 impl MetricsCollection {
     /// Create a new collection from a vector of read metrics
     pub fn new(reads: Vec<ReadMetrics>) -> Self {
-        let summary = MetricsSummary::from_reads(&reads);
-        Self { reads, summary }
+        Self::with_options(reads, &SummaryOptions::default())
+    }
+
+    /// Create a new collection, computing the summary with the given options
+    pub fn with_options(reads: Vec<ReadMetrics>, options: &SummaryOptions) -> Self {
+        let summary = MetricsSummary::from_reads_with_options(&reads, options);
+        Self {
+            reads,
+            summary,
+            provenance: None,
+            options: options.clone(),
+            per_file: BTreeMap::new(),
+            excluded: None,
+        }
+    }
+
+    /// Summary of each input file as extracted, keyed by the path given on the
+    /// command line, so a single bad file in a batch stands out. Filled in by
+    /// `extract_metrics`; `sample` keeps them (they describe the reads before
+    /// sampling), collections built any other way have none.
+    pub fn per_file_summaries(&self) -> &BTreeMap<String, MetricsSummary> {
+        &self.per_file
+    }
+
+    pub(crate) fn set_per_file_summaries(&mut self, per_file: BTreeMap<String, MetricsSummary>) {
+        self.per_file = per_file;
+    }
+
+    pub(crate) fn set_excluded_reads(&mut self, excluded: Option<ExcludedReads>) {
+        self.summary.excluded_reads = excluded.clone();
+        self.excluded = excluded;
+    }
+
+    /// Write the per-file summaries as a `# `-prefixed TSV section, with
+    /// `precision` decimal places (2 by default)
+    pub fn write_per_file_tsv<W: Write>(
+        &self,
+        w: &mut W,
+        precision: Option<usize>,
+    ) -> Result<(), NanogetError> {
+        let stats_precision = precision.unwrap_or(2);
+        writeln!(w, "\n# Per-file summary")?;
+        writeln!(
+            w,
+            "# file\treads\tmean_length\tmedian_length\tmax_length\tmean_quality\tmedian_quality"
+        )?;
+        for (file, summary) in &self.per_file {
+            let quality = |value: fn(&StatsSummary) -> f64| {
+                summary
+                    .quality_stats
+                    .as_ref()
+                    .map(|q| format!("{:.*}", stats_precision, value(q)))
+                    .unwrap_or_default()
+            };
+            writeln!(
+                w,
+                "# {}\t{}\t{:.*}\t{:.*}\t{}\t{}\t{}",
+                file,
+                summary.read_count,
+                stats_precision,
+                summary.length_stats.mean,
+                stats_precision,
+                summary.length_stats.median,
+                summary.length_stats.max,
+                quality(|q| q.mean),
+                quality(|q| q.median)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// A NanoComp-style comparison of the datasets of a tracked collection
+    /// (`CombineMethod::Track`), as TSV: one row per dataset, in the order in
+    /// which the datasets first appear, with its read count, bases, read length
+    /// N50, mean and median length and mean quality. Reads without a dataset
+    /// are left out.
+    pub fn per_dataset_summary_table(&self) -> String {
+        let mut datasets: Vec<(&str, Vec<&ReadMetrics>)> = Vec::new();
+        let mut index: HashMap<&str, usize> = HashMap::new();
+        for read in &self.reads {
+            let Some(dataset) = read.dataset().map(AsRef::as_ref) else {
+                continue;
+            };
+            let i = *index.entry(dataset).or_insert_with(|| {
+                datasets.push((dataset, Vec::new()));
+                datasets.len() - 1
+            });
+            datasets[i].1.push(read);
+        }
+
+        let mut table =
+            String::from("dataset\treads\tbases\tn50\tmean_length\tmedian_length\tmean_quality\n");
+        for (dataset, reads) in datasets {
+            let group = GroupSummary::from_reads(reads);
+            table.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{:.2}\t{:.2}\t{}\n",
+                dataset,
+                group.read_count,
+                group.bases,
+                group.n50,
+                group.mean_length,
+                group.median_length,
+                group
+                    .mean_quality
+                    .map(|q| format!("{:.2}", q))
+                    .unwrap_or_default()
+            ));
+        }
+        table
+    }
+
+    /// Select a random subset of `n` reads with reservoir sampling, keeping their
+    /// original order. The same `seed` always selects the same reads; when `n` is
+    /// at least the number of reads, all reads are kept. The summary is recomputed
+    /// and the sampling is recorded in the provenance.
+    pub fn sample(self, n: usize, seed: u64) -> MetricsCollection {
+        let original_read_count = self.reads.len();
+        let mut sampler = ReservoirSampler::new(n, seed);
+        for read in self.reads {
+            sampler.add(read);
+        }
+        let mut sampled = MetricsCollection::with_options(sampler.finish(), &self.options);
+        let mut provenance = self.provenance.unwrap_or_default();
+        provenance.sampling = Some(SamplingInfo {
+            requested: n,
+            seed,
+            original_read_count,
+        });
+        sampled.provenance = Some(provenance);
+        sampled.per_file = self.per_file;
+        sampled.set_excluded_reads(self.excluded);
+        sampled
+    }
+
+    /// Add a single read and recompute the summary
+    #[allow(dead_code)]
+    pub fn push(&mut self, read: ReadMetrics) {
+        self.reads.push(read);
+        self.refresh_summary();
+    }
+
+    /// Append the reads of `other` and recompute the summary. Dataset labels
+    /// are kept as they are; use `combine_with_options` to assign them.
+    #[allow(dead_code)]
+    pub fn merge(&mut self, other: MetricsCollection) {
+        self.reads.extend(other.reads);
+        self.refresh_summary();
+    }
+
+    fn refresh_summary(&mut self) {
+        self.summary = MetricsSummary::from_reads_with_options(&self.reads, &self.options);
+        self.summary.excluded_reads = self.excluded.clone();
     }
 
     /// Combine multiple collections
-    pub fn combine(collections: Vec<Self>, method: &str, names: Option<Vec<String>>) -> Self {
+    pub fn combine(
+        collections: Vec<Self>,
+        method: CombineMethod,
+        names: Option<Vec<String>>,
+    ) -> Self {
+        Self::combine_with_options(collections, method, names, &SummaryOptions::default())
+    }
+
+    /// Combine multiple collections, computing the combined summary with the
+    /// given options; their excluded read counts are added up
+    pub fn combine_with_options(
+        collections: Vec<Self>,
+        method: CombineMethod,
+        names: Option<Vec<String>>,
+        options: &SummaryOptions,
+    ) -> Self {
         let mut all_reads = Vec::new();
+        let excluded = collections
+            .iter()
+            .filter_map(|collection| collection.excluded.as_ref())
+            .fold(None, |total: Option<ExcludedReads>, excluded| {
+                let mut total = total.unwrap_or_default();
+                total.add(excluded);
+                Some(total)
+            });
 
         match method {
-            "track" => {
-                // Add dataset names to reads
+            CombineMethod::Track => {
+                // Add dataset names to reads, one shared copy per dataset. Reads
+                // that already have one (the rows of a single summary with
+                // their FASTQ batch) keep it.
                 for (i, mut collection) in collections.into_iter().enumerate() {
-                    let dataset_name = names
-                        .as_ref()
-                        .and_then(|n| n.get(i))
-                        .cloned()
-                        .unwrap_or_else(|| format!("dataset_{}", i));
+                    let dataset_name: Arc<str> = match names.as_ref().and_then(|n| n.get(i)) {
+                        Some(name) => Arc::from(name.as_str()),
+                        None => Arc::from(format!("dataset_{}", i)),
+                    };
 
                     for read in &mut collection.reads {
-                        read.dataset = Some(dataset_name.clone());
+                        read.extended_mut()
+                            .dataset
+                            .get_or_insert_with(|| Arc::clone(&dataset_name));
                     }
                     all_reads.extend(collection.reads);
                 }
             }
-            _ => {
-                // Simple concatenation
+            CombineMethod::Simple => {
                 for collection in collections {
                     all_reads.extend(collection.reads);
                 }
             }
         }
 
-        Self::new(all_reads)
+        let mut combined = Self::with_options(all_reads, options);
+        combined.set_excluded_reads(excluded);
+        combined
     }
 
     /// Get reads from a specific dataset (when using track mode)
@@ -154,402 +826,3627 @@ impl MetricsCollection {
     pub fn reads_for_dataset(&self, dataset_name: &str) -> Vec<&ReadMetrics> {
         self.reads
             .iter()
-            .filter(|read| read.dataset.as_deref() == Some(dataset_name))
+            .filter(|read| read.dataset().map(AsRef::as_ref) == Some(dataset_name))
             .collect()
     }
 
     /// Get all unique dataset names
     #[allow(dead_code)]
     pub fn dataset_names(&self) -> Vec<String> {
-        let mut names: Vec<String> = self
+        let names: BTreeSet<&str> = self
             .reads
             .iter()
-            .filter_map(|read| read.dataset.clone())
+            .filter_map(|read| read.dataset().map(AsRef::as_ref))
             .collect();
-        names.sort();
-        names.dedup();
-        names
+        names.into_iter().map(str::to_string).collect()
     }
 
-    /// Filter reads by minimum length
+    /// Group split reads with their parent: reads carrying a parent id (dorado `pi`
+    /// tag) are grouped under it, other reads under their own read id. Reads with
+    /// neither a parent id nor a read id are left out.
     #[allow(dead_code)]
-    pub fn filter_by_length(&self, min_length: u32) -> MetricsCollection {
-        let filtered_reads: Vec<ReadMetrics> = self
-            .reads
-            .iter()
-            .filter(|read| read.length >= min_length)
-            .cloned()
-            .collect();
-        MetricsCollection::new(filtered_reads)
+    pub fn group_by_parent(&self) -> HashMap<String, Vec<&ReadMetrics>> {
+        let mut groups: HashMap<String, Vec<&ReadMetrics>> = HashMap::new();
+        for read in &self.reads {
+            if let Some(key) = read.parent_id().or(read.read_id.as_deref()) {
+                groups.entry(key.to_string()).or_default().push(read);
+            }
+        }
+        groups
     }
 
-    /// Filter reads by minimum quality
+    /// Number of alignments of each aligned read, keyed by (dataset, read id)
+    /// so that the same read in two datasets of track mode is counted apart:
+    /// the primary alignment plus its supplementary alignments, which are kept
+    /// unless `keep_supplementary` is off (secondary alignments are never
+    /// extracted). More than one points to a split or chimeric read.
     #[allow(dead_code)]
-    pub fn filter_by_quality(&self, min_quality: f64) -> MetricsCollection {
-        let filtered_reads: Vec<ReadMetrics> = self
+    pub fn alignments_per_read(&self) -> HashMap<(Option<String>, String), usize> {
+        count_alignments(&self.reads)
+            .into_iter()
+            .map(|((dataset, read_id), alignments)| {
+                (
+                    (dataset.map(str::to_string), read_id.to_string()),
+                    alignments,
+                )
+            })
+            .collect()
+    }
+
+    /// Quality-weighted N50 (QN50): the read length at which the reads this
+    /// long or longer contain at least half of the quality-weighted bases,
+    /// each read weighing length × (1 - error rate of its mean quality).
+    /// Reads without a quality are left out; `None` when no read has one or
+    /// the weighted bases add up to zero.
+    #[allow(dead_code)]
+    pub fn qn50(&self) -> Option<f64> {
+        let mut weighted: Vec<(u64, f64)> = self
             .reads
             .iter()
-            .filter(|read| read.quality.map(|q| q >= min_quality).unwrap_or(false))
-            .cloned()
+            .filter_map(|read| {
+                let quality = read.quality.filter(|q| q.is_finite())?;
+                let accuracy = 1.0 - 10.0_f64.powf(-quality / 10.0);
+                Some((read.length, read.length as f64 * accuracy.max(0.0)))
+            })
             .collect();
-        MetricsCollection::new(filtered_reads)
+        let total: f64 = weighted.iter().map(|&(_, bases)| bases).sum();
+        if total <= 0.0 {
+            return None;
+        }
+        weighted.sort_unstable_by_key(|&(length, _)| std::cmp::Reverse(length));
+        let mut cumulative = 0.0;
+        for (length, bases) in weighted {
+            cumulative += bases;
+            if cumulative * 2.0 >= total {
+                return Some(length as f64);
+            }
+        }
+        None
     }
 
-    /// Get reads longer than a percentile threshold
+    /// Count reads per cell of a length-vs-quality grid, as plotted by NanoPlot.
+    ///
+    /// Cells are keyed by their lower edges `(length, quality)` and returned in
+    /// ascending order; only non-empty cells are listed. Reads without a quality
+    /// are skipped. A non-positive `qual_bin` yields no cells.
     #[allow(dead_code)]
-    pub fn reads_above_length_percentile(&self, percentile: f64) -> MetricsCollection {
-        let mut lengths: Vec<u32> = self.reads.iter().map(|r| r.length).collect();
-        lengths.sort();
-
-        let index = (percentile / 100.0 * (lengths.len() - 1) as f64) as usize;
-        let threshold = lengths.get(index).copied().unwrap_or(0);
-
-        self.filter_by_length(threshold)
+    pub fn length_quality_density(&self, len_bin: u64, qual_bin: f64) -> Vec<((u64, f64), usize)> {
+        compute_length_quality_density(&self.reads, len_bin, qual_bin)
     }
 
-    /// Export to JSON string
-    /// Export to pretty-printed JSON string
+    /// Split the collection into one collection per barcode; reads without a
+    /// barcode or labelled "unclassified" are left out, as in
+    /// `summary.per_barcode`. This clones the reads: when only the per-barcode
+    /// numbers are needed, use `summary.per_barcode`, which is computed in place.
     #[allow(dead_code)]
-    pub fn to_json(&self) -> Result<String, serde_json::Error> {
-        serde_json::to_string_pretty(self)
+    pub fn group_by_barcode(&self) -> HashMap<String, MetricsCollection> {
+        let mut groups: HashMap<&str, Vec<ReadMetrics>> = HashMap::new();
+        for read in &self.reads {
+            if let Some(barcode) = read.barcode().filter(|b| !is_unclassified(b)) {
+                groups.entry(barcode).or_default().push(read.clone());
+            }
+        }
+        groups
+            .into_iter()
+            .map(|(barcode, reads)| (barcode.to_string(), MetricsCollection::new(reads)))
+            .collect()
     }
 
-    /// Export to compact JSON string
+    /// Split the collection into one collection per read class (simplex,
+    /// duplex, duplex parent); reads without a `dx` tag are left out. This
+    /// clones the reads, like `group_by_barcode`; `summary.per_read_class` has
+    /// the per-class numbers without copying.
     #[allow(dead_code)]
-    pub fn to_json_compact(&self) -> Result<String, serde_json::Error> {
-        serde_json::to_string(self)
+    pub fn split_by_class(&self) -> HashMap<ReadClass, MetricsCollection> {
+        let mut groups: HashMap<ReadClass, Vec<ReadMetrics>> = HashMap::new();
+        for read in &self.reads {
+            if let Some(class) = read.read_class() {
+                groups.entry(class).or_default().push(read.clone());
+            }
+        }
+        groups
+            .into_iter()
+            .map(|(class, reads)| (class, MetricsCollection::with_options(reads, &self.options)))
+            .collect()
     }
 
-    /// Export to TSV format
-    pub fn to_tsv(&self) -> Result<String, NanogetError> {
-        let mut output = String::new();
+    /// Keep the reads matching `predicate` in a new collection; the reads are
+    /// cloned and the summary is recomputed with this collection's options.
+    /// Filters can be chained, e.g. `c.filter_by_length(1000).filter_by_quality(10.0)`.
+    /// When the collection itself is no longer needed, `into_filtered` avoids
+    /// the clones, and `view` avoids building a collection at all.
+    pub fn filter<F: Fn(&ReadMetrics) -> bool>(&self, predicate: F) -> MetricsCollection {
+        let filtered_reads = self.view(predicate).into_iter().cloned().collect();
+        MetricsCollection::with_options(filtered_reads, &self.options)
+    }
 
-        // Header row for individual reads
-        output.push_str("read_id\tlength\tquality\taligned_length\taligned_quality\tmapping_quality\tpercent_identity\tchannel_id\tstart_time\tduration\tbarcode\trun_id\tdataset\n");
+    /// Consuming variant of `filter` that drops non-matching reads in place
+    /// and moves the survivors, without cloning any of them
+    #[allow(dead_code)]
+    pub fn into_filtered<F: FnMut(&ReadMetrics) -> bool>(
+        mut self,
+        predicate: F,
+    ) -> MetricsCollection {
+        self.reads.retain(predicate);
+        self.refresh_summary();
+        self
+    }
 
-        // Individual read data
-        for read in &self.reads {
-            output.push_str(&format!(
-                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
-                read.read_id.as_deref().unwrap_or(""),
-                read.length,
-                read.quality
-                    .map(|q| format!("{:.3}", q))
-                    .unwrap_or_default(),
-                read.aligned_length
-                    .map(|l| l.to_string())
-                    .unwrap_or_default(),
-                read.aligned_quality
-                    .map(|q| format!("{:.3}", q))
-                    .unwrap_or_default(),
-                read.mapping_quality
-                    .map(|q| q.to_string())
-                    .unwrap_or_default(),
-                read.percent_identity
-                    .map(|p| format!("{:.3}", p))
-                    .unwrap_or_default(),
-                read.channel_id.map(|c| c.to_string()).unwrap_or_default(),
-                read.start_time.map(|t| t.to_rfc3339()).unwrap_or_default(),
-                read.duration
-                    .map(|d| format!("{:.3}", d))
-                    .unwrap_or_default(),
-                read.barcode.as_deref().unwrap_or(""),
-                read.run_id.as_deref().unwrap_or(""),
-                read.dataset.as_deref().unwrap_or("")
-            ));
-        }
+    /// Same as `into_filtered`
+    #[allow(dead_code)]
+    pub fn retain<F: FnMut(&ReadMetrics) -> bool>(self, predicate: F) -> MetricsCollection {
+        self.into_filtered(predicate)
+    }
 
-        // Add summary statistics as a comment section
-        output.push_str("\n# Summary Statistics\n");
-        output.push_str(&format!("# Total reads: {}\n", self.summary.read_count));
-
-        // Length statistics
-        output.push_str(&format!(
-            "# Length stats - count: {}, mean: {:.2}, median: {:.2}, min: {:.2}, max: {:.2}, std_dev: {:.2}, q25: {:.2}, q75: {:.2}\n",
-            self.summary.length_stats.count,
-            self.summary.length_stats.mean,
-            self.summary.length_stats.median,
-            self.summary.length_stats.min,
-            self.summary.length_stats.max,
-            self.summary.length_stats.std_dev,
-            self.summary.length_stats.q25,
-            self.summary.length_stats.q75
-        ));
+    /// The reads matching `predicate`, borrowed rather than cloned; `summarize`
+    /// computes their summary when needed
+    pub fn view<F: Fn(&ReadMetrics) -> bool>(&self, predicate: F) -> Vec<&ReadMetrics> {
+        self.reads.iter().filter(|read| predicate(read)).collect()
+    }
 
-        // Quality statistics if available
-        if let Some(quality_stats) = &self.summary.quality_stats {
-            output.push_str(&format!(
-                "# Quality stats - count: {}, mean: {:.2}, median: {:.2}, min: {:.2}, max: {:.2}, std_dev: {:.2}, q25: {:.2}, q75: {:.2}\n",
-                quality_stats.count,
-                quality_stats.mean,
-                quality_stats.median,
-                quality_stats.min,
-                quality_stats.max,
-                quality_stats.std_dev,
-                quality_stats.q25,
-                quality_stats.q75
-            ));
-        }
+    /// Summary of `reads` (e.g. a `view` of this collection) with this
+    /// collection's options
+    #[allow(dead_code)]
+    pub fn summarize(&self, reads: &[&ReadMetrics]) -> MetricsSummary {
+        MetricsSummary::from_read_refs(reads, &self.options)
+    }
 
-        // Mapping quality statistics if available
-        if let Some(mapping_quality_stats) = &self.summary.mapping_quality_stats {
-            output.push_str(&format!(
-                "# Mapping quality stats - count: {}, mean: {:.2}, median: {:.2}, min: {:.2}, max: {:.2}, std_dev: {:.2}, q25: {:.2}, q75: {:.2}\n",
-                mapping_quality_stats.count,
-                mapping_quality_stats.mean,
-                mapping_quality_stats.median,
-                mapping_quality_stats.min,
-                mapping_quality_stats.max,
-                mapping_quality_stats.std_dev,
-                mapping_quality_stats.q25,
-                mapping_quality_stats.q75
-            ));
+    /// Filter reads by minimum length
+    #[allow(dead_code)]
+    pub fn filter_by_length(&self, min_length: u64) -> MetricsCollection {
+        self.filter(|read| read.length >= min_length)
+    }
+
+    /// Consuming variant of `filter_by_length`
+    #[allow(dead_code)]
+    pub fn into_filtered_by_length(self, min_length: u64) -> MetricsCollection {
+        self.into_filtered(|read| read.length >= min_length)
+    }
+
+    /// Borrowed variant of `filter_by_length`
+    #[allow(dead_code)]
+    pub fn view_by_length(&self, min_length: u64) -> Vec<&ReadMetrics> {
+        self.view(|read| read.length >= min_length)
+    }
+
+    /// Filter reads by minimum quality
+    #[allow(dead_code)]
+    pub fn filter_by_quality(&self, min_quality: f64) -> MetricsCollection {
+        self.filter(|read| read.quality.map(|q| q >= min_quality).unwrap_or(false))
+    }
+
+    /// Consuming variant of `filter_by_quality`
+    #[allow(dead_code)]
+    pub fn into_filtered_by_quality(self, min_quality: f64) -> MetricsCollection {
+        self.into_filtered(|read| read.quality.map(|q| q >= min_quality).unwrap_or(false))
+    }
+
+    /// Borrowed variant of `filter_by_quality`
+    #[allow(dead_code)]
+    pub fn view_by_quality(&self, min_quality: f64) -> Vec<&ReadMetrics> {
+        self.view(|read| read.quality.map(|q| q >= min_quality).unwrap_or(false))
+    }
+
+    /// Number and fraction of the reads of at most `max_length` bases; a
+    /// spike of very short reads can point to adapter dimers
+    #[allow(dead_code)]
+    pub fn short_read_spike(&self, max_length: u64) -> (usize, f64) {
+        let count = self
+            .reads
+            .iter()
+            .filter(|read| read.length <= max_length)
+            .count();
+        (count, short_read_fraction(count, self.reads.len()))
+    }
+
+    /// Filter reads with a GC content within `[min, max]` percent; reads
+    /// without a GC content are excluded
+    #[allow(dead_code)]
+    pub fn filter_by_gc_range(&self, min: f64, max: f64) -> MetricsCollection {
+        self.filter(|read| {
+            read.gc_content()
+                .is_some_and(|gc| (min..=max).contains(&gc))
+        })
+    }
+
+    /// Filter reads assigned to `barcode`
+    #[allow(dead_code)]
+    pub fn filter_by_barcode(&self, barcode: &str) -> MetricsCollection {
+        self.filter(|read| read.barcode().map(AsRef::as_ref) == Some(barcode))
+    }
+
+    /// Filter reads from dataset `dataset_name` (track mode)
+    #[allow(dead_code)]
+    pub fn filter_by_dataset(&self, dataset_name: &str) -> MetricsCollection {
+        self.filter(|read| read.dataset().map(AsRef::as_ref) == Some(dataset_name))
+    }
+
+    /// Filter aligned reads by minimum percent identity
+    #[allow(dead_code)]
+    pub fn filter_by_min_identity(&self, min_identity: f64) -> MetricsCollection {
+        self.filter(|read| {
+            read.percent_identity()
+                .map(|p| p >= min_identity)
+                .unwrap_or(false)
+        })
+    }
+
+    /// Get reads longer than a percentile threshold.
+    /// An empty collection yields an empty collection.
+    #[allow(dead_code)]
+    pub fn reads_above_length_percentile(&self, percentile: f64) -> MetricsCollection {
+        if self.reads.is_empty() {
+            return MetricsCollection::new(Vec::new());
+        }
+        let mut lengths: Vec<u64> = self.reads.iter().map(|r| r.length).collect();
+        lengths.sort();
+
+        let index = (percentile / 100.0 * (lengths.len() - 1) as f64) as usize;
+        let threshold = lengths.get(index).copied().unwrap_or(0);
+
+        self.filter_by_length(threshold)
+    }
+
+    /// Share of the total bases in the longest `top_fraction` of reads (e.g.
+    /// 0.1 for the longest 10%, rounded up to whole reads). `top_fraction`
+    /// must be in (0, 1]; an empty collection yields 0.
+    #[allow(dead_code)]
+    pub fn yield_concentration(&self, top_fraction: f64) -> Result<f64, NanogetError> {
+        if !(top_fraction > 0.0 && top_fraction <= 1.0) {
+            return Err(NanogetError::InvalidInput(format!(
+                "Fraction of reads {} is outside the range (0, 1]",
+                top_fraction
+            )));
+        }
+        let mut lengths: Vec<u64> = self.reads.iter().map(|r| r.length).collect();
+        let total: u64 = lengths.iter().sum();
+        if total == 0 {
+            return Ok(0.0);
+        }
+        let top = ((top_fraction * lengths.len() as f64).ceil() as usize).clamp(1, lengths.len());
+        lengths.select_nth_unstable_by(top - 1, |a, b| b.cmp(a));
+        let top_bases: u64 = lengths[..top].iter().sum();
+        Ok(top_bases as f64 / total as f64)
+    }
+
+    /// Sort reads by length in place. The sort is stable, so reads of equal
+    /// length keep their relative order; the summary does not depend on order.
+    #[allow(dead_code)]
+    pub fn sort_by_length(&mut self, descending: bool) {
+        self.sort_by(SortOrder {
+            key: SortKey::Length,
+            descending,
+        });
+    }
+
+    /// Sort reads by mean quality in place (stable); reads without a quality
+    /// sort as the lowest
+    #[allow(dead_code)]
+    pub fn sort_by_quality(&mut self, descending: bool) {
+        self.sort_by(SortOrder {
+            key: SortKey::Quality,
+            descending,
+        });
+    }
+
+    /// Sort reads in place by `order` (stable)
+    #[allow(dead_code)]
+    pub fn sort_by(&mut self, order: SortOrder) {
+        self.reads.sort_by(|a, b| order.compare(a, b));
+    }
+
+    /// Percentile rank of `read` by length: the percentage of reads in the
+    /// collection that are not longer than it. `None` for an empty collection.
+    #[allow(dead_code)]
+    pub fn rank_percentile_of(&self, read: &ReadMetrics) -> Option<f64> {
+        if self.reads.is_empty() {
+            return None;
+        }
+        let not_longer = self
+            .reads
+            .iter()
+            .filter(|r| r.length <= read.length)
+            .count();
+        Some(not_longer as f64 / self.reads.len() as f64 * 100.0)
+    }
+
+    /// Export to JSON string
+    /// Export to pretty-printed JSON string
+    #[allow(dead_code)]
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// JSON Schema (draft 2020-12) of the collection as written by `to_json`
+    /// and `nanoget extract -f json`: the fields, their types and which ones
+    /// may be missing or null. The output format version is given as
+    /// `x-schema-version`.
+    pub fn json_schema() -> serde_json::Value {
+        versioned_schema(schemars::schema_for!(MetricsCollection))
+    }
+
+    /// Load a collection exported with `to_json` or `nanoget extract -f json`.
+    /// The summary is taken as stored; summary options are not part of the
+    /// export, so collections derived from it use the default options.
+    #[allow(dead_code)]
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Load a collection from a JSON reader; see `from_json`
+    pub fn from_json_reader<R: std::io::Read>(reader: R) -> Result<Self, serde_json::Error> {
+        serde_json::from_reader(std::io::BufReader::new(reader))
+    }
+
+    /// Load a collection from a (possibly compressed) JSON file written by
+    /// `nanoget extract -f json`. A summary without the per-read records
+    /// (`-f json-summary`) is an error that says so.
+    pub fn from_json_file(path: &std::path::Path) -> Result<Self, NanogetError> {
+        Self::from_json_reader(utils::open_file(path)?).map_err(|e| {
+            // Tell a summary-only export apart from a malformed file
+            #[derive(Deserialize)]
+            struct Probe {
+                reads: Option<serde::de::IgnoredAny>,
+                read_count: Option<serde::de::IgnoredAny>,
+            }
+            let summary_only = utils::open_file(path)
+                .ok()
+                .and_then(|reader| serde_json::from_reader::<_, Probe>(reader).ok())
+                .is_some_and(|probe| probe.reads.is_none() && probe.read_count.is_some());
+            if summary_only {
+                NanogetError::ParseError(format!(
+                    "{} holds a summary without per-read records; write it with extract -f json",
+                    path.display()
+                ))
+            } else {
+                NanogetError::ParseError(format!("{}: {}", path.display(), e))
+            }
+        })
+    }
+
+    /// Export to compact JSON string
+    #[allow(dead_code)]
+    pub fn to_json_compact(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    /// Export to TSV format
+    #[allow(dead_code)]
+    pub fn to_tsv(&self) -> Result<String, NanogetError> {
+        let mut buffer = Vec::new();
+        self.write_tsv(&mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| NanogetError::ProcessingError(e.to_string()))
+    }
+
+    /// Stream TSV output to a writer, one row at a time, without building the
+    /// whole document in memory
+    pub fn write_tsv<W: Write>(&self, w: &mut W) -> Result<(), NanogetError> {
+        self.write_tsv_with_precision(w, None)
+    }
+
+    /// Stream TSV output with `precision` decimal places for every fractional
+    /// field. `None` keeps the defaults: 3 for per-read values, 2 for statistics.
+    pub fn write_tsv_with_precision<W: Write>(
+        &self,
+        w: &mut W,
+        precision: Option<usize>,
+    ) -> Result<(), NanogetError> {
+        self.write_tsv_sorted(w, precision, None)
+    }
+
+    /// Stream TSV output like `write_tsv_with_precision`, writing the read rows
+    /// in `sort` order (input order when `None`) without reordering the collection
+    pub fn write_tsv_sorted<W: Write>(
+        &self,
+        w: &mut W,
+        precision: Option<usize>,
+        sort: Option<SortOrder>,
+    ) -> Result<(), NanogetError> {
+        // Extra BAM tag columns: the union of tags seen on any read, in sorted order
+        let extra_columns: Vec<&str> = self
+            .reads
+            .iter()
+            .flat_map(|r| r.extra().keys().chain(r.extra_text().keys()))
+            .map(String::as_str)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        write_tsv_header(w, &extra_columns)?;
+
+        let mut rows: Vec<&ReadMetrics> = self.reads.iter().collect();
+        if let Some(order) = sort {
+            rows.sort_by(|a, b| order.compare(a, b));
+        }
+        for read in rows {
+            write_tsv_row(w, read, &extra_columns, precision)?;
+        }
+
+        // Add summary statistics as a comment section
+        writeln!(w)?;
+        self.summary.write_tsv(w, precision)
+    }
+}
+
+/// Write the header row of the per-read TSV output, with a column per BAM tag
+/// in `extra_columns`
+pub(crate) fn write_tsv_header<W: Write>(
+    w: &mut W,
+    extra_columns: &[&str],
+) -> Result<(), NanogetError> {
+    write!(w, "read_id\tlength\tquality\tquality_std\taligned_length\taligned_quality\tmapping_quality\tpercent_identity\tchannel_id\tmux\tend_reason\tstart_time\tduration\tbarcode\trun_id\tdataset\tparent_id\tpolya_length\tn_count\tgc_content")?;
+    for column in extra_columns {
+        write!(w, "\t{}", column)?;
+    }
+    writeln!(w)?;
+    Ok(())
+}
+
+/// Write the TSV row of `read`, with `precision` decimal places for its
+/// fractional values (3 when `None`)
+pub(crate) fn write_tsv_row<W: Write>(
+    w: &mut W,
+    read: &ReadMetrics,
+    extra_columns: &[&str],
+    precision: Option<usize>,
+) -> Result<(), NanogetError> {
+    let read_precision = precision.unwrap_or(3);
+    write!(
+        w,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        read.read_id.as_deref().unwrap_or(""),
+        read.length,
+        read.quality
+            .map(|q| format!("{:.*}", read_precision, q))
+            .unwrap_or_default(),
+        read.quality_std
+            .map(|s| format!("{:.*}", read_precision, s))
+            .unwrap_or_default(),
+        read.aligned_length()
+            .map(|l| l.to_string())
+            .unwrap_or_default(),
+        read.aligned_quality()
+            .map(|q| format!("{:.*}", read_precision, q))
+            .unwrap_or_default(),
+        read.mapping_quality()
+            .map(|q| q.to_string())
+            .unwrap_or_default(),
+        read.percent_identity()
+            .map(|p| format!("{:.*}", read_precision, p))
+            .unwrap_or_default(),
+        read.channel_id().map(|c| c.to_string()).unwrap_or_default(),
+        read.mux().map(|m| m.to_string()).unwrap_or_default(),
+        read.end_reason().unwrap_or(""),
+        read.start_time()
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default(),
+        read.duration()
+            .map(|d| format!("{:.*}", read_precision, d))
+            .unwrap_or_default(),
+        read.barcode().map(AsRef::as_ref).unwrap_or(""),
+        read.run_id().map(AsRef::as_ref).unwrap_or(""),
+        read.dataset().map(AsRef::as_ref).unwrap_or(""),
+        read.parent_id().unwrap_or(""),
+        read.polya_length()
+            .map(|l| l.to_string())
+            .unwrap_or_default(),
+        read.n_count().map(|n| n.to_string()).unwrap_or_default(),
+        read.gc_content()
+            .map(|gc| format!("{:.*}", read_precision, gc))
+            .unwrap_or_default()
+    )?;
+    for column in extra_columns {
+        match (read.extra().get(*column), read.extra_text().get(*column)) {
+            (Some(value), _) => write!(w, "\t{}", value)?,
+            (None, Some(text)) => write!(w, "\t{}", text)?,
+            (None, None) => write!(w, "\t")?,
+        }
+    }
+    writeln!(w)?;
+    Ok(())
+}
+
+impl Extend<ReadMetrics> for MetricsCollection {
+    /// Append all reads, recomputing the summary once at the end
+    fn extend<T: IntoIterator<Item = ReadMetrics>>(&mut self, iter: T) {
+        self.reads.extend(iter);
+        self.refresh_summary();
+    }
+}
+
+impl IntoIterator for MetricsCollection {
+    type Item = ReadMetrics;
+    type IntoIter = std::vec::IntoIter<ReadMetrics>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.reads.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MetricsCollection {
+    type Item = &'a ReadMetrics;
+    type IntoIter = std::slice::Iter<'a, ReadMetrics>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.reads.iter()
+    }
+}
+
+/// Version of the JSON output format, recorded in `Provenance` and in the
+/// schema printed by `nanoget schema`; raised when fields are renamed,
+/// removed or change type (adding a field does not change it)
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `schema` as JSON, with `SCHEMA_VERSION` added as `x-schema-version`
+fn versioned_schema(schema: schemars::Schema) -> serde_json::Value {
+    let mut schema = schema.to_value();
+    if let Some(fields) = schema.as_object_mut() {
+        fields.insert("x-schema-version".to_string(), SCHEMA_VERSION.into());
+    }
+    schema
+}
+
+/// How an output document was produced
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Provenance {
+    /// Version of nanoget that produced the output
+    pub nanoget_version: String,
+
+    /// Version of the output format (see `SCHEMA_VERSION`); 0 for outputs
+    /// written before it was versioned
+    #[serde(default)]
+    pub schema_version: u32,
+
+    /// Random downsampling applied after extraction (if any)
+    pub sampling: Option<SamplingInfo>,
+
+    /// What the extraction cost, with `--resource-report`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceReport>,
+}
+
+impl Default for Provenance {
+    fn default() -> Self {
+        Self {
+            nanoget_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version: SCHEMA_VERSION,
+            sampling: None,
+            resources: None,
+        }
+    }
+}
+
+/// Parameters of a `--downsample` run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SamplingInfo {
+    /// Number of reads requested
+    pub requested: usize,
+
+    /// Seed of the sampling
+    pub seed: u64,
+
+    /// Number of reads before sampling
+    pub original_read_count: usize,
+}
+
+/// Uniform random sample of a fixed number of reads from a stream of unknown
+/// length (reservoir sampling, Algorithm R). Memory is bounded by `n` reads, so
+/// it can also sit in front of a streaming consumer. Deterministic for a seed.
+#[derive(Debug)]
+pub struct ReservoirSampler {
+    n: usize,
+    seen: u64,
+    rng: utils::SplitMix64,
+    /// (input index, read) so that `finish` can restore the input order
+    reservoir: Vec<(u64, ReadMetrics)>,
+}
+
+impl ReservoirSampler {
+    pub fn new(n: usize, seed: u64) -> Self {
+        Self {
+            n,
+            seen: 0,
+            rng: utils::SplitMix64::new(seed),
+            reservoir: Vec::new(),
+        }
+    }
+
+    /// Offer one read to the sample
+    pub fn add(&mut self, read: ReadMetrics) {
+        let index = self.seen;
+        self.seen += 1;
+        if self.reservoir.len() < self.n {
+            self.reservoir.push((index, read));
+        } else if self.n > 0 {
+            let slot = self.rng.below(index + 1) as usize;
+            if slot < self.n {
+                self.reservoir[slot] = (index, read);
+            }
+        }
+    }
+
+    /// The sampled reads, in input order
+    pub fn finish(mut self) -> Vec<ReadMetrics> {
+        self.reservoir.sort_unstable_by_key(|(index, _)| *index);
+        self.reservoir.into_iter().map(|(_, read)| read).collect()
+    }
+}
+
+/// Write one `# <label> stats - ...` comment line of the TSV summary section
+fn write_stats_line<W: Write>(
+    w: &mut W,
+    label: &str,
+    stats: &StatsSummary,
+    precision: usize,
+) -> Result<(), NanogetError> {
+    write!(w, "# {} stats - count: {}", label, stats.count)?;
+    let fields = [
+        ("mean", stats.mean),
+        ("median", stats.median),
+        ("min", stats.min),
+        ("max", stats.max),
+        ("std_dev", stats.std_dev),
+        ("q25", stats.q25),
+        ("q75", stats.q75),
+        ("mad", stats.mad),
+        ("iqr", stats.iqr),
+    ];
+    for (name, value) in fields {
+        write!(w, ", {}: {:.*}", name, precision, value)?;
+    }
+    for (percentile, value) in stats.extra_percentiles.iter().flatten() {
+        write!(w, ", p{}: {:.*}", percentile, precision, value)?;
+    }
+    writeln!(w)?;
+    Ok(())
+}
+
+/// Summary statistics for a collection of reads
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MetricsSummary {
+    /// Total number of reads
+    pub read_count: usize,
+
+    /// Reads dropped by the extraction filters (`--min-length`, `--max-length`,
+    /// `--min-quality`, `--min-gc`, `--max-gc`), per criterion; None when no
+    /// filter was active. Not part of `read_count`.
+    pub excluded_reads: Option<ExcludedReads>,
+
+    /// Length statistics
+    pub length_stats: StatsSummary,
+
+    /// Most populated bin of the read length histogram, reported as its range
+    /// rather than its center, which can be far from every read when the
+    /// reads are much shorter than the bin (None without reads)
+    pub length_mode_bin: Option<LengthBin>,
+
+    /// Up to three histogram peaks (bins holding more reads than their
+    /// neighbours), most populated first, for multi-modal runs (if requested)
+    pub length_modes: Option<Vec<LengthBin>>,
+
+    /// Statistics of log10(length) over the reads with a length, as read
+    /// lengths are roughly log-normal: 10 to the mean is the geometric mean
+    /// length (if requested)
+    pub log_length_stats: Option<StatsSummary>,
+
+    /// Reads up to a maximum length, a spike of which points to adapter
+    /// dimers, with a warning above the limit (if requested)
+    pub short_read_spike: Option<ShortReadSpike>,
+
+    /// (read id, length) of the longest read, the first one on ties (if that
+    /// read has an id)
+    pub longest_read: Option<(String, u64)>,
+
+    /// Quality statistics (if available)
+    pub quality_stats: Option<StatsSummary>,
+
+    /// (read id, quality) of the read with the highest mean quality, the first
+    /// one on ties (if qualities are available and that read has an id)
+    pub highest_quality_read: Option<(String, f64)>,
+
+    /// Statistics of the per-read quality standard deviations (if available)
+    pub quality_std_stats: Option<StatsSummary>,
+
+    /// Mapping quality statistics (if available)
+    pub mapping_quality_stats: Option<StatsSummary>,
+
+    /// Percent identity statistics (if available)
+    pub percent_identity_stats: Option<StatsSummary>,
+
+    /// Aligned length statistics (if available)
+    pub aligned_length_stats: Option<StatsSummary>,
+
+    /// N50 of the aligned lengths (if available; not in streaming mode)
+    pub aligned_length_n50: Option<u64>,
+
+    /// Aligned reads per number of alignments (primary plus supplementary
+    /// records sharing a read id), e.g. {1: 950, 2: 48, 3: 2} (if available;
+    /// not in streaming mode)
+    pub alignment_count_distribution: Option<HashMap<usize, usize>>,
+
+    /// Fraction of the aligned reads with more than one alignment: split or
+    /// chimeric reads (if available; not in streaming mode)
+    pub multi_alignment_fraction: Option<f64>,
+
+    /// Read duration statistics, in seconds (if available)
+    pub duration_stats: Option<StatsSummary>,
+
+    /// Poly(A) tail length statistics, from the dorado `pt`/`pa` aux tags (if available)
+    pub polya_length_stats: Option<StatsSummary>,
+
+    /// Fraction of ambiguous (non-ACGT) bases in the reads with an N count (if available)
+    pub n_fraction: Option<f64>,
+
+    /// Channel distribution (if available)
+    pub channel_distribution: Option<HashMap<u32, usize>>,
+
+    /// Gini coefficient of the reads per active channel: 0 when every channel
+    /// that produced reads produced equally many, approaching 1 as reads
+    /// concentrate on a few channels (if channels are available)
+    pub channel_uniformity: Option<f64>,
+
+    /// (channel, reads) of the channel with the most reads (if available)
+    pub busiest_channel: Option<(u32, usize)>,
+
+    /// Reads per mux, to spot uneven mux usage (if available)
+    pub mux_distribution: Option<HashMap<u8, usize>>,
+
+    /// Reads per end reason, e.g. to see how many reads adaptive sampling
+    /// rejected (if available)
+    pub end_reason_distribution: Option<HashMap<String, usize>>,
+
+    /// Barcode distribution (if available)
+    pub barcode_distribution: Option<HashMap<String, usize>>,
+
+    /// Fraction of the barcode-labelled reads assigned to a real barcode, i.e.
+    /// not `unclassified` (if barcodes are available)
+    pub demux_rate: Option<f64>,
+
+    /// Reads labelled `unclassified`, in any case (if barcodes are available)
+    pub unclassified_count: Option<usize>,
+
+    /// Reads without any barcode label, e.g. from an input without barcode
+    /// information; they are not part of `demux_rate` (if barcodes are available)
+    pub missing_barcode_count: Option<usize>,
+
+    /// Yield, N50 and quality per barcode, leaving out `unclassified` reads
+    /// (if barcodes are available)
+    pub per_barcode: Option<HashMap<String, GroupSummary>>,
+
+    /// Yield, N50 and quality of simplex and duplex reads (if `dx` tags are available)
+    pub per_read_class: Option<HashMap<ReadClass, GroupSummary>>,
+
+    /// Reads and bases per time bin since the earliest read (if start times are
+    /// available); only the non-empty bins when the series would be longer
+    /// than `MAX_THROUGHPUT_BINS`
+    pub throughput_over_time: Option<Vec<ThroughputBin>>,
+
+    /// Throughput per dataset, each binned from its own earliest read (track mode)
+    pub dataset_throughput_over_time: Option<HashMap<String, Vec<ThroughputBin>>>,
+
+    /// (reads, bases) points on the curve of reads sorted longest first:
+    /// the N longest reads contain this many bases (if requested)
+    pub cumulative_yield: Option<Vec<(u64, u64)>>,
+
+    /// ((length, quality), reads) cells of the length-vs-quality density (if requested)
+    pub length_quality_density: Option<Vec<((u64, f64), usize)>>,
+}
+
+impl MetricsSummary {
+    /// JSON Schema of the summary as written by `nanoget extract -f
+    /// json-summary` (see `MetricsCollection::json_schema`)
+    pub fn json_schema() -> serde_json::Value {
+        versioned_schema(schemars::schema_for!(MetricsSummary))
+    }
+
+    /// Write the summary as the `# `-prefixed comment section of the TSV output,
+    /// with `precision` decimal places (2 by default)
+    pub fn write_tsv<W: Write>(
+        &self,
+        w: &mut W,
+        precision: Option<usize>,
+    ) -> Result<(), NanogetError> {
+        let stats_precision = precision.unwrap_or(2);
+
+        writeln!(w, "# Summary Statistics")?;
+        writeln!(w, "# Total reads: {}", self.read_count)?;
+        if let Some(excluded) = &self.excluded_reads {
+            writeln!(
+                w,
+                "# Excluded reads: {} (min length: {}, max length: {}, min quality: {}, GC content: {})",
+                excluded.total(),
+                excluded.min_length,
+                excluded.max_length,
+                excluded.min_quality,
+                excluded.gc_content
+            )?;
+        }
+
+        write_stats_line(w, "Length", &self.length_stats, stats_precision)?;
+        if let Some(mode) = &self.length_mode_bin {
+            writeln!(
+                w,
+                "# Length mode bin: [{}, {}) ({} reads)",
+                mode.start, mode.end, mode.read_count
+            )?;
+        }
+        if let Some(modes) = &self.length_modes {
+            let modes: Vec<String> = modes
+                .iter()
+                .map(|mode| format!("[{}, {}) ({} reads)", mode.start, mode.end, mode.read_count))
+                .collect();
+            writeln!(w, "# Length modes: {}", modes.join(", "))?;
+        }
+        if let Some(log_length_stats) = &self.log_length_stats {
+            write_stats_line(w, "Log10 length", log_length_stats, stats_precision)?;
+        }
+        if let Some(spike) = &self.short_read_spike {
+            writeln!(
+                w,
+                "# Short reads (at most {} bases): {} ({:.*}%)",
+                spike.max_length,
+                spike.count,
+                stats_precision,
+                spike.fraction * 100.0
+            )?;
+            if let Some(warning) = &spike.warning {
+                writeln!(w, "# Warning: {}", warning)?;
+            }
+        }
+        if let Some((read_id, length)) = &self.longest_read {
+            writeln!(w, "# Longest read: {} ({} bases)", read_id, length)?;
+        }
+        if let Some(quality_stats) = &self.quality_stats {
+            write_stats_line(w, "Quality", quality_stats, stats_precision)?;
+        }
+        if let Some((read_id, quality)) = &self.highest_quality_read {
+            writeln!(
+                w,
+                "# Highest quality read: {} (Q{:.*})",
+                read_id, stats_precision, quality
+            )?;
+        }
+        if let Some(quality_std_stats) = &self.quality_std_stats {
+            write_stats_line(w, "Quality std", quality_std_stats, stats_precision)?;
+        }
+        if let Some(mapping_quality_stats) = &self.mapping_quality_stats {
+            write_stats_line(w, "Mapping quality", mapping_quality_stats, stats_precision)?;
+        }
+        if let Some(percent_identity_stats) = &self.percent_identity_stats {
+            write_stats_line(
+                w,
+                "Percent identity",
+                percent_identity_stats,
+                stats_precision,
+            )?;
+        }
+        if let Some(aligned_length_stats) = &self.aligned_length_stats {
+            write_stats_line(w, "Aligned length", aligned_length_stats, stats_precision)?;
+        }
+        if let Some(n50) = self.aligned_length_n50 {
+            writeln!(w, "# Aligned length N50: {}", n50)?;
+        }
+        if let (Some(distribution), Some(fraction)) = (
+            &self.alignment_count_distribution,
+            self.multi_alignment_fraction,
+        ) {
+            let mut counts: Vec<(&usize, &usize)> = distribution.iter().collect();
+            counts.sort();
+            let counts: Vec<String> = counts
+                .into_iter()
+                .map(|(alignments, reads)| format!("{}: {}", alignments, reads))
+                .collect();
+            writeln!(
+                w,
+                "# Alignments per read: {} ({:.*}% of reads with more than one)",
+                counts.join(", "),
+                stats_precision,
+                fraction * 100.0
+            )?;
+        }
+        if let Some(duration_stats) = &self.duration_stats {
+            write_stats_line(w, "Duration", duration_stats, stats_precision)?;
+        }
+        if let Some(polya_length_stats) = &self.polya_length_stats {
+            write_stats_line(w, "Poly(A) length", polya_length_stats, stats_precision)?;
+        }
+        if let Some(n_fraction) = self.n_fraction {
+            writeln!(w, "# N fraction: {:.*}", stats_precision + 2, n_fraction)?;
+        }
+        if let (Some(gini), Some((channel, reads))) =
+            (self.channel_uniformity, self.busiest_channel)
+        {
+            writeln!(
+                w,
+                "# Channel uniformity (Gini): {:.*}, busiest channel: {} ({} reads)",
+                stats_precision, gini, channel, reads
+            )?;
+        }
+        if let Some(mux_distribution) = &self.mux_distribution {
+            let mut muxes: Vec<(&u8, &usize)> = mux_distribution.iter().collect();
+            muxes.sort();
+            let muxes: Vec<String> = muxes
+                .into_iter()
+                .map(|(mux, reads)| format!("{}: {}", mux, reads))
+                .collect();
+            writeln!(w, "# Mux distribution: {}", muxes.join(", "))?;
+        }
+        if let Some(end_reasons) = &self.end_reason_distribution {
+            writeln!(
+                w,
+                "# End reasons: {}",
+                format_end_reasons(end_reasons, stats_precision)
+            )?;
+        }
+
+        if let (Some(rate), Some(unclassified), Some(missing)) = (
+            self.demux_rate,
+            self.unclassified_count,
+            self.missing_barcode_count,
+        ) {
+            writeln!(
+                w,
+                "# Demultiplexing rate: {:.*} (unclassified: {}, without barcode: {})",
+                stats_precision + 2,
+                rate,
+                unclassified,
+                missing
+            )?;
+        }
+        if let Some(per_barcode) = &self.per_barcode {
+            let mut groups: Vec<(&str, &GroupSummary)> = per_barcode
+                .iter()
+                .map(|(barcode, group)| (barcode.as_str(), group))
+                .collect();
+            groups.sort_by_key(|(barcode, _)| *barcode);
+            write_group_table(w, "barcode", &groups, stats_precision)?;
+        }
+        if let Some(per_read_class) = &self.per_read_class {
+            let mut groups: Vec<(&str, &GroupSummary)> = per_read_class
+                .iter()
+                .map(|(class, group)| (class.as_str(), group))
+                .collect();
+            groups.sort_by_key(|(class, _)| *class);
+            write_group_table(w, "read_class", &groups, stats_precision)?;
+        }
+
+        Ok(())
+    }
+
+    /// Calculate summary statistics from a collection of reads
+    #[allow(dead_code)]
+    pub fn from_reads(reads: &[ReadMetrics]) -> Self {
+        Self::from_reads_with_options(reads, &SummaryOptions::default())
+    }
+
+    /// Calculate summary statistics from a collection of reads with the given options
+    pub fn from_reads_with_options(reads: &[ReadMetrics], options: &SummaryOptions) -> Self {
+        let refs: Vec<&ReadMetrics> = reads.iter().collect();
+        Self::from_read_refs(&refs, options)
+    }
+
+    /// `from_reads_with_options` over borrowed reads, such as a
+    /// `MetricsCollection::view`, so a subset can be summarized without
+    /// cloning it
+    pub fn from_read_refs(reads: &[&ReadMetrics], options: &SummaryOptions) -> Self {
+        let read_count = reads.len();
+
+        // One pass over the reads collects the values of every metric and the
+        // reads per channel, barcode, read class and dataset
+        let mut lengths: Vec<u64> = Vec::with_capacity(read_count);
+        let mut length_histogram = LengthHistogram::new(options.length_bin);
+        let mut log_lengths: Vec<f64> = Vec::new();
+        let mut short_reads = 0;
+        let mut qualities: Vec<f64> = Vec::with_capacity(read_count);
+        let mut quality_stds: Vec<f64> = Vec::new();
+        let mut mapping_qualities: Vec<u8> = Vec::new();
+        let mut percent_identities: Vec<f64> = Vec::new();
+        let mut aligned_lengths: Vec<u64> = Vec::new();
+        let mut alignment_counts: HashMap<(Option<&str>, &str), usize> = HashMap::new();
+        let mut durations: Vec<f64> = Vec::new();
+        let mut polya_lengths: Vec<f64> = Vec::new();
+        let mut ambiguous_bases = (0, 0);
+        let mut channel_counts: HashMap<u32, usize> = HashMap::new();
+        let mut mux_counts: HashMap<u8, usize> = HashMap::new();
+        let mut end_reason_counts: HashMap<String, usize> = HashMap::new();
+        let mut barcode_counts: HashMap<String, usize> = HashMap::new();
+        let mut barcode_reads: HashMap<&str, Vec<&ReadMetrics>> = HashMap::new();
+        let mut class_reads: HashMap<ReadClass, Vec<&ReadMetrics>> = HashMap::new();
+        let mut dataset_reads: HashMap<&str, Vec<&ReadMetrics>> = HashMap::new();
+        let mut longest_read = None;
+        let mut highest_quality_read = None;
+        for &read in reads {
+            lengths.push(read.length);
+            length_histogram.add(read.length);
+            if options.log_length && read.length > 0 {
+                log_lengths.push((read.length as f64).log10());
+            }
+            if let Some((max_length, _)) = options.short_read_spike {
+                if read.length <= max_length {
+                    short_reads += 1;
+                }
+            }
+            keep_top(&mut longest_read, read, Some(read.length));
+            keep_top(&mut highest_quality_read, read, read.quality);
+            match read.quality {
+                Some(q) => qualities.push(q),
+                None if options.missing_quality == MissingQuality::Zero => qualities.push(0.0),
+                None => {}
+            }
+            if let Some(std) = read.quality_std {
+                quality_stds.push(std);
+            }
+            if let Some(q) = read.mapping_quality() {
+                mapping_qualities.push(q);
+            }
+            if let Some(identity) = read.percent_identity() {
+                percent_identities.push(identity);
+            }
+            if let Some(aligned_length) = read.aligned_length() {
+                aligned_lengths.push(aligned_length);
+                if let Some(read_id) = &read.read_id {
+                    *alignment_counts
+                        .entry(alignment_key(read, read_id))
+                        .or_insert(0) += 1;
+                }
+            }
+            if let Some(duration) = read.duration() {
+                durations.push(duration);
+            }
+            if let Some(polya_length) = read.polya_length() {
+                polya_lengths.push(f64::from(polya_length));
+            }
+            if let Some(n) = read.n_count() {
+                ambiguous_bases.0 += n;
+                ambiguous_bases.1 += read.length;
+            }
+            if let Some(channel) = read.channel_id() {
+                *channel_counts.entry(channel).or_insert(0) += 1;
+            }
+            if let Some(mux) = read.mux() {
+                *mux_counts.entry(mux).or_insert(0) += 1;
+            }
+            if let Some(end_reason) = read.end_reason() {
+                count_label(&mut end_reason_counts, end_reason);
+            }
+            if let Some(barcode) = read.barcode() {
+                count_label(&mut barcode_counts, barcode);
+                if !is_unclassified(barcode) {
+                    barcode_reads.entry(barcode).or_default().push(read);
+                }
+            }
+            if let Some(class) = read.read_class() {
+                class_reads.entry(class).or_default().push(read);
+            }
+            if let Some(dataset) = read.dataset() {
+                dataset_reads.entry(dataset).or_default().push(read);
+            }
+        }
+
+        // The exact quantiles and the MAD need every metric sorted. Integer
+        // values are sorted as integers, which orders their f64 conversions
+        // the same way, faster than sorting the floats.
+        let stats = |values: Vec<f64>| {
+            (!values.is_empty())
+                .then(|| StatsSummary::from_vec_with_percentiles(values, &options.percentiles))
+        };
+        let sorted_stats = |sorted: &[f64]| {
+            (!sorted.is_empty())
+                .then(|| StatsSummary::from_sorted_with_percentiles(sorted, &options.percentiles))
+        };
+        lengths.sort_unstable();
+        let sorted_lengths: Vec<f64> = lengths.iter().map(|&l| l as f64).collect();
+        let length_stats =
+            StatsSummary::from_sorted_with_percentiles(&sorted_lengths, &options.percentiles);
+        let log_length_stats = stats(log_lengths);
+        let short_read_spike = options.short_read_spike.map(|(max_length, limit)| {
+            ShortReadSpike::new(max_length, short_reads, read_count, limit)
+        });
+        let quality_stats = stats(qualities);
+        let quality_std_stats = stats(quality_stds);
+        mapping_qualities.sort_unstable();
+        let mapping_quality_stats = sorted_stats(
+            &mapping_qualities
+                .iter()
+                .map(|&q| f64::from(q))
+                .collect::<Vec<f64>>(),
+        );
+        let percent_identity_stats = stats(percent_identities);
+        aligned_lengths.sort_unstable();
+        let aligned_length_stats = sorted_stats(
+            &aligned_lengths
+                .iter()
+                .map(|&l| l as f64)
+                .collect::<Vec<f64>>(),
+        );
+        let aligned_length_n50 = (!aligned_lengths.is_empty()).then(|| n50(&aligned_lengths));
+        let (alignment_count_distribution, multi_alignment_fraction) =
+            if !alignment_counts.is_empty() {
+                let mut distribution: HashMap<usize, usize> = HashMap::new();
+                for &alignments in alignment_counts.values() {
+                    *distribution.entry(alignments).or_insert(0) += 1;
+                }
+                let multi = alignment_counts.values().filter(|&&n| n > 1).count();
+                (
+                    Some(distribution),
+                    Some(multi as f64 / alignment_counts.len() as f64),
+                )
+            } else {
+                (None, None)
+            };
+        let duration_stats = stats(durations);
+        let polya_length_stats = stats(polya_lengths);
+        let n_fraction = ambiguous_fraction(std::iter::once(ambiguous_bases));
+
+        let channel_uniformity = channel_gini(&channel_counts);
+        let busiest_channel = busiest_channel(&channel_counts);
+        let channel_distribution = if !channel_counts.is_empty() {
+            Some(channel_counts)
+        } else {
+            None
+        };
+        let mux_distribution = if !mux_counts.is_empty() {
+            Some(mux_counts)
+        } else {
+            None
+        };
+        let end_reason_distribution = if !end_reason_counts.is_empty() {
+            Some(end_reason_counts)
+        } else {
+            None
+        };
+        let (demux_rate, unclassified_count, missing_barcode_count) =
+            demultiplexing(&barcode_counts, read_count);
+        let barcode_distribution = if !barcode_counts.is_empty() {
+            Some(barcode_counts)
+        } else {
+            None
+        };
+        let per_read_class = if !class_reads.is_empty() {
+            Some(
+                class_reads
+                    .into_iter()
+                    .map(|(class, reads)| (class, GroupSummary::from_reads(reads)))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        let per_barcode = if !barcode_reads.is_empty() {
+            Some(
+                barcode_reads
+                    .into_iter()
+                    .map(|(barcode, reads)| (barcode.to_string(), GroupSummary::from_reads(reads)))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        // Throughput over time, overall and per dataset
+        let throughput_over_time = compute_throughput(
+            reads.iter().copied(),
+            options.throughput_bin_seconds,
+            options.run_start,
+        );
+        let dataset_throughput: HashMap<String, Vec<ThroughputBin>> = dataset_reads
+            .into_iter()
+            .filter_map(|(dataset, reads)| {
+                compute_throughput(reads, options.throughput_bin_seconds, options.run_start)
+                    .map(|bins| (dataset.to_string(), bins))
+            })
+            .collect();
+        let dataset_throughput_over_time = if !dataset_throughput.is_empty() {
+            Some(dataset_throughput)
+        } else {
+            None
+        };
+
+        let cumulative_yield = if options.cumulative_yield {
+            compute_cumulative_yield(reads.iter().copied(), CUMULATIVE_YIELD_POINTS)
+        } else {
+            None
+        };
+
+        let length_quality_density = options.length_quality_density.map(|(len_bin, qual_bin)| {
+            compute_length_quality_density(reads.iter().copied(), len_bin, qual_bin)
+        });
+
+        Self {
+            read_count,
+            length_stats,
+            excluded_reads: None,
+            length_mode_bin: length_histogram.mode(),
+            length_modes: options
+                .length_modes
+                .then(|| length_histogram.peaks(LENGTH_MODES)),
+            log_length_stats,
+            short_read_spike,
+            longest_read: top_read(longest_read),
+            quality_stats,
+            highest_quality_read: top_read(highest_quality_read),
+            quality_std_stats,
+            mapping_quality_stats,
+            percent_identity_stats,
+            aligned_length_stats,
+            aligned_length_n50,
+            alignment_count_distribution,
+            multi_alignment_fraction,
+            duration_stats,
+            polya_length_stats,
+            n_fraction,
+            channel_distribution,
+            channel_uniformity,
+            busiest_channel,
+            mux_distribution,
+            end_reason_distribution,
+            barcode_distribution,
+            demux_rate,
+            unclassified_count,
+            missing_barcode_count,
+            per_barcode,
+            per_read_class,
+            throughput_over_time,
+            dataset_throughput_over_time,
+            cumulative_yield,
+            length_quality_density,
+        }
+    }
+}
+
+/// Replace `top` by (read id, `value`) of `read` when the value is larger, so
+/// that the first read with the largest value is kept; reads without a value
+/// are skipped. Reads without an id still compete (see `top_read`).
+fn keep_top<T: PartialOrd + Copy>(
+    top: &mut Option<(Option<String>, T)>,
+    read: &ReadMetrics,
+    value: Option<T>,
+) {
+    if let Some(value) = value {
+        if top.as_ref().is_none_or(|(_, best)| value > *best) {
+            *top = Some((read.read_id.clone(), value));
+        }
+    }
+}
+
+/// The read kept by `keep_top`, if it has an id: a read without one cannot be
+/// reported, and a shorter or lower-quality read must not stand in for it
+fn top_read<T>(top: Option<(Option<String>, T)>) -> Option<(String, T)> {
+    let (read_id, value) = top?;
+    Some((read_id?, value))
+}
+
+/// Whether a barcode label means the read could not be demultiplexed
+fn is_unclassified(barcode: &str) -> bool {
+    barcode.eq_ignore_ascii_case("unclassified")
+}
+
+/// (demux rate, unclassified reads, reads without a barcode) from the reads per
+/// barcode label and the total read count; all `None` without any label
+fn demultiplexing(
+    barcode_counts: &HashMap<String, usize>,
+    read_count: usize,
+) -> (Option<f64>, Option<usize>, Option<usize>) {
+    if barcode_counts.is_empty() {
+        return (None, None, None);
+    }
+    let labelled: usize = barcode_counts.values().sum();
+    let unclassified: usize = barcode_counts
+        .iter()
+        .filter(|(barcode, _)| is_unclassified(barcode))
+        .map(|(_, count)| count)
+        .sum();
+    (
+        Some((labelled - unclassified) as f64 / labelled as f64),
+        Some(unclassified),
+        Some(read_count - labelled),
+    )
+}
+
+/// Gini coefficient of the reads per channel. Only channels that produced
+/// reads are counted: a channel missing from `counts` may be inactive or may
+/// not exist on the flow cell, so it is not taken as a zero.
+fn channel_gini(counts: &HashMap<u32, usize>) -> Option<f64> {
+    let mut values: Vec<f64> = counts.values().map(|&c| c as f64).collect();
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let n = values.len() as f64;
+    let total: f64 = values.iter().sum();
+    let weighted: f64 = values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i + 1) as f64 * v)
+        .sum();
+    Some((2.0 * weighted / (n * total) - (n + 1.0) / n).max(0.0))
+}
+
+/// The channel with the most reads; ties go to the lowest channel number
+fn busiest_channel(counts: &HashMap<u32, usize>) -> Option<(u32, usize)> {
+    counts
+        .iter()
+        .map(|(&channel, &reads)| (channel, reads))
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+}
+
+/// Ambiguous bases over total bases of (n_count, length) pairs; `None` without
+/// any pair or bases
+fn ambiguous_fraction(counts: impl Iterator<Item = (u64, u64)>) -> Option<f64> {
+    let (ambiguous, bases) = counts.fold((0u64, 0u64), |(a, b), (n, len)| (a + n, b + len));
+    (bases > 0).then(|| ambiguous as f64 / bases as f64)
+}
+
+/// Write a `# Per-<key> summary` TSV comment section with one row per group
+fn write_group_table<W: Write>(
+    w: &mut W,
+    key: &str,
+    groups: &[(&str, &GroupSummary)],
+    precision: usize,
+) -> Result<(), NanogetError> {
+    writeln!(w, "\n# Per-{} summary", key.replace('_', "-"))?;
+    writeln!(
+        w,
+        "# {}\treads\tbases\tn50\tmean_length\tmedian_length\tmean_quality",
+        key
+    )?;
+    for (name, group) in groups {
+        writeln!(
+            w,
+            "# {}\t{}\t{}\t{}\t{:.*}\t{:.*}\t{}",
+            name,
+            group.read_count,
+            group.bases,
+            group.n50,
+            precision,
+            group.mean_length,
+            precision,
+            group.median_length,
+            group
+                .mean_quality
+                .map(|q| format!("{:.*}", precision, q))
+                .unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+/// Compact summary of a group of reads (e.g. one barcode), kept small so that
+/// runs with hundreds of groups do not repeat the full `MetricsSummary`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GroupSummary {
+    /// Number of reads in the group
+    pub read_count: usize,
+
+    /// Total number of bases
+    pub bases: u64,
+
+    /// Read length N50
+    pub n50: u64,
+
+    /// Mean read length
+    pub mean_length: f64,
+
+    /// Median read length
+    pub median_length: f64,
+
+    /// Mean of the per-read average qualities (if available)
+    pub mean_quality: Option<f64>,
+
+    /// Reads per end reason (if available), e.g. adaptive sampling rejections
+    /// per barcode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_reasons: Option<HashMap<String, usize>>,
+}
+
+impl GroupSummary {
+    /// Summarize a group of reads without copying them
+    pub fn from_reads<'a>(reads: impl IntoIterator<Item = &'a ReadMetrics>) -> Self {
+        let mut lengths = Vec::new();
+        let mut quality_sum = 0.0;
+        let mut quality_count = 0usize;
+        let mut end_reasons = HashMap::new();
+        for read in reads {
+            lengths.push(read.length);
+            if let Some(end_reason) = read.end_reason() {
+                count_label(&mut end_reasons, end_reason);
+            }
+            if let Some(q) = read.quality.filter(|q| !q.is_nan()) {
+                quality_sum += q;
+                quality_count += 1;
+            }
+        }
+        lengths.sort_unstable();
+
+        let read_count = lengths.len();
+        let bases: u64 = lengths.iter().sum();
+        let sorted: Vec<f64> = lengths.iter().map(|&l| l as f64).collect();
+        Self {
+            read_count,
+            bases,
+            n50: n50(&lengths),
+            mean_length: if read_count > 0 {
+                bases as f64 / read_count as f64
+            } else {
+                0.0
+            },
+            median_length: calculate_percentile(&sorted, 50.0),
+            mean_quality: if quality_count > 0 {
+                Some(quality_sum / quality_count as f64)
+            } else {
+                None
+            },
+            end_reasons: (!end_reasons.is_empty()).then_some(end_reasons),
+        }
+    }
+}
+
+/// Alignment records per (dataset, read id), over the reads with an alignment
+fn count_alignments<'a>(
+    reads: impl IntoIterator<Item = &'a ReadMetrics>,
+) -> HashMap<(Option<&'a str>, &'a str), usize> {
+    let mut counts = HashMap::new();
+    for read in reads
+        .into_iter()
+        .filter(|read| read.aligned_length().is_some())
+    {
+        if let Some(read_id) = &read.read_id {
+            *counts.entry(alignment_key(read, read_id)).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// The key of `read` in `count_alignments`: the same read id in two datasets
+/// (track mode) is two reads
+fn alignment_key<'a>(read: &'a ReadMetrics, read_id: &'a str) -> (Option<&'a str>, &'a str) {
+    (read.dataset().map(AsRef::as_ref), read_id)
+}
+
+/// Add a read to the reads per label (end reason, barcode), copying the label
+/// only when it is first seen
+fn count_label(counts: &mut HashMap<String, usize>, label: &str) {
+    match counts.get_mut(label) {
+        Some(count) => *count += 1,
+        None => {
+            counts.insert(label.to_string(), 1);
+        }
+    }
+}
+
+/// End reasons with their read counts and percentages, most common first
+fn format_end_reasons(end_reasons: &HashMap<String, usize>, precision: usize) -> String {
+    let total: usize = end_reasons.values().sum();
+    let mut end_reasons: Vec<(&String, &usize)> = end_reasons.iter().collect();
+    end_reasons.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    end_reasons
+        .into_iter()
+        .map(|(reason, reads)| {
+            format!(
+                "{}: {} ({:.*}%)",
+                reason,
+                reads,
+                precision,
+                *reads as f64 / total as f64 * 100.0
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Length N50 of ascending sorted lengths: the length of the read at which the
+/// reads this long or longer contain at least half of all bases
+pub(crate) fn n50(sorted_lengths: &[u64]) -> u64 {
+    let total: u64 = sorted_lengths.iter().sum();
+    let mut cumulative: u64 = 0;
+    for &length in sorted_lengths.iter().rev() {
+        cumulative += length;
+        if cumulative * 2 >= total {
+            return length;
+        }
+    }
+    0
+}
+
+/// Bin reads with a quality into a 2D length/quality grid; see
+/// `MetricsCollection::length_quality_density`
+fn compute_length_quality_density<'a>(
+    reads: impl IntoIterator<Item = &'a ReadMetrics>,
+    len_bin: u64,
+    qual_bin: f64,
+) -> Vec<((u64, f64), usize)> {
+    if !(qual_bin > 0.0 && qual_bin.is_finite()) {
+        return Vec::new();
+    }
+    let len_bin = len_bin.max(1);
+
+    // Key on integer bin indices: floats are neither Ord nor reliably hashable
+    let mut cells: BTreeMap<(u64, i64), usize> = BTreeMap::new();
+    for read in reads {
+        if let Some(quality) = read.quality.filter(|q| q.is_finite()) {
+            let key = (read.length / len_bin, (quality / qual_bin).floor() as i64);
+            *cells.entry(key).or_insert(0) += 1;
+        }
+    }
+    cells
+        .into_iter()
+        .map(|((l, q), count)| ((l * len_bin, q as f64 * qual_bin), count))
+        .collect()
+}
+
+/// Sample the cumulative base count of reads sorted by decreasing length at up to
+/// `points` evenly spaced read ranks. The first and last ranks are always included
+/// and the sampling only depends on the read count, so output is reproducible.
+fn compute_cumulative_yield<'a>(
+    reads: impl IntoIterator<Item = &'a ReadMetrics>,
+    points: usize,
+) -> Option<Vec<(u64, u64)>> {
+    let mut lengths: Vec<u64> = reads.into_iter().map(|r| r.length).collect();
+    if lengths.is_empty() || points == 0 {
+        return None;
+    }
+
+    lengths.sort_unstable_by(|a, b| b.cmp(a));
+    let mut cumulative = Vec::with_capacity(lengths.len());
+    let mut total: u64 = 0;
+    for length in lengths {
+        total += length;
+        cumulative.push(total);
+    }
+
+    let n = cumulative.len();
+    if n <= points {
+        return Some(
+            cumulative
+                .iter()
+                .enumerate()
+                .map(|(i, &bases)| (i as u64 + 1, bases))
+                .collect(),
+        );
+    }
+
+    let mut curve = Vec::with_capacity(points);
+    for k in 0..points {
+        // Integer arithmetic keeps the chosen ranks identical across platforms
+        let index = if points == 1 {
+            n - 1
+        } else {
+            k * (n - 1) / (points - 1)
+        };
+        curve.push((index as u64 + 1, cumulative[index]));
+    }
+    curve.dedup();
+    Some(curve)
+}
+
+/// Reads dropped by the extraction filters, each counted under the first
+/// criterion it failed (min length, max length, min quality, GC content)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExcludedReads {
+    /// Reads shorter than `--min-length`
+    pub min_length: usize,
+
+    /// Reads longer than `--max-length`
+    pub max_length: usize,
+
+    /// Reads below `--min-quality`, or without a quality
+    pub min_quality: usize,
+
+    /// Reads outside `--min-gc`/`--max-gc`, or without a GC content
+    pub gc_content: usize,
+}
+
+impl ExcludedReads {
+    /// Number of excluded reads
+    pub fn total(&self) -> usize {
+        self.min_length + self.max_length + self.min_quality + self.gc_content
+    }
+
+    fn add(&mut self, other: &ExcludedReads) {
+        self.min_length += other.min_length;
+        self.max_length += other.max_length;
+        self.min_quality += other.min_quality;
+        self.gc_content += other.gc_content;
+    }
+}
+
+/// Reads of at most `max_length` bases (see
+/// `MetricsCollection::short_read_spike`): a spike of very short reads can
+/// point to adapter dimers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ShortReadSpike {
+    /// Longest read length counted
+    pub max_length: u64,
+
+    /// Number of reads of at most `max_length` bases
+    pub count: usize,
+
+    /// Fraction of all reads
+    pub fraction: f64,
+
+    /// Set when the fraction exceeds the limit (`--short-read-limit`)
+    pub warning: Option<String>,
+}
+
+impl ShortReadSpike {
+    fn new(max_length: u64, count: usize, read_count: usize, limit: f64) -> Self {
+        let fraction = short_read_fraction(count, read_count);
+        let warning = (fraction > limit).then(|| {
+            format!(
+                "{:.1}% of the reads are at most {} bases long (limit {:.1}%); possible adapter dimers",
+                fraction * 100.0,
+                max_length,
+                limit * 100.0
+            )
+        });
+        Self {
+            max_length,
+            count,
+            fraction,
+            warning,
+        }
+    }
+}
+
+/// `count` short reads as a fraction of `read_count` reads (0 without reads)
+fn short_read_fraction(count: usize, read_count: usize) -> f64 {
+    if read_count == 0 {
+        0.0
+    } else {
+        count as f64 / read_count as f64
+    }
+}
+
+/// One bin of the read length histogram
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LengthBin {
+    /// Shortest read length in the bin
+    pub start: u64,
+
+    /// End of the bin (exclusive)
+    pub end: u64,
+
+    /// Center of the bin
+    pub center: f64,
+
+    /// Number of reads in the bin
+    pub read_count: usize,
+}
+
+/// Read counts per fixed-width length bin, keyed by bin index
+#[derive(Debug, Clone)]
+struct LengthHistogram {
+    width: u64,
+    counts: BTreeMap<u64, usize>,
+}
+
+impl LengthHistogram {
+    fn new(width: u64) -> Self {
+        Self {
+            width: width.max(1),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, length: u64) {
+        *self.counts.entry(length / self.width).or_insert(0) += 1;
+    }
+
+    fn bin(&self, index: u64, read_count: usize) -> LengthBin {
+        let start = index * self.width;
+        LengthBin {
+            start,
+            end: start.saturating_add(self.width),
+            center: start as f64 + self.width as f64 / 2.0,
+            read_count,
+        }
+    }
+
+    /// The most populated bin; ties go to the shortest bin
+    fn mode(&self) -> Option<LengthBin> {
+        let (&index, &count) = self
+            .counts
+            .iter()
+            .max_by(|(a_index, a), (b_index, b)| a.cmp(b).then(b_index.cmp(a_index)))?;
+        Some(self.bin(index, count))
+    }
+
+    /// Up to `n` local maxima, most populated first. A bin is a peak when it
+    /// holds more reads than the bin before it and at least as many as the bin
+    /// after it (missing bins count as empty), so a plateau yields one peak.
+    fn peaks(&self, n: usize) -> Vec<LengthBin> {
+        let count = |index: Option<u64>| {
+            index
+                .and_then(|i| self.counts.get(&i))
+                .copied()
+                .unwrap_or(0)
+        };
+        let mut peaks: Vec<(u64, usize)> = self
+            .counts
+            .iter()
+            .filter(|(&index, &c)| {
+                c > count(index.checked_sub(1)) && c >= count(index.checked_add(1))
+            })
+            .map(|(&index, &c)| (index, c))
+            .collect();
+        peaks.sort_by(|(a_index, a), (b_index, b)| b.cmp(a).then(a_index.cmp(b_index)));
+        peaks
+            .into_iter()
+            .take(n)
+            .map(|(index, c)| self.bin(index, c))
+            .collect()
+    }
+}
+
+/// Reads and bases produced during one time bin
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ThroughputBin {
+    /// Start of the bin, in hours since the earliest read (or the run start,
+    /// when given)
+    pub start_hours: f64,
+
+    /// Number of reads that started in this bin
+    pub read_count: usize,
+
+    /// Number of bases in reads that started in this bin
+    pub bases: u64,
+}
+
+/// Throughput series longer than this many bins keep only their non-empty bins
+pub const MAX_THROUGHPUT_BINS: u64 = 10_000;
+
+/// Bin reads by start time into consecutive bins of `bin_seconds`, starting at
+/// `run_start` or else the earliest read; reads that started before `run_start`
+/// are left out. Empty bins are kept so the series is continuous, unless it
+/// would span more than `MAX_THROUGHPUT_BINS` (e.g. a start time that is far
+/// off, such as the 1970 epoch): then only the non-empty bins are returned,
+/// with a warning. Returns None when no read has a start time.
+fn compute_throughput<'a>(
+    reads: impl IntoIterator<Item = &'a ReadMetrics>,
+    bin_seconds: u64,
+    run_start: Option<DateTime<Utc>>,
+) -> Option<Vec<ThroughputBin>> {
+    let timed: Vec<(DateTime<Utc>, u64)> = reads
+        .into_iter()
+        .filter_map(|r| r.start_time().map(|t| (t, r.length)))
+        .collect();
+    let first = run_start.or_else(|| timed.iter().map(|(t, _)| *t).min())?;
+    if timed.is_empty() {
+        return None;
+    }
+    let bin_seconds = bin_seconds.max(1);
+
+    let empty_bin = |index: u64| ThroughputBin {
+        start_hours: (index * bin_seconds) as f64 / 3600.0,
+        read_count: 0,
+        bases: 0,
+    };
+    let mut bins: BTreeMap<u64, ThroughputBin> = BTreeMap::new();
+    for (start_time, length) in timed {
+        let Ok(offset) = u64::try_from((start_time - first).num_seconds()) else {
+            continue;
+        };
+        let index = offset / bin_seconds;
+        let bin = bins.entry(index).or_insert_with(|| empty_bin(index));
+        bin.read_count += 1;
+        bin.bases += length;
+    }
+
+    let bin_count = bins.last_key_value().map_or(0, |(&last, _)| last + 1);
+    if bin_count > MAX_THROUGHPUT_BINS {
+        warn!(
+            "Read start times span {} throughput bins of {} seconds; only the {} non-empty bins \
+             are reported (check for outlying start times or --run-start)",
+            bin_count,
+            bin_seconds,
+            bins.len()
+        );
+        return Some(bins.into_values().collect());
+    }
+    Some(
+        (0..bin_count)
+            .map(|index| bins.remove(&index).unwrap_or_else(|| empty_bin(index)))
+            .collect(),
+    )
+}
+
+/// Basic statistical summary for numerical data
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StatsSummary {
+    pub count: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+    pub std_dev: f64,
+    pub q25: f64,
+    pub q75: f64,
+    /// Median absolute deviation from the median; robust to the long tail of
+    /// read length distributions, unlike `std_dev`
+    pub mad: f64,
+    /// Interquartile range (`q75 - q25`)
+    pub iqr: f64,
+    /// Additional (percentile, value) pairs, when requested
+    pub extra_percentiles: Option<Vec<(f64, f64)>>,
+}
+
+impl StatsSummary {
+    /// Calculate statistics from a vector of values
+    #[allow(dead_code)]
+    pub fn from_values(values: &[f64]) -> Self {
+        Self::from_values_with_percentiles(values, &[])
+    }
+
+    /// Calculate statistics, additionally reporting the given percentiles (0-100).
+    /// Out-of-range percentiles are clamped to [0, 100].
+    ///
+    /// NaN values are treated as missing: they are dropped before any statistic
+    /// is computed and are not included in `count`.
+    pub fn from_values_with_percentiles(values: &[f64], percentiles: &[f64]) -> Self {
+        Self::from_vec_with_percentiles(values.to_vec(), percentiles)
+    }
+
+    /// `from_values_with_percentiles`, sorting `values` in place
+    fn from_vec_with_percentiles(mut values: Vec<f64>, percentiles: &[f64]) -> Self {
+        values.retain(|v| !v.is_nan());
+        values.sort_by(f64::total_cmp);
+        Self::from_sorted_with_percentiles(&values, percentiles)
+    }
+
+    /// `from_values_with_percentiles` over values already sorted, without NaN
+    fn from_sorted_with_percentiles(sorted_values: &[f64], percentiles: &[f64]) -> Self {
+        if sorted_values.is_empty() {
+            return Self {
+                count: 0,
+                mean: 0.0,
+                median: 0.0,
+                min: 0.0,
+                max: 0.0,
+                std_dev: 0.0,
+                q25: 0.0,
+                q75: 0.0,
+                mad: 0.0,
+                iqr: 0.0,
+                extra_percentiles: None,
+            };
+        }
+
+        let count = sorted_values.len();
+        let mean = sorted_values.iter().sum::<f64>() / count as f64;
+        let median = calculate_percentile(sorted_values, 50.0);
+        let min = sorted_values[0];
+        let max = sorted_values[count - 1];
+        let q25 = calculate_percentile(sorted_values, 25.0);
+        let q75 = calculate_percentile(sorted_values, 75.0);
+
+        // Calculate standard deviation
+        let variance = sorted_values
+            .iter()
+            .map(|x| (x - mean).powi(2))
+            .sum::<f64>()
+            / count as f64;
+        let std_dev = variance.sqrt();
+
+        let mad = median_absolute_deviation(sorted_values, median);
+
+        let extra_percentiles = if percentiles.is_empty() {
+            None
+        } else {
+            Some(
+                percentiles
+                    .iter()
+                    .map(|&p| (p, calculate_percentile(sorted_values, p.clamp(0.0, 100.0))))
+                    .collect(),
+            )
+        };
+
+        Self {
+            count,
+            mean,
+            median,
+            min,
+            max,
+            std_dev,
+            q25,
+            q75,
+            mad,
+            iqr: q75 - q25,
+            extra_percentiles,
+        }
+    }
+}
+
+/// Single-pass accumulator for one metric, convertible into a `StatsSummary`
+/// without keeping the values in memory.
+///
+/// Count, mean, standard deviation, min and max are exact (mean and variance
+/// via Welford's algorithm, equal to the batch values up to rounding).
+/// Quantiles (median, q25, q75, extra percentiles) use the P² estimator, which
+/// keeps five markers per quantile; for the smooth, unimodal distributions of
+/// read lengths and qualities its error is typically well under 1% of the value
+/// range, but it is not bounded for arbitrary input. `mad` is estimated as the
+/// P² median of deviations from the running median estimate, so it is the least
+/// precise field. With five or fewer values all quantiles and `mad` are exact.
+#[derive(Debug, Clone)]
+pub struct OnlineStats {
+    count: usize,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+    q25: P2Quantile,
+    median: P2Quantile,
+    q75: P2Quantile,
+    deviation: P2Quantile,
+    extra: Vec<(f64, P2Quantile)>,
+}
+
+impl OnlineStats {
+    /// Create an empty accumulator that also tracks the given percentiles (0-100)
+    pub fn new(percentiles: &[f64]) -> Self {
+        Self {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            q25: P2Quantile::new(0.25),
+            median: P2Quantile::new(0.5),
+            q75: P2Quantile::new(0.75),
+            deviation: P2Quantile::new(0.5),
+            extra: percentiles
+                .iter()
+                .map(|&p| (p, P2Quantile::new(p.clamp(0.0, 100.0) / 100.0)))
+                .collect(),
+        }
+    }
+
+    /// Add one value; NaN is treated as missing, as in `StatsSummary`
+    pub fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        self.q25.add(value);
+        self.median.add(value);
+        self.q75.add(value);
+        self.deviation.add((value - self.median.estimate()).abs());
+        for (_, estimator) in &mut self.extra {
+            estimator.add(value);
+        }
+    }
+
+    /// Number of (non-NaN) values added
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Summarize the values seen so far
+    pub fn to_stats_summary(&self) -> StatsSummary {
+        if self.count == 0 {
+            return StatsSummary::from_values(&[]);
+        }
+        let q25 = self.q25.estimate();
+        let q75 = self.q75.estimate();
+        let mad = if self.count <= 5 {
+            // The estimators still hold every value, so the MAD can be exact
+            let mut sorted = self.median.heights.clone();
+            sorted.sort_by(f64::total_cmp);
+            median_absolute_deviation(&sorted, calculate_percentile(&sorted, 50.0))
+        } else {
+            self.deviation.estimate()
+        };
+        let extra_percentiles = if self.extra.is_empty() {
+            None
+        } else {
+            Some(
+                self.extra
+                    .iter()
+                    .map(|(p, estimator)| (*p, estimator.estimate()))
+                    .collect(),
+            )
+        };
+        StatsSummary {
+            count: self.count,
+            mean: self.mean,
+            median: self.median.estimate(),
+            min: self.min,
+            max: self.max,
+            std_dev: (self.m2 / self.count as f64).sqrt(),
+            q25,
+            q75,
+            mad,
+            iqr: q75 - q25,
+            extra_percentiles,
+        }
+    }
+}
+
+/// P² streaming estimator of a single quantile (Jain & Chlamtac, 1985)
+#[derive(Debug, Clone)]
+struct P2Quantile {
+    /// Target quantile in [0, 1]
+    p: f64,
+    /// Marker heights; until five values are seen, the values themselves
+    heights: Vec<f64>,
+    /// Actual marker positions
+    positions: [f64; 5],
+    /// Desired marker positions
+    desired: [f64; 5],
+    /// Increments of the desired positions per value
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    fn new(p: f64) -> Self {
+        Self {
+            p,
+            heights: Vec::with_capacity(5),
+            positions: [0.0, 1.0, 2.0, 3.0, 4.0],
+            desired: [0.0, 2.0 * p, 4.0 * p, 2.0 + 2.0 * p, 4.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        if self.heights.len() < 5 {
+            self.heights.push(value);
+            if self.heights.len() == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+
+        let q = &mut self.heights;
+        let k = if value < q[0] {
+            q[0] = value;
+            0
+        } else if value >= q[4] {
+            q[4] = value;
+            3
+        } else {
+            (0..4).find(|&i| value < q[i + 1]).unwrap_or(3)
+        };
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        let n = &mut self.positions;
+        for i in 1..4 {
+            let d = self.desired[i] - n[i];
+            if (d >= 1.0 && n[i + 1] - n[i] > 1.0) || (d <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let d = d.signum();
+                let parabolic = q[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]));
+                q[i] = if q[i - 1] < parabolic && parabolic < q[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+                };
+                n[i] += d;
+            }
+        }
+    }
+
+    fn estimate(&self) -> f64 {
+        if self.heights.len() < 5 {
+            // Too few values for the markers: exact, as in `StatsSummary`
+            let mut sorted = self.heights.clone();
+            sorted.sort_by(f64::total_cmp);
+            return calculate_percentile(&sorted, self.p * 100.0);
         }
+        // The outer markers track the exact extremes
+        match self.p {
+            p if p <= 0.0 => self.heights[0],
+            p if p >= 1.0 => self.heights[4],
+            _ => self.heights[2],
+        }
+    }
+}
+
+/// Single-pass builder of a `MetricsSummary`, for inputs too large to keep
+/// every `ReadMetrics` in memory.
+///
+/// Read count, length mode, channel, mux, end reason and barcode distributions
+/// are exact; the statistics
+/// follow `OnlineStats`. Fields that need all reads at once (per-barcode and
+/// per-read-class summaries, aligned length N50, throughput over time,
+/// cumulative yield, length-vs-quality density) are left empty.
+#[derive(Debug, Clone)]
+pub struct OnlineSummary {
+    read_count: usize,
+    length: OnlineStats,
+    quality: OnlineStats,
+    quality_std: OnlineStats,
+    mapping_quality: OnlineStats,
+    percent_identity: OnlineStats,
+    aligned_length: OnlineStats,
+    duration: OnlineStats,
+    polya_length: OnlineStats,
+    ambiguous_bases: (u64, u64),
+    length_histogram: LengthHistogram,
+    length_modes: bool,
+    log_length: Option<OnlineStats>,
+    /// (maximum length, fraction limit, reads counted)
+    short_reads: Option<(u64, f64, usize)>,
+    missing_quality: MissingQuality,
+    longest_read: Option<(Option<String>, u64)>,
+    highest_quality_read: Option<(Option<String>, f64)>,
+    channel_counts: HashMap<u32, usize>,
+    mux_counts: HashMap<u8, usize>,
+    end_reason_counts: HashMap<String, usize>,
+    barcode_counts: HashMap<String, usize>,
+}
+
+impl OnlineSummary {
+    /// Create an empty summary; `options.percentiles` and the length histogram
+    /// options apply
+    pub fn new(options: &SummaryOptions) -> Self {
+        Self {
+            read_count: 0,
+            length: OnlineStats::new(&options.percentiles),
+            quality: OnlineStats::new(&options.percentiles),
+            quality_std: OnlineStats::new(&options.percentiles),
+            mapping_quality: OnlineStats::new(&options.percentiles),
+            percent_identity: OnlineStats::new(&options.percentiles),
+            aligned_length: OnlineStats::new(&options.percentiles),
+            duration: OnlineStats::new(&options.percentiles),
+            polya_length: OnlineStats::new(&options.percentiles),
+            ambiguous_bases: (0, 0),
+            length_histogram: LengthHistogram::new(options.length_bin),
+            length_modes: options.length_modes,
+            log_length: options
+                .log_length
+                .then(|| OnlineStats::new(&options.percentiles)),
+            short_reads: options
+                .short_read_spike
+                .map(|(max_length, limit)| (max_length, limit, 0)),
+            missing_quality: options.missing_quality,
+            longest_read: None,
+            highest_quality_read: None,
+            channel_counts: HashMap::new(),
+            mux_counts: HashMap::new(),
+            end_reason_counts: HashMap::new(),
+            barcode_counts: HashMap::new(),
+        }
+    }
+
+    /// Add one read
+    pub fn add(&mut self, read: &ReadMetrics) {
+        self.read_count += 1;
+        self.length.add(read.length as f64);
+        self.length_histogram.add(read.length);
+        if let Some(log_length) = self.log_length.as_mut().filter(|_| read.length > 0) {
+            log_length.add((read.length as f64).log10());
+        }
+        if let Some((max_length, _, count)) = self.short_reads.as_mut() {
+            if read.length <= *max_length {
+                *count += 1;
+            }
+        }
+        keep_top(&mut self.longest_read, read, Some(read.length));
+        keep_top(&mut self.highest_quality_read, read, read.quality);
+        match read.quality {
+            Some(q) => self.quality.add(q),
+            None if self.missing_quality == MissingQuality::Zero => self.quality.add(0.0),
+            None => {}
+        }
+        if let Some(std) = read.quality_std {
+            self.quality_std.add(std);
+        }
+        if let Some(q) = read.mapping_quality() {
+            self.mapping_quality.add(f64::from(q));
+        }
+        if let Some(identity) = read.percent_identity() {
+            self.percent_identity.add(identity);
+        }
+        if let Some(aligned_length) = read.aligned_length() {
+            self.aligned_length.add(aligned_length as f64);
+        }
+        if let Some(duration) = read.duration() {
+            self.duration.add(duration);
+        }
+        if let Some(polya_length) = read.polya_length() {
+            self.polya_length.add(f64::from(polya_length));
+        }
+        if let Some(n) = read.n_count() {
+            self.ambiguous_bases.0 += n;
+            self.ambiguous_bases.1 += read.length;
+        }
+        if let Some(channel) = read.channel_id() {
+            *self.channel_counts.entry(channel).or_insert(0) += 1;
+        }
+        if let Some(mux) = read.mux() {
+            *self.mux_counts.entry(mux).or_insert(0) += 1;
+        }
+        if let Some(end_reason) = read.end_reason() {
+            count_label(&mut self.end_reason_counts, end_reason);
+        }
+        if let Some(barcode) = read.barcode() {
+            count_label(&mut self.barcode_counts, barcode);
+        }
+    }
+
+    /// Finish into a `MetricsSummary`
+    pub fn finish(self) -> MetricsSummary {
+        let (demux_rate, unclassified_count, missing_barcode_count) =
+            demultiplexing(&self.barcode_counts, self.read_count);
+        let stats = |online: &OnlineStats| {
+            if online.count() > 0 {
+                Some(online.to_stats_summary())
+            } else {
+                None
+            }
+        };
+        MetricsSummary {
+            read_count: self.read_count,
+            length_stats: self.length.to_stats_summary(),
+            excluded_reads: None,
+            length_mode_bin: self.length_histogram.mode(),
+            length_modes: self
+                .length_modes
+                .then(|| self.length_histogram.peaks(LENGTH_MODES)),
+            log_length_stats: self.log_length.as_ref().and_then(stats),
+            short_read_spike: self.short_reads.map(|(max_length, limit, count)| {
+                ShortReadSpike::new(max_length, count, self.read_count, limit)
+            }),
+            longest_read: top_read(self.longest_read),
+            quality_stats: stats(&self.quality),
+            highest_quality_read: top_read(self.highest_quality_read),
+            quality_std_stats: stats(&self.quality_std),
+            mapping_quality_stats: stats(&self.mapping_quality),
+            percent_identity_stats: stats(&self.percent_identity),
+            aligned_length_stats: stats(&self.aligned_length),
+            aligned_length_n50: None,
+            alignment_count_distribution: None,
+            multi_alignment_fraction: None,
+            duration_stats: stats(&self.duration),
+            polya_length_stats: stats(&self.polya_length),
+            n_fraction: ambiguous_fraction(std::iter::once(self.ambiguous_bases)),
+            channel_uniformity: channel_gini(&self.channel_counts),
+            busiest_channel: busiest_channel(&self.channel_counts),
+            channel_distribution: (!self.channel_counts.is_empty()).then_some(self.channel_counts),
+            mux_distribution: (!self.mux_counts.is_empty()).then_some(self.mux_counts),
+            end_reason_distribution: (!self.end_reason_counts.is_empty())
+                .then_some(self.end_reason_counts),
+            demux_rate,
+            unclassified_count,
+            missing_barcode_count,
+            barcode_distribution: (!self.barcode_counts.is_empty()).then_some(self.barcode_counts),
+            per_barcode: None,
+            per_read_class: None,
+            throughput_over_time: None,
+            dataset_throughput_over_time: None,
+            cumulative_yield: None,
+            length_quality_density: None,
+        }
+    }
+}
+
+/// Median absolute deviation of sorted values around their `median`.
+///
+/// The deviations left of the median are non-increasing and those right of it
+/// non-decreasing, so merging the two runs yields them sorted without a sort.
+fn median_absolute_deviation(sorted_values: &[f64], median: f64) -> f64 {
+    let split = sorted_values.partition_point(|&v| v < median);
+    let mut below = sorted_values[..split]
+        .iter()
+        .rev()
+        .map(|v| median - v)
+        .peekable();
+    let mut above = sorted_values[split..].iter().map(|v| v - median).peekable();
+
+    let mut deviations = Vec::with_capacity(sorted_values.len());
+    loop {
+        let next = match (below.peek(), above.peek()) {
+            (Some(&b), Some(&a)) if b <= a => below.next(),
+            (Some(_), Some(_)) => above.next(),
+            (Some(_), None) => below.next(),
+            (None, _) => above.next(),
+        };
+        match next {
+            Some(d) => deviations.push(d),
+            None => break,
+        }
+    }
+    calculate_percentile(&deviations, 50.0)
+}
+
+/// Calculate percentile from sorted values
+fn calculate_percentile(sorted_values: &[f64], percentile: f64) -> f64 {
+    if sorted_values.is_empty() {
+        return 0.0;
+    }
+
+    let index = (percentile / 100.0) * (sorted_values.len() - 1) as f64;
+    let lower = index.floor() as usize;
+    let upper = index.ceil() as usize;
+
+    if lower == upper {
+        sorted_values[lower]
+    } else {
+        let weight = index - lower as f64;
+        sorted_values[lower] * (1.0 - weight) + sorted_values[upper] * weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_stats_summary() {
+        let values = vec![1.0, 2.0, 3.0, 4.0, 5.0];
+        let stats = StatsSummary::from_values(&values);
+
+        assert_eq!(stats.count, 5);
+        assert_eq!(stats.mean, 3.0);
+        assert_eq!(stats.median, 3.0);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.max, 5.0);
+    }
+
+    #[test]
+    fn test_stats_summary_extra_percentiles() {
+        let values: Vec<f64> = (0..=100).map(f64::from).collect();
+        let stats = StatsSummary::from_values_with_percentiles(&values, &[1.0, 5.0, 95.0, 99.0]);
+
+        assert_eq!(
+            stats.extra_percentiles,
+            Some(vec![(1.0, 1.0), (5.0, 5.0), (95.0, 95.0), (99.0, 99.0)])
+        );
+        assert!(StatsSummary::from_values(&values)
+            .extra_percentiles
+            .is_none());
+
+        let reads = vec![
+            ReadMetrics::new(None, 100),
+            ReadMetrics::new(None, 200),
+            ReadMetrics::new(None, 300),
+        ];
+        let options = SummaryOptions {
+            percentiles: vec![50.0],
+            ..Default::default()
+        };
+        let collection = MetricsCollection::with_options(reads, &options);
+        let tsv = collection.to_tsv().unwrap();
+        assert!(tsv.contains("q75: 250.00, mad: 100.00, iqr: 100.00, p50: 200.00\n"));
+    }
+
+    #[test]
+    fn test_stats_summary_robust_dispersion() {
+        // Median 2; deviations 1, 1, 0, 0, 2, 4, 7 -> sorted 0, 0, 1, 1, 2, 4, 7
+        let stats = StatsSummary::from_values(&[1.0, 1.0, 2.0, 2.0, 4.0, 6.0, 9.0]);
+        assert_eq!(stats.mad, 1.0);
+        // q25 = 1.5 and q75 = 5.0 with linear interpolation
+        assert_eq!(stats.iqr, 3.5);
+
+        // Even count: median 3.5; deviations 2.5, 1.5, 0.5, 0.5, 6.5, 16.5
+        // -> sorted 0.5, 0.5, 1.5, 2.5, 6.5, 16.5, median (1.5 + 2.5) / 2
+        let stats = StatsSummary::from_values(&[20.0, 1.0, 10.0, 2.0, 4.0, 3.0]);
+        assert_eq!(stats.mad, 2.0);
+
+        assert_eq!(StatsSummary::from_values(&[7.0]).mad, 0.0);
+    }
+
+    #[test]
+    fn test_stats_summary_ignores_nan() {
+        let values = vec![1.0, f64::NAN, 3.0, 2.0, f64::NAN];
+        let stats = StatsSummary::from_values(&values);
+
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.mean, 2.0);
+        assert_eq!(stats.median, 2.0);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.max, 3.0);
+        assert!(stats.std_dev.is_finite());
+
+        let all_nan = StatsSummary::from_values(&[f64::NAN, f64::NAN]);
+        assert_eq!(all_nan.count, 0);
+        assert_eq!(all_nan.mean, 0.0);
+    }
+
+    #[test]
+    fn test_reads_above_length_percentile_empty() {
+        let empty = MetricsCollection::new(Vec::new());
+        let result = empty.reads_above_length_percentile(90.0);
+        assert!(result.reads.is_empty());
+        assert_eq!(result.summary.read_count, 0);
+    }
+
+    #[test]
+    fn test_read_metrics_builder() {
+        let metrics = ReadMetrics::new(Some("read1".to_string()), 1000)
+            .with_quality(35.0)
+            .with_alignment(950, Some(36.0), Some(60), Some(95.5));
+
+        assert_eq!(metrics.length, 1000);
+        assert_eq!(metrics.quality, Some(35.0));
+        assert_eq!(metrics.aligned_length(), Some(950));
+        assert_eq!(metrics.percent_identity(), Some(95.5));
+    }
+
+    // A read is its id, length and qualities and a pointer to the rest
+    const _: () = assert!(std::mem::size_of::<ReadMetrics>() <= 72);
+
+    #[test]
+    fn test_extended_metrics_serde() {
+        let plain = ReadMetrics::new(Some("plain".to_string()), 100).with_quality(12.0);
+        assert!(plain.extended().is_none());
+        assert!(plain.extra().is_empty());
+
+        // The extended fields are written in line and in order, as nulls when
+        // absent
+        let json = serde_json::to_string(&plain).unwrap();
+        assert!(json.starts_with(
+            r#"{"read_id":"plain","length":100,"quality":12.0,"quality_std":null,"aligned_length":null,"#
+        ));
+        assert!(json.ends_with(r#","n_count":null,"gc_content":null}"#));
+        assert_eq!(json.matches(":null").count(), 18);
+        let back: ReadMetrics = serde_json::from_str(&json).unwrap();
+        assert!(back.extended().is_none());
+
+        let mut aligned = ReadMetrics::new(Some("aligned".to_string()), 100).with_alignment(
+            90,
+            None,
+            Some(60),
+            Some(97.5),
+        );
+        aligned.extended_mut().barcode = Some(Arc::from("barcode01"));
+        aligned.extended_mut().extra.insert("NM".to_string(), 3.0);
+        let json = serde_json::to_value(&aligned).unwrap();
+        assert_eq!(json["aligned_length"], 90);
+        assert_eq!(json["extra"]["NM"], 3.0);
+        let back: ReadMetrics = serde_json::from_value(json).unwrap();
+        assert_eq!(back.extended(), aligned.extended());
+
+        // and read back from the CBOR of spilled reads
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&aligned, &mut cbor).unwrap();
+        let back: ReadMetrics = ciborium::from_reader(&cbor[..]).unwrap();
+        assert_eq!(back.barcode().map(AsRef::as_ref), Some("barcode01"));
+        assert_eq!(back.extended(), aligned.extended());
+    }
+
+    #[test]
+    fn test_tsv_output() {
+        let read1 = ReadMetrics::new(Some("read1".to_string()), 1000).with_quality(35.5);
+        let read2 = ReadMetrics::new(Some("read2".to_string()), 2000)
+            .with_quality(40.0)
+            .with_alignment(1900, Some(41.0), Some(60), Some(95.5));
+
+        let metrics = MetricsCollection::new(vec![read1, read2]);
+        let tsv_output = metrics.to_tsv().unwrap();
+
+        // Check that it contains the header
+        assert!(tsv_output.contains("read_id\tlength\tquality"));
+
+        // Check that it contains the read data with tabs
+        assert!(tsv_output.contains("read1\t1000\t35.500"));
+        assert!(tsv_output.contains("read2\t2000\t40.000"));
+
+        // Check that it contains summary statistics
+        assert!(tsv_output.contains("# Summary Statistics"));
+        assert!(tsv_output.contains("# Total reads: 2"));
+        assert!(tsv_output.contains("# Length stats"));
+        assert!(tsv_output.contains("# Quality stats"));
+    }
+
+    #[test]
+    fn test_throughput_over_time() {
+        let t0 = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let timed_read = |minutes: i64, length: u64, dataset: &str| {
+            let mut read = ReadMetrics::new(None, length).with_sequencing_metadata(
+                None,
+                Some(t0 + chrono::Duration::minutes(minutes)),
+                None,
+            );
+            read.extended_mut().dataset = Some(dataset.into());
+            read
+        };
+        let reads = vec![
+            timed_read(0, 100, "a"),
+            timed_read(30, 200, "a"),
+            timed_read(90, 300, "a"),
+            timed_read(185, 400, "b"),
+            ReadMetrics::new(None, 500),
+        ];
+
+        let summary = MetricsSummary::from_reads(&reads);
+        let bins = summary.throughput_over_time.unwrap();
+        assert_eq!(bins.len(), 4);
+        assert_eq!((bins[0].read_count, bins[0].bases), (2, 300));
+        assert_eq!((bins[1].read_count, bins[1].bases), (1, 300));
+        assert_eq!((bins[2].read_count, bins[2].bases), (0, 0));
+        assert_eq!((bins[3].read_count, bins[3].bases), (1, 400));
+        assert_eq!(bins[3].start_hours, 3.0);
+
+        // Each dataset is binned from its own earliest read
+        let per_dataset = summary.dataset_throughput_over_time.unwrap();
+        assert_eq!(per_dataset["a"].len(), 2);
+        assert_eq!(per_dataset["b"].len(), 1);
+        assert_eq!(per_dataset["b"][0].bases, 400);
+
+        // Configurable bin width
+        let options = SummaryOptions {
+            throughput_bin_seconds: 2 * 3600,
+            ..Default::default()
+        };
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options);
+        let bins = summary.throughput_over_time.unwrap();
+        assert_eq!(bins.len(), 2);
+        assert_eq!(bins[0].read_count, 3);
+
+        // A run start before all reads shifts the bins: the first read is
+        // 90 minutes in, and the bins of every dataset share the anchor
+        let options = SummaryOptions {
+            run_start: Some(t0 - chrono::Duration::minutes(90)),
+            ..Default::default()
+        };
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options);
+        let bins = summary.throughput_over_time.unwrap();
+        let counts: Vec<(usize, u64)> = bins.iter().map(|b| (b.read_count, b.bases)).collect();
+        assert_eq!(counts, [(0, 0), (1, 100), (1, 200), (1, 300), (1, 400)]);
+        let per_dataset = summary.dataset_throughput_over_time.unwrap();
+        assert_eq!(per_dataset["b"].len(), 5);
+        assert_eq!(per_dataset["b"][4].bases, 400);
+
+        // Reads before a later run start are left out
+        let options = SummaryOptions {
+            run_start: Some(t0 + chrono::Duration::minutes(60)),
+            ..Default::default()
+        };
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options);
+        let bins = summary.throughput_over_time.unwrap();
+        assert_eq!(bins.iter().map(|b| b.read_count).sum::<usize>(), 2);
+        assert_eq!((bins[0].read_count, bins[0].bases), (1, 300));
+
+        // A read with an outlying start time does not fill the years between
+        // with empty bins
+        let mut outlier = ReadMetrics::new(None, 600);
+        outlier.extended_mut().start_time =
+            Some(Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap());
+        let mut with_outlier = reads.clone();
+        with_outlier.push(outlier);
+        let summary = MetricsSummary::from_reads(&with_outlier);
+        let bins = summary.throughput_over_time.unwrap();
+        let counts: Vec<(usize, u64)> = bins.iter().map(|b| (b.read_count, b.bases)).collect();
+        assert_eq!(counts, [(1, 600), (2, 300), (1, 300), (1, 400)]);
+        assert_eq!(bins[0].start_hours, 0.0);
+        assert_eq!(bins[3].start_hours, 473352.0 + 3.0);
+    }
+
+    #[test]
+    fn test_throughput_without_start_times() {
+        let reads = vec![ReadMetrics::new(None, 100), ReadMetrics::new(None, 200)];
+        let summary = MetricsSummary::from_reads(&reads);
+        assert!(summary.throughput_over_time.is_none());
+        assert!(summary.dataset_throughput_over_time.is_none());
+
+        let empty = MetricsSummary::from_reads(&[]);
+        assert!(empty.throughput_over_time.is_none());
+    }
+
+    #[test]
+    fn test_cumulative_yield() {
+        let reads: Vec<ReadMetrics> = [100, 400, 300, 200]
+            .iter()
+            .map(|&l| ReadMetrics::new(None, l))
+            .collect();
+
+        // Off by default
+        assert!(MetricsSummary::from_reads(&reads)
+            .cumulative_yield
+            .is_none());
+
+        let options = SummaryOptions {
+            cumulative_yield: true,
+            ..Default::default()
+        };
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options);
+        assert_eq!(
+            summary.cumulative_yield.unwrap(),
+            vec![(1, 400), (2, 700), (3, 900), (4, 1000)]
+        );
+    }
+
+    #[test]
+    fn test_cumulative_yield_downsampling() {
+        let reads: Vec<ReadMetrics> = (1..=10_000).map(|l| ReadMetrics::new(None, l)).collect();
+        let curve = compute_cumulative_yield(&reads, CUMULATIVE_YIELD_POINTS).unwrap();
+
+        assert_eq!(curve.len(), CUMULATIVE_YIELD_POINTS);
+        // Starts with the single longest read and ends with all bases
+        assert_eq!(curve[0], (1, 10_000));
+        assert_eq!(curve[curve.len() - 1], (10_000, 10_000 * 10_001 / 2));
+        assert!(curve.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+
+        // Deterministic
+        assert_eq!(
+            curve,
+            compute_cumulative_yield(&reads, CUMULATIVE_YIELD_POINTS).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_tsv_extra_tag_columns() {
+        let mut read1 = ReadMetrics::new(Some("read1".to_string()), 100);
+        read1.extended_mut().extra.insert("sp".to_string(), 42.0);
+        let mut read2 = ReadMetrics::new(Some("read2".to_string()), 200);
+        read2
+            .extended_mut()
+            .extra_text
+            .insert("RG".to_string(), "run1".to_string());
+
+        let tsv = MetricsCollection::new(vec![read1, read2]).to_tsv().unwrap();
+        let mut lines = tsv.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .ends_with("\tdataset\tparent_id\tpolya_length\tn_count\tgc_content\tRG\tsp"));
+        assert!(lines.next().unwrap().ends_with("\t\t42"));
+        assert!(lines.next().unwrap().ends_with("\trun1\t"));
+    }
+
+    #[test]
+    fn test_per_dataset_summary_table() {
+        let read = |dataset: Option<&str>, length: u64, quality: f64| {
+            let mut read = ReadMetrics::new(None, length).with_quality(quality);
+            read.extended_mut().dataset = dataset.map(Arc::from);
+            read
+        };
+        let collection = MetricsCollection::new(vec![
+            read(Some("run2"), 3000, 12.0),
+            read(Some("run1"), 1000, 10.0),
+            read(Some("run2"), 1000, 14.0),
+            read(Some("run1"), 2000, 11.0),
+            read(Some("run2"), 2000, 13.0),
+            read(None, 500, 5.0),
+        ]);
+        let table = collection.per_dataset_summary_table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "dataset\treads\tbases\tn50\tmean_length\tmedian_length\tmean_quality",
+                "run2\t3\t6000\t3000\t2000.00\t2000.00\t13.00",
+                "run1\t2\t3000\t2000\t1500.00\t1500.00\t10.50",
+            ]
+        );
+
+        let untracked = MetricsCollection::new(vec![ReadMetrics::new(None, 100)]);
+        assert_eq!(untracked.per_dataset_summary_table().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_per_barcode_summary() {
+        let barcoded = |length: u64, barcode: &str, quality: f64| {
+            let mut read = ReadMetrics::new(None, length).with_quality(quality);
+            read.extended_mut().barcode = Some(barcode.into());
+            read
+        };
+        let reads = vec![
+            barcoded(100, "barcode01", 10.0),
+            barcoded(200, "barcode01", 20.0),
+            barcoded(700, "barcode01", 30.0),
+            barcoded(50, "barcode02", 12.0),
+            barcoded(400, "unclassified", 8.0),
+            ReadMetrics::new(None, 1000),
+        ];
+        let collection = MetricsCollection::new(reads);
+
+        let per_barcode = collection.summary.per_barcode.as_ref().unwrap();
+        assert_eq!(per_barcode.len(), 2);
+        let bc01 = &per_barcode["barcode01"];
+        assert_eq!(bc01.read_count, 3);
+        assert_eq!(bc01.bases, 1000);
+        // The 700 bp read alone holds at least half of the 1000 bases
+        assert_eq!(bc01.n50, 700);
+        assert_eq!(bc01.median_length, 200.0);
+        assert_eq!(bc01.mean_quality, Some(20.0));
+        assert_eq!(per_barcode["barcode02"].n50, 50);
+
+        let groups = collection.group_by_barcode();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups["barcode01"].summary.read_count, 3);
+        assert_eq!(groups["barcode01"].summary.length_stats.max, 700.0);
+
+        let tsv = collection.to_tsv().unwrap();
+        assert!(tsv.contains("# Per-barcode summary\n"));
+        assert!(tsv.contains("# barcode01\t3\t1000\t700\t333.33\t200.00\t20.00\n"));
+
+        assert!(MetricsCollection::new(vec![ReadMetrics::new(None, 10)])
+            .summary
+            .per_barcode
+            .is_none());
+    }
+
+    #[test]
+    fn test_length_quality_density() {
+        let reads = vec![
+            ReadMetrics::new(None, 500).with_quality(10.2),
+            ReadMetrics::new(None, 900).with_quality(10.9),
+            ReadMetrics::new(None, 1500).with_quality(10.5),
+            ReadMetrics::new(None, 1999).with_quality(12.0),
+            ReadMetrics::new(None, 4000).with_quality(12.4),
+            ReadMetrics::new(None, 700),
+        ];
+        let collection = MetricsCollection::new(reads);
+
+        assert_eq!(
+            collection.length_quality_density(1000, 1.0),
+            vec![
+                ((0, 10.0), 2),
+                ((1000, 10.0), 1),
+                ((1000, 12.0), 1),
+                ((4000, 12.0), 1),
+            ]
+        );
+        assert!(collection.length_quality_density(1000, 0.0).is_empty());
+        assert!(collection.summary.length_quality_density.is_none());
+
+        let options = SummaryOptions {
+            length_quality_density: Some((2000, 5.0)),
+            ..Default::default()
+        };
+        let reads = collection.reads.clone();
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options);
+        assert_eq!(
+            summary.length_quality_density,
+            Some(vec![((0, 10.0), 4), ((4000, 10.0), 1)])
+        );
+    }
+
+    #[test]
+    fn test_log_length_stats() {
+        let reads: Vec<ReadMetrics> = [10, 100, 1000, 0]
+            .iter()
+            .map(|&length| ReadMetrics::new(None, length))
+            .collect();
+        assert!(MetricsSummary::from_reads(&reads)
+            .log_length_stats
+            .is_none());
 
-        // Percent identity statistics if available
-        if let Some(percent_identity_stats) = &self.summary.percent_identity_stats {
-            output.push_str(&format!(
-                "# Percent identity stats - count: {}, mean: {:.2}, median: {:.2}, min: {:.2}, max: {:.2}, std_dev: {:.2}, q25: {:.2}, q75: {:.2}\n",
-                percent_identity_stats.count,
-                percent_identity_stats.mean,
-                percent_identity_stats.median,
-                percent_identity_stats.min,
-                percent_identity_stats.max,
-                percent_identity_stats.std_dev,
-                percent_identity_stats.q25,
-                percent_identity_stats.q75
-            ));
-        }
+        let options = SummaryOptions {
+            log_length: true,
+            ..Default::default()
+        };
+        // The empty read is left out
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options);
+        let stats = summary.log_length_stats.unwrap();
+        assert_eq!(stats.count, 3);
+        assert!((stats.mean - 2.0).abs() < 1e-12);
+        assert!((stats.median - 2.0).abs() < 1e-12);
+        let tsv = MetricsCollection::with_options(reads.clone(), &options)
+            .to_tsv()
+            .unwrap();
+        assert!(tsv.contains("# Log10 length stats - count: 3, mean: 2.00"));
 
-        Ok(output)
+        let mut online = OnlineSummary::new(&options);
+        for read in &reads {
+            online.add(read);
+        }
+        let stats = online.finish().log_length_stats.unwrap();
+        assert_eq!(stats.count, 3);
+        assert!((stats.mean - 2.0).abs() < 1e-12);
     }
-}
 
-/// Summary statistics for a collection of reads
-#[derive(Debug, Serialize, Deserialize)]
-pub struct MetricsSummary {
-    /// Total number of reads
-    pub read_count: usize,
+    #[test]
+    fn test_missing_quality_zero() {
+        let reads = vec![
+            ReadMetrics::new(None, 100).with_quality(20.0),
+            ReadMetrics::new(None, 100),
+        ];
+        let stats = MetricsSummary::from_reads(&reads).quality_stats.unwrap();
+        assert_eq!((stats.count, stats.mean), (1, 20.0));
 
-    /// Length statistics
-    pub length_stats: StatsSummary,
+        let options = SummaryOptions {
+            missing_quality: MissingQuality::Zero,
+            ..Default::default()
+        };
+        let stats = MetricsSummary::from_reads_with_options(&reads, &options)
+            .quality_stats
+            .unwrap();
+        assert_eq!((stats.count, stats.mean, stats.min), (2, 10.0, 0.0));
 
-    /// Quality statistics (if available)
-    pub quality_stats: Option<StatsSummary>,
+        let mut online = OnlineSummary::new(&options);
+        for read in &reads {
+            online.add(read);
+        }
+        let stats = online.finish().quality_stats.unwrap();
+        assert_eq!((stats.count, stats.mean), (2, 10.0));
+    }
 
-    /// Mapping quality statistics (if available)
-    pub mapping_quality_stats: Option<StatsSummary>,
+    #[test]
+    fn test_short_read_spike() {
+        // 30 adapter dimers of 60-80 bases among 70 full-length reads
+        let reads: Vec<ReadMetrics> = (0..100)
+            .map(|i| ReadMetrics::new(None, if i % 10 < 3 { 60 + i % 21 } else { 5000 + i }))
+            .collect();
+        let collection = MetricsCollection::new(reads.clone());
+        assert_eq!(collection.short_read_spike(100), (30, 0.3));
+        assert_eq!(collection.short_read_spike(80), (30, 0.3));
+        assert_eq!(collection.short_read_spike(59), (0, 0.0));
+        assert_eq!(
+            MetricsCollection::new(Vec::new()).short_read_spike(100),
+            (0, 0.0)
+        );
+        assert!(collection.summary.short_read_spike.is_none());
 
-    /// Percent identity statistics (if available)
-    pub percent_identity_stats: Option<StatsSummary>,
+        let options = |limit| SummaryOptions {
+            short_read_spike: Some((100, limit)),
+            ..Default::default()
+        };
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options(0.05));
+        let spike = summary.short_read_spike.unwrap();
+        assert_eq!((spike.count, spike.fraction), (30, 0.3));
+        assert_eq!(
+            spike.warning.as_deref(),
+            Some("30.0% of the reads are at most 100 bases long (limit 5.0%); possible adapter dimers")
+        );
+        let tsv = MetricsCollection::with_options(reads.clone(), &options(0.05))
+            .to_tsv()
+            .unwrap();
+        assert!(tsv.contains("# Short reads (at most 100 bases): 30 (30.00%)\n# Warning: 30.0%"));
 
-    /// Channel distribution (if available)
-    pub channel_distribution: Option<HashMap<u16, usize>>,
+        // At or under the limit there is no warning
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options(0.3));
+        assert!(summary.short_read_spike.unwrap().warning.is_none());
 
-    /// Barcode distribution (if available)
-    pub barcode_distribution: Option<HashMap<String, usize>>,
-}
+        let mut online = OnlineSummary::new(&options(0.05));
+        for read in &reads {
+            online.add(read);
+        }
+        assert_eq!(online.finish().short_read_spike, Some(spike));
+    }
 
-impl MetricsSummary {
-    /// Calculate summary statistics from a collection of reads
-    pub fn from_reads(reads: &[ReadMetrics]) -> Self {
-        let read_count = reads.len();
+    #[test]
+    fn test_length_modes() {
+        // Bimodal amplicon-like run: a large peak around 1 kb and a smaller one
+        // around 5 kb, with a few scattered reads in between
+        let mut lengths: Vec<u64> = Vec::new();
+        lengths.extend(std::iter::repeat_n(1020, 50));
+        lengths.extend(std::iter::repeat_n(960, 20));
+        lengths.extend(std::iter::repeat_n(1150, 10));
+        lengths.extend(std::iter::repeat_n(5010, 30));
+        lengths.extend(std::iter::repeat_n(4920, 12));
+        lengths.extend([2500, 3100, 3150]);
+        let reads: Vec<ReadMetrics> = lengths
+            .iter()
+            .map(|&length| ReadMetrics::new(None, length))
+            .collect();
 
-        // Length statistics
-        let lengths: Vec<f64> = reads.iter().map(|r| r.length as f64).collect();
-        let length_stats = StatsSummary::from_values(&lengths);
+        let summary = MetricsSummary::from_reads(&reads);
+        assert_eq!(
+            summary.length_mode_bin,
+            Some(LengthBin {
+                start: 1000,
+                end: 1100,
+                center: 1050.0,
+                read_count: 50,
+            })
+        );
+        assert!(summary.length_modes.is_none());
 
-        // Quality statistics
-        let qualities: Vec<f64> = reads.iter().filter_map(|r| r.quality).collect();
-        let quality_stats = if !qualities.is_empty() {
-            Some(StatsSummary::from_values(&qualities))
-        } else {
-            None
+        let options = SummaryOptions {
+            length_modes: true,
+            ..Default::default()
         };
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options);
+        let modes: Vec<(u64, usize)> = summary
+            .length_modes
+            .unwrap()
+            .iter()
+            .map(|mode| (mode.start, mode.read_count))
+            .collect();
+        assert_eq!(modes, vec![(1000, 50), (5000, 30), (3100, 2)]);
 
-        // Mapping quality statistics
-        let mapping_qualities: Vec<f64> = reads
+        // Wider bins merge the shoulders into their peaks
+        let options = SummaryOptions {
+            length_bin: 1000,
+            length_modes: true,
+            ..Default::default()
+        };
+        let summary = MetricsSummary::from_reads_with_options(&reads, &options);
+        let mode = summary.length_mode_bin.as_ref().unwrap();
+        assert_eq!(
+            (mode.start, mode.center, mode.read_count),
+            (1000, 1500.0, 60)
+        );
+        let modes: Vec<u64> = summary
+            .length_modes
+            .as_ref()
+            .unwrap()
             .iter()
-            .filter_map(|r| r.mapping_quality.map(|q| q as f64))
+            .map(|mode| mode.start)
             .collect();
-        let mapping_quality_stats = if !mapping_qualities.is_empty() {
-            Some(StatsSummary::from_values(&mapping_qualities))
-        } else {
-            None
+        assert_eq!(modes, vec![1000, 5000]);
+
+        // The streaming summary keeps an exact histogram
+        let mut online = OnlineSummary::new(&options);
+        reads.iter().for_each(|read| online.add(read));
+        let online = online.finish();
+        assert_eq!(online.length_mode_bin, summary.length_mode_bin);
+        assert_eq!(online.length_modes, summary.length_modes);
+
+        assert!(MetricsSummary::from_reads(&[]).length_mode_bin.is_none());
+    }
+
+    #[test]
+    fn test_incremental_building_matches_from_scratch() {
+        let reads: Vec<ReadMetrics> = (1..=20u32)
+            .map(|i| {
+                let mut read = ReadMetrics::new(Some(format!("read{}", i)), u64::from(i) * 100)
+                    .with_quality(f64::from(i % 7) + 5.0);
+                read.extended_mut().barcode = Some(format!("barcode0{}", i % 3).into());
+                read
+            })
+            .collect();
+        let options = SummaryOptions {
+            percentiles: vec![10.0, 90.0],
+            ..Default::default()
         };
+        let expected = MetricsCollection::with_options(reads.clone(), &options);
 
-        // Percent identity statistics
-        let percent_identities: Vec<f64> =
-            reads.iter().filter_map(|r| r.percent_identity).collect();
-        let percent_identity_stats = if !percent_identities.is_empty() {
-            Some(StatsSummary::from_values(&percent_identities))
-        } else {
-            None
+        let mut pushed = MetricsCollection::with_options(Vec::new(), &options);
+        for read in reads[..8].iter().cloned() {
+            pushed.push(read);
+        }
+        pushed.extend(reads[8..15].iter().cloned());
+        pushed.merge(MetricsCollection::new(reads[15..].to_vec()));
+
+        assert_eq!(pushed.reads.len(), 20);
+        assert_eq!(
+            serde_json::to_value(&pushed.summary).unwrap(),
+            serde_json::to_value(&expected.summary).unwrap()
+        );
+        assert_eq!(
+            pushed.summary.length_stats.extra_percentiles,
+            expected.summary.length_stats.extra_percentiles
+        );
+
+        let lengths: Vec<u64> = (&pushed).into_iter().map(|r| r.length).collect();
+        assert_eq!(lengths.len(), 20);
+        let ids: Vec<String> = pushed.into_iter().filter_map(|r| r.read_id).collect();
+        assert_eq!(ids.first().map(String::as_str), Some("read1"));
+        assert_eq!(ids.last().map(String::as_str), Some("read20"));
+    }
+
+    /// Deterministic skewed "read length" values: exp of a sum of uniforms from
+    /// a fixed-seed LCG, roughly log-normal like real length distributions
+    fn synthetic_lengths(n: usize) -> Vec<f64> {
+        let mut state: u64 = 42;
+        let mut uniform = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
         };
+        (0..n)
+            .map(|_| {
+                let z = (0..4).map(|_| uniform()).sum::<f64>() - 2.0;
+                (8.5 + 1.5 * z).exp().round()
+            })
+            .collect()
+    }
 
-        // Channel and barcode distribution (combined loop for efficiency)
-        let mut channel_counts: HashMap<u16, usize> = HashMap::new();
-        let mut barcode_counts: HashMap<String, usize> = HashMap::new();
-        for read in reads {
-            if let Some(channel) = read.channel_id {
-                *channel_counts.entry(channel).or_insert(0) += 1;
-            }
-            if let Some(barcode) = &read.barcode {
-                // Use entry API efficiently - only clone when inserting new key
-                barcode_counts
-                    .entry(barcode.clone())
-                    .and_modify(|e| *e += 1)
-                    .or_insert(1);
-            }
+    #[test]
+    fn test_online_stats_matches_exact() {
+        let values = synthetic_lengths(100_000);
+        let exact = StatsSummary::from_values_with_percentiles(&values, &[10.0, 90.0]);
+        let mut online = OnlineStats::new(&[10.0, 90.0]);
+        for &v in &values {
+            online.add(v);
         }
-        let channel_distribution = if !channel_counts.is_empty() {
-            Some(channel_counts)
-        } else {
-            None
+        let approx = online.to_stats_summary();
+
+        assert_eq!(approx.count, exact.count);
+        assert_eq!(approx.min, exact.min);
+        assert_eq!(approx.max, exact.max);
+        assert!((approx.mean - exact.mean).abs() / exact.mean < 1e-9);
+        assert!((approx.std_dev - exact.std_dev).abs() / exact.std_dev < 1e-9);
+
+        // Quantile estimates within 1% of the exact value
+        let close = |a: f64, e: f64| (a - e).abs() / e < 0.01;
+        assert!(
+            close(approx.median, exact.median),
+            "{} vs {}",
+            approx.median,
+            exact.median
+        );
+        assert!(
+            close(approx.q25, exact.q25),
+            "{} vs {}",
+            approx.q25,
+            exact.q25
+        );
+        assert!(
+            close(approx.q75, exact.q75),
+            "{} vs {}",
+            approx.q75,
+            exact.q75
+        );
+        for ((p, a), (_, e)) in approx
+            .extra_percentiles
+            .unwrap()
+            .into_iter()
+            .zip(exact.extra_percentiles.unwrap())
+        {
+            assert!(close(a, e), "p{}: {} vs {}", p, a, e);
+        }
+        // MAD is estimated around a moving median, so it gets a looser bound
+        assert!(
+            (approx.mad - exact.mad).abs() / exact.mad < 0.05,
+            "{} vs {}",
+            approx.mad,
+            exact.mad
+        );
+    }
+
+    #[test]
+    fn test_online_stats_small_and_nan() {
+        let mut online = OnlineStats::new(&[]);
+        assert_eq!(online.to_stats_summary().count, 0);
+        for v in [4.0, f64::NAN, 1.0, 3.0] {
+            online.add(v);
+        }
+        let stats = online.to_stats_summary();
+        let exact = StatsSummary::from_values(&[4.0, 1.0, 3.0]);
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.median, exact.median);
+        assert_eq!(stats.q25, exact.q25);
+        assert_eq!(stats.q75, exact.q75);
+        assert_eq!(stats.mad, exact.mad);
+    }
+
+    #[test]
+    fn test_online_summary() {
+        let reads: Vec<ReadMetrics> = (1..=50u32)
+            .map(|i| {
+                let mut read =
+                    ReadMetrics::new(None, u64::from(i) * 10).with_quality(f64::from(i % 10));
+                read.extended_mut().channel_id = Some(i % 4);
+                read
+            })
+            .collect();
+        let mut online = OnlineSummary::new(&SummaryOptions::default());
+        for read in &reads {
+            online.add(read);
+        }
+        let summary = online.finish();
+        let exact = MetricsSummary::from_reads(&reads);
+
+        assert_eq!(summary.read_count, 50);
+        assert_eq!(summary.length_stats.max, 500.0);
+        assert_eq!(summary.quality_stats.unwrap().count, 50);
+        assert!(summary.mapping_quality_stats.is_none());
+        assert_eq!(summary.channel_distribution, exact.channel_distribution);
+        assert!(summary.barcode_distribution.is_none());
+    }
+
+    #[test]
+    fn test_sample_is_reproducible() {
+        let reads: Vec<ReadMetrics> = (0..1000u32)
+            .map(|i| ReadMetrics::new(Some(format!("read{}", i)), u64::from(i) + 1))
+            .collect();
+        let ids = |c: &MetricsCollection| -> Vec<String> {
+            c.reads.iter().map(|r| r.read_id.clone().unwrap()).collect()
         };
-        let barcode_distribution = if !barcode_counts.is_empty() {
-            Some(barcode_counts)
-        } else {
+
+        let first = MetricsCollection::new(reads.clone()).sample(100, 7);
+        let second = MetricsCollection::new(reads.clone()).sample(100, 7);
+        let other_seed = MetricsCollection::new(reads.clone()).sample(100, 8);
+        assert_eq!(first.reads.len(), 100);
+        assert_eq!(ids(&first), ids(&second));
+        assert_ne!(ids(&first), ids(&other_seed));
+
+        // Input order is preserved and the summary describes the sample
+        assert!(first.reads.windows(2).all(|w| w[0].length < w[1].length));
+        assert_eq!(first.summary.read_count, 100);
+        let max = first.reads.iter().map(|r| r.length).max().unwrap();
+        assert_eq!(first.summary.length_stats.max, max as f64);
+        assert_eq!(
+            first.provenance.unwrap().sampling,
+            Some(SamplingInfo {
+                requested: 100,
+                seed: 7,
+                original_read_count: 1000
+            })
+        );
+
+        // Asking for more reads than available keeps everything
+        let all = MetricsCollection::new(reads[..10].to_vec()).sample(100, 7);
+        assert_eq!(all.reads.len(), 10);
+        assert_eq!(
+            ids(&all),
+            ids(&MetricsCollection::new(reads[..10].to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_chained_filters() {
+        let read = |length: u64, quality: f64, barcode: &str| {
+            let mut read = ReadMetrics::new(None, length).with_quality(quality);
+            read.extended_mut().barcode = Some(barcode.into());
+            read
+        };
+        let collection = MetricsCollection::new(vec![
+            read(500, 15.0, "barcode01"),
+            read(2000, 8.0, "barcode01"),
+            read(3000, 12.0, "barcode01"),
+            read(4000, 20.0, "barcode02"),
+            read(5000, 11.0, "barcode01"),
+        ]);
+
+        let filtered = collection
+            .filter(|r| r.length >= 1000 && r.quality.is_some_and(|q| q >= 10.0))
+            .filter_by_barcode("barcode01");
+        assert_eq!(filtered.summary.read_count, 2);
+        assert_eq!(filtered.summary.length_stats.min, 3000.0);
+        assert_eq!(filtered.summary.length_stats.max, 5000.0);
+        assert_eq!(filtered.summary.quality_stats.as_ref().unwrap().mean, 11.5);
+
+        // Views borrow the same reads and summarize to the same statistics
+        let view = collection.view_by_length(1000);
+        assert_eq!(view.len(), 4);
+        assert!(std::ptr::eq(view[0], &collection.reads[1]));
+        let summary = collection.summarize(&view);
+        let cloned = collection.filter_by_length(1000).summary;
+        assert_eq!(summary.length_stats.mean, cloned.length_stats.mean);
+        assert_eq!(summary.barcode_distribution, cloned.barcode_distribution);
+
+        let retained = collection
+            .retain(|r| r.length >= 1000)
+            .retain(|r| r.barcode().map(AsRef::as_ref) == Some("barcode02"));
+        assert_eq!(retained.reads.len(), 1);
+        assert_eq!(retained.summary.length_stats.mean, 4000.0);
+        assert!(retained.filter_by_min_identity(90.0).reads.is_empty());
+    }
+
+    #[test]
+    fn test_sorting_and_rank() {
+        let read = |id: &str, length: u64, quality: Option<f64>| {
+            let mut read = ReadMetrics::new(Some(id.to_string()), length);
+            read.quality = quality;
+            read
+        };
+        let mut collection = MetricsCollection::new(vec![
+            read("a", 300, Some(12.0)),
+            read("b", 100, None),
+            read("c", 300, Some(8.0)),
+            read("d", 200, Some(12.0)),
+        ]);
+        let ids = |c: &MetricsCollection| {
+            c.reads
+                .iter()
+                .map(|r| r.read_id.clone().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        collection.sort_by_length(true);
+        assert_eq!(ids(&collection), ["a", "c", "d", "b"]);
+        collection.sort_by_quality(false);
+        assert_eq!(ids(&collection), ["b", "c", "a", "d"]);
+        collection.sort_by_quality(true);
+        assert_eq!(ids(&collection), ["a", "d", "c", "b"]);
+
+        assert_eq!(
+            collection.rank_percentile_of(&collection.reads[3]),
+            Some(25.0)
+        );
+        assert_eq!(
+            collection.rank_percentile_of(&collection.reads[0]),
+            Some(100.0)
+        );
+        assert_eq!(
+            MetricsCollection::new(Vec::new()).rank_percentile_of(&read("x", 1, None)),
             None
+        );
+
+        assert_eq!(
+            "length:desc".parse::<SortOrder>().unwrap(),
+            SortOrder {
+                key: SortKey::Length,
+                descending: true
+            }
+        );
+        assert!(!"quality".parse::<SortOrder>().unwrap().descending);
+        assert!("length:down".parse::<SortOrder>().is_err());
+        assert!("mass".parse::<SortOrder>().is_err());
+    }
+
+    #[test]
+    fn test_filter_by_gc_range() {
+        let reads: Vec<ReadMetrics> = [Some(30.0), Some(50.0), Some(70.0), None]
+            .into_iter()
+            .map(|gc| {
+                let mut read = ReadMetrics::new(None, 100);
+                read.extended_mut().gc_content = gc;
+                read
+            })
+            .collect();
+        let filtered = MetricsCollection::new(reads).filter_by_gc_range(40.0, 60.0);
+        assert_eq!(filtered.summary.read_count, 1);
+        assert_eq!(filtered.reads[0].gc_content(), Some(50.0));
+    }
+
+    #[test]
+    fn test_channel_uniformity() {
+        let collection = |channels: &[u32]| {
+            MetricsCollection::new(
+                channels
+                    .iter()
+                    .map(|&ch| {
+                        let mut read = ReadMetrics::new(None, 100);
+                        read.extended_mut().channel_id = Some(ch);
+                        read
+                    })
+                    .collect(),
+            )
         };
 
-        Self {
-            read_count,
-            length_stats,
-            quality_stats,
-            mapping_quality_stats,
-            percent_identity_stats,
-            channel_distribution,
-            barcode_distribution,
-        }
+        // Even usage of the active channels; absent channels are not zeros
+        let even = collection(&[1, 2, 3, 4, 1, 2, 3, 4]);
+        assert_eq!(even.summary.channel_uniformity, Some(0.0));
+        assert_eq!(even.summary.busiest_channel, Some((1, 2)));
+
+        // Reads per channel 1, 1, 1, 5: G = 2 * 26 / (4 * 8) - 5 / 4 = 0.375
+        let skewed = collection(&[1, 2, 3, 4, 4, 4, 4, 4]);
+        let gini = skewed.summary.channel_uniformity.unwrap();
+        assert!((gini - 0.375).abs() < 1e-12);
+        assert_eq!(skewed.summary.busiest_channel, Some((4, 5)));
+
+        let none = MetricsCollection::new(vec![ReadMetrics::new(None, 100)]);
+        assert!(none.summary.channel_uniformity.is_none());
+        assert!(none.summary.busiest_channel.is_none());
     }
-}
 
-/// Basic statistical summary for numerical data
-#[derive(Debug, Serialize, Deserialize)]
-pub struct StatsSummary {
-    pub count: usize,
-    pub mean: f64,
-    pub median: f64,
-    pub min: f64,
-    pub max: f64,
-    pub std_dev: f64,
-    pub q25: f64,
-    pub q75: f64,
-}
+    #[test]
+    fn test_split_by_class() {
+        let read = |length: u64, quality: f64, class: Option<ReadClass>| {
+            let mut read = ReadMetrics::new(None, length).with_quality(quality);
+            read.extended_mut().read_class = class;
+            read
+        };
+        let collection = MetricsCollection::new(vec![
+            read(100, 10.0, Some(ReadClass::Simplex)),
+            read(200, 12.0, Some(ReadClass::Simplex)),
+            read(300, 20.0, Some(ReadClass::Duplex)),
+            read(400, 9.0, None),
+        ]);
 
-impl StatsSummary {
-    /// Calculate statistics from a vector of values
-    pub fn from_values(values: &[f64]) -> Self {
-        if values.is_empty() {
-            return Self {
-                count: 0,
-                mean: 0.0,
-                median: 0.0,
-                min: 0.0,
-                max: 0.0,
-                std_dev: 0.0,
-                q25: 0.0,
-                q75: 0.0,
-            };
-        }
+        let classes = collection.split_by_class();
+        assert_eq!(classes.len(), 2);
+        assert_eq!(classes[&ReadClass::Simplex].summary.read_count, 2);
+        assert_eq!(classes[&ReadClass::Duplex].summary.read_count, 1);
 
-        let mut sorted_values = values.to_vec();
-        // Use unwrap_or(Equal) to handle NaN values gracefully
-        sorted_values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let per_class = collection.summary.per_read_class.as_ref().unwrap();
+        assert_eq!(per_class[&ReadClass::Simplex].mean_quality, Some(11.0));
+        assert_eq!(per_class[&ReadClass::Duplex].mean_quality, Some(20.0));
 
-        let count = values.len();
-        let mean = values.iter().sum::<f64>() / count as f64;
-        let median = calculate_percentile(&sorted_values, 50.0);
-        let min = sorted_values[0];
-        let max = sorted_values[count - 1];
-        let q25 = calculate_percentile(&sorted_values, 25.0);
-        let q75 = calculate_percentile(&sorted_values, 75.0);
+        let tsv = collection.to_tsv().unwrap();
+        assert!(tsv.contains("# Per-read-class summary\n# read_class\t"));
+        assert!(tsv.contains("# duplex\t1\t300\t300\t300.00\t300.00\t20.00\n"));
 
-        // Calculate standard deviation
-        let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / count as f64;
-        let std_dev = variance.sqrt();
+        assert_eq!(ReadClass::from_dx(-1), Some(ReadClass::DuplexParent));
+        assert_eq!(ReadClass::from_dx(2), None);
+    }
 
-        Self {
-            count,
-            mean,
-            median,
-            min,
-            max,
-            std_dev,
-            q25,
-            q75,
+    #[test]
+    fn test_demux_rate() {
+        let read = |barcode: Option<&str>| {
+            let mut read = ReadMetrics::new(None, 100);
+            read.extended_mut().barcode = barcode.map(Arc::from);
+            read
+        };
+        let reads = vec![
+            read(Some("barcode01")),
+            read(Some("barcode01")),
+            read(Some("barcode02")),
+            read(Some("unclassified")),
+            read(Some("Unclassified")),
+            read(None),
+            read(None),
+        ];
+        let summary = MetricsSummary::from_reads(&reads);
+        assert_eq!(summary.unclassified_count, Some(2));
+        assert_eq!(summary.missing_barcode_count, Some(2));
+        assert_eq!(summary.demux_rate, Some(0.6));
+        let per_barcode = summary.per_barcode.as_ref().unwrap();
+        assert_eq!(per_barcode.len(), 2);
+        assert!(per_barcode
+            .keys()
+            .all(|b| !b.eq_ignore_ascii_case("unclassified")));
+        assert_eq!(
+            summary.barcode_distribution.as_ref().unwrap()["unclassified"],
+            1
+        );
+
+        let mut online = OnlineSummary::new(&SummaryOptions::default());
+        for read in &reads {
+            online.add(read);
         }
-    }
-}
+        let online = online.finish();
+        assert_eq!(online.demux_rate, Some(0.6));
+        assert_eq!(online.unclassified_count, Some(2));
+        assert_eq!(online.missing_barcode_count, Some(2));
 
-/// Calculate percentile from sorted values
-fn calculate_percentile(sorted_values: &[f64], percentile: f64) -> f64 {
-    if sorted_values.is_empty() {
-        return 0.0;
+        let summary = MetricsSummary::from_reads(&[read(None)]);
+        assert!(summary.demux_rate.is_none());
+        assert!(summary.unclassified_count.is_none());
+        assert!(summary.missing_barcode_count.is_none());
     }
 
-    let index = (percentile / 100.0) * (sorted_values.len() - 1) as f64;
-    let lower = index.floor() as usize;
-    let upper = index.ceil() as usize;
+    #[test]
+    fn test_aligned_length_and_duration_stats() {
+        let reads: Vec<ReadMetrics> = [(100, 90, 1.0), (200, 180, 2.0), (400, 350, 6.0)]
+            .into_iter()
+            .map(|(length, aligned, duration)| {
+                let mut read =
+                    ReadMetrics::new(None, length).with_alignment(aligned, None, None, None);
+                read.extended_mut().duration = Some(duration);
+                read
+            })
+            .chain(std::iter::once(ReadMetrics::new(None, 50)))
+            .collect();
+        let summary = MetricsSummary::from_reads(&reads);
 
-    if lower == upper {
-        sorted_values[lower]
-    } else {
-        let weight = index - lower as f64;
-        sorted_values[lower] * (1.0 - weight) + sorted_values[upper] * weight
+        let aligned = summary.aligned_length_stats.as_ref().unwrap();
+        assert_eq!(aligned.count, 3);
+        assert_eq!(aligned.median, 180.0);
+        assert_eq!(summary.aligned_length_n50, Some(350));
+        let duration = summary.duration_stats.as_ref().unwrap();
+        assert_eq!(duration.mean, 3.0);
+        assert_eq!(duration.max, 6.0);
+
+        let mut tsv = Vec::new();
+        summary.write_tsv(&mut tsv, None).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert!(tsv.contains("# Aligned length stats - count: 3,"));
+        assert!(tsv.contains("# Aligned length N50: 350\n"));
+        assert!(tsv.contains("# Duration stats - count: 3, mean: 3.00,"));
+
+        let plain = MetricsSummary::from_reads(&[ReadMetrics::new(None, 50)]);
+        assert!(plain.aligned_length_stats.is_none());
+        assert!(plain.aligned_length_n50.is_none());
+        assert!(plain.duration_stats.is_none());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_yield_concentration() {
+        // Bimodal: 90 short reads of 100 bases and 10 long reads of 20 kb
+        let reads: Vec<ReadMetrics> = std::iter::repeat_n(100, 90)
+            .chain(std::iter::repeat_n(20_000, 10))
+            .map(|length| ReadMetrics::new(None, length))
+            .collect();
+        let collection = MetricsCollection::new(reads);
+
+        let top10 = collection.yield_concentration(0.1).unwrap();
+        assert!((top10 - 200_000.0 / 209_000.0).abs() < 1e-12);
+        assert!(top10 > 0.95);
+        assert_eq!(collection.yield_concentration(1.0).unwrap(), 1.0);
+        // Rounded up to whole reads: 0.5% of 100 reads is the single longest one
+        assert!(
+            (collection.yield_concentration(0.005).unwrap() - 20_000.0 / 209_000.0).abs() < 1e-12
+        );
+
+        assert!(collection.yield_concentration(0.0).is_err());
+        assert!(collection.yield_concentration(1.5).is_err());
+        assert!(collection.yield_concentration(f64::NAN).is_err());
+        assert_eq!(
+            MetricsCollection::new(Vec::new())
+                .yield_concentration(0.1)
+                .unwrap(),
+            0.0
+        );
+    }
 
     #[test]
-    fn test_stats_summary() {
-        let values = vec![1.0, 2.0, 3.0, 4.0, 5.0];
-        let stats = StatsSummary::from_values(&values);
+    fn test_qn50() {
+        let reads = vec![
+            // 900, 1980 and 0 quality-weighted bases
+            ReadMetrics::new(None, 1000).with_quality(10.0),
+            ReadMetrics::new(None, 2000).with_quality(20.0),
+            ReadMetrics::new(None, 3000).with_quality(0.0),
+            ReadMetrics::new(None, 10000),
+        ];
+        let collection = MetricsCollection::new(reads.clone());
+        // The 3000-base read holds half of the bases but none of the weight
+        assert_eq!(collection.qn50(), Some(2000.0));
+        assert_eq!(
+            MetricsCollection::new(reads[..1].to_vec()).qn50(),
+            Some(1000.0)
+        );
 
-        assert_eq!(stats.count, 5);
-        assert_eq!(stats.mean, 3.0);
-        assert_eq!(stats.median, 3.0);
-        assert_eq!(stats.min, 1.0);
-        assert_eq!(stats.max, 5.0);
+        assert_eq!(MetricsCollection::new(reads[2..].to_vec()).qn50(), None);
+        assert_eq!(MetricsCollection::new(reads[3..].to_vec()).qn50(), None);
+        assert_eq!(MetricsCollection::new(Vec::new()).qn50(), None);
     }
 
     #[test]
-    fn test_read_metrics_builder() {
-        let metrics = ReadMetrics::new(Some("read1".to_string()), 1000)
-            .with_quality(35.0)
-            .with_alignment(950, Some(36.0), Some(60), Some(95.5));
+    fn test_n50() {
+        assert_eq!(n50(&[]), 0);
+        assert_eq!(n50(&[5]), 5);
+        // Total 10: 4 + 3 = 7 >= 5
+        assert_eq!(n50(&[1, 2, 3, 4]), 3);
+        // Exactly half counts: 5 * 2 >= 10
+        assert_eq!(n50(&[1, 1, 1, 1, 1, 5]), 5);
+        // Lengths beyond u32::MAX are not truncated
+        let long = u64::from(u32::MAX) + 10;
+        assert_eq!(n50(&[100, long]), long);
+    }
 
-        assert_eq!(metrics.length, 1000);
-        assert_eq!(metrics.quality, Some(35.0));
-        assert_eq!(metrics.aligned_length, Some(950));
-        assert_eq!(metrics.percent_identity, Some(95.5));
+    #[test]
+    fn test_group_by_parent() {
+        let split_read = |id: &str, parent: &str| {
+            let mut read = ReadMetrics::new(Some(id.to_string()), 100);
+            read.extended_mut().parent_id = Some(parent.to_string());
+            read
+        };
+        let collection = MetricsCollection::new(vec![
+            split_read("child1", "parent"),
+            split_read("child2", "parent"),
+            ReadMetrics::new(Some("unsplit".to_string()), 200),
+            ReadMetrics::new(None, 300),
+        ]);
+
+        let groups = collection.group_by_parent();
+        assert_eq!(groups.len(), 2);
+        let children: Vec<_> = groups["parent"]
+            .iter()
+            .map(|r| r.read_id.as_deref().unwrap())
+            .collect();
+        assert_eq!(children, vec!["child1", "child2"]);
+        assert_eq!(groups["unsplit"].len(), 1);
     }
 
     #[test]
-    fn test_tsv_output() {
+    fn test_write_tsv_output() {
         let read1 = ReadMetrics::new(Some("read1".to_string()), 1000).with_quality(35.5);
         let read2 = ReadMetrics::new(Some("read2".to_string()), 2000)
             .with_quality(40.0)
             .with_alignment(1900, Some(41.0), Some(60), Some(95.5));
-
         let metrics = MetricsCollection::new(vec![read1, read2]);
-        let tsv_output = metrics.to_tsv().unwrap();
 
-        // Check that it contains the header
-        assert!(tsv_output.contains("read_id\tlength\tquality"));
+        let mut buffer: Vec<u8> = Vec::new();
+        metrics.write_tsv(&mut buffer).unwrap();
 
-        // Check that it contains the read data with tabs
-        assert!(tsv_output.contains("read1\t1000\t35.500"));
-        assert!(tsv_output.contains("read2\t2000\t40.000"));
+        let expected = concat!(
+            "read_id\tlength\tquality\tquality_std\taligned_length\taligned_quality\tmapping_quality\tpercent_identity\tchannel_id\tmux\tend_reason\tstart_time\tduration\tbarcode\trun_id\tdataset\tparent_id\tpolya_length\tn_count\tgc_content\n",
+            "read1\t1000\t35.500\t\t\t\t\t\t\t\t\t\t\t\t\t\t\t\t\t\n",
+            "read2\t2000\t40.000\t\t1900\t41.000\t60\t95.500\t\t\t\t\t\t\t\t\t\t\t\t\n",
+            "\n",
+            "# Summary Statistics\n",
+            "# Total reads: 2\n",
+            "# Length stats - count: 2, mean: 1500.00, median: 1500.00, min: 1000.00, max: 2000.00, std_dev: 500.00, q25: 1250.00, q75: 1750.00, mad: 500.00, iqr: 500.00\n",
+            "# Length mode bin: [1000, 1100) (1 reads)\n",
+            "# Longest read: read2 (2000 bases)\n",
+            "# Quality stats - count: 2, mean: 37.75, median: 37.75, min: 35.50, max: 40.00, std_dev: 2.25, q25: 36.62, q75: 38.88, mad: 2.25, iqr: 2.25\n",
+            "# Highest quality read: read2 (Q40.00)\n",
+            "# Mapping quality stats - count: 1, mean: 60.00, median: 60.00, min: 60.00, max: 60.00, std_dev: 0.00, q25: 60.00, q75: 60.00, mad: 0.00, iqr: 0.00\n",
+            "# Percent identity stats - count: 1, mean: 95.50, median: 95.50, min: 95.50, max: 95.50, std_dev: 0.00, q25: 95.50, q75: 95.50, mad: 0.00, iqr: 0.00\n",
+            "# Aligned length stats - count: 1, mean: 1900.00, median: 1900.00, min: 1900.00, max: 1900.00, std_dev: 0.00, q25: 1900.00, q75: 1900.00, mad: 0.00, iqr: 0.00\n",
+            "# Aligned length N50: 1900\n",
+            "# Alignments per read: 1: 1 (0.00% of reads with more than one)\n",
+        );
+        assert_eq!(String::from_utf8(buffer).unwrap(), expected);
+    }
 
-        // Check that it contains summary statistics
-        assert!(tsv_output.contains("# Summary Statistics"));
-        assert!(tsv_output.contains("# Total reads: 2"));
-        assert!(tsv_output.contains("# Length stats"));
-        assert!(tsv_output.contains("# Quality stats"));
+    #[test]
+    fn test_alignments_per_read() {
+        let alignment = |read_id: &str| {
+            ReadMetrics::new(Some(read_id.to_string()), 1000).with_alignment(
+                800,
+                None,
+                Some(60),
+                Some(95.0),
+            )
+        };
+        let collection = MetricsCollection::new(vec![
+            alignment("split"),
+            alignment("single"),
+            alignment("split"),
+            alignment("other"),
+            alignment("split"),
+            alignment("other"),
+            // Unaligned reads are not counted
+            ReadMetrics::new(Some("unaligned".to_string()), 500),
+        ]);
+
+        let counts = collection.alignments_per_read();
+        let count = |read_id: &str| counts[&(None, read_id.to_string())];
+        assert_eq!(counts.len(), 3);
+        assert_eq!(count("split"), 3);
+        assert_eq!(count("other"), 2);
+        assert_eq!(count("single"), 1);
+
+        let summary = &collection.summary;
+        assert_eq!(
+            summary.alignment_count_distribution,
+            Some(HashMap::from([(1, 1), (2, 1), (3, 1)]))
+        );
+        assert!((summary.multi_alignment_fraction.unwrap() - 2.0 / 3.0).abs() < 1e-12);
+
+        // The same read in two datasets (track mode) is two reads with one
+        // alignment each
+        let in_dataset = |dataset: &str| {
+            let mut read = alignment("shared");
+            read.extended_mut().dataset = Some(dataset.into());
+            read
+        };
+        let tracked = MetricsCollection::new(vec![in_dataset("a"), in_dataset("b")]);
+        let counts = tracked.alignments_per_read();
+        assert_eq!(counts[&(Some("a".to_string()), "shared".to_string())], 1);
+        assert_eq!(counts[&(Some("b".to_string()), "shared".to_string())], 1);
+        assert_eq!(
+            tracked.summary.alignment_count_distribution,
+            Some(HashMap::from([(1, 2)]))
+        );
+        assert_eq!(tracked.summary.multi_alignment_fraction, Some(0.0));
+
+        let unaligned = MetricsSummary::from_reads(&[ReadMetrics::new(None, 100)]);
+        assert!(unaligned.alignment_count_distribution.is_none());
+        assert!(unaligned.multi_alignment_fraction.is_none());
+    }
+
+    #[test]
+    fn test_longest_and_highest_quality_read() {
+        let read = |id: Option<&str>, length: u64, quality: f64| {
+            ReadMetrics::new(id.map(str::to_string), length).with_quality(quality)
+        };
+        let mut reads = vec![
+            read(Some("short"), 500, 15.0),
+            read(Some("long"), 4000, 12.0),
+            read(Some("tied"), 4000, 15.0),
+        ];
+
+        // Ties go to the first read
+        let summary = MetricsSummary::from_reads(&reads);
+        assert_eq!(summary.longest_read, Some(("long".to_string(), 4000)));
+        assert_eq!(
+            summary.highest_quality_read,
+            Some(("short".to_string(), 15.0))
+        );
+
+        let mut online = OnlineSummary::new(&SummaryOptions::default());
+        for read in &reads {
+            online.add(read);
+        }
+        let streamed = online.finish();
+        assert_eq!(streamed.longest_read, summary.longest_read);
+        assert_eq!(streamed.highest_quality_read, summary.highest_quality_read);
+
+        let mut tsv = Vec::new();
+        summary.write_tsv(&mut tsv, None).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert!(tsv.contains("# Longest read: long (4000 bases)\n"));
+        assert!(tsv.contains("# Highest quality read: short (Q15.00)\n"));
+
+        // A read without an id cannot be reported, but no other read is
+        // reported in its place
+        reads.insert(1, read(None, 9000, 30.0));
+        let summary = MetricsSummary::from_reads(&reads);
+        assert!(summary.longest_read.is_none());
+        assert!(summary.highest_quality_read.is_none());
+        assert_eq!(summary.length_stats.max, 9000.0);
+        let mut online = OnlineSummary::new(&SummaryOptions::default());
+        for read in &reads {
+            online.add(read);
+        }
+        let streamed = online.finish();
+        assert!(streamed.longest_read.is_none());
+        assert!(streamed.highest_quality_read.is_none());
     }
 }
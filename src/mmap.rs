@@ -0,0 +1,343 @@
+use crate::error::NanogetError;
+use crate::utils;
+use log::warn;
+use memchr::{memchr, memmem};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::path::Path;
+
+/// Map `file` into memory for `--mmap`, or None when it should be read with
+/// the standard reader instead: compressed files, which can only be
+/// decompressed as a stream, and files that cannot be mapped (e.g. pipes)
+pub fn map_file(file: &Path) -> Result<Option<Mmap>, NanogetError> {
+    utils::check_file_exists(file)?;
+    let handle = File::open(file)?;
+    // The mapping is only read. As with any memory-mapped reader, a file
+    // truncated by another process while it is being read raises SIGBUS.
+    let map = match unsafe { Mmap::map(&handle) } {
+        Ok(map) => map,
+        Err(e) => {
+            warn!(
+                "{} cannot be memory-mapped ({}); reading it as a stream",
+                file.display(),
+                e
+            );
+            return Ok(None);
+        }
+    };
+    if let Some(compression) = utils::compression_of(&map) {
+        warn!(
+            "{} is {}-compressed and cannot be memory-mapped; reading it as a stream",
+            file.display(),
+            compression
+        );
+        return Ok(None);
+    }
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+    Ok(Some(map))
+}
+
+/// Whitespace trimmed from the end of lines, as `str::trim_end` in the bio
+/// readers does for ASCII text
+fn trim_end(mut line: &[u8]) -> &[u8] {
+    while let [rest @ .., last] = line {
+        if !matches!(last, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c) {
+            break;
+        }
+        line = rest;
+    }
+    line
+}
+
+/// Split a header line (without its first character) at the first character
+/// matching `separator`, into the read id and the description
+fn split_header(
+    header: &[u8],
+    separator: impl Fn(char) -> bool,
+) -> Result<(&str, Option<&str>), std::io::Error> {
+    let header = std::str::from_utf8(trim_end(header)).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        )
+    })?;
+    Ok(match header.char_indices().find(|&(_, c)| separator(c)) {
+        Some((i, c)) => (&header[..i], Some(&header[i + c.len_utf8()..])),
+        None => (header, None),
+    })
+}
+
+/// The lines of a byte slice, without their line terminator
+struct Lines<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Lines<'a> {
+    /// The next line, None at the end of the data. The last line need not end
+    /// with a newline.
+    fn next_line(&mut self) -> Option<&'a [u8]> {
+        let rest = &self.data[self.position..];
+        if rest.is_empty() {
+            return None;
+        }
+        let end = memchr(b'\n', rest).map_or(rest.len(), |i| i + 1);
+        self.position += end;
+        Some(&rest[..end])
+    }
+}
+
+/// A FASTQ record borrowed from the mapped file. The sequence and quality are
+/// only copied for the rare records split over several lines.
+pub struct FastqRecord<'a> {
+    pub id: &'a str,
+    pub desc: Option<&'a str>,
+    pub seq: Cow<'a, [u8]>,
+    pub qual: Cow<'a, [u8]>,
+}
+
+/// The FASTQ records of `data`, parsed as `bio::io::fastq::Reader` does: the
+/// id is the header up to the first space, and a sequence may span several
+/// lines up to the `+` line, followed by as many quality lines
+pub struct FastqRecords<'a> {
+    lines: Lines<'a>,
+    failed: bool,
+}
+
+impl<'a> FastqRecords<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            lines: Lines { data, position: 0 },
+            failed: false,
+        }
+    }
+
+    /// Bytes of the data consumed so far
+    pub fn position(&self) -> usize {
+        self.lines.position
+    }
+
+    fn read(&mut self) -> Result<Option<FastqRecord<'a>>, bio::io::fastq::ReadError> {
+        use bio::io::fastq::ReadError as Error;
+
+        let Some(header) = self.lines.next_line() else {
+            return Ok(None);
+        };
+        let Some(header) = header.strip_prefix(b"@") else {
+            return Err(Error::MissingAt);
+        };
+        let (id, desc) = split_header(header, |c| c == ' ')?;
+
+        let mut seq = Cow::Borrowed(&b""[..]);
+        let mut lines = 0;
+        while let Some(line) = self.lines.next_line() {
+            if line.starts_with(b"+") {
+                break;
+            }
+            append(&mut seq, trim_end(line), lines);
+            lines += 1;
+        }
+        let mut qual = Cow::Borrowed(&b""[..]);
+        for i in 0..lines {
+            let line = self.lines.next_line().unwrap_or_default();
+            append(&mut qual, trim_end(line), i);
+        }
+        if qual.is_empty() {
+            return Err(Error::IncompleteRecord);
+        }
+        Ok(Some(FastqRecord {
+            id,
+            desc,
+            seq,
+            qual,
+        }))
+    }
+}
+
+/// Add the `index`th line to `text`, borrowing the first one
+fn append<'a>(text: &mut Cow<'a, [u8]>, line: &'a [u8], index: usize) {
+    if index == 0 {
+        *text = Cow::Borrowed(line);
+    } else {
+        text.to_mut().extend_from_slice(line);
+    }
+}
+
+impl<'a> Iterator for FastqRecords<'a> {
+    type Item = Result<FastqRecord<'a>, NanogetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.read() {
+            Ok(record) => record.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(NanogetError::parse(&e)))
+            }
+        }
+    }
+}
+
+/// A FASTA record borrowed from the mapped file; its sequence is kept as the
+/// lines it spans, without copying them into one
+pub struct FastaRecord<'a> {
+    pub id: &'a str,
+    #[allow(dead_code)]
+    pub desc: Option<&'a str>,
+    lines: &'a [u8],
+}
+
+impl<'a> FastaRecord<'a> {
+    /// The lines of the sequence, trimmed
+    pub fn seq_lines(&self) -> impl Iterator<Item = &'a [u8]> {
+        let mut lines = Lines {
+            data: self.lines,
+            position: 0,
+        };
+        std::iter::from_fn(move || lines.next_line()).map(trim_end)
+    }
+
+    /// Length of the sequence
+    pub fn seq_len(&self) -> usize {
+        self.seq_lines().map(<[u8]>::len).sum()
+    }
+}
+
+/// The FASTA records of `data`, parsed as `bio::io::fasta::Reader` does: the
+/// id is the header up to the first whitespace, and the sequence is made of
+/// the lines up to the next header
+pub struct FastaRecords<'a> {
+    data: &'a [u8],
+    position: usize,
+    failed: bool,
+}
+
+impl<'a> FastaRecords<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            failed: false,
+        }
+    }
+
+    /// Bytes of the data consumed so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn read(&mut self) -> Result<Option<FastaRecord<'a>>, std::io::Error> {
+        let rest = &self.data[self.position..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        let Some(header) = rest.strip_prefix(b">") else {
+            return Err(std::io::Error::other("Expected > at record start."));
+        };
+        let header_end = memchr(b'\n', header).map_or(header.len(), |i| i + 1);
+        let (id, desc) = split_header(&header[..header_end], char::is_whitespace)?;
+        let body = &header[header_end..];
+        // The sequence ends before the next line starting with '>', which
+        // may be the first one
+        let body_end = if body.starts_with(b">") {
+            0
+        } else {
+            memmem::find(body, b"\n>").map_or(body.len(), |i| i + 1)
+        };
+        self.position += 1 + header_end + body_end;
+        let record = FastaRecord {
+            id,
+            desc,
+            lines: &body[..body_end],
+        };
+        // A record without id, description or sequence ends the records
+        if id.is_empty() && desc.is_none() && record.seq_len() == 0 {
+            return Ok(None);
+        }
+        Ok(Some(record))
+    }
+}
+
+impl<'a> Iterator for FastaRecords<'a> {
+    type Item = Result<FastaRecord<'a>, NanogetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.read() {
+            Ok(record) => record.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(NanogetError::parse(&e)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fastq_records_match_bio() {
+        let data: &[u8] =
+            b"@r1 desc text\nACGT\n+\nIIII\n@r2\r\nAC\nGT\n+r2\n!!\n##\n@r3\tx\nA\n+\n5";
+        let ours: Vec<_> = FastqRecords::new(data).map(Result::unwrap).collect();
+        let bio: Vec<_> = bio::io::fastq::Reader::new(data)
+            .records()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(ours.len(), bio.len());
+        for (ours, bio) in ours.iter().zip(&bio) {
+            assert_eq!(ours.id, bio.id());
+            assert_eq!(ours.desc, bio.desc());
+            assert_eq!(&*ours.seq, bio.seq());
+            assert_eq!(&*ours.qual, bio.qual());
+        }
+        assert!(matches!(ours[0].seq, Cow::Borrowed(_)));
+        assert!(matches!(ours[1].seq, Cow::Owned(_)));
+
+        let errors = |data: &[u8]| {
+            let ours = FastqRecords::new(data).find_map(Result::err).unwrap();
+            let bio = bio::io::fastq::Reader::new(data)
+                .records()
+                .find_map(Result::err)
+                .unwrap();
+            (ours.to_string(), NanogetError::parse(&bio).to_string())
+        };
+        let (ours, bio) = errors(b"@r1\nACGT\n+\nIIII\n\n");
+        assert_eq!(ours, bio);
+        let (ours, bio) = errors(b"@r1\nACGT\n");
+        assert_eq!(ours, bio);
+    }
+
+    #[test]
+    fn test_fasta_records_match_bio() {
+        let data: &[u8] = b">r1 first\tread\nACGT\nAC\n\n>r2\r\nGGGG\r\n>r3 empty\n>r4\nNNA";
+        let ours: Vec<_> = FastaRecords::new(data).map(Result::unwrap).collect();
+        let bio: Vec<_> = bio::io::fasta::Reader::new(data)
+            .records()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(ours.len(), bio.len());
+        for (ours, bio) in ours.iter().zip(&bio) {
+            assert_eq!(ours.id, bio.id());
+            assert_eq!(ours.desc, bio.desc());
+            assert_eq!(ours.seq_lines().collect::<Vec<_>>().concat(), bio.seq());
+            assert_eq!(ours.seq_len(), bio.seq().len());
+        }
+
+        let ours = FastaRecords::new(b"ACGT\n").next().unwrap().err().unwrap();
+        let bio = bio::io::fasta::Reader::new(&b"ACGT\n"[..])
+            .records()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(ours.to_string(), NanogetError::parse(&bio).to_string());
+    }
+}
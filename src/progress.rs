@@ -0,0 +1,275 @@
+//! Progress bars for `--progress`: one bar for each input file being read,
+//! advanced by the bytes read from it (read counts are not known up front), and
+//! one for all inputs together. Also the periodic throughput lines of
+//! `--log-interval`, which are plain log lines and so also work when stderr is
+//! not a terminal.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::info;
+use std::cell::RefCell;
+use std::io::{IsTerminal, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Log target of the `--log-interval` lines, which `init` enables at info
+/// level whatever the verbosity
+pub const THROUGHPUT_TARGET: &str = "throughput";
+
+struct Bars {
+    multi: MultiProgress,
+    total: OnceLock<ProgressBar>,
+}
+
+/// Set by `init` when progress bars are shown
+static BARS: OnceLock<Bars> = OnceLock::new();
+
+thread_local! {
+    /// Bar of the file being read on this thread
+    static CURRENT: RefCell<Option<ProgressBar>> = const { RefCell::new(None) };
+}
+
+/// Set up logging at `level`, or as set by `RUST_LOG` when it is, and with
+/// `progress` the progress bars. Bars are left out when stderr is not a
+/// terminal; when shown, log lines are printed above them rather than through
+/// them. With `throughput`, the lines of `ThroughputLog` are logged too.
+pub fn init(level: log::LevelFilter, progress: bool, throughput: bool) {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level);
+    if throughput {
+        builder.filter(Some(THROUGHPUT_TARGET), log::LevelFilter::Info);
+    }
+    if std::env::var_os(env_logger::DEFAULT_FILTER_ENV).is_some() {
+        builder.parse_default_env();
+    }
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    if !progress || !std::io::stderr().is_terminal() {
+        let _ = log::set_boxed_logger(Box::new(logger));
+        return;
+    }
+    let bars = BARS.get_or_init(|| Bars {
+        multi: MultiProgress::new(),
+        total: OnceLock::new(),
+    });
+    let _ = log::set_boxed_logger(Box::new(SuspendingLogger {
+        inner: logger,
+        multi: bars.multi.clone(),
+    }));
+}
+
+/// Logger that hides the progress bars while a line is written
+struct SuspendingLogger {
+    inner: env_logger::Logger,
+    multi: MultiProgress,
+}
+
+impl log::Log for SuspendingLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.inner.matches(record) {
+            self.multi.suspend(|| self.inner.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ")
+}
+
+/// Add the bar for all of `files`, sized by their total size on disk
+pub(crate) fn start(files: &[&Path]) {
+    let Some(bars) = BARS.get() else {
+        return;
+    };
+    let bytes = files
+        .iter()
+        .filter_map(|file| std::fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let total = bars.multi.add(
+        ProgressBar::new(bytes)
+            .with_style(style(
+                "{msg:>24!} [{bar:40}] {bytes}/{total_bytes} ({elapsed}, {eta} left)",
+            ))
+            .with_message(format!("{} files", files.len())),
+    );
+    let _ = bars.total.set(total);
+}
+
+/// Bar of a file being read; removed when dropped
+pub(crate) struct FileProgress {
+    bar: ProgressBar,
+}
+
+/// Show a bar for `file` while the returned guard lives; the readers opened on
+/// this thread in the meantime (`wrap`) advance it. None without progress bars
+/// or for stdin.
+pub(crate) fn track_file(file: &Path) -> Option<FileProgress> {
+    let bars = BARS.get()?;
+    let bytes = std::fs::metadata(file).ok()?.len();
+    let name = file.file_name().unwrap_or(file.as_os_str());
+    let bar = bars.multi.add(
+        ProgressBar::new(bytes)
+            .with_style(style("{msg:>24!} [{bar:40}] {bytes}/{total_bytes}"))
+            .with_message(name.to_string_lossy().into_owned()),
+    );
+    CURRENT.with(|current| *current.borrow_mut() = Some(bar.clone()));
+    Some(FileProgress { bar })
+}
+
+impl Drop for FileProgress {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().take());
+        // The whole file counts towards the total, also when it was not read
+        // to the end or its position is not known (CRAM)
+        if let Some(total) = BARS.get().and_then(|bars| bars.total.get()) {
+            let length = self.bar.length().unwrap_or(0);
+            total.inc(length.saturating_sub(self.bar.position()));
+            if total.position() >= total.length().unwrap_or(0) {
+                total.finish_and_clear();
+            }
+        }
+        self.bar.finish_and_clear();
+    }
+}
+
+/// `reader` advancing the bar of the file being read on this thread, if any
+pub(crate) fn wrap<R: Read + Send + 'static>(reader: R) -> Box<dyn Read + Send> {
+    match CURRENT.with(|current| current.borrow().clone()) {
+        Some(bar) => Box::new(CountingReader { inner: reader, bar }),
+        None => Box::new(reader),
+    }
+}
+
+/// Move the bar of the file being read on this thread to `bytes`, for readers
+/// that do their own I/O (htslib)
+pub(crate) fn advance_to(bytes: u64) {
+    CURRENT.with(|current| {
+        if let Some(bar) = current.borrow().as_ref() {
+            advance(bar, bytes.saturating_sub(bar.position()));
+        }
+    });
+}
+
+fn advance(bar: &ProgressBar, bytes: u64) {
+    bar.inc(bytes);
+    if let Some(total) = BARS.get().and_then(|bars| bars.total.get()) {
+        total.inc(bytes);
+    }
+}
+
+struct CountingReader<R> {
+    inner: R,
+    bar: ProgressBar,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        advance(&self.bar, n as u64);
+        Ok(n)
+    }
+}
+
+/// Reads processed by the workers of one extraction, counted in a shared
+/// atomic, and with an interval a background thread logging the count and the
+/// rate at every interval (`--log-interval`) until dropped
+#[derive(Debug, Default)]
+pub struct ThroughputLog {
+    reads: Arc<AtomicU64>,
+    stop: Option<mpsc::Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ThroughputLog {
+    /// Start counting; with `interval`, also start logging
+    pub fn start(interval: Option<Duration>) -> Self {
+        let Some(interval) = interval else {
+            return Self::default();
+        };
+        let reads = Arc::new(AtomicU64::new(0));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let counter = Arc::clone(&reads);
+        let worker = std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut last = (started, 0);
+            loop {
+                let finished = match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+                };
+                let now = Instant::now();
+                let total = counter.load(Ordering::Relaxed);
+                if finished {
+                    info!(
+                        target: THROUGHPUT_TARGET,
+                        "{} reads processed in {:.1} s ({:.0} reads/s)",
+                        total,
+                        now.duration_since(started).as_secs_f64(),
+                        rate(total, now.duration_since(started))
+                    );
+                    return;
+                }
+                info!(
+                    target: THROUGHPUT_TARGET,
+                    "{} reads processed ({:.0} reads/s)",
+                    total,
+                    rate(total - last.1, now.duration_since(last.0))
+                );
+                last = (now, total);
+            }
+        });
+        Self {
+            reads,
+            stop: Some(stop),
+            worker: Some(worker),
+        }
+    }
+
+    /// Count `reads` more reads; workers add them in batches to keep the
+    /// shared counter off the per-read path
+    pub fn add(&self, reads: u64) {
+        if reads > 0 {
+            self.reads.fetch_add(reads, Ordering::Relaxed);
+        }
+    }
+
+    /// Reads counted so far
+    #[allow(dead_code)]
+    pub fn reads(&self) -> u64 {
+        self.reads.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for ThroughputLog {
+    fn drop(&mut self) {
+        // Closing the channel wakes the worker for its final line
+        self.stop.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Reads per second
+fn rate(reads: u64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        reads as f64 / seconds
+    } else {
+        0.0
+    }
+}
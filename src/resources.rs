@@ -0,0 +1,220 @@
+use crate::error::NanogetError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What a run cost (`--resource-report`): where the wall-clock time went,
+/// and the CPU time and peak memory of the process
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ResourceReport {
+    /// Wall-clock time of the run, in seconds
+    pub wall_seconds: f64,
+
+    /// Time spent reading the input files, in seconds
+    pub extract_seconds: f64,
+
+    /// Time spent combining the files and computing the summary, in seconds
+    pub combine_seconds: f64,
+
+    /// Time spent writing the output, in seconds; only known once it is
+    /// written, so only in the report printed to stderr
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serialize_seconds: Option<f64>,
+
+    /// User and system CPU time of the process, in seconds (unix)
+    pub cpu_seconds: Option<f64>,
+
+    /// Peak resident set size of the process, in bytes (Linux)
+    pub peak_rss_bytes: Option<u64>,
+
+    /// Reads extracted
+    pub reads: u64,
+
+    /// Reads extracted per second of wall-clock time
+    pub reads_per_second: f64,
+
+    /// Processing time of each input file, in the order in which they
+    /// finished
+    pub files: Vec<FileTime>,
+}
+
+/// Processing time of one input file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileTime {
+    pub file: String,
+
+    /// Wall-clock time spent on the file, in seconds
+    pub seconds: f64,
+
+    /// Reads passed on from the file
+    pub reads: u64,
+}
+
+/// Times an extraction for its `ResourceReport`; files are timed by the
+/// threads processing them
+#[derive(Debug)]
+pub struct ResourceRecorder {
+    started: Instant,
+    files: Mutex<Vec<FileTime>>,
+}
+
+impl ResourceRecorder {
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            files: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Time since the recorder was started
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Record that `file` took `elapsed` and gave `reads` reads
+    pub fn record_file(&self, file: &Path, reads: usize, elapsed: Duration) {
+        // A panic while holding the lock cannot leave the list inconsistent
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files.push(FileTime {
+            file: file.display().to_string(),
+            seconds: elapsed.as_secs_f64(),
+            reads: reads as u64,
+        });
+    }
+
+    /// The report of an extraction of `reads` reads whose files were read in
+    /// `extract` and then combined in `combine`
+    pub fn report(&self, reads: u64, extract: Duration, combine: Duration) -> ResourceReport {
+        let wall_seconds = self.elapsed().as_secs_f64();
+        ResourceReport {
+            wall_seconds,
+            extract_seconds: extract.as_secs_f64(),
+            combine_seconds: combine.as_secs_f64(),
+            serialize_seconds: None,
+            cpu_seconds: cpu_seconds(),
+            peak_rss_bytes: peak_rss_bytes(),
+            reads,
+            reads_per_second: per_second(reads, wall_seconds),
+            files: self.files.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+        }
+    }
+}
+
+impl ResourceReport {
+    /// Add the time spent writing the output, which also counts towards the
+    /// wall-clock time, and measure the CPU time and memory again
+    pub fn add_serialize(&mut self, serialize: Duration) {
+        self.serialize_seconds = Some(serialize.as_secs_f64());
+        self.wall_seconds += serialize.as_secs_f64();
+        self.cpu_seconds = cpu_seconds().or(self.cpu_seconds);
+        self.peak_rss_bytes = peak_rss_bytes().or(self.peak_rss_bytes);
+        self.reads_per_second = per_second(self.reads, self.wall_seconds);
+    }
+
+    /// Write the report as a short block of text, as printed to stderr
+    pub fn write_block<W: Write>(&self, w: &mut W) -> Result<(), NanogetError> {
+        let serialize = self
+            .serialize_seconds
+            .map(|s| format!(", serialize {:.2} s", s))
+            .unwrap_or_default();
+        writeln!(w, "Resource usage")?;
+        writeln!(
+            w,
+            "  Wall time:  {:.2} s (extract {:.2} s, combine {:.2} s{})",
+            self.wall_seconds, self.extract_seconds, self.combine_seconds, serialize
+        )?;
+        if let Some(cpu) = self.cpu_seconds {
+            writeln!(w, "  CPU time:   {:.2} s", cpu)?;
+        }
+        if let Some(rss) = self.peak_rss_bytes {
+            writeln!(w, "  Peak RSS:   {:.1} MiB", rss as f64 / (1024.0 * 1024.0))?;
+        }
+        writeln!(
+            w,
+            "  Reads:      {} ({:.0} reads/s)",
+            self.reads, self.reads_per_second
+        )?;
+        for file in &self.files {
+            writeln!(
+                w,
+                "  {}: {:.2} s, {} reads",
+                file.file, file.seconds, file.reads
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn per_second(reads: u64, seconds: f64) -> f64 {
+    if seconds > 0.0 {
+        reads as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// User and system CPU time of the process so far
+#[cfg(unix)]
+fn cpu_seconds() -> Option<f64> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: `usage` is valid for writes of a `rusage`, which getrusage fills
+    // in completely when it returns 0; only then is it read as initialised.
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let usage = unsafe { usage.assume_init() };
+    let seconds = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1e6;
+    Some(seconds(usage.ru_utime) + seconds(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_seconds() -> Option<f64> {
+    None
+}
+
+/// Peak resident set size of the process so far: the VmHWM line of
+/// /proc/self/status, in kB
+fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_vm_hwm(&status)
+}
+
+fn parse_vm_hwm(status: &str) -> Option<u64> {
+    let line = status.lines().find_map(|l| l.strip_prefix("VmHWM:"))?;
+    let kb: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_report() {
+        assert_eq!(
+            parse_vm_hwm("Name:\tnanoget\nVmPeak:\t  20000 kB\nVmHWM:\t    1536 kB\n"),
+            Some(1536 * 1024)
+        );
+        assert_eq!(parse_vm_hwm("Name:\tnanoget\n"), None);
+
+        let recorder = ResourceRecorder::start();
+        recorder.record_file(Path::new("a.fastq"), 10, Duration::from_millis(500));
+        let mut report = recorder.report(10, Duration::from_secs(1), Duration::from_secs(1));
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.files[0].seconds, 0.5);
+        report.add_serialize(Duration::from_secs(2));
+        assert!(report.wall_seconds >= 2.0);
+        assert_eq!(report.serialize_seconds, Some(2.0));
+        #[cfg(target_os = "linux")]
+        assert!(report.peak_rss_bytes.is_some_and(|rss| rss > 0));
+
+        let mut block = Vec::new();
+        report.write_block(&mut block).unwrap();
+        let block = String::from_utf8(block).unwrap();
+        assert!(block.contains("serialize 2.00 s"));
+        assert!(block.contains("  a.fastq: 0.50 s, 10 reads"));
+    }
+}
@@ -0,0 +1,353 @@
+use crate::error::NanogetError;
+use crate::metrics::{
+    self, ExtendedMetrics, MetricsSummary, OnlineSummary, Provenance, ReadMetrics, SummaryOptions,
+};
+use log::info;
+use serde::ser::{Error as _, SerializeSeq};
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Memory of a read besides its own strings and tags: the struct itself,
+/// which is also its slot in the buffer
+const READ_OVERHEAD: u64 = std::mem::size_of::<ReadMetrics>() as u64;
+
+/// Memory of the extended fields of a read that has them, besides their
+/// strings and tags
+const EXTENDED_OVERHEAD: u64 = std::mem::size_of::<ExtendedMetrics>() as u64;
+
+/// Memory of a tag besides its key and text: the hash table entry and its
+/// share of the empty slots
+const TAG_OVERHEAD: u64 = 64;
+
+/// Coarse estimate of the memory held by `read`: the struct, its extended
+/// fields, its own strings and its tags. The barcode, run id and dataset are
+/// shared with other reads and not counted.
+pub fn estimated_size(read: &ReadMetrics) -> u64 {
+    let text = |s: &Option<String>| s.as_ref().map_or(0, |s| s.capacity() as u64);
+    let Some(extended) = read.extended() else {
+        return READ_OVERHEAD + text(&read.read_id);
+    };
+    let tags: u64 = extended
+        .extra
+        .keys()
+        .map(|key| key.capacity() as u64 + TAG_OVERHEAD)
+        .chain(
+            extended
+                .extra_text
+                .iter()
+                .map(|(key, value)| (key.capacity() + value.capacity()) as u64 + TAG_OVERHEAD),
+        )
+        .sum();
+    READ_OVERHEAD
+        + EXTENDED_OVERHEAD
+        + text(&read.read_id)
+        + text(&extended.end_reason)
+        + text(&extended.parent_id)
+        + tags
+}
+
+/// Collects the reads of an extraction under a memory cap (`--max-memory`):
+/// reads are buffered until their estimated size (see `estimated_size`)
+/// reaches the cap, and the buffer is then written to a temporary file as a
+/// chunk of CBOR records. The summary is computed online, as in streaming
+/// mode (see `OnlineSummary` for which values are approximate or omitted).
+pub struct SpillingCollector {
+    max_memory: u64,
+    buffered: u64,
+    buffer: Vec<ReadMetrics>,
+    dir: tempfile::TempDir,
+    chunks: Vec<PathBuf>,
+    spilled: usize,
+    online: OnlineSummary,
+    numeric_tags: BTreeSet<String>,
+    text_tags: BTreeSet<String>,
+    /// The first failed spill; later reads are dropped
+    error: Option<NanogetError>,
+}
+
+impl SpillingCollector {
+    /// A collector keeping about `max_memory` bytes of reads in memory, which
+    /// spills to a new temporary directory (removed with the `SpilledReads`)
+    pub fn new(max_memory: u64, options: &SummaryOptions) -> Result<Self, NanogetError> {
+        Ok(Self {
+            max_memory,
+            buffered: 0,
+            buffer: Vec::new(),
+            dir: tempfile::Builder::new().prefix("nanoget-spill").tempdir()?,
+            chunks: Vec::new(),
+            spilled: 0,
+            online: OnlineSummary::new(options),
+            numeric_tags: BTreeSet::new(),
+            text_tags: BTreeSet::new(),
+            error: None,
+        })
+    }
+
+    /// Add the next read, spilling the buffer when it reaches the cap
+    pub fn add(&mut self, read: ReadMetrics) {
+        if self.error.is_some() {
+            return;
+        }
+        self.online.add(&read);
+        for tag in read.extra().keys() {
+            if !self.numeric_tags.contains(tag) {
+                self.numeric_tags.insert(tag.clone());
+            }
+        }
+        for tag in read.extra_text().keys() {
+            if !self.text_tags.contains(tag) {
+                self.text_tags.insert(tag.clone());
+            }
+        }
+        self.buffered += estimated_size(&read);
+        self.buffer.push(read);
+        if self.buffered >= self.max_memory {
+            if let Err(e) = self.spill() {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Estimated size of the reads held in memory
+    #[allow(dead_code)]
+    pub fn buffered_bytes(&self) -> u64 {
+        self.buffered
+    }
+
+    /// Write the buffered reads to the next chunk file and empty the buffer
+    fn spill(&mut self) -> Result<(), NanogetError> {
+        let path = self
+            .dir
+            .path()
+            .join(format!("chunk_{}.cbor", self.chunks.len()));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for read in &self.buffer {
+            ciborium::into_writer(read, &mut writer).map_err(|e| {
+                NanogetError::ProcessingError(format!("Cannot write {}: {}", path.display(), e))
+            })?;
+        }
+        writer.flush()?;
+        self.spilled += self.buffer.len();
+        self.buffer.clear();
+        self.buffered = 0;
+        self.chunks.push(path);
+        Ok(())
+    }
+
+    /// The collected reads, in input order, with their summary
+    pub fn finish(self) -> Result<SpilledReads, NanogetError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if !self.chunks.is_empty() {
+            info!(
+                "Spilled {} reads in {} chunks to {}",
+                self.spilled,
+                self.chunks.len(),
+                self.dir.path().display()
+            );
+        }
+        Ok(SpilledReads {
+            summary: self.online.finish(),
+            provenance: None,
+            _dir: self.dir,
+            chunks: self.chunks,
+            tail: self.buffer,
+            numeric_tags: self.numeric_tags,
+            text_tags: self.text_tags,
+        })
+    }
+}
+
+/// The reads of a `--max-memory` extraction (see `SpillingCollector`): the
+/// spilled chunks, read back one read at a time, followed by the reads still
+/// in memory. The chunk files are removed when this is dropped.
+pub struct SpilledReads {
+    /// Summary statistics, computed online
+    pub summary: MetricsSummary,
+
+    /// How the reads were produced (set by `extract_metrics_spilling`)
+    pub provenance: Option<Provenance>,
+
+    _dir: tempfile::TempDir,
+    chunks: Vec<PathBuf>,
+    tail: Vec<ReadMetrics>,
+    numeric_tags: BTreeSet<String>,
+    text_tags: BTreeSet<String>,
+}
+
+impl SpilledReads {
+    /// Number of chunks written to disk
+    #[allow(dead_code)]
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The reads in input order; spilled reads are read back from disk
+    pub fn reads(&self) -> impl Iterator<Item = Result<Cow<'_, ReadMetrics>, NanogetError>> {
+        self.chunks
+            .iter()
+            .flat_map(|path| ChunkReader::new(path).map(|read| read.map(Cow::Owned)))
+            .chain(self.tail.iter().map(|read| Ok(Cow::Borrowed(read))))
+    }
+
+    /// Names of the BAM tags with a numeric value, and with a text value, on
+    /// any read
+    pub(crate) fn tags(&self) -> (BTreeSet<&str>, BTreeSet<&str>) {
+        (
+            self.numeric_tags.iter().map(String::as_str).collect(),
+            self.text_tags.iter().map(String::as_str).collect(),
+        )
+    }
+
+    /// Stream the reads and the summary as JSON, in the layout of a
+    /// `MetricsCollection`
+    pub fn write_json<W: Write>(&self, w: &mut W) -> Result<(), NanogetError> {
+        #[derive(Serialize)]
+        struct Document<'a> {
+            reads: ReadStream<'a>,
+            summary: &'a MetricsSummary,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            provenance: Option<&'a Provenance>,
+        }
+        let document = Document {
+            reads: ReadStream(self),
+            summary: &self.summary,
+            provenance: self.provenance.as_ref(),
+        };
+        serde_json::to_writer_pretty(&mut *w, &document)?;
+        writeln!(w)?;
+        Ok(())
+    }
+
+    /// Stream the reads and the summary as TSV, as
+    /// `MetricsCollection::write_tsv_with_precision`
+    pub fn write_tsv<W: Write>(
+        &self,
+        w: &mut W,
+        precision: Option<usize>,
+    ) -> Result<(), NanogetError> {
+        let extra_columns: Vec<&str> = self
+            .numeric_tags
+            .union(&self.text_tags)
+            .map(String::as_str)
+            .collect();
+        metrics::write_tsv_header(w, &extra_columns)?;
+        for read in self.reads() {
+            metrics::write_tsv_row(w, &*read?, &extra_columns, precision)?;
+        }
+        writeln!(w)?;
+        self.summary.write_tsv(w, precision)
+    }
+}
+
+/// The reads of `SpilledReads` as a JSON array, read back while it is written
+struct ReadStream<'a>(&'a SpilledReads);
+
+impl Serialize for ReadStream<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for read in self.0.reads() {
+            seq.serialize_element(&*read.map_err(S::Error::custom)?)?;
+        }
+        seq.end()
+    }
+}
+
+/// The reads of one chunk file, in order
+struct ChunkReader<'a> {
+    path: &'a Path,
+    reader: Option<BufReader<File>>,
+    opened: bool,
+}
+
+impl<'a> ChunkReader<'a> {
+    fn new(path: &'a Path) -> Self {
+        Self {
+            path,
+            reader: None,
+            opened: false,
+        }
+    }
+}
+
+impl Iterator for ChunkReader<'_> {
+    type Item = Result<ReadMetrics, NanogetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.opened {
+            self.opened = true;
+            match File::open(self.path) {
+                Ok(file) => self.reader = Some(BufReader::new(file)),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        let reader = self.reader.as_mut()?;
+        let result = match reader.fill_buf() {
+            Ok([]) => None,
+            Ok(_) => Some(ciborium::from_reader(&mut *reader).map_err(|e| {
+                NanogetError::ProcessingError(format!("Cannot read {}: {}", self.path.display(), e))
+            })),
+            Err(e) => Some(Err(e.into())),
+        };
+        // Stop at the end of the file and after an error
+        if !matches!(result, Some(Ok(_))) {
+            self.reader = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_under_cap() {
+        let cap = 4096;
+        let mut collector = SpillingCollector::new(cap, &SummaryOptions::default()).unwrap();
+        let reads: Vec<ReadMetrics> = (0..1000)
+            .map(|i| {
+                let mut read = ReadMetrics::new(Some(format!("read{}", i)), 100 + i)
+                    .with_quality(10.0 + (i % 7) as f64);
+                if i % 3 == 0 {
+                    read.extended_mut().extra.insert("NM".to_string(), i as f64);
+                }
+                read
+            })
+            .collect();
+        let total: u64 = reads.iter().map(estimated_size).sum();
+        for read in reads.iter().cloned() {
+            collector.add(read);
+            assert!(collector.buffered_bytes() < cap);
+        }
+        let spilled = collector.finish().unwrap();
+        // Each chunk holds at least the cap, and less than twice it
+        let chunks = spilled.chunk_count() as u64;
+        assert!(chunks <= total / cap && chunks >= total / (2 * cap));
+        assert_eq!(spilled.summary.read_count, 1000);
+
+        // Spilled reads come back complete and in input order
+        let back: Vec<ReadMetrics> = spilled
+            .reads()
+            .map(|read| read.unwrap().into_owned())
+            .collect();
+        assert_eq!(back.len(), reads.len());
+        for (read, expected) in back.iter().zip(&reads) {
+            assert_eq!(read.read_id, expected.read_id);
+            assert_eq!(read.length, expected.length);
+            assert_eq!(read.quality, expected.quality);
+            assert_eq!(read.extra(), expected.extra());
+        }
+
+        let mut tsv = Vec::new();
+        spilled.write_tsv(&mut tsv, None).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert!(tsv.lines().next().unwrap().ends_with("\tNM"));
+        assert!(tsv.contains("\nread999\t1099\t"));
+    }
+}
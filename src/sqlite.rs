@@ -0,0 +1,267 @@
+use crate::error::NanogetError;
+use crate::metrics::{MetricsCollection, MetricsSummary, ReadMetrics};
+use crate::spill::SpilledReads;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, Transaction};
+use std::borrow::Borrow;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Columns of the `reads` table with their SQL types, in order
+const READ_COLUMNS: [(&str, &str); 21] = [
+    ("read_id", "TEXT"),
+    ("length", "INTEGER NOT NULL"),
+    ("quality", "REAL"),
+    ("quality_std", "REAL"),
+    ("aligned_length", "INTEGER"),
+    ("aligned_quality", "REAL"),
+    ("mapping_quality", "INTEGER"),
+    ("percent_identity", "REAL"),
+    ("channel_id", "INTEGER"),
+    ("mux", "INTEGER"),
+    ("end_reason", "TEXT"),
+    ("start_time", "TEXT"),
+    ("duration", "REAL"),
+    ("barcode", "TEXT"),
+    ("run_id", "TEXT"),
+    ("dataset", "TEXT"),
+    ("parent_id", "TEXT"),
+    ("read_class", "TEXT"),
+    ("polya_length", "INTEGER"),
+    ("n_count", "INTEGER"),
+    ("gc_content", "REAL"),
+];
+
+impl MetricsCollection {
+    /// Write the reads and the summary to a new SQLite database at `path`: a
+    /// `reads` table with a typed column per metric (NULL when missing, start
+    /// times as RFC 3339 text) plus one per BAM tag, and a `summary` table
+    /// (see `MetricsSummary::write_sqlite`). Everything is inserted in one
+    /// transaction.
+    pub fn write_sqlite(&self, path: &Path) -> Result<(), NanogetError> {
+        let mut conn = Connection::open(path)?;
+        let tx = conn.transaction()?;
+        // BAM tag columns, as in the TSV output: numeric tags are REAL, text TEXT
+        let numeric_tags: BTreeSet<&str> = self
+            .reads
+            .iter()
+            .flat_map(|r| r.extra().keys())
+            .map(String::as_str)
+            .collect();
+        let text_tags: BTreeSet<&str> = self
+            .reads
+            .iter()
+            .flat_map(|r| r.extra_text().keys())
+            .map(String::as_str)
+            .collect();
+        write_reads(&tx, &numeric_tags, &text_tags, self.reads.iter().map(Ok))?;
+        write_summary(&tx, &self.summary)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl MetricsSummary {
+    /// Write the summary to a new SQLite database at `path`, as a `summary`
+    /// table of (name, value) rows: the numbers and text of the JSON summary,
+    /// with nested fields named by their path (e.g. `length_stats.median`,
+    /// `channel_distribution.12`)
+    pub fn write_sqlite(&self, path: &Path) -> Result<(), NanogetError> {
+        let mut conn = Connection::open(path)?;
+        let tx = conn.transaction()?;
+        write_summary(&tx, self)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl SpilledReads {
+    /// Write the reads and the summary to a new SQLite database at `path`, as
+    /// `MetricsCollection::write_sqlite`, reading the spilled reads back
+    pub fn write_sqlite(&self, path: &Path) -> Result<(), NanogetError> {
+        let mut conn = Connection::open(path)?;
+        let tx = conn.transaction()?;
+        let (numeric_tags, text_tags) = self.tags();
+        write_reads(&tx, &numeric_tags, &text_tags, self.reads())?;
+        write_summary(&tx, &self.summary)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Create the `reads` table, with a column per tag of `numeric_tags` (REAL)
+/// and of `text_tags` (TEXT; tags that are also numeric are left out), and
+/// insert `reads` in order
+fn write_reads<R: Borrow<ReadMetrics>>(
+    tx: &Transaction,
+    numeric_tags: &BTreeSet<&str>,
+    text_tags: &BTreeSet<&str>,
+    reads: impl IntoIterator<Item = Result<R, NanogetError>>,
+) -> Result<(), NanogetError> {
+    let text_tags: BTreeSet<&str> = text_tags.difference(numeric_tags).copied().collect();
+
+    let columns: Vec<String> = READ_COLUMNS
+        .iter()
+        .map(|(name, sql_type)| format!("\"{}\" {}", name, sql_type))
+        .chain(numeric_tags.iter().map(|tag| format!("\"{}\" REAL", tag)))
+        .chain(text_tags.iter().map(|tag| format!("\"{}\" TEXT", tag)))
+        .collect();
+    tx.execute(&format!("CREATE TABLE reads ({})", columns.join(", ")), [])?;
+
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let mut insert = tx.prepare(&format!("INSERT INTO reads VALUES ({})", placeholders))?;
+    let text = |value: Option<&str>| value.map_or(Value::Null, |v| Value::Text(v.to_string()));
+    for read in reads {
+        let read = read?;
+        let read = read.borrow();
+        let mut values = vec![
+            text(read.read_id.as_deref()),
+            Value::Integer(read.length as i64),
+            read.quality.map_or(Value::Null, Value::Real),
+            read.quality_std.map_or(Value::Null, Value::Real),
+            read.aligned_length()
+                .map_or(Value::Null, |l| Value::Integer(l as i64)),
+            read.aligned_quality().map_or(Value::Null, Value::Real),
+            read.mapping_quality()
+                .map_or(Value::Null, |q| Value::Integer(i64::from(q))),
+            read.percent_identity().map_or(Value::Null, Value::Real),
+            read.channel_id()
+                .map_or(Value::Null, |c| Value::Integer(i64::from(c))),
+            read.mux()
+                .map_or(Value::Null, |m| Value::Integer(i64::from(m))),
+            text(read.end_reason()),
+            read.start_time()
+                .map_or(Value::Null, |t| Value::Text(t.to_rfc3339())),
+            read.duration().map_or(Value::Null, Value::Real),
+            text(read.barcode().map(AsRef::as_ref)),
+            text(read.run_id().map(AsRef::as_ref)),
+            text(read.dataset().map(AsRef::as_ref)),
+            text(read.parent_id()),
+            text(read.read_class().map(|c| c.as_str())),
+            read.polya_length()
+                .map_or(Value::Null, |l| Value::Integer(i64::from(l))),
+            read.n_count()
+                .map_or(Value::Null, |n| Value::Integer(n as i64)),
+            read.gc_content().map_or(Value::Null, Value::Real),
+        ];
+        for tag in numeric_tags {
+            values.push(
+                read.extra()
+                    .get(*tag)
+                    .map_or(Value::Null, |&v| Value::Real(v)),
+            );
+        }
+        for tag in &text_tags {
+            values.push(text(read.extra_text().get(*tag).map(String::as_str)));
+        }
+        insert.execute(params_from_iter(values))?;
+    }
+    Ok(())
+}
+
+fn write_summary(tx: &Transaction, summary: &MetricsSummary) -> Result<(), NanogetError> {
+    tx.execute("CREATE TABLE summary (name TEXT PRIMARY KEY, value)", [])?;
+    let mut rows = Vec::new();
+    flatten("", &serde_json::to_value(summary)?, &mut rows);
+    let mut insert = tx.prepare("INSERT INTO summary VALUES (?1, ?2)")?;
+    for (name, value) in rows {
+        insert.execute((name, value))?;
+    }
+    Ok(())
+}
+
+/// Collect the leaves of `value` as (path, value) rows, joining object keys
+/// and array indices with dots
+fn flatten(prefix: &str, value: &serde_json::Value, rows: &mut Vec<(String, Value)>) {
+    let child = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields {
+                flatten(&child(key), value, rows);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for (i, value) in items.iter().enumerate() {
+                flatten(&child(&i.to_string()), value, rows);
+            }
+        }
+        serde_json::Value::Null => rows.push((prefix.to_string(), Value::Null)),
+        serde_json::Value::Bool(b) => {
+            rows.push((prefix.to_string(), Value::Integer(i64::from(*b))))
+        }
+        serde_json::Value::Number(n) => {
+            let value = match n.as_i64() {
+                Some(i) => Value::Integer(i),
+                None => n.as_f64().map_or(Value::Null, Value::Real),
+            };
+            rows.push((prefix.to_string(), value));
+        }
+        serde_json::Value::String(s) => rows.push((prefix.to_string(), Value::Text(s.clone()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.sqlite");
+        let mut aligned = ReadMetrics::new(Some("read2".to_string()), 2000)
+            .with_quality(14.0)
+            .with_alignment(1900, None, Some(60), Some(95.5));
+        aligned.extended_mut().extra.insert("NM".to_string(), 12.0);
+        let collection = MetricsCollection::new(vec![
+            ReadMetrics::new(Some("read1".to_string()), 1000).with_quality(10.0),
+            aligned,
+        ]);
+        collection.write_sqlite(&path).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM reads", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        let (length, mapq, nm): (i64, Option<i64>, Option<f64>) = conn
+            .query_row(
+                "SELECT length, mapping_quality, NM FROM reads WHERE read_id = 'read2'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((length, mapq, nm), (2000, Some(60), Some(12.0)));
+        // Missing values are NULL, not empty strings or zeros
+        let nulls: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM reads WHERE mapping_quality IS NULL AND NM IS NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(nulls, 1);
+
+        let median: f64 = conn
+            .query_row(
+                "SELECT value FROM summary WHERE name = 'length_stats.median'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(median, 1500.0);
+        let read_count: i64 = conn
+            .query_row(
+                "SELECT value FROM summary WHERE name = 'read_count'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(read_count, 2);
+    }
+}
@@ -1,6 +1,9 @@
 use crate::error::NanogetError;
-use std::path::Path;
-use std::sync::OnceLock;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 
 /// Precomputed error probabilities for Phred scores 0–255.
 /// Replaces per-base `powf` calls with a table lookup.
@@ -16,6 +19,116 @@ fn phred_to_prob_table() -> &'static [f64; 256] {
     })
 }
 
+/// Offset of the standard (Sanger / Illumina 1.8+) FASTQ quality encoding.
+/// FASTQ parsers return the raw ASCII quality characters, so this must be
+/// subtracted to obtain Phred scores.
+pub const FASTQ_PHRED_OFFSET: u8 = 33;
+
+/// Small deterministic pseudo-random generator (SplitMix64).
+///
+/// Used for seeded sampling: unlike a library RNG, its output for a given seed
+/// can never change with a dependency upgrade, so results stay reproducible.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform integer in `0..bound` (`bound` > 0), without modulo bias
+    pub fn below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// Number of independently locked shards of a `StringInterner`
+const INTERNER_SHARDS: usize = 16;
+
+/// Shared copies of the strings that repeat across reads (barcodes, run ids),
+/// so that each distinct value is allocated once per extraction. The set is
+/// split into shards by hash, each behind its own lock, so that files
+/// processed in parallel rarely wait on each other.
+#[derive(Debug)]
+pub struct StringInterner {
+    hasher: RandomState,
+    shards: Vec<Mutex<HashSet<Arc<str>>>>,
+}
+
+impl Default for StringInterner {
+    fn default() -> Self {
+        Self {
+            hasher: RandomState::new(),
+            shards: (0..INTERNER_SHARDS).map(|_| Mutex::default()).collect(),
+        }
+    }
+}
+
+impl StringInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The shared copy of `value`, added on first use
+    pub fn intern(&self, value: &str) -> Arc<str> {
+        let shard = self.hasher.hash_one(value) as usize % self.shards.len();
+        // A panic while holding the lock cannot leave the set inconsistent
+        let mut set = self.shards[shard]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match set.get(value) {
+            Some(shared) => Arc::clone(shared),
+            None => {
+                let shared: Arc<str> = Arc::from(value);
+                set.insert(Arc::clone(&shared));
+                shared
+            }
+        }
+    }
+
+    /// Number of distinct strings interned
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .lock()
+                    .map_or_else(|p| p.into_inner().len(), |s| s.len())
+            })
+            .sum()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The number of threads for a `--threads` value: all available cores for 0
+pub fn resolve_threads(threads: usize) -> usize {
+    match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
 /// Check if a file exists
 pub fn check_file_exists(path: &Path) -> Result<(), NanogetError> {
     if !path.exists() {
@@ -26,33 +139,254 @@ pub fn check_file_exists(path: &Path) -> Result<(), NanogetError> {
     Ok(())
 }
 
+/// Fail unless `path` can be written: it must not exist yet, unless `force`
+pub fn check_output_path(path: &Path, force: bool) -> Result<(), NanogetError> {
+    if !force && path.exists() {
+        return Err(NanogetError::InvalidInput(format!(
+            "Output file {} already exists; pass --force to overwrite it",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// `name` (e.g. a barcode) as a file name: characters other than letters,
+/// digits, `-`, `_` and `.` are replaced with `_`, so that it cannot name
+/// another directory; names made of dots only are prefixed with `_`
+pub fn sanitize_file_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if sanitized.chars().all(|c| c == '.') {
+        format!("_{}", sanitized)
+    } else {
+        sanitized
+    }
+}
+
+/// An output file written atomically: the content goes to a temporary file
+/// in the same directory, which only replaces `path` on `commit`. An
+/// interrupted run, or one that fails before `commit`, leaves no partial file
+/// behind (the temporary file is removed when dropped).
+pub struct AtomicFile {
+    writer: BufWriter<tempfile::NamedTempFile>,
+    path: PathBuf,
+    force: bool,
+}
+
+impl AtomicFile {
+    /// Start writing `path`; an existing file is an error unless `force`
+    pub fn create(path: &Path, force: bool) -> Result<Self, NanogetError> {
+        check_output_path(path, force)?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".nanoget-").suffix(".tmp");
+        // Temporary files are private by default; give the output the usual
+        // permissions (subject to the umask)
+        #[cfg(unix)]
+        builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+        let file = builder.tempfile_in(dir)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            path: path.to_path_buf(),
+            force,
+        })
+    }
+
+    /// Path of the temporary file, for writers that need a path rather than
+    /// a stream; it is moved into place as it is on `commit`
+    pub fn temp_path(&self) -> &Path {
+        self.writer.get_ref().path()
+    }
+
+    /// Move the complete file into place
+    pub fn commit(self) -> Result<(), NanogetError> {
+        let file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        let persisted = if self.force {
+            file.persist(&self.path)
+        } else {
+            // Another process may have created the file in the meantime
+            file.persist_noclobber(&self.path)
+        };
+        persisted.map_err(|e| match e.error.kind() {
+            std::io::ErrorKind::AlreadyExists => NanogetError::InvalidInput(format!(
+                "Output file {} already exists; pass --force to overwrite it",
+                self.path.display()
+            )),
+            _ => NanogetError::Io(e.error),
+        })?;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
 /// Calculate average quality from Phred scores.
 /// Uses a precomputed lookup table to avoid per-base `powf` calls.
+#[allow(dead_code)]
 pub fn average_quality(qualities: &[u8]) -> Option<f64> {
+    average_quality_shifted(qualities, 0)
+}
+
+/// Calculate average quality after subtracting a fixed `offset` from every score.
+///
+/// Used both to decode FASTQ quality strings (Phred+33) and to apply the
+/// user-supplied `--quality-offset`. Errors when a score is smaller than the
+/// offset rather than silently wrapping around to a very high quality.
+pub fn average_quality_with_offset(
+    qualities: &[u8],
+    offset: u8,
+) -> Result<Option<f64>, NanogetError> {
+    check_quality_offset(qualities, 0, offset)?;
+    Ok(average_quality_shifted(qualities, offset))
+}
+
+/// Arithmetic mean of the Phred scores, skipping the missing-quality sentinel
+/// 255. Other tools report this; it is higher than `average_quality` for a
+/// read whose quality varies, as a few low-quality bases hold most of its
+/// expected errors but weigh no more than any other base in the mean.
+#[allow(dead_code)]
+pub fn average_quality_arithmetic(qualities: &[u8]) -> Option<f64> {
+    average_quality_arithmetic_shifted(qualities, 0)
+}
+
+/// `average_quality_arithmetic` after subtracting `offset` from every score,
+/// as `average_quality_with_offset`
+pub fn average_quality_arithmetic_with_offset(
+    qualities: &[u8],
+    offset: u8,
+) -> Result<Option<f64>, NanogetError> {
+    check_quality_offset(qualities, 0, offset)?;
+    Ok(average_quality_arithmetic_shifted(qualities, offset))
+}
+
+/// Error for a score below `offset` (other than the missing sentinel 255), for
+/// scores stored with `encoding` added (`FASTQ_PHRED_OFFSET` for FASTQ text, 0
+/// for BAM). Scores and offset are reported as Phred values, as the user gave
+/// the offset.
+pub fn check_quality_offset(
+    qualities: &[u8],
+    encoding: u8,
+    offset: u8,
+) -> Result<(), NanogetError> {
+    let floor = u16::from(encoding) + u16::from(offset);
+    if let Some(&q) = qualities
+        .iter()
+        .find(|&&q| q != 255 && u16::from(q) < floor)
+    {
+        if q < encoding {
+            return Err(NanogetError::InvalidInput(format!(
+                "Quality character {:?} is outside the Phred+{} range",
+                char::from(q),
+                encoding
+            )));
+        }
+        return Err(NanogetError::InvalidInput(format!(
+            "Quality value {} is below the quality offset {}",
+            q - encoding,
+            offset
+        )));
+    }
+    Ok(())
+}
+
+fn average_quality_arithmetic_shifted(qualities: &[u8], offset: u8) -> Option<f64> {
+    let (sum, n) = qualities
+        .iter()
+        .filter(|&&q| q != 255)
+        .fold((0u64, 0u64), |(sum, n), &q| {
+            (sum + u64::from(q - offset), n + 1)
+        });
+    (n > 0).then(|| sum as f64 / n as f64)
+}
+
+/// Independent partial sums of `average_quality_shifted`, so that the loop
+/// has no dependency chain between neighbouring bases
+const QUALITY_LANES: usize = 8;
+
+/// Shared implementation of the averaging; callers guarantee `q >= offset`.
+///
+/// The loop is branch-free (missing bases add 0 and are not counted) over
+/// fixed-size chunks with one accumulator per lane, which the compiler can
+/// unroll and vectorize.
+fn average_quality_shifted(qualities: &[u8], offset: u8) -> Option<f64> {
     if qualities.is_empty() {
         return None;
     }
 
     let table = phred_to_prob_table();
-    let mut error_sum = 0.0f64;
-    let mut n = 0usize;
-
-    for &q in qualities {
-        // 255 is the missing-quality sentinel in BAM; skip those bases
-        if q != 255 {
-            error_sum += table[q as usize];
-            n += 1;
+    let mut sums = [0.0f64; QUALITY_LANES];
+    let mut counts = [0usize; QUALITY_LANES];
+    // 255 is the missing-quality sentinel in BAM; skip those bases
+    let mut add = |lane: usize, q: u8| {
+        let present = q != 255;
+        let error = table[q.wrapping_sub(offset) as usize];
+        sums[lane] += if present { error } else { 0.0 };
+        counts[lane] += usize::from(present);
+    };
+    let chunks = qualities.chunks_exact(QUALITY_LANES);
+    let remainder = chunks.remainder();
+    for chunk in chunks {
+        for (lane, &q) in chunk.iter().enumerate() {
+            add(lane, q);
         }
     }
+    for (lane, &q) in remainder.iter().enumerate() {
+        add(lane, q);
+    }
 
+    let n: usize = counts.iter().sum();
     if n == 0 {
         return None;
     }
+    let error_sum: f64 = sums.iter().sum();
 
     let result = -10.0 * (error_sum / n as f64).log10();
     Some(result.clamp(0.0, 60.0))
 }
 
+/// Population standard deviation of the Phred scores after subtracting
+/// `offset`, skipping the missing-quality sentinel 255; `None` without any
+/// score. Scores below the offset count as 0: callers check them first (see
+/// `check_quality_offset`), as the averages do
+pub fn quality_std(qualities: &[u8], offset: u8) -> Option<f64> {
+    let mut n = 0u64;
+    let mut sum = 0u64;
+    let mut sum_squares = 0u64;
+    for &q in qualities {
+        if q != 255 {
+            let q = u64::from(q.saturating_sub(offset));
+            n += 1;
+            sum += q;
+            sum_squares += q * q;
+        }
+    }
+    if n == 0 {
+        return None;
+    }
+    let mean = sum as f64 / n as f64;
+    let variance = sum_squares as f64 / n as f64 - mean * mean;
+    Some(variance.max(0.0).sqrt())
+}
+
 /// Calculate percent identity from CIGAR operations and reference length
 #[allow(dead_code)]
 pub fn calculate_percent_identity(matches: u32, total_aligned: u32) -> f64 {
@@ -74,6 +408,16 @@ pub enum CompressionType {
 }
 
 impl CompressionType {
+    /// Name of the compression in reports
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Bzip2 => "bzip2",
+            Self::Bgzip => "bgzf",
+        }
+    }
+
     pub fn from_path(path: &Path) -> Self {
         let path_str = path.to_string_lossy().to_lowercase();
 
@@ -88,38 +432,265 @@ impl CompressionType {
     }
 }
 
-/// Open a file with appropriate decompression
+/// Whether a location is a URL (`scheme://...`) rather than a local path
+pub fn is_url(location: &str) -> bool {
+    location.split_once("://").is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+/// Open a file with appropriate decompression. Gzip and bzip2 files may hold
+/// several concatenated members (bgzip blocks, `cat a.gz b.gz`, pbzip2), which
+/// are all read; a member of another compression (`cat a.gz b.bz2`) is an
+/// error rather than being dropped.
 pub fn open_file(path: &Path) -> Result<Box<dyn std::io::Read>, NanogetError> {
     use std::fs::File;
     use std::io::BufReader;
 
     check_file_exists(path)?;
 
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+    let file = crate::progress::wrap(File::open(path)?);
+    Ok(decompress(BufReader::new(file), path))
+}
 
+/// `reader` over the content of `path`, decompressed as its extension says
+/// (see `open_file`)
+pub fn decompress<R: std::io::BufRead + 'static>(reader: R, path: &Path) -> Box<dyn std::io::Read> {
     match CompressionType::from_path(path) {
-        CompressionType::None => Ok(Box::new(reader)),
-        CompressionType::Gzip => {
-            use flate2::read::GzDecoder;
-            Ok(Box::new(GzDecoder::new(reader)))
+        CompressionType::None => Box::new(reader),
+        compression => Box::new(MultiMemberDecoder::new(reader, compression, path)),
+    }
+}
+
+/// Leading bytes of the compressed formats recognized after a member, with
+/// their names
+const COMPRESSION_MAGICS: [(&[u8], &str); 4] = [
+    (&[0x1f, 0x8b], "gzip"),
+    (b"BZh", "bzip2"),
+    (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], "xz"),
+    (&[0x28, 0xb5, 0x2f, 0xfd], "zstd"),
+];
+
+/// The compression of data starting with `bytes`, from its magic bytes
+pub fn compression_of(bytes: &[u8]) -> Option<&'static str> {
+    COMPRESSION_MAGICS
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+        .map(|&(_, name)| name)
+}
+
+/// One gzip or bzip2 member being decoded
+enum Member<R> {
+    Gzip(flate2::bufread::GzDecoder<R>),
+    Bzip2(bzip2::bufread::BzDecoder<R>),
+}
+
+/// Decoder of concatenated gzip or bzip2 members, like `MultiGzDecoder` and
+/// `MultiBzDecoder`, that checks the data following each member: more data
+/// of a different compression cannot be decoded by the same decoder, and is
+/// reported instead of failing with a decoder error
+struct MultiMemberDecoder<R> {
+    member: Option<Member<R>>,
+    compression: CompressionType,
+    path: PathBuf,
+}
+
+impl<R: std::io::BufRead> MultiMemberDecoder<R> {
+    fn new(reader: R, compression: CompressionType, path: &Path) -> Self {
+        let mut decoder = Self {
+            member: None,
+            compression,
+            path: path.to_path_buf(),
+        };
+        decoder.member = Some(decoder.start_member(reader));
+        decoder
+    }
+
+    fn start_member(&self, reader: R) -> Member<R> {
+        match self.compression {
+            CompressionType::Bzip2 => Member::Bzip2(bzip2::bufread::BzDecoder::new(reader)),
+            _ => Member::Gzip(flate2::bufread::GzDecoder::new(reader)),
         }
-        CompressionType::Bzip2 => {
-            use bzip2::read::BzDecoder;
-            Ok(Box::new(BzDecoder::new(reader)))
+    }
+
+    /// Check what follows a finished member: nothing, another member of the
+    /// same compression, or (an error) data of another compression
+    fn next_member(&mut self, mut reader: R) -> std::io::Result<Option<Member<R>>> {
+        let rest = reader.fill_buf()?;
+        if rest.is_empty() {
+            return Ok(None);
         }
-        CompressionType::Bgzip => {
-            // For now, treat bgzip same as gzip
-            use flate2::read::GzDecoder;
-            Ok(Box::new(GzDecoder::new(reader)))
+        match compression_of(rest) {
+            Some(name) if name != self.compression.name() => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{}: a {} member is followed by {}-compressed data; files with mixed \
+                     compression cannot be read in one pass, decompress the parts separately",
+                    self.path.display(),
+                    self.compression.name(),
+                    name
+                ),
+            )),
+            _ => Ok(Some(self.start_member(reader))),
         }
     }
 }
 
+impl<R: std::io::BufRead> std::io::Read for MultiMemberDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let n = match &mut self.member {
+                Some(Member::Gzip(decoder)) => decoder.read(buf)?,
+                Some(Member::Bzip2(decoder)) => decoder.read(buf)?,
+                None => return Ok(0),
+            };
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            let reader = match self.member.take() {
+                Some(Member::Gzip(decoder)) => decoder.into_inner(),
+                Some(Member::Bzip2(decoder)) => decoder.into_inner(),
+                None => return Ok(0),
+            };
+            self.member = self.next_member(reader)?;
+        }
+    }
+}
+
+/// Count the G/C bases and the ambiguous (non-ACGT) bases of a sequence,
+/// case-insensitively, in a single scan
+pub fn base_composition(seq: &[u8]) -> (u64, u64) {
+    let mut gc = 0;
+    let mut ambiguous = 0;
+    for base in seq {
+        match base.to_ascii_uppercase() {
+            b'G' | b'C' => gc += 1,
+            b'A' | b'T' => {}
+            _ => ambiguous += 1,
+        }
+    }
+    (gc, ambiguous)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_average_quality_matches_formula() {
+        // The formula the lookup table replaced
+        let reference = |qualities: &[u8], offset: u8| {
+            let errors: Vec<f64> = qualities
+                .iter()
+                .filter(|&&q| q != 255)
+                .map(|&q| 10f64.powf((q - offset) as f64 / -10.0))
+                .collect();
+            (!errors.is_empty()).then(|| {
+                let mean = errors.iter().sum::<f64>() / errors.len() as f64;
+                (-10.0 * mean.log10()).clamp(0.0, 60.0)
+            })
+        };
+        let mut rng = SplitMix64::new(42);
+        for _ in 0..2000 {
+            let offset = [0, FASTQ_PHRED_OFFSET][rng.below(2) as usize];
+            let length = rng.below(300) as usize;
+            // Scores up to Q93, with some missing ones
+            let qualities: Vec<u8> = (0..length)
+                .map(|_| match rng.below(20) {
+                    0 => 255,
+                    _ => offset + rng.below(94) as u8,
+                })
+                .collect();
+            let expected = reference(&qualities, offset);
+            let actual = average_quality_with_offset(&qualities, offset).unwrap();
+            match (actual, expected) {
+                (Some(a), Some(e)) => {
+                    assert!((a - e).abs() < 1e-12, "{:?}: {} vs {}", qualities, a, e)
+                }
+                (a, e) => assert_eq!(a, e, "{:?}", qualities),
+            }
+        }
+    }
+
+    #[test]
+    fn test_mixed_compression() {
+        use std::io::Read;
+        let gzip = |content: &[u8]| {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(content).unwrap();
+            encoder.finish().unwrap()
+        };
+        let bzip2 = |content: &[u8]| {
+            let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), Default::default());
+            encoder.write_all(content).unwrap();
+            encoder.finish().unwrap()
+        };
+        let read = |data: Vec<u8>, name: &str| {
+            let mut content = String::new();
+            decompress(std::io::Cursor::new(data), Path::new(name))
+                .read_to_string(&mut content)
+                .map(|_| content)
+        };
+
+        // Members of the same compression are all read
+        let members = [gzip(b"@r1\n"), gzip(b""), gzip(b"@r2\n")].concat();
+        assert_eq!(read(members, "reads.fastq.gz").unwrap(), "@r1\n@r2\n");
+        let members = [bzip2(b"@r1\n"), bzip2(b"@r2\n")].concat();
+        assert_eq!(read(members, "reads.fastq.bz2").unwrap(), "@r1\n@r2\n");
+
+        let mixed = [gzip(b"@r1\n"), bzip2(b"@r2\n")].concat();
+        let error = read(mixed, "reads.fastq.gz").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error
+            .to_string()
+            .contains("a gzip member is followed by bzip2-compressed data"));
+        let mixed = [bzip2(b"@r1\n"), gzip(b"@r2\n")].concat();
+        let error = read(mixed, "reads.fastq.bz2").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("a bzip2 member is followed by gzip-compressed data"));
+    }
+
+    #[test]
+    fn test_string_interner_concurrent() {
+        let interner = StringInterner::new();
+        let shared: Vec<Vec<Arc<str>>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..1000)
+                            .map(|i| interner.intern(&format!("barcode{:02}", i % 24)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+        assert_eq!(interner.len(), 24);
+        // Every thread got the same allocation for the same value
+        for values in &shared[1..] {
+            for (a, b) in values.iter().zip(&shared[0]) {
+                assert!(Arc::ptr_eq(a, b));
+            }
+        }
+        assert_eq!(&*shared[3][25], "barcode01");
+    }
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://example.org/reads.bam.bai"));
+        assert!(is_url("s3://bucket/reads.bam.csi"));
+        assert!(is_url("file:///data/reads.bam.bai"));
+        assert!(!is_url("/data/reads.bam.bai"));
+        assert!(!is_url("reads.bam.csi"));
+        assert!(!is_url("://missing-scheme"));
+        assert!(!is_url("dir with space://x"));
+    }
+
     #[test]
     fn test_average_quality_basic() {
         // Basic test with typical Nanopore quality scores
@@ -144,7 +715,7 @@ mod tests {
     #[test]
     fn test_average_quality_partial_missing() {
         // Mix of valid and missing quality values - should still calculate
-        // Note: 255 values are included in calculation, not skipped individually
+        // Note: 255 values are skipped individually, not counted as Q0
         let mixed: Vec<u8> = vec![30, 255, 30];
         let result = average_quality(&mixed);
         assert!(result.is_some());
@@ -248,6 +819,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_average_quality_methods() {
+        // One Q10 and one Q40 base: the Q10 base holds nearly all of the
+        // expected errors, so the error-probability mean stays close to Q10
+        let qualities = [10, 40];
+        let eprob = average_quality(&qualities).unwrap();
+        let arithmetic = average_quality_arithmetic(&qualities).unwrap();
+        assert!((eprob - 13.0060).abs() < 1e-4);
+        assert_eq!(arithmetic, 25.0);
+
+        // Both agree on uniform qualities, and skip missing bases
+        assert_eq!(average_quality_arithmetic(&[20, 255, 20]), Some(20.0));
+        assert_eq!(average_quality_arithmetic(&[255]), None);
+        assert_eq!(
+            average_quality_arithmetic_with_offset(&[43, 73], 33).unwrap(),
+            Some(25.0)
+        );
+        assert!(average_quality_arithmetic_with_offset(&[30, 5], 10).is_err());
+    }
+
     #[test]
     fn test_average_quality_large_input() {
         // Test with a large number of quality scores
@@ -270,6 +861,119 @@ mod tests {
         assert!(avg_254 <= 60.0);
     }
 
+    #[test]
+    fn test_average_quality_with_offset() {
+        // Shifting uniform Q30 by 10 yields Q20
+        let avg = average_quality_with_offset(&[30, 30, 30], 10)
+            .unwrap()
+            .unwrap();
+        assert!((avg - 20.0).abs() < 0.01);
+
+        // FASTQ 'I' is Phred+33 encoded Q40
+        let avg = average_quality_with_offset(b"IIII", FASTQ_PHRED_OFFSET)
+            .unwrap()
+            .unwrap();
+        assert!((avg - 40.0).abs() < 0.01);
+
+        // Missing-quality sentinel is still skipped, not validated
+        assert_eq!(average_quality_with_offset(&[255, 255], 10).unwrap(), None);
+
+        // An offset larger than a score must be rejected
+        assert!(average_quality_with_offset(&[30, 5, 30], 10).is_err());
+    }
+
+    #[test]
+    fn test_check_quality_offset() {
+        let message = |qualities: &[u8], encoding, offset| {
+            check_quality_offset(qualities, encoding, offset)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(check_quality_offset(b"+I", FASTQ_PHRED_OFFSET, 10).is_ok());
+        // FASTQ '+' is Q10: reported as Phred, with the offset as given
+        assert_eq!(
+            message(b"+I", FASTQ_PHRED_OFFSET, 50),
+            "Invalid input: Quality value 10 is below the quality offset 50"
+        );
+        assert_eq!(
+            message(b"I", FASTQ_PHRED_OFFSET, 250),
+            "Invalid input: Quality value 40 is below the quality offset 250"
+        );
+        assert_eq!(
+            message(b" I", FASTQ_PHRED_OFFSET, 0),
+            "Invalid input: Quality character ' ' is outside the Phred+33 range"
+        );
+        assert!(check_quality_offset(&[10, 255], 0, 10).is_ok());
+        assert_eq!(
+            message(&[10, 20], 0, 15),
+            "Invalid input: Quality value 10 is below the quality offset 15"
+        );
+    }
+
+    #[test]
+    fn test_atomic_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.json");
+        let entries = || std::fs::read_dir(dir.path()).unwrap().count();
+
+        // Nothing appears until the commit, and an abandoned file leaves nothing
+        let mut file = AtomicFile::create(&path, false).unwrap();
+        file.write_all(b"partial").unwrap();
+        assert!(!path.exists());
+        drop(file);
+        assert_eq!(entries(), 0);
+
+        let mut file = AtomicFile::create(&path, false).unwrap();
+        file.write_all(b"first").unwrap();
+        file.commit().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first");
+
+        let error = AtomicFile::create(&path, false).err().unwrap();
+        assert!(error.to_string().contains("pass --force"));
+
+        let mut file = AtomicFile::create(&path, true).unwrap();
+        file.write_all(b"second").unwrap();
+        file.commit().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(entries(), 1);
+    }
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("barcode01"), "barcode01");
+        assert_eq!(
+            sanitize_file_name("SQK-NBD114.24_barcode01"),
+            "SQK-NBD114.24_barcode01"
+        );
+        assert_eq!(sanitize_file_name("../etc/passwd"), ".._etc_passwd");
+        assert_eq!(sanitize_file_name("bc 01/a"), "bc_01_a");
+        assert_eq!(sanitize_file_name(".."), "_..");
+        assert_eq!(sanitize_file_name(""), "_");
+    }
+
+    #[test]
+    fn test_quality_std() {
+        assert_eq!(quality_std(&[20, 20, 20, 20], 0), Some(0.0));
+        assert_eq!(quality_std(&[10, 30], 0), Some(10.0));
+        // Offsets shift the scores without changing their spread
+        assert_eq!(quality_std(b"+I", FASTQ_PHRED_OFFSET), Some(15.0));
+        // Missing-quality sentinels are skipped
+        assert_eq!(quality_std(&[10, 255, 30], 0), Some(10.0));
+        assert_eq!(quality_std(&[255, 255], 0), None);
+        assert_eq!(quality_std(&[], 0), None);
+    }
+
+    #[test]
+    fn test_split_mix64() {
+        // Reference values of SplitMix64 for seed 0
+        let mut rng = SplitMix64::new(0);
+        assert_eq!(rng.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(rng.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+
+        let mut rng = SplitMix64::new(7);
+        assert!((0..1000).all(|_| rng.below(3) < 3));
+    }
+
     #[test]
     fn test_percent_identity() {
         assert_eq!(calculate_percent_identity(95, 100), 95.0);
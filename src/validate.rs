@@ -0,0 +1,328 @@
+use crate::cli::ValidateArgs;
+use crate::error::NanogetError;
+use crate::extract;
+use crate::formats::FileType;
+use crate::utils::{self, CompressionType};
+use clap::ValueEnum;
+use rust_htslib::bam::{self, Read as BamRead};
+use serde::Serialize;
+use std::cell::Cell;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// What `validate` found out about one input file, from its header and first
+/// records, without computing metrics
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    pub file: String,
+
+    /// Given with --file-type, or detected (None when detection failed)
+    pub file_type: Option<FileType>,
+
+    /// none, gzip or bzip2 for text formats (including SAM); bgzf for BAM,
+    /// cram for CRAM
+    pub compression: String,
+
+    /// Records read, at most `--records`
+    pub records_checked: usize,
+
+    /// Bytes of the file per record, from the records read
+    pub bytes_per_record: Option<f64>,
+
+    /// File size divided by `bytes_per_record`; exact when the whole file
+    /// was read
+    pub estimated_records: Option<u64>,
+
+    /// Why the file would fail (or be incomplete) in a full extraction
+    pub problems: Vec<String>,
+}
+
+impl FileReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Check every input of `args` (see `validate_file`)
+pub fn validate_inputs(args: &ValidateArgs) -> Vec<FileReport> {
+    args.files
+        .iter()
+        .map(|file| validate_file(file, args))
+        .collect()
+}
+
+/// Open `file`, read its header and its first `args.records` records with the
+/// reader of its type, and report any problem: a missing file, an unknown
+/// type, a parse error, a missing EOF marker (a truncated download), no
+/// records, or a `--region` that the index cannot serve
+pub fn validate_file(file: &Path, args: &ValidateArgs) -> FileReport {
+    let mut report = FileReport {
+        file: file.display().to_string(),
+        file_type: None,
+        compression: CompressionType::from_path(file).name().to_string(),
+        records_checked: 0,
+        bytes_per_record: None,
+        estimated_records: None,
+        problems: Vec::new(),
+    };
+    let file_type = match &args.file_type {
+        Some(file_type) => utils::check_file_exists(file).map(|_| file_type.clone()),
+        None => utils::check_file_exists(file)
+            .and_then(|_| FileType::detect(file))
+            .map(|(file_type, _)| file_type),
+    };
+    let file_type = match file_type {
+        Ok(file_type) => file_type,
+        Err(e) => {
+            report.problems.push(e.to_string());
+            return report;
+        }
+    };
+    report.file_type = Some(file_type.clone());
+
+    let outcome = match file_type {
+        FileType::Bam | FileType::Ubam | FileType::Cram | FileType::Sam => {
+            match file_type {
+                FileType::Cram => report.compression = "cram".to_string(),
+                FileType::Sam => {}
+                _ => report.compression = "bgzf".to_string(),
+            }
+            check_alignments(file, args, &mut report)
+        }
+        FileType::Fastq | FileType::FastqRich | FileType::FastqMinimal => {
+            check_text(file, args.records, &mut report, |reader, limit| {
+                let mut count = 0;
+                for result in bio::io::fastq::Reader::new(reader).records().take(limit) {
+                    let record = result.map_err(|e| NanogetError::parse(&e))?;
+                    extract::check_quality_length(&record)?;
+                    count += 1;
+                }
+                Ok(count)
+            })
+        }
+        FileType::Fasta => check_text(file, args.records, &mut report, |reader, limit| {
+            let mut count = 0;
+            for result in bio::io::fasta::Reader::new(reader).records().take(limit) {
+                result.map_err(|e| NanogetError::parse(&e))?;
+                count += 1;
+            }
+            Ok(count)
+        }),
+        FileType::Summary => check_text(file, args.records, &mut report, check_summary),
+    };
+    if let Err(e) = outcome {
+        report.problems.push(e.to_string());
+    } else if report.records_checked == 0 {
+        report
+            .problems
+            .push(NanogetError::NoReads(report.file.clone()).to_string());
+    }
+    report
+}
+
+/// Read the first records of a BAM/CRAM file, and query `--region` with its
+/// index. For CRAM, decoding the records also checks that the reference is
+/// available.
+fn check_alignments(
+    file: &Path,
+    args: &ValidateArgs,
+    report: &mut FileReport,
+) -> Result<(), NanogetError> {
+    if let Some(region) = &args.region {
+        let mut reader = extract::open_indexed_bam(file, args.index.as_deref())?;
+        reader.fetch(region.as_str()).map_err(|_| {
+            NanogetError::InvalidInput(format!(
+                "Invalid region '{}' for {}",
+                region,
+                file.display()
+            ))
+        })?;
+    }
+    let mut reader = bam::Reader::from_path(file)?;
+    if extract::missing_eof_marker(&reader, file) {
+        report
+            .problems
+            .push("Missing EOF marker; the file appears to be truncated".to_string());
+    }
+    let mut record = bam::Record::new();
+    while report.records_checked < args.records {
+        match reader.read(&mut record) {
+            Some(result) => result?,
+            None => break,
+        }
+        report.records_checked += 1;
+    }
+    // The compressed offset of BGZF; CRAM positions are not byte offsets
+    let consumed = (report.compression == "bgzf").then(|| reader.tell() as u64 >> 16);
+    estimate_size(file, consumed, args.records, report);
+    Ok(())
+}
+
+/// Read the first `limit` records of a text file with `read_records`,
+/// counting the bytes read from disk for the size estimate
+fn check_text(
+    file: &Path,
+    limit: usize,
+    report: &mut FileReport,
+    read_records: impl FnOnce(Box<dyn Read>, usize) -> Result<usize, NanogetError>,
+) -> Result<(), NanogetError> {
+    let consumed = Rc::new(Cell::new(0));
+    let raw = CountingReader {
+        inner: std::fs::File::open(file)?,
+        count: Rc::clone(&consumed),
+    };
+    report.records_checked = read_records(utils::decompress(BufReader::new(raw), file), limit)?;
+    estimate_size(file, Some(consumed.get()), limit, report);
+    Ok(())
+}
+
+/// Summary rows, checking that the header has the read length column
+fn check_summary(reader: Box<dyn Read>, limit: usize) -> Result<usize, NanogetError> {
+    let mut rows = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .from_reader(reader);
+    let header = rows.headers()?;
+    if !header
+        .iter()
+        .any(|c| c == "sequence_length_template" || c == "sequence_length_2d")
+    {
+        return Err(NanogetError::ParseError(
+            "Missing column: sequence_length_template".to_string(),
+        ));
+    }
+    let mut count = 0;
+    for row in rows.records().take(limit) {
+        row?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Fill in the size estimates from the `consumed` bytes of the file. When
+/// fewer than `limit` records were found the whole file was read, and the
+/// file size is used instead.
+fn estimate_size(file: &Path, consumed: Option<u64>, limit: usize, report: &mut FileReport) {
+    let Ok(size) = std::fs::metadata(file).map(|m| m.len()) else {
+        return;
+    };
+    if report.records_checked == 0 {
+        return;
+    }
+    if report.records_checked < limit {
+        report.bytes_per_record = Some(size as f64 / report.records_checked as f64);
+        report.estimated_records = Some(report.records_checked as u64);
+        return;
+    }
+    if let Some(consumed) = consumed.filter(|&c| c > 0) {
+        let bytes_per_record = consumed as f64 / report.records_checked as f64;
+        report.bytes_per_record = Some(bytes_per_record);
+        report.estimated_records = Some((size as f64 / bytes_per_record).round() as u64);
+    }
+}
+
+/// Counts the bytes read through it, before decompression
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Write `reports` as a table: one row per file, with its problems joined by
+/// "; " (empty for files that passed)
+pub fn write_table<W: Write>(reports: &[FileReport], w: &mut W) -> Result<(), NanogetError> {
+    writeln!(
+        w,
+        "file\tfile_type\tcompression\trecords_checked\tbytes_per_record\testimated_records\tstatus\tproblems"
+    )?;
+    for report in reports {
+        let file_type = report
+            .file_type
+            .as_ref()
+            .and_then(|t| t.to_possible_value())
+            .map(|v| v.get_name().to_string())
+            .unwrap_or_default();
+        writeln!(
+            w,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            report.file,
+            file_type,
+            report.compression,
+            report.records_checked,
+            report
+                .bytes_per_record
+                .map(|b| format!("{:.1}", b))
+                .unwrap_or_default(),
+            report
+                .estimated_records
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+            if report.is_ok() { "ok" } else { "FAILED" },
+            report.problems.join("; ")
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+
+    fn args(records: usize) -> ValidateArgs {
+        ValidateArgs {
+            files: Vec::new(),
+            file_type: None,
+            records,
+            region: None,
+            index: None,
+            output_format: "table".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reads.fastq.gz");
+        let mut encoder = GzEncoder::new(
+            std::fs::File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        for i in 0..3 {
+            writeln!(encoder, "@read{}\nACGTACGT\n+\nIIIIIIII", i).unwrap();
+        }
+        encoder.finish().unwrap();
+
+        let report = validate_file(&path, &args(2));
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.file_type, Some(FileType::Fastq));
+        assert_eq!(report.compression, "gzip");
+        assert_eq!(report.records_checked, 2);
+        assert!(report.bytes_per_record.is_some());
+
+        let report = validate_file(&path, &args(10));
+        assert_eq!(report.records_checked, 3);
+        assert_eq!(report.estimated_records, Some(3));
+
+        let malformed = dir.path().join("malformed.fastq");
+        std::fs::write(&malformed, "@read1\nACGT\n+\nIII\n").unwrap();
+        let report = validate_file(&malformed, &args(10));
+        assert_eq!(
+            report.problems,
+            ["Parse error: Read read1: quality string of 3 characters for a sequence of 4 bases"]
+        );
+
+        let empty = dir.path().join("empty.fasta");
+        std::fs::write(&empty, "").unwrap();
+        let report = validate_file(&empty, &args(10));
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.records_checked, 0);
+    }
+}
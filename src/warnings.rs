@@ -0,0 +1,66 @@
+use crate::error::NanogetError;
+use log::warn;
+use std::sync::Mutex;
+
+/// Issues met during an extraction that do not stop it, such as truncated or
+/// repeated input files: each is logged when it is added and the extraction
+/// goes on. With `--strict` they fail the run at the end, all reported
+/// together (see `check`).
+#[derive(Debug, Default)]
+pub struct Warnings {
+    messages: Mutex<Vec<String>>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log `message` as a warning and keep it
+    pub fn add(&self, message: String) {
+        warn!("{}", message);
+        // A panic while holding the lock cannot leave the list inconsistent
+        let mut messages = self.messages.lock().unwrap_or_else(|e| e.into_inner());
+        messages.push(message);
+    }
+
+    /// The warnings added so far, in order
+    #[allow(dead_code)]
+    pub fn messages(&self) -> Vec<String> {
+        self.messages
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// With `strict`, an error listing every warning added so far, if any
+    pub fn check(&self, strict: bool) -> Result<(), NanogetError> {
+        let messages = self.messages.lock().unwrap_or_else(|e| e.into_inner());
+        if strict && !messages.is_empty() {
+            return Err(NanogetError::Warnings(messages.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warnings_check() {
+        let warnings = Warnings::new();
+        assert!(warnings.check(true).is_ok());
+        warnings.add("reads.bam appears to be truncated".to_string());
+        warnings.add("reads.fastq was given more than once".to_string());
+        assert_eq!(warnings.messages().len(), 2);
+        assert!(warnings.check(false).is_ok());
+
+        let error = warnings.check(true).unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+        assert_eq!(
+            error.to_string(),
+            "2 warnings with --strict:\n  reads.bam appears to be truncated\n  reads.fastq was given more than once"
+        );
+    }
+}
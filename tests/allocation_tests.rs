@@ -0,0 +1,104 @@
+// Allocation counts of the collection filters: the consuming and borrowed
+// variants must not copy the reads. A separate test binary, as the counting
+// allocator replaces the global allocator of the whole binary.
+
+use nanoget_rs::{MetricsCollection, ReadMetrics};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::Arc;
+
+struct CountingAllocator;
+
+thread_local! {
+    // Per thread, so tests running in parallel do not count each other
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Allocations made by `f` on this thread, and its result
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (usize, T) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    (ALLOCATIONS.with(Cell::get) - before, result)
+}
+
+const READS: usize = 10_000;
+
+/// Reads with an id each and a shared barcode and run id, as extracted, half
+/// of them of at least 1000 bases. Lengths decrease so that the longest read is
+/// the first one.
+fn collection() -> MetricsCollection {
+    let barcode: Arc<str> = Arc::from("barcode01");
+    let run_id: Arc<str> = Arc::from("run1");
+    let reads = (0..READS)
+        .map(|i| {
+            let mut read =
+                ReadMetrics::new(Some(format!("read{}", i)), (2 * (READS - i) / 10) as u64)
+                    .with_quality(10.0);
+            read.extended_mut().barcode = Some(Arc::clone(&barcode));
+            read.extended_mut().run_id = Some(Arc::clone(&run_id));
+            read
+        })
+        .collect();
+    MetricsCollection::new(reads)
+}
+
+#[test]
+fn test_filter_allocations() {
+    let collection = collection();
+
+    // Cloning every surviving read costs its read id and the box of its
+    // extended fields; the barcode and run id in it are shared
+    let (cloning, filtered) = count_allocations(|| collection.filter_by_length(1000));
+    assert_eq!(filtered.reads.len(), READS / 2 + 1);
+    assert!(
+        cloning >= 2 * filtered.reads.len(),
+        "{} allocations",
+        cloning
+    );
+    assert!(
+        cloning < 3 * filtered.reads.len(),
+        "{} allocations",
+        cloning
+    );
+
+    // A view only allocates its vector of references
+    let (borrowing, view) = count_allocations(|| collection.view_by_length(1000));
+    assert_eq!(view.len(), filtered.reads.len());
+    assert!(borrowing <= 20, "{} allocations", borrowing);
+
+    // Summarizing the view allocates per statistic, not per read
+    let (summarizing, summary) = count_allocations(|| collection.summarize(&view));
+    assert_eq!(summary.read_count, filtered.summary.read_count);
+    assert!(
+        summarizing < filtered.reads.len() / 10,
+        "{} allocations",
+        summarizing
+    );
+
+    // Moving the survivors copies none of them
+    let (consuming, moved) = count_allocations(|| collection.into_filtered_by_length(1000));
+    assert_eq!(moved.reads.len(), filtered.reads.len());
+    assert_eq!(
+        moved.summary.length_stats.mean,
+        filtered.summary.length_stats.mean
+    );
+    assert!(
+        consuming < filtered.reads.len() / 10,
+        "{} allocations",
+        consuming
+    );
+}
@@ -1,5 +1,13 @@
-use nanoget_rs::{extract_metrics, ExtractArgs, FileType};
+use assert_cmd::Command;
+use nanoget_rs::{extract_metrics, CombineMethod, ExtractArgs, FileType, ReadType};
+use rust_htslib::bam::{
+    self,
+    header::{Header, HeaderRecord},
+    record::{Cigar, CigarString},
+    Record,
+};
 use std::io::Write;
+use std::path::Path;
 use tempfile::NamedTempFile;
 
 fn create_test_fastq() -> NamedTempFile {
@@ -11,7 +19,7 @@ fn create_test_fastq() -> NamedTempFile {
     writeln!(file, "@read2").unwrap();
     writeln!(file, "GCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCT").unwrap();
     writeln!(file, "+").unwrap();
-    writeln!(file, "JJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJ").unwrap();
+    writeln!(file, "JJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJ").unwrap();
     file
 }
 
@@ -19,18 +27,11 @@ fn create_test_fastq() -> NamedTempFile {
 fn test_fastq_extraction() {
     let temp_file = create_test_fastq();
 
-    let args = ExtractArgs {
-        files: vec![temp_file.path().to_path_buf()],
-        file_type: FileType::Fastq,
-        threads: 1,
-        output_format: "json".to_string(),
-        output: None,
-        read_type: "1D".to_string(),
-        barcoded: false,
-        keep_supplementary: true,
-        combine: "simple".to_string(),
-        names: None,
-    };
+    let args = ExtractArgs::builder()
+        .files(vec![temp_file.path().to_path_buf()])
+        .file_type(FileType::Fastq)
+        .threads(1)
+        .build();
 
     let result = extract_metrics(&args).expect("Failed to extract metrics");
 
@@ -57,18 +58,11 @@ fn test_fastq_extraction() {
 fn test_fastq_minimal() {
     let temp_file = create_test_fastq();
 
-    let args = ExtractArgs {
-        files: vec![temp_file.path().to_path_buf()],
-        file_type: FileType::FastqMinimal,
-        threads: 1,
-        output_format: "json".to_string(),
-        output: None,
-        read_type: "1D".to_string(),
-        barcoded: false,
-        keep_supplementary: true,
-        combine: "simple".to_string(),
-        names: None,
-    };
+    let args = ExtractArgs::builder()
+        .files(vec![temp_file.path().to_path_buf()])
+        .file_type(FileType::FastqMinimal)
+        .threads(1)
+        .build();
 
     let result = extract_metrics(&args).expect("Failed to extract metrics");
 
@@ -92,18 +86,11 @@ fn create_test_fasta() -> NamedTempFile {
 fn test_fasta_extraction() {
     let temp_file = create_test_fasta();
 
-    let args = ExtractArgs {
-        files: vec![temp_file.path().to_path_buf()],
-        file_type: FileType::Fasta,
-        threads: 1,
-        output_format: "json".to_string(),
-        output: None,
-        read_type: "1D".to_string(),
-        barcoded: false,
-        keep_supplementary: true,
-        combine: "simple".to_string(),
-        names: None,
-    };
+    let args = ExtractArgs::builder()
+        .files(vec![temp_file.path().to_path_buf()])
+        .file_type(FileType::Fasta)
+        .threads(1)
+        .build();
 
     let result = extract_metrics(&args).expect("Failed to extract metrics");
 
@@ -121,26 +108,64 @@ fn test_fasta_extraction() {
     assert!(result.reads[1].quality.is_none());
 }
 
+#[test]
+fn test_gzipped_wrapped_fasta() {
+    use flate2::write::GzEncoder;
+
+    // Sequences wrapped at 60 columns: one ending mid-line, one filling its
+    // last line and one shorter than a line
+    let sequences = [
+        ("contig1", "ACGTN".repeat(50)),
+        ("contig2", "GC".repeat(60)),
+        ("contig3", "AT".repeat(5)),
+    ];
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("contigs.fasta.gz");
+    let mut encoder = GzEncoder::new(
+        std::fs::File::create(&file).unwrap(),
+        flate2::Compression::default(),
+    );
+    for (name, sequence) in &sequences {
+        writeln!(encoder, ">{} wrapped", name).unwrap();
+        for line in sequence.as_bytes().chunks(60) {
+            encoder.write_all(line).unwrap();
+            writeln!(encoder).unwrap();
+        }
+    }
+    encoder.finish().unwrap();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fasta", "-f", "json", "--sequence-stats"])
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let reads = json["reads"].as_array().unwrap();
+    assert_eq!(reads.len(), sequences.len());
+    for (read, (name, sequence)) in reads.iter().zip(&sequences) {
+        assert_eq!(read["read_id"], *name);
+        assert_eq!(read["length"], sequence.len());
+        let n_count = sequence.bytes().filter(|&b| b == b'N').count();
+        assert_eq!(read["n_count"], n_count);
+    }
+    assert_eq!(json["summary"]["length_stats"]["max"], 250.0);
+}
+
 #[test]
 fn test_multiple_files_combination() {
     let temp_file1 = create_test_fastq();
     let temp_file2 = create_test_fastq();
 
-    let args = ExtractArgs {
-        files: vec![
+    let args = ExtractArgs::builder()
+        .files(vec![
             temp_file1.path().to_path_buf(),
             temp_file2.path().to_path_buf(),
-        ],
-        file_type: FileType::Fastq,
-        threads: 2,
-        output_format: "json".to_string(),
-        output: None,
-        read_type: "1D".to_string(),
-        barcoded: false,
-        keep_supplementary: true,
-        combine: "simple".to_string(),
-        names: None,
-    };
+        ])
+        .file_type(FileType::Fastq)
+        .threads(2)
+        .build();
 
     let result = extract_metrics(&args).expect("Failed to extract metrics");
 
@@ -154,21 +179,16 @@ fn test_track_combination() {
     let temp_file1 = create_test_fastq();
     let temp_file2 = create_test_fastq();
 
-    let args = ExtractArgs {
-        files: vec![
+    let args = ExtractArgs::builder()
+        .files(vec![
             temp_file1.path().to_path_buf(),
             temp_file2.path().to_path_buf(),
-        ],
-        file_type: FileType::Fastq,
-        threads: 2,
-        output_format: "json".to_string(),
-        output: None,
-        read_type: "1D".to_string(),
-        barcoded: false,
-        keep_supplementary: true,
-        combine: "track".to_string(),
-        names: Some(vec!["sample1".to_string(), "sample2".to_string()]),
-    };
+        ])
+        .file_type(FileType::Fastq)
+        .threads(2)
+        .combine(CombineMethod::Track)
+        .names(["sample1", "sample2"])
+        .build();
 
     let result = extract_metrics(&args).expect("Failed to extract metrics");
 
@@ -180,34 +200,38 @@ fn test_track_combination() {
     let sample1_reads: Vec<_> = result
         .reads
         .iter()
-        .filter(|r| r.dataset == Some("sample1".to_string()))
+        .filter(|r| r.dataset().map(AsRef::as_ref) == Some("sample1"))
         .collect();
     let sample2_reads: Vec<_> = result
         .reads
         .iter()
-        .filter(|r| r.dataset == Some("sample2".to_string()))
+        .filter(|r| r.dataset().map(AsRef::as_ref) == Some("sample2"))
         .collect();
 
     assert_eq!(sample1_reads.len(), 2);
     assert_eq!(sample2_reads.len(), 2);
+
+    // One row per dataset in the comparison table
+    let table = result.per_dataset_summary_table();
+    let rows: Vec<Vec<&str>> = table
+        .lines()
+        .skip(1)
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0][..3], ["sample1", "2", "199"]);
+    assert_eq!(rows[1][..3], ["sample2", "2", "199"]);
 }
 
 #[test]
 fn test_tsv_output_format() {
     let temp_file = create_test_fastq();
 
-    let args = ExtractArgs {
-        files: vec![temp_file.path().to_path_buf()],
-        file_type: FileType::Fastq,
-        threads: 1,
-        output_format: "tsv".to_string(),
-        output: None,
-        read_type: "1D".to_string(),
-        barcoded: false,
-        keep_supplementary: true,
-        combine: "simple".to_string(),
-        names: None,
-    };
+    let args = ExtractArgs::builder()
+        .files(vec![temp_file.path().to_path_buf()])
+        .file_type(FileType::Fastq)
+        .threads(1)
+        .build();
 
     let metrics = extract_metrics(&args).expect("Failed to extract metrics");
     let tsv_output = metrics.to_tsv().expect("Failed to generate TSV output");
@@ -221,3 +245,3215 @@ fn test_tsv_output_format() {
     assert!(tsv_output.contains("# Length stats")); // Stats header
     assert!(tsv_output.contains("# Quality stats")); // Quality stats since FASTQ has quality
 }
+
+#[test]
+fn test_quality_offset() {
+    // Every base in the fixture is 'I' or 'J' (Phred+33 encoded Q40 / Q41)
+    let temp_file = create_test_fastq();
+
+    let mut args = ExtractArgs::builder()
+        .files(vec![temp_file.path().to_path_buf()])
+        .file_type(FileType::Fastq)
+        .threads(1)
+        .build();
+
+    let unshifted = extract_metrics(&args).expect("Failed to extract metrics");
+    assert!((unshifted.reads[0].quality.unwrap() - 40.0).abs() < 0.01);
+
+    args.quality_offset = 10;
+    let shifted = extract_metrics(&args).expect("Failed to extract metrics");
+    assert!((shifted.reads[0].quality.unwrap() - 30.0).abs() < 0.01);
+    assert!((shifted.reads[1].quality.unwrap() - 31.0).abs() < 0.01);
+
+    let mean_shift =
+        unshifted.summary.quality_stats.unwrap().mean - shifted.summary.quality_stats.unwrap().mean;
+    assert!((mean_shift - 10.0).abs() < 0.01);
+
+    // An offset above the lowest base quality cannot be applied; the error
+    // gives the Phred score and the offset as given
+    args.quality_offset = 50;
+    assert_eq!(
+        extract_metrics(&args).unwrap_err().to_string(),
+        "Invalid input: Quality value 40 is below the quality offset 50"
+    );
+}
+
+#[test]
+fn test_quality_method() {
+    // Half of the bases are Q10 ('+') and half Q40 ('I')
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "@read1\nACGTACGT\n+\n++++IIII").unwrap();
+
+    let quality = |method: &str| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args([
+                "extract",
+                "-t",
+                "fastq",
+                "-f",
+                "json",
+                "--quality-method",
+                method,
+            ])
+            .arg(file.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["reads"][0]["quality"].as_f64().unwrap()
+    };
+    assert!((quality("eprob") - 13.0060).abs() < 1e-4);
+    assert_eq!(quality("arithmetic"), 25.0);
+}
+
+#[test]
+fn test_mmap_matches_stream() {
+    use flate2::write::GzEncoder;
+
+    let dir = tempfile::tempdir().unwrap();
+    // Rich headers, CRLF line endings, a multi-line record and no final newline
+    let fastq = dir.path().join("reads.fastq");
+    std::fs::write(
+        &fastq,
+        "@read1 runid=run1 ch=12 start_time=2019-12-23T13:44:31Z\nACGTNNGC\n+\n+++IIII5\n\
+         @read2 ch=7 start_time=1234567890.5 duration=2.5\r\nGGCCA\r\n+\r\nIIII!\r\n\
+         @read3\nACG\nTTA\n+read3\n&&&\nIII",
+    )
+    .unwrap();
+    let fasta = dir.path().join("reads.fasta");
+    std::fs::write(
+        &fasta,
+        ">contig1 first\nACGTAC\nGGNN\n\n>contig2\r\nAAAT\r\n>contig3\n>contig4\nGC",
+    )
+    .unwrap();
+    let gzipped = dir.path().join("reads.fastq.gz");
+    let mut encoder = GzEncoder::new(
+        std::fs::File::create(&gzipped).unwrap(),
+        flate2::Compression::default(),
+    );
+    encoder.write_all(&std::fs::read(&fastq).unwrap()).unwrap();
+    encoder.finish().unwrap();
+
+    let reads = |file_type: &str, file: &Path, mmap: bool| {
+        let mut cmd = Command::cargo_bin("nanoget").unwrap();
+        cmd.args(["extract", "-t", file_type, "-f", "json", "--sequence-stats"])
+            .arg(file);
+        if mmap {
+            cmd.arg("--mmap");
+        }
+        let output = cmd.output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["reads"].clone()
+    };
+    for (file_type, file, count) in [
+        ("fastq-rich", &fastq, 3),
+        ("fastq-minimal", &fastq, 3),
+        ("fasta", &fasta, 4),
+    ] {
+        let mapped = reads(file_type, file, true);
+        assert_eq!(mapped.as_array().unwrap().len(), count);
+        assert_eq!(mapped, reads(file_type, file, false), "{}", file_type);
+    }
+
+    // Compressed input is read as a stream
+    assert_eq!(
+        reads("fastq-rich", &gzipped, true),
+        reads("fastq-rich", &fastq, false)
+    );
+}
+
+/// Build a mapped BAM record of `length` bases with a full-length match CIGAR
+fn bam_record(name: &str, length: usize, mapq: u8) -> Record {
+    let mut record = Record::new();
+    let cigar = CigarString(vec![Cigar::Match(length as u32)]);
+    record.set(
+        name.as_bytes(),
+        Some(&cigar),
+        &vec![b'A'; length],
+        &vec![30u8; length],
+    );
+    record.set_flags(0);
+    record.set_tid(0);
+    record.set_pos(0);
+    record.set_mapq(mapq);
+    record
+}
+
+/// Write records to a BAM file with a single reference sequence
+fn write_test_bam(path: &Path, records: &[Record]) {
+    let mut header = Header::new();
+    header.push_record(
+        HeaderRecord::new(b"SQ")
+            .push_tag(b"SN", "chr1")
+            .push_tag(b"LN", 1_000_000),
+    );
+    let mut writer = bam::Writer::from_path(path, &header, bam::Format::Bam).unwrap();
+    for record in records {
+        writer.write(record).unwrap();
+    }
+}
+
+#[test]
+fn test_bam_without_sequence() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("noseq.bam");
+    // SEQ and QUAL are '*': only the alignment is stored
+    let mut noseq = Record::new();
+    let cigar = CigarString(vec![
+        Cigar::SoftClip(10),
+        Cigar::Match(100),
+        Cigar::Ins(20),
+        Cigar::Del(5),
+        Cigar::Match(20),
+        Cigar::HardClip(30),
+    ]);
+    noseq.set(b"noseq", Some(&cigar), b"", b"");
+    noseq.set_flags(0);
+    noseq.set_tid(0);
+    noseq.set_pos(0);
+    noseq.set_mapq(60);
+    write_test_bam(&path, &[noseq, bam_record("withseq", 200, 60)]);
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no sequence (SEQ is *)"));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    // The query bases of the CIGAR: soft clip, matches and insertion
+    assert_eq!(json["reads"][0]["length"], 150);
+    assert_eq!(json["reads"][0]["aligned_length"], 140);
+    assert_eq!(json["reads"][1]["length"], 200);
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam", "--count-only"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\t350\n");
+}
+
+#[test]
+fn test_bam_workers_match_sequential() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("many.bam");
+    // Several batches of reads, some of them secondary and filtered out
+    let records: Vec<Record> = (0..1500)
+        .map(|i| {
+            let mut record = bam_record(&format!("read{}", i), 100 + i * 37 % 900, (i % 61) as u8);
+            if i % 10 == 0 {
+                record.set_flags(256);
+            }
+            record
+        })
+        .collect();
+    write_test_bam(&path, &records);
+
+    let extract = |workers: &str| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args([
+                "extract",
+                "-t",
+                "bam",
+                "--min-mapq",
+                "5",
+                "--bam-workers",
+                workers,
+            ])
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        let mut json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        // Reads of the workers come in no particular order
+        json["reads"]
+            .as_array_mut()
+            .unwrap()
+            .sort_by_key(|read| read["read_id"].as_str().unwrap().to_string());
+        json
+    };
+    let sequential = extract("0");
+    assert_eq!(sequential["summary"]["read_count"], 1236);
+    let pipelined = extract("3");
+    assert_eq!(pipelined["summary"], sequential["summary"]);
+    assert_eq!(pipelined["reads"], sequential["reads"]);
+}
+
+/// Write a two-read BAM and chop off its trailing BGZF EOF block
+fn create_truncated_bam(dir: &Path) -> std::path::PathBuf {
+    let path = dir.join("truncated.bam");
+    write_test_bam(
+        &path,
+        &[bam_record("read1", 100, 60), bam_record("read2", 200, 60)],
+    );
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    let len = file.metadata().unwrap().len();
+    file.set_len(len - 28).unwrap(); // the BGZF EOF marker is a 28-byte empty block
+    path
+}
+
+#[test]
+fn test_truncated_bam_strict_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = create_truncated_bam(dir.path());
+
+    let mut args = ExtractArgs::builder()
+        .files(vec![path])
+        .file_type(FileType::Bam)
+        .threads(1)
+        .build();
+
+    // Without --strict the complete records are still extracted
+    let result = extract_metrics(&args).expect("Failed to extract metrics");
+    assert_eq!(result.summary.read_count, 2);
+
+    args.strict = true;
+    let err = extract_metrics(&args).unwrap_err().to_string();
+    assert!(err.contains("truncated"), "unexpected error: {}", err);
+    assert!(err.contains("2 reads"), "unexpected error: {}", err);
+}
+
+#[test]
+fn test_truncated_bam_warning() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = create_truncated_bam(dir.path());
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .env("RUST_LOG", "warn")
+        .args(["extract", "-t", "bam"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("appears to be truncated"),
+        "stderr: {}",
+        stderr
+    );
+
+    // An intact BAM must not trigger the warning
+    let intact = dir.path().join("intact.bam");
+    write_test_bam(&intact, &[bam_record("read1", 100, 60)]);
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .env("RUST_LOG", "warn")
+        .args(["extract", "-t", "bam"])
+        .arg(&intact)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("truncated"));
+}
+
+#[test]
+fn test_bam_tags_passthrough() {
+    use rust_htslib::bam::record::Aux;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tags.bam");
+    let mut tagged = bam_record("read1", 100, 60);
+    tagged.push_aux(b"sp", Aux::I32(42)).unwrap();
+    tagged.push_aux(b"RG", Aux::String("run1")).unwrap();
+    write_test_bam(&path, &[tagged, bam_record("read2", 200, 60)]);
+
+    let args = ExtractArgs::builder()
+        .files(vec![path])
+        .file_type(FileType::Bam)
+        .threads(1)
+        .bam_tags(vec!["sp".to_string(), "RG".to_string()])
+        .build();
+
+    let result = extract_metrics(&args).expect("Failed to extract metrics");
+    assert_eq!(result.reads[0].extra().get("sp"), Some(&42.0));
+    assert_eq!(
+        result.reads[0].extra_text().get("RG").map(String::as_str),
+        Some("run1")
+    );
+    // Reads without the tag simply lack the entry
+    assert!(result.reads[1].extra().is_empty());
+
+    let tsv = result.to_tsv().unwrap();
+    assert!(tsv.lines().next().unwrap().ends_with("\tRG\tsp"));
+}
+
+#[test]
+fn test_percentiles_validation() {
+    let temp_file = create_test_fastq();
+
+    let mut args = ExtractArgs::builder()
+        .files(vec![temp_file.path().to_path_buf()])
+        .file_type(FileType::Fastq)
+        .threads(1)
+        .percentiles(vec![5.0, 95.0])
+        .build();
+
+    let result = extract_metrics(&args).expect("Failed to extract metrics");
+    let extra = result.summary.length_stats.extra_percentiles.unwrap();
+    assert_eq!(extra.len(), 2);
+    assert_eq!(extra[0].0, 5.0);
+    assert!(result
+        .summary
+        .quality_stats
+        .unwrap()
+        .extra_percentiles
+        .is_some());
+
+    args.percentiles = vec![50.0, 101.0];
+    assert!(extract_metrics(&args).is_err());
+}
+
+#[test]
+fn test_split_reads_parent_id() {
+    use rust_htslib::bam::record::Aux;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("split.bam");
+    let mut child1 = bam_record("child1", 100, 60);
+    child1.push_aux(b"pi", Aux::String("parent")).unwrap();
+    let mut child2 = bam_record("child2", 150, 60);
+    child2.push_aux(b"pi", Aux::String("parent")).unwrap();
+    write_test_bam(&path, &[child1, child2, bam_record("other", 200, 60)]);
+
+    let args = ExtractArgs::builder()
+        .files(vec![path])
+        .file_type(FileType::Bam)
+        .threads(1)
+        .build();
+
+    let result = extract_metrics(&args).expect("Failed to extract metrics");
+    assert_eq!(result.reads[0].parent_id(), Some("parent"));
+    assert_eq!(result.reads[2].parent_id(), None);
+
+    let groups = result.group_by_parent();
+    assert_eq!(groups["parent"].len(), 2);
+    assert_eq!(groups["other"].len(), 1);
+}
+
+#[test]
+fn test_subsample_every() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    for i in 0..10 {
+        writeln!(file, "@read{}", i).unwrap();
+        writeln!(file, "{}", "A".repeat(100 + i)).unwrap();
+        writeln!(file, "+").unwrap();
+        writeln!(file, "{}", "I".repeat(100 + i)).unwrap();
+    }
+
+    let mut args = ExtractArgs::builder()
+        .files(vec![file.path().to_path_buf()])
+        .file_type(FileType::Fastq)
+        .threads(1)
+        .subsample_every(3)
+        .build();
+
+    let result = extract_metrics(&args).expect("Failed to extract metrics");
+    let kept: Vec<_> = result
+        .reads
+        .iter()
+        .map(|r| r.read_id.clone().unwrap())
+        .collect();
+    assert_eq!(kept, vec!["read0", "read3", "read6", "read9"]);
+    assert_eq!(result.summary.read_count, 4);
+
+    args.subsample_every = Some(0);
+    assert!(extract_metrics(&args).is_err());
+}
+
+#[test]
+fn test_json_summary_output() {
+    let temp_file = create_test_fastq();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "-f", "json-summary"])
+        .arg(temp_file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(summary.get("length_stats").is_some());
+    assert_eq!(summary["read_count"], 2);
+    assert!(summary.get("reads").is_none());
+}
+
+#[test]
+fn test_invalid_combine_method() {
+    // Library callers parsing user input get an error naming the bad value
+    let err = "trak".parse::<CombineMethod>().unwrap_err().to_string();
+    assert!(err.contains("Unknown combine method 'trak'"), "{}", err);
+    assert!(err.contains("simple, track"), "{}", err);
+    assert_eq!(
+        "track".parse::<CombineMethod>().unwrap(),
+        CombineMethod::Track
+    );
+    assert_eq!("1D2".parse::<ReadType>().unwrap(), ReadType::OneDSquared);
+    assert!("1d".parse::<ReadType>().is_err());
+
+    // A typo on the command line no longer falls back to simple concatenation
+    let temp_file = create_test_fastq();
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "--combine", "tracked"])
+        .arg(temp_file.path())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid value 'tracked'"));
+}
+
+#[test]
+fn test_tsv_precision() {
+    let temp_file = create_test_fastq();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "-f", "tsv", "--precision", "5"])
+        .arg(temp_file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // read1 is all 'I' (Q40)
+    assert!(
+        stdout.contains("read1\t100\t40.00000\t"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stdout.contains("# Length stats - count: 2, mean: 99.50000,"));
+}
+
+#[test]
+fn test_split_summary_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let header =
+        "read_id\tchannel\tstart_time\tduration\tsequence_length_template\tmean_qscore_template";
+    let chunk0 = dir.path().join("sequencing_summary_0.txt");
+    std::fs::write(
+        &chunk0,
+        format!(
+            "{}\nr1\t1\t10.0\t1.0\t1000\t10.0\nr2\t2\t20.0\t1.0\t2000\t12.0\n",
+            header
+        ),
+    )
+    .unwrap();
+    // Later chunk written without a header row
+    let chunk1 = dir.path().join("sequencing_summary_1.txt");
+    std::fs::write(&chunk1, "r3\t3\t30.0\t1.0\t3000\t14.0\n").unwrap();
+
+    let mut args = ExtractArgs::builder()
+        .files(vec![chunk0.clone(), chunk1])
+        .file_type(FileType::Summary)
+        .threads(1)
+        .build();
+
+    let result = extract_metrics(&args).expect("Failed to extract metrics");
+    assert_eq!(result.summary.read_count, 3);
+    let lengths: Vec<u64> = result.reads.iter().map(|r| r.length).collect();
+    assert_eq!(lengths, vec![1000, 2000, 3000]);
+    assert_eq!(result.reads[2].channel_id(), Some(3));
+    assert_eq!(result.reads[2].quality, Some(14.0));
+    let per_file = result.per_file_summaries();
+    assert_eq!(per_file.len(), 2);
+    assert_eq!(per_file[&chunk0.display().to_string()].read_count, 2);
+
+    // A chunk with a different column set is rejected
+    let mismatched = dir.path().join("sequencing_summary_2.txt");
+    std::fs::write(
+        &mismatched,
+        "read_id\tsequence_length_template\tmean_qscore_template\nr4\t4000\t9.0\n",
+    )
+    .unwrap();
+    args.files = vec![chunk0, mismatched];
+    let err = extract_metrics(&args).expect_err("Incompatible columns must be rejected");
+    assert!(err
+        .to_string()
+        .contains("missing: [channel, start_time, duration]"));
+}
+
+#[test]
+fn test_streaming_extraction() {
+    use nanoget_rs::{
+        extract_metrics_chunked, extract_metrics_streaming, extract_summary_streaming,
+        DEFAULT_CHUNK_SIZE,
+    };
+
+    let (first_file, second_file) = (create_test_fastq(), create_test_fastq());
+    let args = ExtractArgs::builder()
+        .files(vec![
+            first_file.path().to_path_buf(),
+            second_file.path().to_path_buf(),
+        ])
+        .file_type(FileType::Fastq)
+        .threads(1)
+        .combine(CombineMethod::Track)
+        .names(["first", "second"])
+        .build();
+
+    let mut visited = Vec::new();
+    let count = extract_metrics_streaming(&args, &mut |read| visited.push(read)).unwrap();
+    assert_eq!(count, 4);
+    assert_eq!(visited[0].dataset().map(AsRef::as_ref), Some("first"));
+    assert_eq!(visited[3].dataset().map(AsRef::as_ref), Some("second"));
+
+    // Chunks deliver the same reads, in order, whatever their size
+    for chunk_size in [1, 3, 4, DEFAULT_CHUNK_SIZE] {
+        let mut chunks = Vec::new();
+        let count = extract_metrics_chunked(&args, chunk_size, &mut |chunk| {
+            chunks.push(chunk);
+        })
+        .unwrap();
+        assert_eq!(count, 4);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= chunk_size));
+        let ids: Vec<_> = chunks.concat().into_iter().map(|r| r.read_id).collect();
+        let visited_ids: Vec<_> = visited.iter().map(|r| r.read_id.clone()).collect();
+        assert_eq!(ids, visited_ids);
+        assert_eq!(chunks.len(), 4usize.div_ceil(chunk_size));
+    }
+    assert!(extract_metrics_chunked(&args, 0, &mut |_| {}).is_err());
+
+    let streamed = extract_summary_streaming(&args).unwrap();
+    let batch = extract_metrics(&args).unwrap().summary;
+    assert_eq!(streamed.read_count, batch.read_count);
+    assert_eq!(streamed.length_stats.median, batch.length_stats.median);
+    assert_eq!(streamed.length_stats.max, batch.length_stats.max);
+    let (streamed_quality, batch_quality) = (
+        streamed.quality_stats.unwrap(),
+        batch.quality_stats.unwrap(),
+    );
+    assert!((streamed_quality.mean - batch_quality.mean).abs() < 1e-9);
+}
+
+#[test]
+fn test_max_memory_spill() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("reads.fastq");
+    let mut fastq = String::new();
+    for i in 0..500 {
+        let length = 50 + i % 37;
+        fastq.push_str(&format!(
+            "@read{}\n{}\n+\n{}\n",
+            i,
+            "ACGT".repeat(length).split_at(length).0,
+            "5".repeat(length)
+        ));
+    }
+    std::fs::write(&path, fastq).unwrap();
+    let extract = |extra: &[&str]| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-f", "tsv"])
+            .args(extra)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+    let rows = |stdout: &[u8]| {
+        String::from_utf8(stdout.to_vec())
+            .unwrap()
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    // A cap of a few reads spills many chunks, and the rows come back in order
+    let capped = extract(&["-v", "--max-memory", "2K"]);
+    assert!(
+        capped.status.success(),
+        "{}",
+        String::from_utf8_lossy(&capped.stderr)
+    );
+    assert!(String::from_utf8_lossy(&capped.stderr).contains(" chunks to "));
+    let uncapped = extract(&[]);
+    assert_eq!(rows(&capped.stdout), rows(&uncapped.stdout));
+    assert_eq!(rows(&capped.stdout).len(), 501);
+
+    let json = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-f", "json", "--max-memory", "1.5k"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(json["reads"].as_array().unwrap().len(), 500);
+    assert_eq!(json["reads"][499]["read_id"], "read499");
+    assert_eq!(json["summary"]["read_count"], 500);
+
+    let invalid = extract(&["--max-memory", "lots"]);
+    assert_eq!(invalid.status.code(), Some(6));
+}
+
+#[test]
+fn test_count_only() {
+    let temp_file = create_test_fastq();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "--count-only"])
+        .arg(temp_file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\t199\n");
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("count.bam");
+    write_test_bam(
+        &path,
+        &[bam_record("read1", 100, 60), bam_record("read2", 250, 60)],
+    );
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam", "--count-only"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\t350\n");
+}
+
+#[test]
+fn test_downsample() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    for i in 0..50 {
+        writeln!(file, "@read{}", i).unwrap();
+        writeln!(file, "{}", "A".repeat(100 + i)).unwrap();
+        writeln!(file, "+").unwrap();
+        writeln!(file, "{}", "I".repeat(100 + i)).unwrap();
+    }
+
+    let run = || {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args([
+                "extract",
+                "-t",
+                "fastq",
+                "--downsample",
+                "10",
+                "--seed",
+                "3",
+            ])
+            .arg(file.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+    let first = run();
+    assert_eq!(first["reads"].as_array().unwrap().len(), 10);
+    assert_eq!(first["summary"]["read_count"], 10);
+    assert_eq!(first["provenance"]["sampling"]["seed"], 3);
+    assert_eq!(first["provenance"]["sampling"]["original_read_count"], 50);
+    assert_eq!(first["reads"], run()["reads"]);
+}
+
+#[test]
+fn test_duplicate_input_files() {
+    let temp_file = create_test_fastq();
+    let path = temp_file.path();
+    // The same file under a second spelling of its path
+    let respelled = path
+        .parent()
+        .unwrap()
+        .join(".")
+        .join(path.file_name().unwrap());
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .env("RUST_LOG", "warn")
+        .args(["extract", "-t", "fastq", "-f", "json-summary"])
+        .arg(path)
+        .arg(&respelled)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("was given more than once"),
+        "stderr: {}",
+        stderr
+    );
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["read_count"], 2);
+
+    // With --strict the warning is an error, reported with any others once
+    // the inputs have been read
+    let missing = format!("{}/*.fq.gz", path.parent().unwrap().display());
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "--strict", "--glob", &missing])
+        .arg(path)
+        .arg(&respelled)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 warnings with --strict:"), "{}", stderr);
+    assert!(stderr.contains("was given more than once"), "{}", stderr);
+    assert!(stderr.contains("matches no files"), "{}", stderr);
+}
+
+#[test]
+fn test_sequence_stats_n_count() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    writeln!(file, ">read1").unwrap();
+    writeln!(file, "ACGTNNNN").unwrap();
+    writeln!(file, ">read2").unwrap();
+    writeln!(file, "acgtacgt").unwrap();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fasta", "--sequence-stats"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["reads"][0]["n_count"], 4);
+    assert_eq!(json["reads"][1]["n_count"], 0);
+    assert_eq!(json["summary"]["n_fraction"], 0.25);
+
+    // Without the flag the sequences are not scanned
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fasta"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json["reads"][0]["n_count"].is_null());
+    assert!(json["summary"]["n_fraction"].is_null());
+}
+
+#[test]
+fn test_tsv_sort() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("reads.fasta");
+    std::fs::write(
+        &input,
+        ">short\nACGT\n>long\nACGTACGTACGT\n>mid\nACGTACGT\n",
+    )
+    .unwrap();
+    let output = dir.path().join("out.tsv");
+
+    let status = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args([
+            "extract",
+            "-t",
+            "fasta",
+            "-f",
+            "tsv",
+            "--sort",
+            "length:desc",
+            "-o",
+        ])
+        .arg(&output)
+        .arg(&input)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let tsv = std::fs::read_to_string(&output).unwrap();
+    let ids: Vec<&str> = tsv
+        .lines()
+        .skip(1)
+        .take(3)
+        .map(|l| l.split('\t').next().unwrap())
+        .collect();
+    assert_eq!(ids, ["long", "mid", "short"]);
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fasta", "-f", "tsv", "--sort", "name"])
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_write_reads() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("reads.fastq");
+    std::fs::write(
+        &input,
+        "@short\nACGT\n+\nIIII\n@long\nACGTACGTACGT\n+\nIIIIIIIIIIII\n@mid\nACGTACGT\n+\nIIIIIIII\n",
+    )
+    .unwrap();
+    let filtered = dir.path().join("filtered.fastq");
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args([
+            "extract",
+            "-t",
+            "fastq",
+            "--min-length",
+            "6",
+            "--write-reads",
+        ])
+        .arg(&filtered)
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["read_count"], 2);
+    assert_eq!(
+        std::fs::read_to_string(&filtered).unwrap(),
+        "@long\nACGTACGTACGT\n+\nIIIIIIIIIIII\n@mid\nACGTACGT\n+\nIIIIIIII\n"
+    );
+}
+
+#[test]
+fn test_write_reads_repeated_id() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("reads.fastq");
+    // Only the first `dup` is kept: the second is skipped by
+    // --subsample-every and the third dropped by --min-length
+    std::fs::write(
+        &input,
+        "@dup\nACGTACGT\n+\nIIIIIIII\n@dup\nACGTACGT\n+\nIIIIIIII\n@dup\nACGT\n+\nIIII\n@long\nACGTACGTACGT\n+\nIIIIIIIIIIII\n",
+    )
+    .unwrap();
+    let written = dir.path().join("written.fastq");
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args([
+            "extract",
+            "-t",
+            "fastq",
+            "--min-length",
+            "6",
+            "--subsample-every",
+            "2",
+            "--write-reads",
+        ])
+        .arg(&written)
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["read_count"], 1);
+    assert_eq!(
+        std::fs::read_to_string(&written).unwrap(),
+        "@dup\nACGTACGT\n+\nIIIIIIII\n"
+    );
+
+    // Not silently skipped by the modes that keep no reads
+    for mode in ["--streaming", "--count-only"] {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "fastq", mode, "--write-reads"])
+            .arg(&written)
+            .arg(&input)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+    }
+}
+
+#[test]
+fn test_streaming_rejects_options_it_cannot_honour() {
+    let file = create_test_fastq();
+    for option in [
+        &["--cumulative-yield"][..],
+        &["--length-quality-density"],
+        &["--run-start", "2024-05-01T09:30:00Z"],
+        &["--per-file"],
+    ] {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "fastq", "--streaming"])
+            .args(option)
+            .arg(file.path())
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{:?} was accepted", option);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+    }
+}
+
+#[test]
+fn test_summary_large_channel_id() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    writeln!(
+        file,
+        "read_id\tchannel\tstart_time\tduration\tsequence_length_template\tmean_qscore_template"
+    )
+    .unwrap();
+    writeln!(file, "r1\t70000\t10.0\t1.0\t1000\t10.0").unwrap();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "summary"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["reads"][0]["channel_id"], 70000);
+    assert_eq!(json["summary"]["channel_distribution"]["70000"], 1);
+}
+
+#[test]
+fn test_length_beyond_u32() {
+    // Longer than u32::MAX, as for an assembled chromosome
+    let long = 5_000_000_000u64;
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    writeln!(
+        file,
+        "read_id\tsequence_length_template\tmean_qscore_template"
+    )
+    .unwrap();
+    writeln!(file, "contig1\t{}\t10.0", long).unwrap();
+    writeln!(file, "contig2\t1000\t10.0").unwrap();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "summary", "--min-length", "4294967296"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["reads"].as_array().unwrap().len(), 1);
+    assert_eq!(json["reads"][0]["length"], long);
+    assert_eq!(json["summary"]["length_stats"]["max"], long as f64);
+}
+
+#[test]
+fn test_missing_quality() {
+    let fasta = create_test_fasta();
+    let run = |extra: &[&str]| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "fasta"])
+            .args(extra)
+            .arg(fasta.path())
+            .output()
+            .unwrap()
+    };
+
+    // By default reads without a quality are left out of the statistics
+    let output = run(&[]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json["summary"]["quality_stats"].is_null());
+
+    let output = run(&["--missing-quality", "zero"]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["quality_stats"]["count"], 2);
+    assert_eq!(json["summary"]["quality_stats"]["max"], 0.0);
+
+    for extra in [
+        &["--missing-quality", "error"][..],
+        &["--missing-quality", "error", "--streaming"],
+    ] {
+        let output = run(extra);
+        assert_eq!(output.status.code(), Some(6));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("2 of 2 reads have no quality"));
+    }
+    // Reads that all have a quality pass
+    let fastq = create_test_fastq();
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "--missing-quality", "error"])
+        .arg(fastq.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_gc_filter() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    for (id, seq) in [
+        ("gc30", "GCGAAAAAAT"),
+        ("gc50", "GCGCGAAAAT"),
+        ("gc70", "GCGCGCGAAT"),
+    ] {
+        writeln!(file, ">{}\n{}", id, seq).unwrap();
+    }
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fasta", "--min-gc", "40", "--max-gc", "60"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["read_count"], 1);
+    assert_eq!(json["reads"][0]["read_id"], "gc50");
+    assert_eq!(json["reads"][0]["gc_content"], 50.0);
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fasta", "--min-gc", "60", "--max-gc", "40"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    // Alignments and summaries have no GC content to filter on
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam", "--min-gc", "40"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("need FASTQ or FASTA input, not Bam"));
+}
+
+#[test]
+fn test_per_file_summaries() {
+    let dir = tempfile::tempdir().unwrap();
+    let good = dir.path().join("good.fasta");
+    let bad = dir.path().join("bad.fasta");
+    std::fs::write(&good, ">a\nACGTACGTAC\n>b\nACGTACGTACGT\n").unwrap();
+    std::fs::write(&bad, ">c\nACG\n").unwrap();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fasta", "--per-file", "-f", "json-summary"])
+        .arg(&good)
+        .arg(&bad)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["read_count"], 3);
+    let per_file = &json["per_file"];
+    assert_eq!(per_file[good.display().to_string()]["read_count"], 2);
+    assert_eq!(per_file[bad.display().to_string()]["read_count"], 1);
+    assert_eq!(
+        per_file[bad.display().to_string()]["length_stats"]["max"],
+        3.0
+    );
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fasta", "--per-file", "-f", "tsv"])
+        .arg(&good)
+        .arg(&bad)
+        .output()
+        .unwrap();
+    let tsv = String::from_utf8(output.stdout).unwrap();
+    assert!(tsv.contains("\n# Per-file summary\n"));
+    assert!(tsv.contains(&format!("# {}\t1\t3.00\t3.00\t3\t\t\n", bad.display())));
+
+    // Without the flag the per-file table is left out of the output
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fasta"])
+        .arg(&good)
+        .arg(&bad)
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json.get("per_file").is_none());
+}
+
+#[test]
+fn test_min_mapq() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("mapq.bam");
+    write_test_bam(
+        &path,
+        &[
+            bam_record("low", 100, 10),
+            bam_record("high", 200, 30),
+            bam_record("unavailable", 300, 255),
+        ],
+    );
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam", "--min-mapq", "20"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["read_count"], 1);
+    assert_eq!(json["reads"][0]["read_id"], "high");
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam", "--count-only", "--min-mapq", "20"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\t200\n");
+}
+
+#[test]
+fn test_sam_extraction() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("alignments.sam");
+    let sam = [
+        "@HD\tVN:1.6\tSO:unsorted",
+        "@SQ\tSN:chr1\tLN:10000",
+        "read1\t0\tchr1\t100\t60\t8M\t*\t0\t0\tACGTACGT\tIIIIIIII",
+        "read2\t16\tchr1\t500\t17\t2S10M\t*\t0\t0\tACGTACGTACGT\t++++++++++++",
+        "unmapped\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\tIIII",
+    ];
+    std::fs::write(&path, sam.join("\n") + "\n").unwrap();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .arg("extract")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["read_count"], 2);
+    let reads = json["reads"].as_array().unwrap();
+    assert_eq!(reads[0]["read_id"], "read1");
+    assert_eq!(reads[0]["length"], 8);
+    assert_eq!(reads[0]["aligned_length"], 8);
+    assert_eq!(reads[0]["mapping_quality"], 60);
+    assert_eq!(reads[1]["read_id"], "read2");
+    assert_eq!(reads[1]["length"], 12);
+    assert_eq!(reads[1]["aligned_length"], 10);
+    assert_eq!(reads[1]["mapping_quality"], 17);
+}
+
+#[test]
+fn test_length_filters_use_read_length() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("clipped.bam");
+    // 1000 bases of which 200 are soft-clipped: an aligned length of 800
+    let mut clipped = bam_record("clipped", 1000, 60);
+    let cigar = CigarString(vec![Cigar::Match(800), Cigar::SoftClip(200)]);
+    clipped.set(b"clipped", Some(&cigar), &[b'A'; 1000], &[30u8; 1000]);
+    write_test_bam(&path, &[clipped, bam_record("short", 500, 60)]);
+
+    let extract = |filter: &[&str]| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "bam"])
+            .args(filter)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    // The read length (1000) passes, although the aligned length would not
+    let json = extract(&["--min-length", "900"]);
+    assert_eq!(json["summary"]["read_count"], 1);
+    assert_eq!(json["reads"][0]["read_id"], "clipped");
+    assert_eq!(json["reads"][0]["aligned_length"], 800);
+    assert_eq!(json["summary"]["excluded_reads"]["min_length"], 1);
+
+    // ... and fails an upper bound the aligned length would pass
+    let json = extract(&["--max-length", "900"]);
+    assert_eq!(json["summary"]["read_count"], 1);
+    assert_eq!(json["reads"][0]["read_id"], "short");
+    assert_eq!(json["summary"]["excluded_reads"]["max_length"], 1);
+
+    // Without filters there is nothing to report
+    let json = extract(&[]);
+    assert!(json["summary"]["excluded_reads"].is_null());
+}
+
+#[test]
+fn test_excluded_read_counts() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    // Lengths 5, 10, 10, 20 and 30; qualities Q10 ('+') or Q30 ('?')
+    for (id, length, quality) in [
+        ("short", 5, '?'),
+        ("low_quality", 10, '+'),
+        ("kept", 10, '?'),
+        ("long", 20, '?'),
+        ("long_low_quality", 30, '+'),
+    ] {
+        writeln!(
+            file,
+            "@{}\n{}\n+\n{}",
+            id,
+            "A".repeat(length),
+            quality.to_string().repeat(length)
+        )
+        .unwrap();
+    }
+    let filters = [
+        "--min-length",
+        "8",
+        "--max-length",
+        "15",
+        "--min-quality",
+        "20",
+    ];
+
+    for mode in [
+        &["-f", "json-summary"][..],
+        &["-f", "json-summary", "--streaming"],
+    ] {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "fastq"])
+            .args(filters)
+            .args(mode)
+            .arg(file.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["read_count"], 1);
+        // Each read is counted once, under the first criterion it fails
+        assert_eq!(
+            json["excluded_reads"],
+            serde_json::json!({
+                "min_length": 1,
+                "max_length": 2,
+                "min_quality": 1,
+                "gc_content": 0
+            })
+        );
+    }
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "-f", "tsv"])
+        .args(filters)
+        .arg(file.path())
+        .output()
+        .unwrap();
+    let tsv = String::from_utf8(output.stdout).unwrap();
+    assert!(tsv.contains(
+        "# Excluded reads: 4 (min length: 1, max length: 2, min quality: 1, GC content: 0)\n"
+    ));
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args([
+            "extract",
+            "-t",
+            "fastq",
+            "--min-length",
+            "20",
+            "--max-length",
+            "10",
+        ])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_duplex_read_class() {
+    use rust_htslib::bam::record::Aux;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("duplex.bam");
+    let mut duplex = bam_record("duplex", 300, 60);
+    duplex.push_aux(b"dx", Aux::I32(1)).unwrap();
+    let mut simplex1 = bam_record("simplex1", 100, 60);
+    simplex1.push_aux(b"dx", Aux::I32(0)).unwrap();
+    let mut simplex2 = bam_record("simplex2", 200, 60);
+    simplex2.push_aux(b"dx", Aux::I8(0)).unwrap();
+    write_test_bam(
+        &path,
+        &[duplex, simplex1, simplex2, bam_record("untagged", 50, 60)],
+    );
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let classes: Vec<Option<&str>> = json["reads"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["read_class"].as_str())
+        .collect();
+    assert_eq!(
+        classes,
+        [Some("duplex"), Some("simplex"), Some("simplex"), None]
+    );
+    let per_class = &json["summary"]["per_read_class"];
+    assert_eq!(per_class["duplex"]["read_count"], 1);
+    assert_eq!(per_class["simplex"]["read_count"], 2);
+    assert_eq!(per_class["simplex"]["bases"], 300);
+}
+
+#[test]
+fn test_polya_length() {
+    use rust_htslib::bam::record::Aux;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("polya.bam");
+    let mut tailed = bam_record("tailed", 300, 60);
+    tailed.push_aux(b"pa", Aux::I32(85)).unwrap();
+    let mut estimated = bam_record("estimated", 200, 60);
+    estimated.push_aux(b"pt", Aux::I32(115)).unwrap();
+    write_test_bam(&path, &[tailed, estimated, bam_record("untagged", 100, 60)]);
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["reads"][0]["polya_length"], 85);
+    assert_eq!(json["reads"][1]["polya_length"], 115);
+    assert!(json["reads"][2]["polya_length"].is_null());
+    let stats = &json["summary"]["polya_length_stats"];
+    assert_eq!(stats["count"], 2);
+    assert_eq!(stats["mean"], 100.0);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_timeout_skips_blocked_file() {
+    let dir = tempfile::tempdir().unwrap();
+    // Opening a FIFO without a writer blocks, like a read on a hung network mount
+    let blocked = dir.path().join("blocked.fastq");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&blocked)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let good = create_test_fastq();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "--timeout-secs", "1"])
+        .arg(&blocked)
+        .arg(good.path())
+        .timeout(std::time::Duration::from_secs(30))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["read_count"], 2, "{}", stderr);
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "--timeout-secs", "1", "--strict"])
+        .arg(&blocked)
+        .arg(good.path())
+        .timeout(std::time::Duration::from_secs(30))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not processed within 1 seconds"));
+}
+
+#[test]
+fn test_read_type_validation() {
+    let fastq = create_test_fastq();
+    let mut summary = NamedTempFile::new().unwrap();
+    writeln!(
+        summary,
+        "read_id\tsequence_length_template\tmean_qscore_template\nr1\t1000\t10.0"
+    )
+    .unwrap();
+
+    let run = |file_type: &str, path: &Path, read_type: &str| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", file_type, "--read-type", read_type])
+            .arg(path)
+            .output()
+            .unwrap()
+    };
+
+    // An unknown read type is rejected whatever the input
+    for (file_type, path) in [("fastq", fastq.path()), ("summary", summary.path())] {
+        let output = run(file_type, path, "3D");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("invalid value '3D'"));
+    }
+
+    // 2D/1D2 select summary columns and mean nothing for other inputs
+    assert!(!run("fastq", fastq.path(), "2D").status.success());
+    assert!(run("fastq", fastq.path(), "1D").status.success());
+    assert!(run("summary", summary.path(), "1D").status.success());
+}
+
+#[test]
+fn test_compare_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.json");
+    let second = dir.path().join("second.json");
+    let fastq = create_test_fastq();
+    let fasta = create_test_fasta();
+    for (file_type, input, output) in [
+        ("fastq", fastq.path(), &first),
+        ("fasta", fasta.path(), &second),
+    ] {
+        let status = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", file_type, "-o"])
+            .arg(output)
+            .arg(input)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+    }
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .arg("compare")
+        .arg(&first)
+        .arg(&second)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let table = String::from_utf8(output.stdout).unwrap();
+    assert!(table.starts_with("metric\ta\tb\tdifference\trelative_difference\nreads\t2.00\t"));
+    assert!(table.contains("\n# length\t"));
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["compare", "-f", "json"])
+        .arg(&first)
+        .arg(&second)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["metrics"][0]["metric"], "reads");
+    assert_eq!(json["metrics"][0]["a"], 2.0);
+    assert!(json["length_distribution"]["ks_statistic"].is_number());
+    // The FASTA input has no qualities
+    assert!(json["quality_distribution"].is_null());
+}
+
+#[test]
+fn test_merge_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let fc1 = dir.path().join("fc1.json");
+    let fc2 = dir.path().join("fc2.json");
+    let fastq = create_test_fastq();
+    let fasta = create_test_fasta();
+    for (file_type, input, output) in [("fastq", fastq.path(), &fc1), ("fasta", fasta.path(), &fc2)]
+    {
+        let status = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", file_type, "-o"])
+            .arg(output)
+            .arg(input)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+    }
+    let merge = |extra: &[&str]| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .arg("merge")
+            .arg(&fc1)
+            .arg(&fc2)
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let output = merge(&[]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["read_count"], 4);
+    let datasets: Vec<&str> = json["reads"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["dataset"].as_str().unwrap())
+        .collect();
+    assert_eq!(datasets, ["fc1", "fc1", "fc2", "fc2"]);
+
+    let merged = dir.path().join("all.tsv");
+    let output = merge(&["--names", "a,b", "-o", merged.to_str().unwrap()]);
+    assert!(output.status.success());
+    let tsv = std::fs::read_to_string(&merged).unwrap();
+    let rows: Vec<&str> = tsv.lines().skip(1).take_while(|l| !l.is_empty()).collect();
+    assert_eq!(rows.len(), 4);
+    assert!(rows[0].contains("\ta\t") && rows[3].contains("\tb\t"));
+
+    let output = merge(&["--names", "a"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("one name per file"));
+    let output = merge(&["--names", "a,a"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("the name 'a' more than once"));
+
+    // Exports from another release are refused
+    let mut json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&fc2).unwrap()).unwrap();
+    json["provenance"]["nanoget_version"] = "0.0.1".into();
+    std::fs::write(&fc2, json.to_string()).unwrap();
+    let output = merge(&[]);
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("by nanoget 0.0.1"));
+
+    // ... and so are summaries without the reads
+    let status = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args([
+            "extract",
+            "-t",
+            "fasta",
+            "-f",
+            "json-summary",
+            "--force",
+            "-o",
+        ])
+        .arg(&fc2)
+        .arg(fasta.path())
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let output = merge(&[]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("without per-read records"));
+}
+
+#[test]
+fn test_output_matches_schema() {
+    let schema = |extra: &[&str]| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .arg("schema")
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let schema: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(schema["x-schema-version"], 1);
+        jsonschema::validator_for(&schema).unwrap()
+    };
+    let extract = |args: &[&str], input: &Path| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .arg("extract")
+            .args(args)
+            .arg(input)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let bam = dir.path().join("reads.bam");
+    write_test_bam(
+        &bam,
+        &[bam_record("read1", 100, 60), bam_record("read2", 250, 20)],
+    );
+    let fastq = create_test_fastq();
+
+    let collection = schema(&[]);
+    for document in [
+        extract(&["-t", "fastq", "-f", "json", "--log-length"], fastq.path()),
+        extract(&["-t", "bam", "-f", "json", "--downsample", "1"], &bam),
+    ] {
+        let errors: Vec<String> = collection
+            .iter_errors(&document)
+            .map(|e| e.to_string())
+            .collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(document["provenance"]["schema_version"], 1);
+    }
+    // A document with a field of the wrong type is rejected
+    let mut document = extract(&["-t", "fastq", "-f", "json"], fastq.path());
+    document["reads"][0]["length"] = "100".into();
+    assert!(!collection.is_valid(&document));
+
+    let summary = schema(&["--summary"]);
+    let document = extract(&["-t", "bam", "-f", "json-summary"], &bam);
+    assert!(summary.is_valid(&document));
+}
+
+#[test]
+fn test_human_output_format() {
+    let fastq = create_test_fastq();
+    let run = |extra: &[&str]| {
+        // Captured stdout is never a terminal
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "fastq"])
+            .args(extra)
+            .arg(fastq.path())
+            .output()
+            .unwrap()
+    };
+
+    // Without --output-format, pipes get JSON
+    let output = run(&[]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["read_count"], 2);
+
+    // The human summary is plain text when not on a terminal
+    for extra in [
+        &["-f", "human"][..],
+        &["-f", "human", "--streaming"][..],
+        &["-f", "text"][..],
+    ] {
+        let output = run(extra);
+        assert!(output.status.success());
+        let text = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            text.starts_with("Reads                2\n"),
+            "output: {}",
+            text
+        );
+        assert!(text.contains("Mean quality"));
+        assert!(!text.contains('\x1b'));
+    }
+}
+
+#[test]
+fn test_file_type_detection() {
+    use flate2::write::GzEncoder;
+
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = std::fs::read(create_test_fastq().path()).unwrap();
+    let fasta = std::fs::read(create_test_fasta().path()).unwrap();
+    let write = |name: &str, content: &[u8]| {
+        let path = dir.path().join(name);
+        if name.to_lowercase().ends_with(".gz") {
+            let mut encoder = GzEncoder::new(
+                std::fs::File::create(&path).unwrap(),
+                flate2::Compression::default(),
+            );
+            encoder.write_all(content).unwrap();
+            encoder.finish().unwrap();
+        } else {
+            std::fs::write(&path, content).unwrap();
+        }
+        path
+    };
+    let summary =
+        b"read_id\tchannel\tstart_time\tduration\tsequence_length_template\tmean_qscore_template\n\
+                    r1\t1\t10.0\t1.0\t1000\t10.0\n";
+    let bam = dir.path().join("aligned.bam");
+    write_test_bam(&bam, &[bam_record("read1", 100, 60)]);
+
+    let run = |path: &Path| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .env("RUST_LOG", "info")
+            .args(["extract", "-f", "json-summary"])
+            .arg(path)
+            .output()
+            .unwrap()
+    };
+    let cases = [
+        (write("reads.fastq", &fastq), "Fastq", "extension", 2),
+        (write("reads.fq", &fastq), "Fastq", "extension", 2),
+        (write("reads.fq.gz", &fastq), "Fastq", "extension", 2),
+        (write("reads.FASTQ.GZ", &fastq), "Fastq", "extension", 2),
+        (write("contigs.fa", &fasta), "Fasta", "extension", 2),
+        (write("contigs.fasta.gz", &fasta), "Fasta", "extension", 2),
+        (
+            write("sequencing_summary.txt", summary),
+            "Summary",
+            "extension",
+            1,
+        ),
+        (bam.clone(), "Bam", "extension", 1),
+        // Unknown extensions fall back to the content
+        (write("reads.dat", &fastq), "Fastq", "content", 2),
+        (write("contigs", &fasta), "Fasta", "content", 2),
+        (write("run_stats.tsv", summary), "Summary", "content", 1),
+    ];
+    for (path, file_type, source, reads) in &cases {
+        let output = run(path);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}: {}", path.display(), stderr);
+        assert!(
+            stderr.contains(&format!(
+                "detected file type {} from its {}",
+                file_type, source
+            )),
+            "{}: {}",
+            path.display(),
+            stderr
+        );
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["read_count"], *reads, "{}", path.display());
+    }
+
+    // Undetectable content is an error; mixed types are each detected
+    let output = run(&write("notes.dat", b"just some text\n"));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cannot determine file format"));
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-f", "json-summary"])
+        .arg(&cases[0].0)
+        .arg(&cases[4].0)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["read_count"], 4);
+
+    // An explicit --file-type skips detection
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .env("RUST_LOG", "info")
+        .args(["extract", "-t", "fastq"])
+        .arg(&cases[8].0)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("detected file type"));
+}
+
+#[test]
+fn test_region_with_explicit_index() {
+    use rust_htslib::bam::index;
+
+    let dir = tempfile::tempdir().unwrap();
+    let bam = dir.path().join("sorted.bam");
+    let records: Vec<Record> = [("r1", 0), ("r2", 1000), ("r3", 1500), ("r4", 5000)]
+        .into_iter()
+        .map(|(name, pos)| {
+            let mut record = bam_record(name, 100, 60);
+            record.set_pos(pos);
+            record
+        })
+        .collect();
+    write_test_bam(&bam, &records);
+
+    // Indexes kept away from the data file
+    let index_dir = tempfile::tempdir().unwrap();
+    let bai = index_dir.path().join("reads.bai");
+    let csi = index_dir.path().join("reads.csi");
+    index::build(&bam, Some(&bai), index::Type::Bai, 1).unwrap();
+    index::build(&bam, Some(&csi), index::Type::Csi(14), 1).unwrap();
+
+    let run = |extra: &[&str]| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "bam", "-f", "json-summary"])
+            .args(extra)
+            .arg(&bam)
+            .output()
+            .unwrap()
+    };
+    let csi_url = format!("file://{}", csi.display());
+    for index in [bai.to_str().unwrap(), csi.to_str().unwrap(), &csi_url] {
+        let output = run(&["--region", "chr1:900-2000", "--index", index]);
+        assert!(
+            output.status.success(),
+            "{}: {}",
+            index,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["read_count"], 2, "{}", index);
+
+        // A whole reference
+        let output = run(&["--region", "chr1", "--index", index]);
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["read_count"], 4, "{}", index);
+    }
+
+    // Without --index, the index must sit next to the data
+    let output = run(&["--region", "chr1:900-2000"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("with its index"));
+
+    let output = run(&["--region", "chr7:1-100", "--index", bai.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid region 'chr7:1-100'"));
+
+    let output = run(&["--index", bai.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--index only applies"));
+}
+
+#[test]
+fn test_summary_mux_distribution() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    writeln!(
+        file,
+        "read_id\tchannel\tmux\tstart_time\tduration\tsequence_length_template\tmean_qscore_template"
+    )
+    .unwrap();
+    for (i, mux) in [1, 1, 1, 2, 3, 3, 4].iter().enumerate() {
+        writeln!(file, "r{}\t5\t{}\t{}.0\t1.0\t1000\t10.0", i, mux, i).unwrap();
+    }
+
+    let summary =
+        nanoget_rs::convenience::extract_from_files(vec![file.path()], FileType::Summary, None)
+            .unwrap()
+            .summary;
+    let mut muxes: Vec<(u8, usize)> = summary.mux_distribution.unwrap().into_iter().collect();
+    muxes.sort();
+    assert_eq!(muxes, vec![(1, 3), (2, 1), (3, 2), (4, 1)]);
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "summary", "-f", "tsv"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let tsv = String::from_utf8(output.stdout).unwrap();
+    assert!(tsv.contains("# Mux distribution: 1: 3, 2: 1, 3: 2, 4: 1\n"));
+    assert!(tsv.contains("\tchannel_id\tmux\tend_reason\tstart_time\t"));
+    assert!(tsv.contains("\t5\t2\t"));
+
+    // Newer summaries name the column start_mux
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    writeln!(
+        file,
+        "read_id\tchannel\tstart_mux\tsequence_length_template\tmean_qscore_template\nr1\t5\t2\t1000\t10.0"
+    )
+    .unwrap();
+    let summary =
+        nanoget_rs::convenience::extract_from_files(vec![file.path()], FileType::Summary, None)
+            .unwrap()
+            .summary;
+    assert_eq!(summary.mux_distribution.unwrap().get(&2), Some(&1));
+}
+
+#[test]
+fn test_recursive_directory() {
+    use flate2::write::GzEncoder;
+
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = std::fs::read(create_test_fastq().path()).unwrap();
+    let nested = dir.path().join("fastq_pass").join("barcode01");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(dir.path().join("fastq_pass").join("a.fastq"), &fastq).unwrap();
+    let mut encoder = GzEncoder::new(
+        std::fs::File::create(nested.join("b.fq.gz")).unwrap(),
+        flate2::Compression::default(),
+    );
+    encoder.write_all(&fastq).unwrap();
+    encoder.finish().unwrap();
+    std::fs::write(dir.path().join("report.html"), "<html></html>").unwrap();
+
+    let run = |extra: &[&str]| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-f", "json-summary", "--per-file"])
+            .args(extra)
+            .arg(dir.path())
+            .output()
+            .unwrap()
+    };
+
+    // Both nested FASTQs are found, with and without --file-type
+    for extra in [&["--recursive"][..], &["--recursive", "-t", "fastq"][..]] {
+        let output = run(extra);
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["read_count"], 4);
+        let per_file = json["per_file"].as_object().unwrap();
+        assert_eq!(per_file.len(), 2);
+        assert!(per_file.keys().any(|file| file.ends_with("a.fastq")));
+        assert!(per_file.keys().any(|file| file.ends_with("b.fq.gz")));
+    }
+
+    // No file matches another type
+    let output = run(&["--recursive", "-t", "bam"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No input files found"));
+
+    // A directory without --recursive is an error
+    let output = run(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pass --recursive"));
+}
+
+#[test]
+fn test_drop_supplementary_alignments() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("supplementary.bam");
+    let mut supplementary = bam_record("split", 300, 60);
+    supplementary.set_flags(0x800);
+    write_test_bam(&path, &[bam_record("split", 1000, 60), supplementary]);
+
+    let read_count = |extra: &[&str]| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "bam", "-f", "json-summary"])
+            .args(extra)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["read_count"].clone()
+    };
+    assert_eq!(read_count(&[]), 2);
+    assert_eq!(read_count(&["--keep-supplementary", "true"]), 2);
+    assert_eq!(read_count(&["--keep-supplementary", "false"]), 1);
+}
+
+#[test]
+fn test_progress_without_terminal() {
+    let file = create_test_fastq();
+    // stderr is captured here, not a terminal: no bars, the output is unchanged
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "--progress", "-f", "json-summary"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["read_count"], 2);
+}
+
+#[test]
+fn test_glob_inputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = std::fs::read(create_test_fastq().path()).unwrap();
+    let nested = dir.path().join("run2").join("pass");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::create_dir_all(dir.path().join("run1")).unwrap();
+    std::fs::write(dir.path().join("run1").join("a.fastq"), &fastq).unwrap();
+    std::fs::write(nested.join("b.fastq"), &fastq).unwrap();
+    std::fs::write(dir.path().join("run1").join("notes.txt"), "notes").unwrap();
+
+    let pattern = format!("{}/run*/**/*.fastq", dir.path().display());
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-f", "json-summary", "--per-file", "--glob"])
+        .arg(&pattern)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["read_count"], 4);
+    let per_file = json["per_file"].as_object().unwrap();
+    assert_eq!(per_file.len(), 2);
+    assert!(per_file.keys().any(|file| file.ends_with("a.fastq")));
+    assert!(per_file.keys().any(|file| file.ends_with("b.fastq")));
+
+    // A pattern without matches is a warning next to other inputs, an error
+    // with --strict
+    let missing = format!("{}/*.fq.gz", dir.path().display());
+    let run = |strict: bool| {
+        let mut command = Command::cargo_bin("nanoget").unwrap();
+        command
+            .args(["extract", "-f", "json-summary", "--glob"])
+            .arg(&missing)
+            .arg(dir.path().join("run1").join("a.fastq"));
+        if strict {
+            command.arg("--strict");
+        }
+        command.output().unwrap()
+    };
+    assert!(run(false).status.success());
+    let output = run(true);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("matches no files"));
+}
+
+#[test]
+fn test_split_by_barcode() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    writeln!(
+        file,
+        "read_id\tbarcode_arrangement\tsequence_length_template\tmean_qscore_template"
+    )
+    .unwrap();
+    for (i, barcode) in [
+        "barcode01",
+        "barcode01",
+        "barcode02",
+        "unclassified",
+        "bc/3",
+    ]
+    .iter()
+    .enumerate()
+    {
+        writeln!(file, "r{}\t{}\t{}\t10.0", i, barcode, 1000 * (i + 1)).unwrap();
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let split = |extra: &[&str]| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args([
+                "extract",
+                "-t",
+                "summary",
+                "--barcoded",
+                "--split-by",
+                "barcode",
+            ])
+            .args(extra)
+            .arg("-o")
+            .arg(&out)
+            .arg(file.path())
+            .output()
+            .unwrap()
+    };
+
+    let output = split(&[]);
+    assert!(output.status.success());
+    let mut files: Vec<String> = std::fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "barcode01.tsv",
+            "barcode02.tsv",
+            "bc_3.tsv",
+            "demux_summary.tsv",
+            "unclassified.tsv"
+        ]
+    );
+    let table = std::fs::read_to_string(out.join("barcode01.tsv")).unwrap();
+    // Summary rows have no read ids
+    assert!(table.contains("\n\t1000\t") && table.contains("\n\t2000\t"));
+    assert!(!table.contains("\n\t3000\t"));
+    let summary = std::fs::read_to_string(out.join("demux_summary.tsv")).unwrap();
+    assert!(summary.contains("# Demultiplexing rate"));
+
+    // Existing files are not overwritten without --force
+    let output = split(&[]);
+    assert_eq!(output.status.code(), Some(6));
+    let output = split(&["--force", "-f", "json"]);
+    assert!(output.status.success());
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(out.join("barcode02.json")).unwrap())
+            .unwrap();
+    assert_eq!(json["summary"]["read_count"], 1);
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(out.join("demux_summary.json")).unwrap())
+            .unwrap();
+    assert_eq!(json["read_count"], 5);
+    assert_eq!(json["unclassified_count"], 1);
+}
+
+#[test]
+fn test_summary_batch_datasets() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(
+        file,
+        "filename_fastq\tfilename_pod5\tread_id\tsequence_length_template\tmean_qscore_template"
+    )
+    .unwrap();
+    for (batch, length) in [("batch_0", 1000), ("batch_0", 2000), ("batch_1", 500)] {
+        writeln!(
+            file,
+            "{}.fastq\trun.pod5\tr{}\t{}\t12.0",
+            batch, length, length
+        )
+        .unwrap();
+    }
+
+    let reads = |combine: &str| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args([
+                "extract",
+                "-t",
+                "summary",
+                "-f",
+                "json",
+                "--combine",
+                combine,
+            ])
+            .arg(file.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["reads"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|read| {
+                (
+                    read["dataset"].as_str().map(str::to_string),
+                    read["length"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // In track mode the FASTQ batch is the dataset, rather than the summary file
+    let batch = |name: &str| Some(name.to_string());
+    assert_eq!(
+        reads("track"),
+        [
+            (batch("batch_0.fastq"), 1000),
+            (batch("batch_0.fastq"), 2000),
+            (batch("batch_1.fastq"), 500)
+        ]
+    );
+    assert!(reads("simple").iter().all(|(dataset, _)| dataset.is_none()));
+}
+
+#[test]
+fn test_summary_datasets_with_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut files = Vec::new();
+    for (i, batch) in ["batch_0", "batch_1"].iter().enumerate() {
+        let path = dir.path().join(format!("summary_{}.txt", i));
+        std::fs::write(
+            &path,
+            format!(
+                "filename_fastq\tread_id\tsequence_length_template\tmean_qscore_template\n\
+                 {batch}.fastq\tr{i}a\t1000\t12.0\n{batch}.fastq\tr{i}b\t2000\t12.0\n"
+            ),
+        )
+        .unwrap();
+        files.push(path);
+    }
+
+    let datasets = |extra: &[&str]| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args([
+                "extract",
+                "-t",
+                "summary",
+                "-f",
+                "json",
+                "--combine",
+                "track",
+            ])
+            .args(extra)
+            .args(&files)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["reads"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|read| read["dataset"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // Each summary file is a dataset, named by --names or after the file
+    assert_eq!(
+        datasets(&["--names", "control,treated"]),
+        ["control", "control", "treated", "treated"]
+    );
+    assert_eq!(
+        datasets(&[]),
+        ["summary_0", "summary_0", "summary_1", "summary_1"]
+    );
+}
+
+#[test]
+fn test_summary_end_reasons() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    writeln!(
+        file,
+        "read_id\tchannel\tend_reason\tbarcode_arrangement\tsequence_length_template\tmean_qscore_template"
+    )
+    .unwrap();
+    let reads = [
+        ("signal_positive", "barcode01"),
+        ("signal_positive", "barcode01"),
+        ("data_service_unblock_mux_change", "barcode01"),
+        ("signal_positive", "barcode02"),
+        ("unblock_mux_change", "barcode02"),
+        ("", "barcode02"),
+    ];
+    for (i, (end_reason, barcode)) in reads.iter().enumerate() {
+        writeln!(file, "r{}\t5\t{}\t{}\t1000\t10.0", i, end_reason, barcode).unwrap();
+    }
+
+    let run = |extra: &[&str]| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "summary", "--barcoded"])
+            .args(extra)
+            .arg(file.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+
+    // Reads with an empty end reason are left out of the distribution
+    let expected = serde_json::json!({
+        "signal_positive": 3,
+        "data_service_unblock_mux_change": 1,
+        "unblock_mux_change": 1
+    });
+    for extra in [
+        &["-f", "json-summary"][..],
+        &["-f", "json-summary", "--streaming"],
+    ] {
+        let json: serde_json::Value = serde_json::from_slice(&run(extra)).unwrap();
+        assert_eq!(json["end_reason_distribution"], expected);
+    }
+
+    // ... and split by barcode
+    let json: serde_json::Value = serde_json::from_slice(&run(&["-f", "json-summary"])).unwrap();
+    assert_eq!(
+        json["per_barcode"]["barcode01"]["end_reasons"],
+        serde_json::json!({"signal_positive": 2, "data_service_unblock_mux_change": 1})
+    );
+    assert_eq!(
+        json["per_barcode"]["barcode02"]["end_reasons"],
+        serde_json::json!({"signal_positive": 1, "unblock_mux_change": 1})
+    );
+
+    let tsv = String::from_utf8(run(&["-f", "tsv"])).unwrap();
+    assert!(tsv.contains(
+        "# End reasons: signal_positive: 3 (60.00%), data_service_unblock_mux_change: 1 (20.00%), \
+         unblock_mux_change: 1 (20.00%)\n"
+    ));
+    assert!(tsv.contains("\t5\t\tsignal_positive\t"));
+}
+
+#[test]
+fn test_unknown_output_format() {
+    let file = create_test_fastq();
+    for extra in [&[][..], &["--streaming"][..], &["--count-only"][..]] {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "fastq", "--output-format", "foo"])
+            .args(extra)
+            .arg(file.path())
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Unknown output format 'foo'"), "{}", stderr);
+        assert!(stderr.contains("json-summary, tsv, human"));
+    }
+}
+
+#[test]
+fn test_stats_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("reads.fastq");
+    std::fs::copy(create_test_fastq().path(), &path).unwrap();
+
+    let run = |extra: &[&str]| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .arg("stats")
+            .args(extra)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // The type is detected and the report printed without any flags, also
+    // when not on a terminal; the single pass has no read length N50
+    let report = run(&[]);
+    assert!(report.starts_with("Reads                2\n"), "{}", report);
+    assert!(report.contains("Mean quality"));
+    assert!(!report.contains("N50"));
+    assert!(run(&["--exact"]).contains("Read length N50      100\n"));
+
+    // Machine formats hold the summary only
+    for extra in [&["-f", "json"][..], &["-f", "json", "--exact"][..]] {
+        let json: serde_json::Value = serde_json::from_str(&run(extra)).unwrap();
+        assert_eq!(json["read_count"], 2);
+        assert!(json.get("reads").is_none());
+    }
+    let tsv = run(&["-f", "tsv", "--exact"]);
+    assert!(tsv.contains("# Total reads: 2\n"));
+    assert!(!tsv.contains("read_id\t"));
+}
+
+#[test]
+fn test_stats_options_needing_every_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("reads.fastq");
+    std::fs::copy(create_test_fastq().path(), &path).unwrap();
+    let command = |extra: &[&str]| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["stats", "-f", "json"])
+            .args(extra)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+    let run = |extra: &[&str]| {
+        let output = command(extra);
+        assert!(
+            output.status.success(),
+            "{:?}: {}",
+            extra,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output
+    };
+    let summary = |extra: &[&str]| -> serde_json::Value {
+        serde_json::from_slice(&run(extra).stdout).unwrap()
+    };
+
+    // Each option is honoured by summarizing exactly, rather than dropped by
+    // the single pass
+    let written = dir.path().join("out.fq");
+    run(&["--write-reads", written.to_str().unwrap()]);
+    assert_eq!(
+        std::fs::read_to_string(&written).unwrap().lines().count(),
+        8
+    );
+
+    let output = run(&["--resource-report"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Resource usage"));
+
+    // These reads have no barcode to split by, which is now reported
+    let split = dir.path().join("split");
+    let output = command(&["--split-by", "barcode", "-o", split.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("barcode to split by"));
+
+    let output = run(&["-v", "--max-memory", "1"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--max-memory needs every read"));
+
+    assert!(summary(&["--per-file"])["per_file"].is_object());
+    assert!(summary(&["--cumulative-yield"])["cumulative_yield"].is_array());
+    assert!(summary(&["--length-quality-density"])["length_quality_density"].is_array());
+}
+
+#[test]
+fn test_alignments_per_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("split.bam");
+    let supplementary = |length| {
+        let mut record = bam_record("chimera", length, 60);
+        record.set_flags(0x800);
+        record
+    };
+    write_test_bam(
+        &path,
+        &[
+            bam_record("chimera", 1000, 60),
+            supplementary(400),
+            supplementary(300),
+            bam_record("plain", 500, 60),
+        ],
+    );
+
+    let metrics = nanoget_rs::convenience::extract_from_bam(&path).unwrap();
+    let counts = metrics.alignments_per_read();
+    assert_eq!(counts[&(None, "chimera".to_string())], 3);
+    assert_eq!(counts[&(None, "plain".to_string())], 1);
+    assert_eq!(metrics.summary.multi_alignment_fraction, Some(0.5));
+
+    // Without the supplementary alignments each read has one
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam", "-f", "tsv"])
+        .args(["--keep-supplementary", "false"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let tsv = String::from_utf8(output.stdout).unwrap();
+    assert!(tsv.contains("# Alignments per read: 1: 2 (0.00% of reads with more than one)\n"));
+}
+
+#[test]
+fn test_track_names_validation() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = std::fs::read(create_test_fastq().path()).unwrap();
+    let files: Vec<_> = ["wt.fastq", "ko.fq", "rescue.fastq"]
+        .iter()
+        .map(|name| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, &fastq).unwrap();
+            path
+        })
+        .collect();
+    let run = |names: &[&str]| {
+        let mut command = Command::cargo_bin("nanoget").unwrap();
+        command.args(["extract", "-t", "fastq", "--combine", "track"]);
+        for name in names {
+            command.args(["--names", name]);
+        }
+        command.args(&files).output().unwrap()
+    };
+
+    let output = run(&["wt", "ko"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("--names has 2 names for 3 input files")
+    );
+
+    let output = run(&["wt", "ko", "wt"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("the name 'wt' more than once"));
+
+    let output = run(&["a,b", "c"]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["reads"][4]["dataset"], "c");
+
+    // Without --names, datasets are named after the files
+    let output = run(&[]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let datasets: Vec<&str> = json["reads"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|read| read["dataset"].as_str().unwrap())
+        .collect();
+    assert_eq!(datasets, ["wt", "wt", "ko", "ko", "rescue", "rescue"]);
+}
+
+#[test]
+fn test_compressed_summary() {
+    use flate2::write::GzEncoder;
+
+    let dir = tempfile::tempdir().unwrap();
+    let header = "read_id\tchannel\tstart_time\tduration\tbarcode_arrangement\tsequence_length_template\tmean_qscore_template\n";
+    let rows = |range: std::ops::Range<usize>| {
+        range
+            .map(|i| {
+                format!(
+                    "r{}\t{}\t{}.0\t1.0\tbarcode0{}\t{}\t12.0\n",
+                    i,
+                    i % 3 + 1,
+                    i,
+                    i % 2 + 1,
+                    1000 + i
+                )
+            })
+            .collect::<String>()
+    };
+    let gzip = |content: &str| {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    };
+
+    // One gzip member, and two concatenated members (as written by bgzip or
+    // `cat a.gz b.gz`), which must both be read
+    let single = dir.path().join("sequencing_summary.txt.gz");
+    std::fs::write(&single, gzip(&format!("{}{}", header, rows(0..6)))).unwrap();
+    let concatenated = dir.path().join("concatenated_summary.txt.gz");
+    let mut members = gzip(&format!("{}{}", header, rows(0..3)));
+    members.extend(gzip(&rows(3..6)));
+    std::fs::write(&concatenated, members).unwrap();
+    let bzip2 = dir.path().join("sequencing_summary.txt.bz2");
+    let mut encoder =
+        bzip2::write::BzEncoder::new(std::fs::File::create(&bzip2).unwrap(), Default::default());
+    encoder
+        .write_all(format!("{}{}", header, rows(0..6)).as_bytes())
+        .unwrap();
+    encoder.finish().unwrap();
+
+    for path in [&single, &concatenated, &bzip2] {
+        // The type is detected from the name, compression suffix included
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "--barcoded", "-f", "json-summary"])
+            .arg(path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", path.display());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["read_count"], 6, "{}", path.display());
+        assert_eq!(
+            json["channel_distribution"],
+            serde_json::json!({"1": 2, "2": 2, "3": 2})
+        );
+        assert_eq!(
+            json["barcode_distribution"],
+            serde_json::json!({"barcode01": 3, "barcode02": 3})
+        );
+        assert_eq!(json["length_stats"]["max"], 1005.0);
+    }
+}
+
+#[test]
+fn test_log_interval() {
+    let file = create_test_fastq();
+    // Stderr is a pipe here; the lines are logged without -v
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "--log-interval", "60"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 reads processed in"), "{}", stderr);
+    assert!(!stderr.contains("Processing file"));
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "--log-interval", "0"])
+        .arg(file.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(6));
+}
+
+#[test]
+fn test_resource_report() {
+    let first = create_test_fastq();
+    let second = create_test_fasta();
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-f", "json", "--resource-report"])
+        .arg(first.path())
+        .arg(second.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let report = &json["provenance"]["resources"];
+    assert_eq!(report["reads"], 4);
+    assert_eq!(report["files"].as_array().unwrap().len(), 2);
+    assert!(
+        report["wall_seconds"].as_f64().unwrap() >= report["extract_seconds"].as_f64().unwrap()
+    );
+    assert!(report.get("serialize_seconds").is_none());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Resource usage"), "{}", stderr);
+    assert!(stderr.contains(", serialize "));
+    assert!(stderr.contains(&format!("{}: ", first.path().display())));
+
+    // Without the flag the provenance is unchanged
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "-f", "json"])
+        .arg(first.path())
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json["provenance"].get("resources").is_none());
+}
+
+#[test]
+fn test_mixed_compression() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("reads.fastq.gz");
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(b"@read1\nACGT\n+\nIIII\n").unwrap();
+    let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), Default::default());
+    bzip2.write_all(b"@read2\nACGTA\n+\nIIIII\n").unwrap();
+    std::fs::write(
+        &path,
+        [gzip.finish().unwrap(), bzip2.finish().unwrap()].concat(),
+    )
+    .unwrap();
+
+    // The bzip2 part is reported rather than dropped or failing obscurely
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("a gzip member is followed by bzip2-compressed data"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("decompress the parts separately"));
+}
+
+#[test]
+fn test_mixed_input_types() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = create_test_fastq();
+
+    // An unaligned BAM: no reference sequences, unmapped records
+    let ubam = dir.path().join("calls.bam");
+    let mut writer = bam::Writer::from_path(&ubam, &Header::new(), bam::Format::Bam).unwrap();
+    for (name, length) in [("u1", 500), ("u2", 700), ("u3", 900)] {
+        let mut record = Record::new();
+        record.set(
+            name.as_bytes(),
+            None,
+            &vec![b'C'; length],
+            &vec![20u8; length],
+        );
+        record.set_flags(4);
+        record.set_tid(-1);
+        record.set_pos(-1);
+        writer.write(&record).unwrap();
+    }
+    drop(writer);
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "--combine", "track", "--names", "raw,called"])
+        .arg(fastq.path())
+        .arg(&ubam)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let datasets: Vec<&str> = json["reads"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|read| read["dataset"].as_str().unwrap())
+        .collect();
+    assert_eq!(datasets, ["raw", "raw", "called", "called", "called"]);
+    assert_eq!(json["summary"]["read_count"], 5);
+
+    // Explicit per-file types, with alignment fields in one dataset only
+    let aligned = dir.path().join("aligned.bam");
+    write_test_bam(&aligned, &[bam_record("a1", 800, 60)]);
+    let reads = dir.path().join("reads.txt");
+    std::fs::copy(fastq.path(), &reads).unwrap();
+    let run = |types: &str| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "--combine", "track", "--file-types", types])
+            .arg(&reads)
+            .arg(&aligned)
+            .output()
+            .unwrap()
+    };
+    let output = run("fastq,bam");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["summary"]["read_count"], 3);
+    assert_eq!(json["reads"][0]["dataset"], "reads");
+    assert_eq!(json["reads"][2]["dataset"], "aligned");
+    assert!(json["reads"][0]["aligned_length"].is_null());
+    assert_eq!(json["reads"][2]["aligned_length"], 800);
+
+    let output = run("fastq");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--file-types has 1 types for 2 input files"));
+}
+
+#[test]
+fn test_longest_read_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = dir.path().join("reads.fastq");
+    let record = |id: &str, length: usize, quality: char| {
+        format!(
+            "@{}\n{}\n+\n{}\n",
+            id,
+            "A".repeat(length),
+            quality.to_string().repeat(length)
+        )
+    };
+    let content = [
+        record("short", 200, 'I'),
+        record("longest", 1200, '5'),
+        record("second", 1200, '5'),
+        record("medium", 600, '+'),
+    ]
+    .concat();
+    std::fs::write(&fastq, content).unwrap();
+
+    for command in ["extract", "stats"] {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args([command, "-f", "json-summary"])
+            .arg(&fastq)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(json["longest_read"], serde_json::json!(["longest", 1200]));
+        assert_eq!(json["highest_quality_read"][0], "short");
+        let quality = json["highest_quality_read"][1].as_f64().unwrap();
+        assert!((quality - 40.0).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_decode_threads() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("many.bam");
+    // Enough records for many BGZF blocks
+    let records: Vec<Record> = (0..2000)
+        .map(|i| bam_record(&format!("read{}", i), 500 + i % 700, (i % 60) as u8))
+        .collect();
+    write_test_bam(&path, &records);
+
+    let run = |decode_threads: &str| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .env("RUST_LOG", "info")
+            .args([
+                "extract",
+                "-f",
+                "json-summary",
+                "--decode-threads",
+                decode_threads,
+            ])
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        assert!(stderr.contains(&format!("with {} BGZF threads", decode_threads)));
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+    let single = run("0");
+    let threaded = run("2");
+    assert_eq!(single["read_count"], 2000);
+    assert_eq!(threaded, single);
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, content: &[u8]| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    };
+    let exit_code = |args: &[&std::ffi::OsStr]| {
+        let output = Command::cargo_bin("nanoget")
+            .unwrap()
+            .arg("extract")
+            .args(args)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        (output.status.code().unwrap(), stderr)
+    };
+
+    let missing = dir.path().join("missing.fastq");
+    let (code, stderr) = exit_code(&[missing.as_os_str()]);
+    assert_eq!(code, 2);
+    assert!(stderr.starts_with("Error: File not found"), "{}", stderr);
+
+    let truncated = write("truncated.fastq", b"@read1\nACGT\n");
+    assert_eq!(exit_code(&[truncated.as_os_str()]).0, 3);
+    let corrupt = write("corrupt.bam", b"not a bam file at all");
+    assert_eq!(exit_code(&[corrupt.as_os_str()]).0, 3);
+
+    let empty = write("empty.fastq", b"");
+    let (code, stderr) = exit_code(&["-t".as_ref(), "fastq".as_ref(), empty.as_os_str()]);
+    assert_eq!(code, 4);
+    assert!(stderr.contains("No reads found in input files"));
+
+    let unknown = write("notes.dat", b"just some text\n");
+    assert_eq!(exit_code(&[unknown.as_os_str()]).0, 5);
+
+    let fastq = create_test_fastq();
+    let invalid = [
+        "--downsample".as_ref(),
+        "0".as_ref(),
+        fastq.path().as_os_str(),
+    ];
+    assert_eq!(exit_code(&invalid).0, 6);
+    // Command-line usage errors are invalid input too, not clap's 2
+    assert_eq!(exit_code(&["--no-such-option".as_ref()]).0, 6);
+
+    let (code, _) = exit_code(&[fastq.path().as_os_str()]);
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn test_quality_std() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = dir.path().join("reads.fastq");
+    let mixed: String = (0..100)
+        .map(|i| if i % 2 == 0 { '+' } else { 'I' })
+        .collect();
+    std::fs::write(
+        &fastq,
+        format!(
+            "@uniform\n{}\n+\n{}\n@mixed\n{}\n+\n{}\n",
+            "A".repeat(100),
+            "5".repeat(100),
+            "A".repeat(100),
+            mixed
+        ),
+    )
+    .unwrap();
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-f", "json"])
+        .arg(&fastq)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let std = |i: usize| json["reads"][i]["quality_std"].as_f64().unwrap();
+    assert!(std(0) < 1e-9);
+    // Q10 and Q40 alternating
+    assert!((std(1) - 15.0).abs() < 1e-9);
+    assert_eq!(json["summary"]["quality_std_stats"]["max"], 15.0);
+
+    // Aligned reads have neither a mean quality nor its spread
+    let bam = dir.path().join("aligned.bam");
+    write_test_bam(&bam, &[bam_record("aligned", 100, 60)]);
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "bam", "-f", "json"])
+        .arg(&bam)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json["reads"][0]["quality"].is_null());
+    assert!(json["reads"][0]["quality_std"].is_null());
+    assert!(json["summary"]["quality_std_stats"].is_null());
+}
+
+#[test]
+fn test_output_overwrite() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = create_test_fastq();
+    let output = dir.path().join("metrics.json");
+    let reads = dir.path().join("kept.fastq");
+    let run = |extra: &[&str], input: &Path| {
+        Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "fastq", "-o"])
+            .arg(&output)
+            .arg("--write-reads")
+            .arg(&reads)
+            .args(extra)
+            .arg(input)
+            .output()
+            .unwrap()
+    };
+
+    assert!(run(&[], fastq.path()).status.success());
+    let first = std::fs::read_to_string(&output).unwrap();
+    assert!(first.contains("\"read_count\": 2"));
+
+    // Existing outputs are kept unless --force is given
+    std::fs::write(&output, "last week's results").unwrap();
+    let result = run(&[], fastq.path());
+    assert_eq!(result.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&result.stderr)
+        .contains("already exists; pass --force to overwrite it"));
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "last week's results"
+    );
+
+    // A failed run leaves the previous output intact, not half written
+    let missing = dir.path().join("missing.fastq");
+    assert!(!run(&["--force"], &missing).status.success());
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "last week's results"
+    );
+
+    assert!(run(&["--force"], fastq.path()).status.success());
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), first);
+    assert!(std::fs::read_to_string(&reads)
+        .unwrap()
+        .starts_with("@read1"));
+    // No temporary files are left behind
+    let mut names: Vec<String> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["kept.fastq", "metrics.json"]);
+}
+
+#[test]
+fn test_sqlite_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = create_test_fastq();
+    let database = dir.path().join("metrics.sqlite");
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "-f", "sqlite"])
+        .arg(fastq.path())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sqlite needs --output"));
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["extract", "-t", "fastq", "-f", "sqlite", "-o"])
+        .arg(&database)
+        .arg(fastq.path())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let conn = rusqlite::Connection::open(&database).unwrap();
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM reads", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, 2);
+    let (length, quality, aligned_length): (i64, f64, Option<i64>) = conn
+        .query_row(
+            "SELECT length, quality, aligned_length FROM reads WHERE read_id = 'read2'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(length, 99);
+    assert!((quality - 41.0).abs() < 1e-9);
+    assert_eq!(aligned_length, None);
+    let summary_count: i64 = conn
+        .query_row(
+            "SELECT value FROM summary WHERE name = 'read_count'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(summary_count, 2);
+}
+
+#[test]
+fn test_output_format_from_extension() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = create_test_fastq();
+    let run = |extra: &[&str], output: &std::path::Path| {
+        let result = Command::cargo_bin("nanoget")
+            .unwrap()
+            .args(["extract", "-t", "fastq"])
+            .args(extra)
+            .arg("-o")
+            .arg(output)
+            .arg(fastq.path())
+            .output()
+            .unwrap();
+        assert!(
+            result.status.success(),
+            "{}",
+            String::from_utf8_lossy(&result.stderr)
+        );
+        String::from_utf8_lossy(&result.stderr).into_owned()
+    };
+
+    let tsv = dir.path().join("reads.tsv");
+    run(&[], &tsv);
+    assert!(std::fs::read_to_string(&tsv)
+        .unwrap()
+        .starts_with("read_id\tlength"));
+
+    let database = dir.path().join("metrics.db");
+    run(&[], &database);
+    assert!(rusqlite::Connection::open(&database)
+        .unwrap()
+        .query_row("SELECT COUNT(*) FROM reads", [], |row| row.get::<_, i64>(0))
+        .is_ok());
+
+    // An explicit format wins, with a warning
+    let json = dir.path().join("mismatch.tsv");
+    let stderr = run(&["-f", "json"], &json);
+    assert!(stderr.contains("does not match the extension"));
+    let value: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    assert_eq!(value["summary"]["read_count"], 2);
+
+    // No format for .csv: the default, and a warning rather than silence
+    let csv = dir.path().join("reads.csv");
+    let stderr = run(&[], &csv);
+    assert!(stderr.contains("No output format matches the extension"));
+}
+
+#[test]
+fn test_fastq_quality_length_mismatch() {
+    let mut fastq = NamedTempFile::new().unwrap();
+    write!(
+        fastq,
+        "@read1\nACGTACGT\n+\nIIIIIIII\n@read2\nACGTACGT\n+\nIIIIIII\n"
+    )
+    .unwrap();
+    let error = nanoget_rs::convenience::extract_from_fastq(fastq.path()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Parse error: Read read2: quality string of 7 characters for a sequence of 8 bases"
+    );
+    assert_eq!(error.exit_code(), 3);
+
+    // Also for length-only extraction, where the qualities are not decoded
+    let files = vec![fastq.path()];
+    assert!(
+        nanoget_rs::convenience::extract_from_files(files, FileType::FastqMinimal, None)
+            .unwrap_err()
+            .to_string()
+            .contains("quality string of 7 characters")
+    );
+}
+
+#[test]
+fn test_validate_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = create_test_fastq();
+    let bam = dir.path().join("good.bam");
+    write_test_bam(
+        &bam,
+        &[bam_record("read1", 100, 60), bam_record("read2", 200, 60)],
+    );
+    let truncated = create_truncated_bam(dir.path());
+    let missing = dir.path().join("missing.fastq");
+
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["validate", "-f", "json"])
+        .arg(fastq.path())
+        .arg(&bam)
+        .arg(&truncated)
+        .arg(&missing)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(6));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("2 of 4 input files failed validation")
+    );
+    let reports: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let problems = |i: usize| reports[i]["problems"].as_array().unwrap().len();
+
+    assert_eq!(reports[0]["file_type"], "Fastq");
+    assert_eq!(reports[0]["compression"], "none");
+    assert_eq!(reports[0]["records_checked"], 2);
+    // The whole file was read, so the record count is exact
+    assert_eq!(reports[0]["estimated_records"], 2);
+    assert_eq!(problems(0), 0);
+
+    assert_eq!(reports[1]["file_type"], "Bam");
+    assert_eq!(reports[1]["compression"], "bgzf");
+    assert_eq!(reports[1]["records_checked"], 2);
+    assert_eq!(problems(1), 0);
+
+    assert!(reports[2]["problems"][0]
+        .as_str()
+        .unwrap()
+        .contains("truncated"));
+    assert!(reports[3]["problems"][0]
+        .as_str()
+        .unwrap()
+        .starts_with("File not found"));
+    assert_eq!(reports[3]["file_type"], serde_json::Value::Null);
+
+    // Only the first records are read
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["validate", "-n", "1"])
+        .arg(fastq.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let table = String::from_utf8(output.stdout).unwrap();
+    let row: Vec<&str> = table.lines().nth(1).unwrap().split('\t').collect();
+    assert_eq!(&row[1..4], ["fastq", "none", "1"]);
+    assert_eq!(row[6], "ok");
+
+    // A region query needs an index
+    let output = Command::cargo_bin("nanoget")
+        .unwrap()
+        .args(["validate", "--region", "chr1"])
+        .arg(&bam)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("with its index"));
+}
+
+#[test]
+fn test_config_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    let fastq = create_test_fastq();
+    std::fs::write(
+        dir.path().join("nanoget.toml"),
+        "output-format = \"tsv\"\nmin_length = 100\n",
+    )
+    .unwrap();
+    let run = |extra: &[&str], env: &[(&str, &str)]| {
+        let mut command = Command::cargo_bin("nanoget").unwrap();
+        command
+            .current_dir(dir.path())
+            .env("XDG_CONFIG_HOME", dir.path().join("config"))
+            .env_remove("NANOGET_CONFIG")
+            .args(["extract", "-t", "fastq"])
+            .args(extra)
+            .arg(fastq.path());
+        for (name, value) in env {
+            command.env(name, value);
+        }
+        command.output().unwrap()
+    };
+
+    // nanoget.toml in the current directory: TSV, and read2 (99 bases) dropped
+    let output = run(&[], &[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("read_id\tlength"));
+    assert!(stdout.contains("# Total reads: 1"));
+
+    // The environment overrides the file, the command line both
+    let output = run(&[], &[("NANOGET_OUTPUT_FORMAT", "json-summary")]);
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(summary["read_count"], 1);
+    let output = run(
+        &["-f", "json", "--min-length", "10"],
+        &[("NANOGET_OUTPUT_FORMAT", "json-summary")],
+    );
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(value["summary"]["read_count"], 2);
+
+    // A broken config file is an error, not silently ignored
+    let broken = dir.path().join("broken.toml");
+    std::fs::write(&broken, "threads = [").unwrap();
+    let output = run(&[], &[("NANOGET_CONFIG", broken.to_str().unwrap())]);
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid config file"));
+}
@@ -1,6 +1,10 @@
 // Integration test demonstrating library usage
 
-use nanoget_rs::{convenience::*, MetricsCollection, ReadMetrics};
+use clap::Parser;
+use nanoget_rs::{
+    convenience::*, extract_metrics_in_pool, extract_metrics_spilling, extract_summary_streaming,
+    Cli, Commands, ExtractArgs, FileType, MetricsCollection, ReadMetrics,
+};
 use std::io::Write;
 use tempfile::NamedTempFile;
 
@@ -15,7 +19,7 @@ fn test_convenience_api() {
     writeln!(temp_file, "@read2").unwrap();
     writeln!(temp_file, "GCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCTAGCT").unwrap();
     writeln!(temp_file, "+").unwrap();
-    writeln!(temp_file, "JJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJ").unwrap();
+    writeln!(temp_file, "JJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJJ").unwrap();
 
     // Test convenience function
     let metrics = extract_from_fastq(temp_file.path()).expect("Failed to extract metrics");
@@ -52,8 +56,8 @@ fn test_dataset_functionality() {
     let mut collection = MetricsCollection::new(reads);
 
     // Manually add dataset names to test the functionality
-    collection.reads[0].dataset = Some("Sample1".to_string());
-    collection.reads[1].dataset = Some("Sample2".to_string());
+    collection.reads[0].extended_mut().dataset = Some("Sample1".into());
+    collection.reads[1].extended_mut().dataset = Some("Sample2".into());
 
     let dataset_names = collection.dataset_names();
     assert_eq!(dataset_names, vec!["Sample1", "Sample2"]);
@@ -62,3 +66,86 @@ fn test_dataset_functionality() {
     assert_eq!(sample1_reads.len(), 1);
     assert_eq!(sample1_reads[0].read_id, Some("read1".to_string()));
 }
+
+#[test]
+fn test_extract_in_pool() {
+    let mut files = Vec::new();
+    for i in 0..3 {
+        let mut file = NamedTempFile::new().expect("Failed to create temp file");
+        writeln!(file, ">read{}\n{}", i, "ACGT".repeat(10 * (i + 1))).unwrap();
+        files.push(file);
+    }
+    let args = |threads: &str| {
+        let cli = Cli::try_parse_from(
+            ["nanoget", "extract", "-t", "fasta", "-j", threads]
+                .into_iter()
+                .map(String::from)
+                .chain(files.iter().map(|f| f.path().display().to_string())),
+        )
+        .unwrap();
+        match cli.command {
+            Commands::Extract(args) => args,
+            _ => unreachable!(),
+        }
+    };
+
+    // One pool serves repeated calls, also from within another pool
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(2)
+        .build()
+        .unwrap();
+    let outer = rayon::ThreadPoolBuilder::new()
+        .num_threads(2)
+        .build()
+        .unwrap();
+    for _ in 0..3 {
+        let metrics = outer
+            .install(|| extract_metrics_in_pool(&args("2"), &pool))
+            .unwrap();
+        let lengths: Vec<u64> = metrics.reads.iter().map(|r| r.length).collect();
+        assert_eq!(lengths, [40, 80, 120]);
+    }
+
+    // A single thread gives the same result without rayon
+    let metrics = nanoget_rs::extract_metrics(&args("1")).unwrap();
+    assert_eq!(metrics.summary.read_count, 3);
+    assert_eq!(metrics.reads[2].length, 120);
+}
+
+#[test]
+fn test_builder_output_options() {
+    let mut file = NamedTempFile::new().expect("Failed to create temp file");
+    for i in 1..=3 {
+        writeln!(file, ">read{}\n{}", i, "ACGT".repeat(10 * i)).unwrap();
+    }
+    let builder = ExtractArgs::builder()
+        .files([file.path()])
+        .file_type(FileType::Fasta)
+        .threads(1);
+
+    let args = builder
+        .clone()
+        .output_format("tsv")
+        .output("metrics.tsv")
+        .sort("length:desc")
+        .precision(1)
+        .per_file(true)
+        .progress(false)
+        .build();
+    assert_eq!(args.output_format.as_deref(), Some("tsv"));
+    assert_eq!(args.sort.as_deref(), Some("length:desc"));
+    assert_eq!(args.precision, Some(1));
+    assert!(args.per_file);
+
+    let streaming = builder.clone().streaming(true).count_only(false).build();
+    assert!(streaming.streaming);
+    let summary = extract_summary_streaming(&streaming).unwrap();
+    assert_eq!(summary.read_count, 3);
+
+    let capped = builder.clone().max_memory(1 << 20).build();
+    let spilled = extract_metrics_spilling(&capped, capped.max_memory.unwrap()).unwrap();
+    assert_eq!(spilled.summary.read_count, 3);
+
+    let split = builder.split_by("barcode").output("by_barcode").build();
+    assert_eq!(split.split_by.as_deref(), Some("barcode"));
+}